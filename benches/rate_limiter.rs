@@ -0,0 +1,130 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rate_limited_service::algorithms::{FixedWindow, Limit, SlidingWindow, TokenBucket};
+use rate_limited_service::circuit_breaker::{CircuitBreakerConfig, CircuitBreakerStore};
+use rate_limited_service::metrics::Metrics;
+use rate_limited_service::store::{InMemoryStore, UsageStore};
+use rate_limited_service::{RateLimit, RateLimiter};
+
+const ROUTE: &str = "GET /vault/items";
+const CONTENDING_THREADS: [usize; 3] = [2, 4, 8];
+
+// high enough that hot keys never get limited part way through a run
+fn unlimited() -> RateLimit {
+    RateLimit::new(u64::MAX)
+}
+
+// every store the limiter can run on, all using the fixed window algorithm
+fn stores() -> Vec<(&'static str, Arc<dyn UsageStore>)> {
+    vec![
+        ("in_memory", Arc::new(InMemoryStore::new())),
+        (
+            "circuit_breaker(in_memory)",
+            Arc::new(CircuitBreakerStore::new(InMemoryStore::new(), CircuitBreakerConfig::default(), Arc::new(Metrics::new()))),
+        ),
+    ]
+}
+
+fn cold_keys(c: &mut Criterion) {
+    let mut group = c.benchmark_group("log_usage/cold_keys");
+    group.throughput(Throughput::Elements(1));
+
+    for (name, store) in stores() {
+        let rate_limiter = RateLimiter::with_store(store);
+        let next_token = AtomicU64::new(0);
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| {
+                let token = next_token.fetch_add(1, Ordering::Relaxed).to_string();
+                rate_limiter.clone().log_usage(ROUTE, token, unlimited())
+            })
+        });
+    }
+    group.finish();
+}
+
+fn hot_key(c: &mut Criterion) {
+    let mut group = c.benchmark_group("log_usage/hot_key");
+    group.throughput(Throughput::Elements(1));
+
+    for (name, store) in stores() {
+        let rate_limiter = RateLimiter::with_store(store);
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| rate_limiter.clone().log_usage(ROUTE, "hot-token".to_string(), unlimited()))
+        });
+    }
+    group.finish();
+}
+
+// every thread hammers the same key, so they all fight over one shard lock
+fn contended_key(c: &mut Criterion) {
+    let mut group = c.benchmark_group("log_usage/contended_key");
+    group.throughput(Throughput::Elements(1));
+
+    for (name, store) in stores() {
+        let rate_limiter = RateLimiter::with_store(store);
+        for threads in CONTENDING_THREADS {
+            group.bench_function(BenchmarkId::new(name, format!("{}_threads", threads)), |b| {
+                b.iter_custom(|iters| contended(&rate_limiter, threads, iters))
+            });
+        }
+    }
+    group.finish();
+}
+
+fn contended(rate_limiter: &RateLimiter, threads: usize, iters: u64) -> Duration {
+    let per_thread = iters / threads as u64 + 1;
+    let started = Instant::now();
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                for _ in 0..per_thread {
+                    let _ = rate_limiter.clone().log_usage(ROUTE, "contended-token".to_string(), unlimited());
+                }
+            });
+        }
+    });
+    started.elapsed()
+}
+
+// a single counter on its own, without a store around it. The clock moves a
+// millisecond a request, so windows roll and buckets refill along the way.
+fn algorithms(c: &mut Criterion) {
+    let mut group = c.benchmark_group("try_acquire");
+    group.throughput(Throughput::Elements(1));
+    let limit = Limit::new(1_000, 1_000);
+
+    let mut window = FixedWindow::new(&limit, 0);
+    let mut now_ms = 0;
+    group.bench_function(BenchmarkId::from_parameter("fixed_window"), |b| {
+        b.iter(|| {
+            now_ms += 1;
+            window.try_acquire(&limit, black_box(1), now_ms)
+        })
+    });
+
+    let mut window = SlidingWindow::new(&limit, 0);
+    let mut now_ms = 0;
+    group.bench_function(BenchmarkId::from_parameter("sliding_window"), |b| {
+        b.iter(|| {
+            now_ms += 1;
+            window.try_acquire(&limit, black_box(1), now_ms)
+        })
+    });
+
+    let mut bucket = TokenBucket::new(&limit, 0);
+    let mut now_ms = 0;
+    group.bench_function(BenchmarkId::from_parameter("token_bucket"), |b| {
+        b.iter(|| {
+            now_ms += 1;
+            bucket.try_acquire(&limit, black_box(1), now_ms)
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, cold_keys, hot_key, contended_key, algorithms);
+criterion_main!(benches);
@@ -0,0 +1,190 @@
+use std::fmt;
+
+use crate::client::{AdminClient, ClientError};
+use crate::server::{ClientLimitsResponse, LimitOverrideRequest, StatsResponse};
+use crate::stats::{Offender, TopOffenders};
+use crate::RateLimit;
+
+pub const USAGE: &str = "usage: rate_limited_service admin <command>
+
+  limits show <key>                                     a client's quota on every route
+  limits reset <key>                                    give a client its whole quota back
+  limits set <key> <rate> [--route <route>] [--ttl <seconds>]
+                                                        override a client's limit, e.g. 5000/1m
+  limits unset <key> [--route <route>]                  remove an override
+  stats                                                 usage per route over the last hour
+  stats top [--limit <n>] [--window <seconds>]          the clients with the most 429s and requests
+
+<key> is the client key the routes count requests under, e.g. \"Bearer abc\" or \"api-key:abc\".
+ADMIN_URL (default http://localhost:8080) and ADMIN_TOKEN say which service to talk to.";
+
+// one `admin` subcommand, see USAGE
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    ShowLimits { key: String },
+    ResetLimits { key: String },
+    SetOverride { key: String, rate_limit: RateLimit, route: Option<String>, ttl_seconds: Option<i64> },
+    RemoveOverride { key: String, route: Option<String> },
+    Stats,
+    TopOffenders { limit: Option<usize>, window_seconds: Option<i64> },
+}
+
+// what's wrong with the arguments, printed above USAGE
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsageError(pub String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+// the arguments after `admin`
+pub fn parse_args(args: &[String]) -> Result<Command, UsageError> {
+    let (positional, options) = split_options(args)?;
+    let allowed: &[&str] = match positional.as_slice() {
+        ["limits", "set", ..] => &["route", "ttl"],
+        ["limits", "unset", ..] => &["route"],
+        ["stats", "top"] => &["limit", "window"],
+        _ => &[],
+    };
+    if let Some((name, _)) = options.iter().find(|(name, _)| !allowed.contains(name)) {
+        return Err(UsageError(format!("--{} doesn't apply to this command", name)));
+    }
+    let option = |name: &str| options.iter().find(|(option, _)| *option == name).map(|(_, value)| value.to_string());
+    let number = |name: &str| match option(name) {
+        Some(value) => value.parse::<u64>().map(Some).map_err(|_| UsageError(format!("--{} should be a whole number", name))),
+        None => Ok(None),
+    };
+
+    match positional.as_slice() {
+        ["limits", "show", key] => Ok(Command::ShowLimits { key: key.to_string() }),
+        ["limits", "reset", key] => Ok(Command::ResetLimits { key: key.to_string() }),
+        ["limits", "set", key, rate] => Ok(Command::SetOverride {
+            key: key.to_string(),
+            rate_limit: rate.parse().map_err(|err| UsageError(format!("{}: {}", rate, err)))?,
+            route: option("route"),
+            ttl_seconds: number("ttl")?.map(|ttl| ttl as i64),
+        }),
+        ["limits", "unset", key] => Ok(Command::RemoveOverride { key: key.to_string(), route: option("route") }),
+        ["stats"] => Ok(Command::Stats),
+        ["stats", "top"] => Ok(Command::TopOffenders {
+            limit: number("limit")?.map(|limit| limit as usize),
+            window_seconds: number("window")?.map(|window| window as i64),
+        }),
+        _ => Err(UsageError("unknown command".to_string())),
+    }
+}
+
+// the positional arguments, and the options by name
+type SplitArgs<'a> = (Vec<&'a str>, Vec<(&'a str, &'a str)>);
+
+// `--name value` pairs pulled out from between the other arguments
+fn split_options(args: &[String]) -> Result<SplitArgs<'_>, UsageError> {
+    let (mut positional, mut options) = (Vec::new(), Vec::new());
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.strip_prefix("--") {
+            Some(name) => {
+                let value = args.next().ok_or_else(|| UsageError(format!("--{} needs a value", name)))?;
+                options.push((name, value.as_str()));
+            }
+            None => positional.push(arg.as_str()),
+        }
+    }
+    Ok((positional, options))
+}
+
+// runs `command` against the admin API, returning what to print
+pub async fn run(client: &AdminClient, command: &Command) -> Result<String, ClientError> {
+    match command {
+        Command::ShowLimits { key } => Ok(limits_table(&client.client_limits(key).await?)),
+        Command::ResetLimits { key } => {
+            client.reset_limits(key).await?;
+            Ok(format!("reset {}'s usage on every route\n", key))
+        }
+        Command::SetOverride { key, rate_limit, route, ttl_seconds } => {
+            let request = LimitOverrideRequest {
+                limit: rate_limit.limit,
+                window_seconds: Some(rate_limit.duration.num_seconds()),
+                route: route.clone(),
+                ttl_seconds: *ttl_seconds,
+            };
+            client.set_limit_override(key, &request).await?;
+            Ok(format!("{} is limited to {} per {}s on {}\n", key, rate_limit.limit, rate_limit.duration.num_seconds(), route.as_deref().unwrap_or("every route")))
+        }
+        Command::RemoveOverride { key, route } => {
+            client.remove_limit_override(key, route.as_deref()).await?;
+            Ok(format!("removed {}'s override on {}\n", key, route.as_deref().unwrap_or("every route")))
+        }
+        Command::Stats => Ok(stats_table(&client.stats().await?)),
+        Command::TopOffenders { limit, window_seconds } => Ok(top_offenders_tables(&client.top_offenders(*limit, *window_seconds).await?)),
+    }
+}
+
+pub fn limits_table(limits: &ClientLimitsResponse) -> String {
+    let rows = limits.routes.iter().map(|route| {
+        let limit = format!("{}/{}s{}", route.limit, route.window_seconds, if route.overridden { " (override)" } else { "" });
+        vec![route.route.clone(), limit, route.remaining.to_string(), route.resets_at.clone()]
+    });
+    format!("limits of {}\n{}", limits.key, table(&["route", "limit", "remaining", "resets at"], rows))
+}
+
+pub fn stats_table(stats: &StatsResponse) -> String {
+    let rows = stats.routes.iter().map(|(route, stats)| {
+        let p95_remaining = stats.p95_remaining.map_or("-".to_string(), |remaining| remaining.to_string());
+        vec![
+            route.clone(),
+            stats.requests_last_minute.to_string(),
+            stats.requests_last_hour.to_string(),
+            stats.active_keys.to_string(),
+            stats.limited_requests.to_string(),
+            p95_remaining,
+        ]
+    });
+    format!("as of {}\n{}", stats.generated_at, table(&["route", "last minute", "last hour", "clients", "limited", "p95 remaining"], rows))
+}
+
+pub fn top_offenders_tables(top_offenders: &TopOffenders) -> String {
+    let rows = |offenders: &[Offender]| {
+        offenders
+            .iter()
+            .map(|offender| {
+                vec![
+                    offender.key_sha256.clone(),
+                    offender.tenant.clone().unwrap_or_else(|| "-".to_string()),
+                    offender.requests.to_string(),
+                    offender.limited_requests.to_string(),
+                ]
+            })
+            .collect::<Vec<_>>()
+    };
+    let headers = ["key sha256", "tenant", "requests", "limited"];
+    format!(
+        "most limited\n{}\nmost requests\n{}",
+        table(&headers, rows(&top_offenders.by_limited_requests)),
+        table(&headers, rows(&top_offenders.by_requests)),
+    )
+}
+
+// left aligned columns as wide as their widest cell
+fn table(headers: &[&str], rows: impl IntoIterator<Item = Vec<String>>) -> String {
+    let rows: Vec<Vec<String>> = rows.into_iter().collect();
+    let widths: Vec<usize> = headers
+        .iter()
+        .enumerate()
+        .map(|(column, header)| rows.iter().map(|row| row[column].len()).chain([header.len()]).max().unwrap_or_default())
+        .collect();
+
+    let line = |cells: Vec<&str>| {
+        let cells: Vec<String> = cells.iter().zip(&widths).map(|(cell, width)| format!("{:<width$}", cell, width = *width)).collect();
+        format!("{}\n", cells.join("  ").trim_end())
+    };
+    let mut table = line(headers.to_vec());
+    for row in &rows {
+        table.push_str(&line(row.iter().map(String::as_str).collect()));
+    }
+    table
+}
@@ -0,0 +1,168 @@
+// Rate limiting algorithms as plain state machines. Times are milliseconds
+// since the Unix epoch passed in by the caller, and only `core` is used (no
+// std, chrono or tokio), so this file builds as-is in a `#![no_std]` crate,
+// e.g. a proxy-wasm filter pulling it in with `#[path]`. The service itself
+// counts with FixedWindow, see store::InMemoryStore.
+
+// a limit of `limit` units every `window_ms`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limit {
+    pub limit: u64,
+    pub window_ms: i64,
+}
+
+impl Limit {
+    pub const fn new(limit: u64, window_ms: i64) -> Self {
+        Limit { limit, window_ms }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    // what's left once this request is counted, and when all of the limit is available again
+    Allowed { remaining: u64, resets_at_ms: i64 },
+    // nothing was counted, and a request of the same cost could succeed from `retry_at_ms`
+    Limited { retry_at_ms: i64 },
+}
+
+impl Decision {
+    pub fn is_allowed(&self) -> bool {
+        matches!(self, Decision::Allowed { .. })
+    }
+}
+
+// The whole limit is available again once a window has passed since the
+// window's first request, whatever was spent in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedWindow {
+    pub remaining: u64,
+    pub resets_at_ms: i64,
+}
+
+impl FixedWindow {
+    pub fn new(limit: &Limit, now_ms: i64) -> Self {
+        FixedWindow { remaining: limit.limit, resets_at_ms: now_ms.saturating_add(limit.window_ms) }
+    }
+
+    // starts a new window if the current one is over
+    pub fn refresh(&mut self, limit: &Limit, now_ms: i64) {
+        if self.resets_at_ms < now_ms {
+            *self = FixedWindow::new(limit, now_ms);
+        }
+    }
+
+    pub fn try_acquire(&mut self, limit: &Limit, cost: u64, now_ms: i64) -> Decision {
+        self.refresh(limit, now_ms);
+        if self.remaining < cost {
+            return Decision::Limited { retry_at_ms: self.resets_at_ms };
+        }
+        self.remaining -= cost;
+        Decision::Allowed { remaining: self.remaining, resets_at_ms: self.resets_at_ms }
+    }
+
+    // gives back `cost` charged at `charged_at_ms`, but only to the window it was
+    // charged in and never past the limit
+    pub fn refund(&mut self, limit: &Limit, cost: u64, charged_at_ms: i64) {
+        if self.resets_at_ms - limit.window_ms <= charged_at_ms && charged_at_ms <= self.resets_at_ms {
+            self.remaining = self.remaining.saturating_add(cost).min(limit.limit);
+        }
+    }
+}
+
+// Weighs the previous window's usage by how much of it still overlaps the
+// last `window_ms`, so a burst at the end of one window and the start of the
+// next can't spend twice the limit like it can with a fixed window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlidingWindow {
+    // windows are aligned to multiples of window_ms
+    pub window_start_ms: i64,
+    pub current: u64,
+    pub previous: u64,
+}
+
+impl SlidingWindow {
+    pub fn new(limit: &Limit, now_ms: i64) -> Self {
+        SlidingWindow { window_start_ms: window_start(limit, now_ms), current: 0, previous: 0 }
+    }
+
+    fn roll(&mut self, limit: &Limit, now_ms: i64) {
+        let start = window_start(limit, now_ms);
+        if start == self.window_start_ms {
+            return;
+        }
+        // a gap of more than one window leaves nothing to carry over
+        self.previous = if start - self.window_start_ms == limit.window_ms { self.current } else { 0 };
+        self.current = 0;
+        self.window_start_ms = start;
+    }
+
+    // usage over the last window_ms, rounded up
+    pub fn used(&mut self, limit: &Limit, now_ms: i64) -> u64 {
+        self.roll(limit, now_ms);
+        let window = limit.window_ms.max(1) as u128;
+        let overlap = (window - (now_ms - self.window_start_ms) as u128).min(window);
+        let carried = (self.previous as u128 * overlap).div_ceil(window);
+        u64::try_from(carried).unwrap_or(u64::MAX).saturating_add(self.current)
+    }
+
+    pub fn try_acquire(&mut self, limit: &Limit, cost: u64, now_ms: i64) -> Decision {
+        let used = self.used(limit, now_ms);
+        let window_end_ms = self.window_start_ms + limit.window_ms;
+        if used.saturating_add(cost) > limit.limit {
+            // the previous window's share shrinks as time passes, but only this window's end is certain to be enough
+            return Decision::Limited { retry_at_ms: window_end_ms };
+        }
+        self.current += cost;
+        Decision::Allowed { remaining: limit.limit - used - cost, resets_at_ms: window_end_ms + limit.window_ms }
+    }
+}
+
+fn window_start(limit: &Limit, now_ms: i64) -> i64 {
+    now_ms - now_ms.rem_euclid(limit.window_ms.max(1))
+}
+
+// Refills at limit per window_ms, a unit at a time, up to the limit, so
+// clients can burst up to the limit but only sustain the rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenBucket {
+    pub tokens: u64,
+    // the time the tokens were last counted up to, kept behind now by any fraction of a token
+    pub refilled_at_ms: i64,
+}
+
+impl TokenBucket {
+    pub fn new(limit: &Limit, now_ms: i64) -> Self {
+        TokenBucket { tokens: limit.limit, refilled_at_ms: now_ms }
+    }
+
+    pub fn refill(&mut self, limit: &Limit, now_ms: i64) {
+        let window = limit.window_ms.max(1) as u128;
+        let elapsed = now_ms.saturating_sub(self.refilled_at_ms).max(0) as u128;
+        let added = elapsed * limit.limit as u128 / window;
+        let tokens = (self.tokens as u128 + added).min(limit.limit as u128) as u64;
+        if tokens == limit.limit || limit.limit == 0 {
+            self.refilled_at_ms = now_ms;
+        } else {
+            // only the time the added tokens took, the rest counts towards the next one
+            self.refilled_at_ms += (added * window / limit.limit as u128) as i64;
+        }
+        self.tokens = tokens;
+    }
+
+    pub fn try_acquire(&mut self, limit: &Limit, cost: u64, now_ms: i64) -> Decision {
+        self.refill(limit, now_ms);
+        if self.tokens < cost {
+            return Decision::Limited { retry_at_ms: self.refilled_at_ms + self.time_for(limit, cost - self.tokens) };
+        }
+        self.tokens -= cost;
+        Decision::Allowed { remaining: self.tokens, resets_at_ms: self.refilled_at_ms + self.time_for(limit, limit.limit - self.tokens) }
+    }
+
+    // how long `tokens` take to refill
+    fn time_for(&self, limit: &Limit, tokens: u64) -> i64 {
+        if limit.limit == 0 {
+            return i64::MAX / 2;
+        }
+        i64::try_from((tokens as u128 * limit.window_ms.max(1) as u128).div_ceil(limit.limit as u128)).unwrap_or(i64::MAX / 2)
+    }
+}
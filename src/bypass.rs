@@ -0,0 +1,132 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// header clients send a bypass token in, separate from the bearer token so the
+// client's identity (and its normal quota) is unchanged
+pub const BYPASS_TOKEN_HEADER: &str = "X-Ratelimit-Bypass";
+
+// Issues and verifies short-lived bypass tokens of the form
+// `<hex subject>.<expiry unix seconds>.<hex hmac-sha256>`
+#[derive(Debug, Clone)]
+pub struct BypassTokens {
+    secret: Vec<u8>,
+    max_ttl: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BypassClaims {
+    pub subject: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BypassTokenError {
+    Malformed,
+    BadSignature,
+    Expired,
+}
+
+impl BypassTokens {
+    pub fn new(secret: impl Into<Vec<u8>>, max_ttl: Duration) -> Self {
+        BypassTokens { secret: secret.into(), max_ttl }
+    }
+
+    pub fn issue(&self, subject: &str, ttl: Duration) -> (String, DateTime<Utc>) {
+        // tokens are meant for emergencies, so never hand out anything longer lived than max_ttl
+        let ttl = if ttl > self.max_ttl { self.max_ttl } else { ttl };
+        let expires_at = Utc::now() + ttl;
+        let payload = format!("{}.{}", hex::encode(subject), expires_at.timestamp());
+        let signature = hex::encode(self.mac(&payload).finalize().into_bytes());
+
+        (format!("{}.{}", payload, signature), expires_at)
+    }
+
+    pub fn verify(&self, token: &str) -> Result<BypassClaims, BypassTokenError> {
+        let (payload, signature) = token.rsplit_once('.').ok_or(BypassTokenError::Malformed)?;
+        let (subject, expiry) = payload.split_once('.').ok_or(BypassTokenError::Malformed)?;
+        let signature = hex::decode(signature).map_err(|_| BypassTokenError::Malformed)?;
+
+        // the signature is checked before anything in the payload is trusted
+        self.mac(payload)
+            .verify_slice(&signature)
+            .map_err(|_| BypassTokenError::BadSignature)?;
+
+        let subject = hex::decode(subject)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .ok_or(BypassTokenError::Malformed)?;
+        let expires_at = expiry
+            .parse::<i64>()
+            .ok()
+            .and_then(|seconds| Utc.timestamp_opt(seconds, 0).single())
+            .ok_or(BypassTokenError::Malformed)?;
+
+        if expires_at < Utc::now() {
+            return Err(BypassTokenError::Expired);
+        }
+
+        Ok(BypassClaims { subject, expires_at })
+    }
+
+    fn mac(&self, payload: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.secret).expect("hmac accepts keys of any length");
+        mac.update(payload.as_bytes());
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bypass_tokens() -> BypassTokens {
+        BypassTokens::new("bypass-secret", Duration::minutes(15))
+    }
+
+    #[test]
+    fn verifies_the_tokens_it_issued() {
+        let tokens = bypass_tokens();
+        let (token, expires_at) = tokens.issue("incident-1234", Duration::minutes(5));
+
+        let claims = tokens.verify(&token).unwrap();
+        assert_eq!(claims.subject, "incident-1234");
+        assert_eq!(claims.expires_at.timestamp(), expires_at.timestamp());
+    }
+
+    #[test]
+    fn never_issues_tokens_past_the_max_ttl() {
+        let (_, expires_at) = bypass_tokens().issue("incident-1234", Duration::days(7));
+        assert!(expires_at <= Utc::now() + Duration::minutes(15));
+    }
+
+    #[test]
+    fn rejects_expired_tokens() {
+        let tokens = bypass_tokens();
+        let (token, _) = tokens.issue("incident-1234", Duration::seconds(-10));
+        assert_eq!(tokens.verify(&token), Err(BypassTokenError::Expired));
+    }
+
+    #[test]
+    fn rejects_tampered_and_foreign_tokens() {
+        let tokens = bypass_tokens();
+        let (token, _) = tokens.issue("incident-1234", Duration::minutes(5));
+        let (payload, signature) = token.rsplit_once('.').unwrap();
+        let (_, expiry) = payload.split_once('.').unwrap();
+
+        // someone else's subject under the original signature
+        let other_subject = format!("{}.{}.{}", hex::encode("someone-else"), expiry, signature);
+        assert_eq!(tokens.verify(&other_subject), Err(BypassTokenError::BadSignature));
+        // a later expiry under the original signature
+        let later = format!("{}.{}.{}", hex::encode("incident-1234"), Utc::now().timestamp() + 86_400, signature);
+        assert_eq!(tokens.verify(&later), Err(BypassTokenError::BadSignature));
+        // signed with another secret
+        let foreign = BypassTokens::new("other-secret", Duration::minutes(15)).issue("incident-1234", Duration::minutes(5)).0;
+        assert_eq!(tokens.verify(&foreign), Err(BypassTokenError::BadSignature));
+
+        assert_eq!(tokens.verify("not-a-token"), Err(BypassTokenError::Malformed));
+        assert_eq!(tokens.verify(&format!("{}.zz", payload)), Err(BypassTokenError::Malformed));
+    }
+}
@@ -0,0 +1,196 @@
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::metrics::Metrics;
+use crate::store::{LimitOverride, MultiUsageResult, StoreError, UsageCharge, UsageResult, UsageStore};
+use crate::RateLimit;
+
+const STATE_CLOSED: u64 = 0;
+const STATE_OPEN: u64 = 1;
+const STATE_HALF_OPEN: u64 = 2;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CircuitBreakerConfig {
+    // consecutive failed or slow calls before the breaker opens
+    pub failure_threshold: u32,
+    // how long the breaker stays open before letting a probe request through
+    pub open_seconds: u64,
+    // calls slower than this count as failures even if they succeed
+    pub slow_call_ms: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        CircuitBreakerConfig {
+            failure_threshold: 5,
+            open_seconds: 30,
+            slow_call_ms: 250,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Closed { consecutive_failures: u32 },
+    Open { until: Instant },
+    // a single probe request is in flight, everything else fails fast
+    HalfOpen,
+}
+
+// Wraps a (typically remote) store so that once it starts failing or timing out
+// requests stop waiting on it. While open every call fails fast with
+// StoreError::CircuitOpen, leaving the RateLimiter's failure policy to decide.
+#[derive(Debug)]
+pub struct CircuitBreakerStore<S> {
+    inner: S,
+    config: CircuitBreakerConfig,
+    state: Mutex<State>,
+    metrics: Arc<Metrics>,
+}
+
+impl<S: UsageStore> CircuitBreakerStore<S> {
+    pub fn new(inner: S, config: CircuitBreakerConfig, metrics: Arc<Metrics>) -> Self {
+        metrics.store_circuit_breaker_state.store(STATE_CLOSED, Ordering::Relaxed);
+        CircuitBreakerStore {
+            inner,
+            config,
+            state: Mutex::new(State::Closed { consecutive_failures: 0 }),
+            metrics,
+        }
+    }
+
+    // whether this call may go to the backing store, moving open -> half open once the open period is over
+    fn acquire(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            State::Closed { .. } => true,
+            State::Open { until } if Instant::now() >= until => {
+                self.set_state(&mut state, State::HalfOpen);
+                true
+            }
+            State::Open { .. } | State::HalfOpen => false,
+        }
+    }
+
+    fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        self.set_state(&mut state, State::Closed { consecutive_failures: 0 });
+    }
+
+    fn record_failure(&self) {
+        self.metrics.store_errors.fetch_add(1, Ordering::Relaxed);
+
+        let mut state = self.state.lock().unwrap();
+        let consecutive_failures = match *state {
+            State::Closed { consecutive_failures } => consecutive_failures + 1,
+            // a failed probe reopens the breaker straight away
+            State::HalfOpen => self.config.failure_threshold,
+            State::Open { .. } => return,
+        };
+
+        if consecutive_failures >= self.config.failure_threshold {
+            tracing::warn!(consecutive_failures, "usage store circuit breaker opened");
+            self.metrics.store_circuit_breaker_trips.fetch_add(1, Ordering::Relaxed);
+            let until = Instant::now() + Duration::from_secs(self.config.open_seconds);
+            self.set_state(&mut state, State::Open { until });
+        } else {
+            self.set_state(&mut state, State::Closed { consecutive_failures });
+        }
+    }
+
+    fn set_state(&self, state: &mut State, new_state: State) {
+        *state = new_state;
+        let gauge = match new_state {
+            State::Closed { .. } => STATE_CLOSED,
+            State::Open { .. } => STATE_OPEN,
+            State::HalfOpen => STATE_HALF_OPEN,
+        };
+        self.metrics.store_circuit_breaker_state.store(gauge, Ordering::Relaxed);
+    }
+}
+
+impl<S: UsageStore> UsageStore for CircuitBreakerStore<S> {
+    fn log_usage(&self, key: &str, rate_limit: &RateLimit, cost: u64, now: DateTime<Utc>) -> Result<UsageResult, StoreError> {
+        self.call(|inner| inner.log_usage(key, rate_limit, cost, now))
+    }
+
+    fn log_usage_many(&self, charges: &[UsageCharge<'_>], cost: u64, now: DateTime<Utc>) -> Result<MultiUsageResult, StoreError> {
+        self.call(|inner| inner.log_usage_many(charges, cost, now))
+    }
+
+    fn check_usage(&self, key: &str, rate_limit: &RateLimit, cost: u64, now: DateTime<Utc>) -> Result<UsageResult, StoreError> {
+        self.call(|inner| inner.check_usage(key, rate_limit, cost, now))
+    }
+
+    fn refund(&self, key: &str, rate_limit: &RateLimit, cost: u64, charged_at: DateTime<Utc>) -> Result<(), StoreError> {
+        self.call(|inner| inner.refund(key, rate_limit, cost, charged_at))
+    }
+
+    fn reserve(&self, key: &str, rate_limit: &RateLimit, id: &str, amount: u64, expires_at: DateTime<Utc>, now: DateTime<Utc>) -> Result<UsageResult, StoreError> {
+        self.call(|inner| inner.reserve(key, rate_limit, id, amount, expires_at, now))
+    }
+
+    fn release(&self, key: &str, rate_limit: &RateLimit, id: &str, cost: u64, now: DateTime<Utc>) -> Result<Option<UsageResult>, StoreError> {
+        self.call(|inner| inner.release(key, rate_limit, id, cost, now))
+    }
+
+    fn set_limit_override(&self, key: &str, limit_override: Option<LimitOverride>) -> Result<(), StoreError> {
+        self.call(|inner| inner.set_limit_override(key, limit_override))
+    }
+
+    fn limit_override(&self, key: &str, now: DateTime<Utc>) -> Result<Option<LimitOverride>, StoreError> {
+        self.call(|inner| inner.limit_override(key, now))
+    }
+
+    fn migrate_key(&self, from: &str, to: &str) -> Result<(), StoreError> {
+        self.call(|inner| inner.migrate_key(from, to))
+    }
+
+    fn record_nonce(&self, key: &str, expires_at: DateTime<Utc>, now: DateTime<Utc>) -> Result<bool, StoreError> {
+        self.call(|inner| inner.record_nonce(key, expires_at, now))
+    }
+
+    fn health_check(&self) -> Result<(), StoreError> {
+        self.call(|inner| inner.health_check())
+    }
+
+    fn load_state(&self) -> Result<(), StoreError> {
+        self.call(|inner| inner.load_state())
+    }
+
+    // only reads what the store already knows, so it doesn't count towards tripping the breaker
+    fn was_evicted(&self, key: &str, now: DateTime<Utc>) -> bool {
+        self.inner.was_evicted(key, now)
+    }
+}
+
+impl<S: UsageStore> CircuitBreakerStore<S> {
+    fn call<T>(&self, call: impl FnOnce(&S) -> Result<T, StoreError>) -> Result<T, StoreError> {
+        if !self.acquire() {
+            return Err(StoreError::CircuitOpen);
+        }
+
+        let started = Instant::now();
+        match call(&self.inner) {
+            Ok(result) => {
+                // a slow answer is still a correct one, but it counts towards tripping the breaker
+                if started.elapsed() > Duration::from_millis(self.config.slow_call_ms) {
+                    self.record_failure();
+                } else {
+                    self.record_success();
+                }
+                Ok(result)
+            }
+            Err(err) => {
+                tracing::warn!(error = %err, "usage store call failed");
+                self.record_failure();
+                Err(err)
+            }
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::replies::HeaderNames;
+use crate::server::{ClientLimitsResponse, LimitOverrideRequest, StatsResponse};
+use crate::stats::TopOffenders;
+
+pub const POST_VAULT_ROUTE: &str = "POST /vault";
+pub const GET_VAULT_ITEMS_ROUTE: &str = "GET /vault/items";
+pub const PUT_VAULT_ITEM_ROUTE: &str = "PUT /vault/items/<:id>";
+pub const DELETE_VAULT_ITEM_ROUTE: &str = "DELETE /vault/items/<:id>";
+pub const POST_VAULT_ITEMS_BATCH_ROUTE: &str = "POST /vault/items:batch";
+
+const DEFAULT_MAX_RETRIES: u32 = 3;
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(60);
+// used when a 429 comes back without any retry hint
+const FALLBACK_BACKOFF: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct VaultItem {
+    pub id: String,
+    pub data: serde_json::Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ItemsPage {
+    pub items: Vec<VaultItem>,
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ListItemsParams {
+    pub offset: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_prefix: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchCreateItemsResponse {
+    items: Vec<VaultItem>,
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    Http(reqwest::Error),
+    Unauthorized,
+    // still limited after using up every retry
+    RateLimited { retry_after: Duration },
+    Status(StatusCode),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Http(err) => write!(f, "request failed: {}", err),
+            ClientError::Unauthorized => write!(f, "bearer token was rejected"),
+            ClientError::RateLimited { retry_after } => write!(f, "rate limited, retry after {}s", retry_after.as_secs()),
+            ClientError::Status(status) => write!(f, "unexpected status {}", status),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(err: reqwest::Error) -> Self {
+        ClientError::Http(err)
+    }
+}
+
+// Client for the vault API that keeps track of the quota the server reports
+// and, when limited, waits out Retry-After before trying again.
+#[derive(Debug, Clone)]
+pub struct VaultClient {
+    http: reqwest::Client,
+    base_url: String,
+    bearer_token: String,
+    max_retries: u32,
+    max_backoff: Duration,
+    header_names: HeaderNames,
+    // last X-Ratelimit-Remaining seen per route
+    remaining: Arc<Mutex<HashMap<&'static str, u64>>>,
+}
+
+impl VaultClient {
+    pub fn new(base_url: impl Into<String>, bearer_token: impl Into<String>) -> Self {
+        VaultClient {
+            http: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            bearer_token: bearer_token.into(),
+            max_retries: DEFAULT_MAX_RETRIES,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            header_names: HeaderNames::default(),
+            remaining: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn with_http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    // a 429 asking us to wait longer than this is returned to the caller instead of slept through
+    pub fn with_max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    // for servers configured with other rate limiting header names
+    pub fn with_header_names(mut self, header_names: HeaderNames) -> Self {
+        self.header_names = header_names;
+        self
+    }
+
+    // requests left in the current window for `route`, as of the last response from it
+    pub fn remaining(&self, route: &str) -> Option<u64> {
+        self.remaining.lock().unwrap().get(route).copied()
+    }
+
+    pub async fn create_vault(&self) -> Result<(), ClientError> {
+        self.send(POST_VAULT_ROUTE, || self.http.post(self.url("/vault"))).await?;
+        Ok(())
+    }
+
+    pub async fn list_items(&self, params: &ListItemsParams) -> Result<ItemsPage, ClientError> {
+        let response = self.send(GET_VAULT_ITEMS_ROUTE, || self.http.get(self.url("/vault/items")).query(params)).await?;
+        Ok(response.json().await?)
+    }
+
+    pub async fn put_item(&self, id: &str, data: &serde_json::Value) -> Result<VaultItem, ClientError> {
+        let url = self.url(&format!("/vault/items/{}", id));
+        let response = self.send(PUT_VAULT_ITEM_ROUTE, || self.http.put(url.clone()).json(data)).await?;
+        Ok(response.json().await?)
+    }
+
+    pub async fn delete_item(&self, id: &str) -> Result<(), ClientError> {
+        let url = self.url(&format!("/vault/items/{}", id));
+        self.send(DELETE_VAULT_ITEM_ROUTE, || self.http.delete(url.clone())).await?;
+        Ok(())
+    }
+
+    pub async fn create_items(&self, items: &[serde_json::Value]) -> Result<Vec<VaultItem>, ClientError> {
+        let body = serde_json::json!({ "items": items });
+        let response = self.send(POST_VAULT_ITEMS_BATCH_ROUTE, || self.http.post(self.url("/vault/items:batch")).json(&body)).await?;
+        Ok(response.json::<BatchCreateItemsResponse>().await?.items)
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    async fn send<F>(&self, route: &'static str, request: F) -> Result<Response, ClientError>
+    where
+        F: Fn() -> RequestBuilder,
+    {
+        let mut attempt = 0;
+        loop {
+            let response = request().header("Authorization", &self.bearer_token).send().await?;
+            if let Some(remaining) = header(&response, &self.header_names.remaining) {
+                self.remaining.lock().unwrap().insert(route, remaining);
+            }
+
+            match response.status() {
+                StatusCode::TOO_MANY_REQUESTS => {
+                    self.remaining.lock().unwrap().insert(route, 0);
+                    let retry_after = retry_after(&response, &self.header_names);
+                    if attempt >= self.max_retries || retry_after > self.max_backoff {
+                        return Err(ClientError::RateLimited { retry_after });
+                    }
+                    attempt += 1;
+                    tokio::time::sleep(retry_after).await;
+                }
+                StatusCode::UNAUTHORIZED => return Err(ClientError::Unauthorized),
+                status if status.is_success() => return Ok(response),
+                status => return Err(ClientError::Status(status)),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct TopOffendersParams {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    window_seconds: Option<i64>,
+}
+
+// Client for the admin API, sending the admin token with every request. The
+// admin endpoints aren't rate limited, so nothing is retried.
+#[derive(Debug, Clone)]
+pub struct AdminClient {
+    http: reqwest::Client,
+    base_url: String,
+    admin_token: String,
+}
+
+impl AdminClient {
+    pub fn new(base_url: impl Into<String>, admin_token: impl Into<String>) -> Self {
+        AdminClient {
+            http: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            admin_token: admin_token.into(),
+        }
+    }
+
+    pub fn with_http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    // `key` is the client key as the routes count it, e.g. "Bearer abc" or "api-key:abc"
+    pub async fn client_limits(&self, key: &str) -> Result<ClientLimitsResponse, ClientError> {
+        let response = self.send(self.http.get(self.limits_url(key, ""))).await?;
+        Ok(response.json().await?)
+    }
+
+    pub async fn reset_limits(&self, key: &str) -> Result<(), ClientError> {
+        self.send(self.http.post(self.limits_url(key, "/reset"))).await?;
+        Ok(())
+    }
+
+    pub async fn set_limit_override(&self, key: &str, request: &LimitOverrideRequest) -> Result<(), ClientError> {
+        self.send(self.http.put(self.limits_url(key, "")).json(request)).await?;
+        Ok(())
+    }
+
+    // `route` names the route the override was set on, None for one on every route
+    pub async fn remove_limit_override(&self, key: &str, route: Option<&str>) -> Result<(), ClientError> {
+        let mut request = self.http.delete(self.limits_url(key, ""));
+        if let Some(route) = route {
+            request = request.query(&[("route", route)]);
+        }
+        self.send(request).await?;
+        Ok(())
+    }
+
+    pub async fn stats(&self) -> Result<StatsResponse, ClientError> {
+        let response = self.send(self.http.get(format!("{}/admin/stats", self.base_url))).await?;
+        Ok(response.json().await?)
+    }
+
+    pub async fn top_offenders(&self, limit: Option<usize>, window_seconds: Option<i64>) -> Result<TopOffenders, ClientError> {
+        let request = self.http.get(format!("{}/admin/top-offenders", self.base_url)).query(&TopOffendersParams { limit, window_seconds });
+        Ok(self.send(request).await?.json().await?)
+    }
+
+    fn limits_url(&self, key: &str, suffix: &str) -> String {
+        format!("{}/admin/limits/{}{}", self.base_url, utf8_percent_encode(key, NON_ALPHANUMERIC), suffix)
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<Response, ClientError> {
+        let response = request.bearer_auth(&self.admin_token).send().await?;
+        match response.status() {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(ClientError::Unauthorized),
+            status if status.is_success() => Ok(response),
+            status => Err(ClientError::Status(status)),
+        }
+    }
+}
+
+// the server sends X-Ratelimit-Retry-After, but a standard Retry-After (e.g. from a proxy in front) is honoured too
+fn retry_after(response: &Response, header_names: &HeaderNames) -> Duration {
+    header::<i64>(response, &header_names.retry_after)
+        .or_else(|| header(response, "Retry-After"))
+        // whole seconds are rounded down, so the window can still be closed for up to a second longer
+        .map(|seconds| Duration::from_secs(seconds.max(0) as u64 + 1))
+        .unwrap_or(FALLBACK_BACKOFF)
+}
+
+fn header<T: FromStr>(response: &Response, name: &str) -> Option<T> {
+    response.headers().get(name)?.to_str().ok()?.trim().parse().ok()
+}
@@ -0,0 +1,135 @@
+use std::io::{self, Write};
+
+use flate2::{write::GzEncoder, Compression};
+use warp::hyper::HeaderMap;
+
+// below this the framing overhead usually outweighs any savings
+const MIN_COMPRESSIBLE_BYTES: usize = 256;
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW_BITS: u32 = 22;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+}
+
+// picks the encoding with the highest q-value in Accept-Encoding, preferring brotli on ties
+pub fn negotiate(headers: &HeaderMap) -> Option<Encoding> {
+    let accept_encoding = headers.get("Accept-Encoding")?.to_str().ok()?;
+    let mut best: Option<(Encoding, f32)> = None;
+
+    for candidate in accept_encoding.split(',') {
+        let mut parts = candidate.split(';').map(|part| part.trim());
+        let encoding = match parts.next() {
+            Some(coding) if coding.eq_ignore_ascii_case("br") => Encoding::Brotli,
+            Some(coding) if coding.eq_ignore_ascii_case("gzip") || coding == "*" => Encoding::Gzip,
+            _ => continue,
+        };
+        let quality = parts
+            .find_map(|param| param.strip_prefix("q="))
+            .and_then(|quality| quality.parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        if quality <= 0.0 {
+            continue;
+        }
+        match best {
+            Some((best_encoding, best_quality)) if best_quality > quality || (best_quality == quality && best_encoding == Encoding::Brotli) => {}
+            _ => best = Some((encoding, quality)),
+        }
+    }
+
+    best.map(|(encoding, _)| encoding)
+}
+
+pub fn compress(encoding: Encoding, body: &[u8]) -> io::Result<Vec<u8>> {
+    match encoding {
+        Encoding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(body)?;
+            encoder.finish()
+        }
+        Encoding::Brotli => {
+            let mut compressed = Vec::new();
+            {
+                let mut encoder = brotli::CompressorWriter::new(&mut compressed, 4096, BROTLI_QUALITY, BROTLI_WINDOW_BITS);
+                encoder.write_all(body)?;
+            }
+            Ok(compressed)
+        }
+    }
+}
+
+// compresses `body` if the client accepts it and it is worth it, returning the encoding that was applied
+pub fn encode_body(headers: &HeaderMap, body: Vec<u8>) -> (Option<Encoding>, Vec<u8>) {
+    if body.len() < MIN_COMPRESSIBLE_BYTES {
+        return (None, body);
+    }
+
+    match negotiate(headers) {
+        Some(encoding) => match compress(encoding, &body) {
+            Ok(compressed) => (Some(encoding), compressed),
+            Err(_) => (None, body),
+        },
+        None => (None, body),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    fn accepting(accept_encoding: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("Accept-Encoding", accept_encoding.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn negotiates_the_encoding_by_q_value() {
+        let cases = [
+            ("gzip;q=0.5, br;q=0.8", Some(Encoding::Brotli)),
+            ("gzip, br;q=0.5", Some(Encoding::Gzip)),
+            ("gzip, br", Some(Encoding::Brotli)),
+            ("br;q=0, gzip", Some(Encoding::Gzip)),
+            ("*", Some(Encoding::Gzip)),
+            ("gzip;q=0", None),
+            ("identity, deflate", None),
+        ];
+        for (accept_encoding, expected) in cases {
+            assert_eq!(negotiate(&accepting(accept_encoding)), expected, "{}", accept_encoding);
+        }
+        assert_eq!(negotiate(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn compresses_only_bodies_worth_it() {
+        let small = b"{}".to_vec();
+        assert_eq!(encode_body(&accepting("gzip"), small.clone()), (None, small));
+
+        let body = "compressible ".repeat(50).into_bytes();
+        let (encoding, gzipped) = encode_body(&accepting("gzip"), body.clone());
+        assert_eq!(encoding, Some(Encoding::Gzip));
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(&gzipped[..]).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, body);
+
+        let (encoding, brotlied) = encode_body(&accepting("br"), body.clone());
+        assert_eq!(encoding, Some(Encoding::Brotli));
+        let mut decoded = Vec::new();
+        brotli::Decompressor::new(&brotlied[..], 4096).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, body);
+    }
+}
@@ -0,0 +1,735 @@
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+use std::{env, fmt, fs, io};
+
+use chrono::{DateTime, Datelike, Duration, NaiveTime, Utc, Weekday};
+use serde::Deserialize;
+
+use crate::circuit_breaker::CircuitBreakerConfig;
+use crate::decision_events::DecisionEventsConfig;
+use crate::encryption::{self, KeyConfig};
+use crate::graphql::GraphqlConfig;
+use crate::key_extractor::KeyExtractorConfig;
+use crate::listener::{HttpConfig, ListenConfig};
+use crate::proxy::ProxyConfig;
+use crate::region::RegionConfig;
+use crate::response_cache::ResponseCacheConfig;
+use crate::replies::{AuthPolicy, HeaderNames};
+use crate::router::{RoutePattern, Router};
+use crate::scopes::{ApiKeyConfig, Authenticator};
+use crate::server;
+use crate::startup::StartupConfig;
+use crate::store::{EvictionPolicy, FailurePolicy};
+use crate::telemetry::TelemetryConfig;
+use crate::usage_export::UsageExportConfig;
+use crate::write_behind::WriteBehindConfig;
+use crate::{KeyHasher, RateLimit};
+
+const DEFAULT_BYPASS_TOKEN_MAX_TTL_SECONDS: i64 = 15 * 60;
+const DEFAULT_NOT_MODIFIED_COST: u64 = 1;
+const DEFAULT_SIGNATURE_TOLERANCE_SECONDS: i64 = 5 * 60;
+// longer only tells clients to stay away for no reason
+const MAX_RETRY_AFTER_JITTER_SECONDS: u64 = 60 * 60;
+
+// Settings are read from the TOML file named by CONFIG_PATH (if any), then
+// overridden by environment variables so secrets don't have to live in the file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // admin endpoints are disabled unless an admin token is configured
+    pub admin_token: Option<String>,
+    pub bypass_token_secret: Option<String>,
+    pub bypass_token_max_ttl_seconds: i64,
+    // API keys and the scopes they grant
+    pub api_keys: Vec<ApiKeyConfig>,
+    // HS256 secret for bearer tokens that are JWTs carrying their scopes as claims
+    pub jwt_secret: Option<String>,
+    // how long after it expires a JWT is still accepted, with a warning header, 0 rejects it straight away
+    pub jwt_expiry_grace_seconds: i64,
+    // how far a signed request's X-Timestamp can be from now, see ApiKeyConfig::signing_secret
+    pub signature_tolerance_seconds: i64,
+    // how requests failing authentication or missing a scope are answered
+    pub auth: AuthPolicy,
+    // checks credentials in place of api_keys and jwt_secret, only settable in code
+    #[serde(skip)]
+    pub authenticator: Option<Arc<dyn Authenticator>>,
+    // HMAC key for the hashes usage is stored under, they're unsalted sha256 without one
+    pub key_hash_secret: Option<String>,
+    // the secret being rotated out, "" for the unsalted hash. Usage stored under it is moved over as it's used
+    pub previous_key_hash_secret: Option<String>,
+    // quota charged for a conditional GET answered with 304 Not Modified, 0 makes them free
+    pub not_modified_cost: u64,
+    // give back the quota of allowed requests that end in a 5xx
+    pub refund_server_errors: bool,
+    // serves a client's repeated GET /vault/items from a short lived cache
+    pub response_cache: Option<ResponseCacheConfig>,
+    // up to this many seconds are added at random to the Retry-After of 429s, so clients limited
+    // together don't all come back the moment the window resets. Limits are still enforced exactly.
+    // At most an hour, longer is cut to that
+    pub retry_after_jitter_seconds: u64,
+    // vault item data is encrypted at rest with the highest version, older ones are kept to decrypt
+    pub encryption_keys: Vec<KeyConfig>,
+    // keyed by route, e.g. "GET /vault/items"
+    pub routes: HashMap<String, RouteConfig>,
+    // limits for proxied requests no route template sets a limit for, by method
+    pub method_defaults: MethodDefaults,
+    // routes sharing one pool of quota, keyed by the group's name
+    pub groups: HashMap<String, GroupConfig>,
+    pub store: StoreConfig,
+    // what happens before GET /readyz reports ready
+    pub startup: StartupConfig,
+    // limits above the per-route ones, every request has to pass all of them
+    pub limits: LimitsConfig,
+    // the deployment region, counters are kept per region once it's set
+    pub region: Option<RegionConfig>,
+    // when set the service runs as a rate limiting reverse proxy in front of this upstream
+    pub proxy: Option<ProxyConfig>,
+    // names of the rate limiting headers in responses
+    pub headers: HeaderNames,
+    // where the service accepts connections
+    pub listen: ListenConfig,
+    pub http: HttpConfig,
+    pub telemetry: TelemetryConfig,
+    pub graphql: GraphqlConfig,
+    // hourly request counts per tenant are written to an S3 compatible bucket when set, e.g. for billing
+    pub usage_export: Option<UsageExportConfig>,
+    // every allow/deny decision is published to Kafka or NATS when set, e.g. for fraud detection
+    pub decision_events: Option<DecisionEventsConfig>,
+    // matches requests against the templates in `routes`, built on first use
+    #[serde(skip)]
+    router: OnceLock<Router>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct StoreConfig {
+    // applied to any request the limiter couldn't decide because the store failed
+    pub failure_policy: FailurePolicy,
+    // the usage store is only wrapped in a circuit breaker when this section is present
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    // decide requests against a local copy of the counters and write usage to the store in batches
+    pub write_behind: Option<WriteBehindConfig>,
+    // caps the counters the in-memory store keeps, unbounded if unset
+    pub max_keys: Option<usize>,
+    // which counters are evicted to make room once max_keys are kept
+    pub eviction_policy: EvictionPolicy,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    // per client, across all routes
+    pub token: Option<LevelConfig>,
+    // shared by all of a tenant's tokens, see ApiKeyConfig::tenant
+    pub tenant: Option<LevelConfig>,
+    // shared by every request
+    pub global: Option<LevelConfig>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct LevelConfig {
+    pub limit: u64,
+    // defaults to a one minute window
+    pub window_seconds: Option<i64>,
+    // limit and window in one, e.g. "100/5m", used in place of both
+    pub rate: Option<RateLimit>,
+}
+
+impl LevelConfig {
+    pub fn rate_limit(&self) -> RateLimit {
+        if let Some(rate) = &self.rate {
+            return rate.clone();
+        }
+        let mut rate_limit = RateLimit::new(self.limit);
+        if let Some(window) = self.window_seconds.filter(|seconds| *seconds > 0).and_then(Duration::try_seconds) {
+            rate_limit.duration = window;
+        }
+        rate_limit
+    }
+}
+
+// Fallback limits by the kind of method, so an endpoint added upstream without a
+// route template of its own gets a sensible limit rather than the catch-all's.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MethodDefaults {
+    // GET, HEAD and OPTIONS
+    pub read: Option<RateLimit>,
+    // every other method
+    pub write: Option<RateLimit>,
+}
+
+impl MethodDefaults {
+    // the class `method` falls in and its limit, if that class has one
+    pub fn for_method(&self, method: &str) -> Option<(&'static str, RateLimit)> {
+        match method.to_ascii_uppercase().as_str() {
+            "GET" | "HEAD" | "OPTIONS" => Some(("read", self.read.clone()?)),
+            _ => Some(("write", self.write.clone()?)),
+        }
+    }
+}
+
+// Routes counted against one limit between them in place of their own, e.g.
+// every write operation sharing 100 a minute.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct GroupConfig {
+    // route templates as they appear in `routes` or the built in ones, e.g. "PUT /vault/items/<:id>"
+    pub routes: Vec<String>,
+    pub limit: u64,
+    // defaults to a one minute window
+    pub window_seconds: Option<i64>,
+    // limit and window in one, e.g. "100/5m", used in place of both
+    pub rate: Option<RateLimit>,
+}
+
+impl GroupConfig {
+    pub fn rate_limit(&self) -> RateLimit {
+        LevelConfig { limit: self.limit, window_seconds: self.window_seconds, rate: self.rate.clone() }.rate_limit()
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RouteConfig {
+    // what requests are counted under, defaults to the bearer token
+    pub key: KeyExtractorConfig,
+    // overrides the route's built in limit
+    pub limit: Option<u64>,
+    // defaults to a one minute window
+    pub window_seconds: Option<i64>,
+    // limit and window in one, e.g. "100/5m", used in place of both
+    pub rate: Option<RateLimit>,
+    // allowed responses carry a warning header once this percentage of the limit is used, e.g. 80
+    pub soft_limit_percent: Option<u8>,
+    // compress response bodies when the client sends a matching Accept-Encoding
+    pub compression: bool,
+    // body sent with 429 responses, {retry_after}, {limit}, {window_seconds}, {level} and {group} are filled in
+    pub rate_limited_body: Option<String>,
+    pub rate_limited_content_type: Option<String>,
+    // overrides the top level retry_after_jitter_seconds
+    pub retry_after_jitter_seconds: Option<u64>,
+    // a token missing any of these gets a 403 without being charged
+    pub required_scopes: Vec<String>,
+    // caps each direction of a websocket connection on this route
+    pub messages_per_second: Option<u64>,
+    // limit overrides for tokens holding a scope, the highest matching one wins
+    pub scope_limits: HashMap<String, u64>,
+    // limits for set times, e.g. lower during nightly maintenance, the first one in effect wins
+    pub schedules: Vec<ScheduleConfig>,
+    // requests answered with a 4xx also count against this, e.g. "5/10m" against brute forcing,
+    // and a client that has used it up is turned away however much of its normal quota is left
+    pub abuse_limit: Option<RateLimit>,
+    // marks the route as on its way out, see DeprecationConfig
+    pub deprecation: Option<DeprecationConfig>,
+}
+
+impl RouteConfig {
+    // the limit a schedule sets at `now`, if one is in effect
+    pub fn scheduled_limit(&self, now: DateTime<Utc>) -> Option<u64> {
+        self.schedules.iter().find(|schedule| schedule.is_active(now)).map(|schedule| schedule.limit)
+    }
+}
+
+// A limit that applies while `now` falls within every range given: a daily UTC time
+// range, optionally only on some days, and/or an absolute one.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScheduleConfig {
+    pub limit: u64,
+    // e.g. "22:00" to "06:00", a range ending before it starts runs past midnight
+    #[serde(default)]
+    pub from: Option<NaiveTime>,
+    #[serde(default)]
+    pub until: Option<NaiveTime>,
+    // the days a daily range starts on, every day if empty
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    #[serde(default)]
+    pub starts_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub ends_at: Option<DateTime<Utc>>,
+}
+
+impl ScheduleConfig {
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        if self.starts_at.is_some_and(|starts_at| now < starts_at) || self.ends_at.is_some_and(|ends_at| now >= ends_at) {
+            return false;
+        }
+        let (from, until) = (self.from.unwrap_or(NaiveTime::MIN), self.until.unwrap_or(NaiveTime::MIN));
+        if from == until {
+            return self.days.is_empty() || self.days.contains(&now.weekday());
+        }
+
+        let time = now.time();
+        let started_on = if from < until {
+            (from <= time && time < until).then(|| now.weekday())
+        } else if time >= from {
+            Some(now.weekday())
+        } else {
+            // past midnight, so the range started the day before
+            (time < until).then(|| now.weekday().pred())
+        };
+        started_on.is_some_and(|day| self.days.is_empty() || self.days.contains(&day))
+    }
+}
+
+// A route being retired. Its responses carry Deprecation (RFC 9745) and Sunset
+// (RFC 8594) headers from the start, and once `since` has passed it can be held
+// to a stricter limit so clients feel the push to move off it.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeprecationConfig {
+    // when the route is or was deprecated, a date still to come announces it
+    pub since: DateTime<Utc>,
+    // when the route stops being served
+    #[serde(default)]
+    pub sunset: Option<DateTime<Utc>>,
+    // where clients can read about moving off the route, sent as a Link with rel="deprecation"
+    #[serde(default)]
+    pub link: Option<String>,
+    // used in place of the route's limit once deprecated, e.g. "10/1m"
+    #[serde(default)]
+    pub rate: Option<RateLimit>,
+}
+
+impl DeprecationConfig {
+    // the limit the route is held to at `now`, if it's deprecated by then and has one
+    pub fn rate_limit(&self, now: DateTime<Utc>) -> Option<RateLimit> {
+        self.rate.clone().filter(|_| now >= self.since)
+    }
+}
+
+// something --check-config turns up, see Config::problems
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigProblem {
+    // not "<METHOD> <path>", or outside proxy mode matching none of the service's routes
+    UnknownRoute(String),
+    // two templates matching exactly the same requests, so which one applies is arbitrary
+    DuplicateRoute(String, String),
+    // a route or limit level that would reject every request
+    ZeroLimit(String),
+    // a route with a schedule ending before it starts
+    EmptySchedule(String),
+    // a section needing a cargo feature the service was built without
+    MissingFeature(String, &'static str),
+    // a [region] without a name, so its counters would be shared with every other unnamed region
+    UnnamedRegion,
+    // a group listing a route that is neither built in nor in `routes`
+    UnknownGroupRoute(String, String),
+    // a route listed by two groups, only the first by name applies
+    GroupedTwice(String, String, String),
+    // a [headers] setting and its value, which isn't a valid header name
+    InvalidHeaderName(&'static str, String),
+    // a route keyed by a fingerprint without any signals, so every client would share one counter
+    EmptyFingerprint(String),
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigProblem::UnknownRoute(route) => write!(f, "routes.\"{}\" doesn't match any route the service serves", route),
+            ConfigProblem::DuplicateRoute(first, second) => write!(f, "routes.\"{}\" and routes.\"{}\" match the same requests", first, second),
+            ConfigProblem::ZeroLimit(name) => write!(f, "the limit for {} is 0, so every request would be rejected", name),
+            ConfigProblem::EmptySchedule(route) => write!(f, "a schedule for routes.\"{}\" ends before it starts", route),
+            ConfigProblem::MissingFeature(section, feature) => write!(f, "{} needs the service built with the {} feature", section, feature),
+            ConfigProblem::UnnamedRegion => write!(f, "region.name (or REGION) isn't set"),
+            ConfigProblem::UnknownGroupRoute(group, route) => write!(f, "groups.{} lists \"{}\", which is neither a built in route nor one in routes", toml_key(group), route),
+            ConfigProblem::GroupedTwice(route, first, second) => write!(f, "\"{}\" is in groups.{} and groups.{}, only groups.{} applies", route, toml_key(first), toml_key(second), toml_key(first)),
+            ConfigProblem::EmptyFingerprint(route) => write!(f, "routes.\"{}\".key is a fingerprint without signals, so every client would share one counter", route),
+            ConfigProblem::InvalidHeaderName(setting, name) => write!(f, "headers.{} is \"{}\", which isn't a valid header name", setting, name),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(io::Error),
+    Parse(toml::de::Error),
+    EncryptionKeys,
+    // a route or limit level with a zero or negative window_seconds, or one too long for a Duration
+    Window(String),
+    // a jwt_expiry_grace_seconds too long for a Duration
+    JwtExpiryGrace,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(err) => write!(f, "could not read config file: {}", err),
+            ConfigError::Parse(err) => write!(f, "could not parse config file: {}", err),
+            ConfigError::EncryptionKeys => write!(f, "VAULT_ENCRYPTION_KEYS should be comma separated <version>:<base64 key> pairs"),
+            ConfigError::Window(name) => write!(f, "window_seconds for {} should be positive and in range", name),
+            ConfigError::JwtExpiryGrace => write!(f, "jwt_expiry_grace_seconds is out of range"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Read(err) => Some(err),
+            ConfigError::Parse(err) => Some(err),
+            ConfigError::EncryptionKeys | ConfigError::Window(_) | ConfigError::JwtExpiryGrace => None,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            admin_token: None,
+            bypass_token_secret: None,
+            bypass_token_max_ttl_seconds: DEFAULT_BYPASS_TOKEN_MAX_TTL_SECONDS,
+            api_keys: Vec::new(),
+            jwt_secret: None,
+            jwt_expiry_grace_seconds: 0,
+            signature_tolerance_seconds: DEFAULT_SIGNATURE_TOLERANCE_SECONDS,
+            auth: AuthPolicy::default(),
+            authenticator: None,
+            key_hash_secret: None,
+            previous_key_hash_secret: None,
+            not_modified_cost: DEFAULT_NOT_MODIFIED_COST,
+            refund_server_errors: true,
+            response_cache: None,
+            retry_after_jitter_seconds: 0,
+            encryption_keys: Vec::new(),
+            routes: HashMap::new(),
+            method_defaults: MethodDefaults::default(),
+            groups: HashMap::new(),
+            store: StoreConfig::default(),
+            startup: StartupConfig::default(),
+            limits: LimitsConfig::default(),
+            region: None,
+            proxy: None,
+            headers: HeaderNames::default(),
+            listen: ListenConfig::default(),
+            http: HttpConfig::default(),
+            telemetry: TelemetryConfig::default(),
+            graphql: GraphqlConfig::default(),
+            usage_export: None,
+            decision_events: None,
+            router: OnceLock::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Result<Self, ConfigError> {
+        let mut config = match config_path() {
+            Some(path) => Config::parse(&fs::read_to_string(path).map_err(ConfigError::Read)?)?,
+            None => Config::default(),
+        };
+        // secrets can be mounted as files, e.g. from a secrets manager
+        if let Some(path) = non_empty_var("KEY_HASH_SECRET_FILE") {
+            config.key_hash_secret = Some(read_secret(&path)?);
+        }
+        if let Some(path) = non_empty_var("KEY_HASH_SECRET_PREVIOUS_FILE") {
+            config.previous_key_hash_secret = Some(read_secret(&path)?);
+        }
+        // replaces the file's keys, so they can be kept out of it entirely
+        if let Some(keys) = non_empty_var("VAULT_ENCRYPTION_KEYS") {
+            config.encryption_keys = encryption::parse_keys(&keys).ok_or(ConfigError::EncryptionKeys)?;
+        }
+
+        Ok(config.with_env_overrides())
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, ConfigError> {
+        let config: Config = toml::from_str(contents).map_err(ConfigError::Parse)?;
+        config.check_windows()?;
+        // the grace is added to a token's expiry, so it has to fit in a Duration like a window
+        if Duration::try_seconds(config.jwt_expiry_grace_seconds).is_none() {
+            return Err(ConfigError::JwtExpiryGrace);
+        }
+        Ok(config)
+    }
+
+    // a window that isn't positive would never reset, rates are already checked when parsed
+    fn check_windows(&self) -> Result<(), ConfigError> {
+        let routes = self.routes.iter().map(|(route, config)| (route.as_str(), config.window_seconds));
+        let region_global = self.region.as_ref().and_then(|region| region.global.as_ref());
+        let levels = [
+            ("limits.token", self.limits.token.as_ref()),
+            ("limits.tenant", self.limits.tenant.as_ref()),
+            ("limits.global", self.limits.global.as_ref()),
+            ("region.global", region_global),
+        ]
+        .into_iter()
+        .filter_map(|(level, config)| Some((level, config?.window_seconds)));
+        let groups: Vec<(String, Option<i64>)> = self.groups.iter().map(|(group, config)| (format!("groups.{}", toml_key(group)), config.window_seconds)).collect();
+        let groups = groups.iter().map(|(group, window_seconds)| (group.as_str(), *window_seconds));
+        // a window has to fit in a Duration too, or building the limit would overflow
+        let invalid = routes.chain(levels).chain(groups).find(|(_, window_seconds)| window_seconds.is_some_and(|seconds| seconds <= 0 || Duration::try_seconds(seconds).is_none()));
+        match invalid {
+            Some((name, _)) => Err(ConfigError::Window(name.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    // Mistakes that parse but wouldn't do what was meant, for --check-config.
+    // Bad windows and rates are already rejected by parse.
+    pub fn problems(&self) -> Vec<ConfigProblem> {
+        let mut problems = Vec::new();
+
+        let mut templates: Vec<&String> = self.routes.keys().collect();
+        templates.sort();
+        let mut patterns: Vec<RoutePattern> = Vec::new();
+        for template in templates {
+            let Some(pattern) = RoutePattern::parse(template) else {
+                problems.push(ConfigProblem::UnknownRoute(template.clone()));
+                continue;
+            };
+            if let Some(same) = patterns.iter().find(|other| other.same_requests(&pattern)) {
+                problems.push(ConfigProblem::DuplicateRoute(same.template().to_string(), template.clone()));
+            }
+            // a proxy forwards anything, otherwise the template has to match one of the service's own routes
+            if self.proxy.is_none() && !server::LIMITED_ROUTES.iter().any(|route| *route == template.as_str() || matches_route(&pattern, route)) {
+                problems.push(ConfigProblem::UnknownRoute(template.clone()));
+            }
+            patterns.push(pattern);
+        }
+
+        let mut routes: Vec<(&String, &RouteConfig)> = self.routes.iter().collect();
+        routes.sort_by_key(|(route, _)| *route);
+        for (route, route_config) in routes {
+            // the built in limit applies when neither is set, and is never 0
+            if route_config.rate.as_ref().map(|rate| rate.limit).or(route_config.limit) == Some(0) {
+                problems.push(ConfigProblem::ZeroLimit(route.clone()));
+            }
+            if route_config.abuse_limit.as_ref().is_some_and(|abuse_limit| abuse_limit.limit == 0) {
+                problems.push(ConfigProblem::ZeroLimit(format!("routes.\"{}\".abuse_limit", route)));
+            }
+            if route_config.key.has_empty_fingerprint() {
+                problems.push(ConfigProblem::EmptyFingerprint(route.clone()));
+            }
+            for schedule in &route_config.schedules {
+                if let (Some(starts_at), Some(ends_at)) = (schedule.starts_at, schedule.ends_at) {
+                    if starts_at >= ends_at {
+                        problems.push(ConfigProblem::EmptySchedule(route.clone()));
+                    }
+                }
+            }
+        }
+        let region_global = self.region.as_ref().and_then(|region| region.global.clone());
+        for (level, config) in [("limits.token", &self.limits.token), ("limits.tenant", &self.limits.tenant), ("limits.global", &self.limits.global), ("region.global", &region_global)] {
+            if config.as_ref().is_some_and(|config| config.rate_limit().limit == 0) {
+                problems.push(ConfigProblem::ZeroLimit(level.to_string()));
+            }
+        }
+        if self.region.as_ref().is_some_and(|region| region.name.is_empty()) {
+            problems.push(ConfigProblem::UnnamedRegion);
+        }
+        for (class, rate) in [("method_defaults.read", &self.method_defaults.read), ("method_defaults.write", &self.method_defaults.write)] {
+            if rate.as_ref().is_some_and(|rate| rate.limit == 0) {
+                problems.push(ConfigProblem::ZeroLimit(class.to_string()));
+            }
+        }
+
+        let mut groups: Vec<(&String, &GroupConfig)> = self.groups.iter().collect();
+        groups.sort_by_key(|(group, _)| *group);
+        let mut grouped: HashMap<&str, &str> = HashMap::new();
+        for (group, group_config) in groups {
+            if group_config.rate_limit().limit == 0 {
+                problems.push(ConfigProblem::ZeroLimit(format!("groups.{}", toml_key(group))));
+            }
+            for route in &group_config.routes {
+                // the limiter looks groups up by the route template a request was matched to
+                if self.proxy.is_none() && !server::LIMITED_ROUTES.contains(&route.as_str()) && !self.routes.contains_key(route) {
+                    problems.push(ConfigProblem::UnknownGroupRoute(group.clone(), route.clone()));
+                }
+                if let Some(first) = grouped.insert(route, group) {
+                    problems.push(ConfigProblem::GroupedTwice(route.clone(), first.to_string(), group.clone()));
+                }
+            }
+        }
+        for (setting, name) in self.headers.invalid() {
+            problems.push(ConfigProblem::InvalidHeaderName(setting, name.to_string()));
+        }
+        if let Some(feature) = self.decision_events.as_ref().and_then(DecisionEventsConfig::missing_feature) {
+            problems.push(ConfigProblem::MissingFeature(format!("decision_events.{}", feature), feature));
+        }
+        problems
+    }
+
+    pub fn route(&self, route: &str) -> RouteConfig {
+        self.routes.get(route).cloned().unwrap_or_default()
+    }
+
+    // the most a route's 429s add to Retry-After
+    pub fn retry_after_jitter(&self, route_config: &RouteConfig) -> u64 {
+        route_config.retry_after_jitter_seconds.unwrap_or(self.retry_after_jitter_seconds).min(MAX_RETRY_AFTER_JITTER_SECONDS)
+    }
+
+    // the group `route` is counted under, the first by name if several list it
+    pub fn group(&self, route: &str) -> Option<(&str, &GroupConfig)> {
+        self.groups
+            .iter()
+            .filter(|(_, group)| group.routes.iter().any(|grouped| grouped == route))
+            .min_by_key(|(name, _)| *name)
+            .map(|(name, group)| (name.as_str(), group))
+    }
+
+    // the most specific route template in config matching a request, e.g. "PUT /vault/items/{id}"
+    pub fn match_route(&self, method: &str, path: &str) -> Option<&str> {
+        self.router
+            .get_or_init(|| Router::new(self.routes.keys().map(String::as_str)))
+            .match_route(method, path)
+    }
+
+    // whether config gives `route` a limit of its own
+    pub fn sets_limit(&self, route: &str) -> bool {
+        self.routes.get(route).is_some_and(|route| route.rate.is_some() || route.limit.is_some())
+    }
+
+    // the limit configured for `route`, or `default_limit` per minute if there isn't one
+    pub fn rate_limit(&self, route: &str, default_limit: u64) -> RateLimit {
+        let route_config = self.routes.get(route);
+        if let Some(rate) = route_config.and_then(|route| route.rate.as_ref()) {
+            return rate.clone();
+        }
+        let mut rate_limit = RateLimit::new(route_config.and_then(|route| route.limit).unwrap_or(default_limit));
+        if let Some(window) = route_config.and_then(|route| route.window_seconds).filter(|seconds| *seconds > 0).and_then(Duration::try_seconds) {
+            rate_limit.duration = window;
+        }
+        rate_limit
+    }
+
+    pub fn key_hasher(&self) -> KeyHasher {
+        let secret = |secret: &String| Some(secret.as_bytes().to_vec()).filter(|secret| !secret.is_empty());
+        let key_hasher = KeyHasher::new(self.key_hash_secret.as_ref().and_then(secret));
+        match &self.previous_key_hash_secret {
+            Some(previous) => key_hasher.with_previous(secret(previous)),
+            None => key_hasher,
+        }
+    }
+
+    fn with_env_overrides(mut self) -> Self {
+        if let Some(admin_token) = non_empty_var("ADMIN_TOKEN") {
+            self.admin_token = Some(admin_token);
+        }
+        if let Some(secret) = non_empty_var("BYPASS_TOKEN_SECRET") {
+            self.bypass_token_secret = Some(secret);
+        }
+        if let Some(secret) = non_empty_var("KEY_HASH_SECRET") {
+            self.key_hash_secret = Some(secret);
+        }
+        if let Some(secret) = non_empty_var("KEY_HASH_SECRET_PREVIOUS") {
+            self.previous_key_hash_secret = Some(secret);
+        }
+        if let Some(secret) = non_empty_var("JWT_SECRET") {
+            self.jwt_secret = Some(secret);
+        }
+        if let Some(ttl) = non_empty_var("BYPASS_TOKEN_MAX_TTL_SECONDS").and_then(|ttl| ttl.parse().ok()) {
+            self.bypass_token_max_ttl_seconds = ttl;
+        }
+        if let Some(cost) = non_empty_var("NOT_MODIFIED_COST").and_then(|cost| cost.parse().ok()) {
+            self.not_modified_cost = cost;
+        }
+        if let Some(region) = non_empty_var("REGION") {
+            self.region.get_or_insert_with(RegionConfig::default).name = region;
+        }
+        // the usual AWS variables, only read when usage export is configured
+        if let Some(usage_export) = &mut self.usage_export {
+            if let Some(access_key_id) = non_empty_var("AWS_ACCESS_KEY_ID") {
+                usage_export.access_key_id = access_key_id;
+            }
+            if let Some(secret) = non_empty_var("AWS_SECRET_ACCESS_KEY") {
+                usage_export.secret_access_key = secret;
+            }
+        }
+        self
+    }
+}
+
+// whether `pattern` matches requests to the built in `route`, e.g. "PUT /vault/items/<:id>"
+fn matches_route(pattern: &RoutePattern, route: &str) -> bool {
+    let Some((method, path)) = route.split_once(' ') else {
+        return false;
+    };
+    let path: Vec<&str> = path.split('/').map(|segment| if segment.starts_with("<:") { "id" } else { segment }).collect();
+    pattern.matches(method, &path.join("/"))
+}
+
+// Every setting in a config file that changes the default, as "path = value"
+// pairs with secrets redacted. A setting changes the default if config parsed
+// from it alone differs from the defaults.
+pub fn changed_settings(contents: &str) -> Result<Vec<(String, String)>, ConfigError> {
+    let file: toml::Table = toml::from_str(contents).map_err(ConfigError::Parse)?;
+    let defaults = format!("{:?}", Config::default());
+
+    let mut settings = Vec::new();
+    flatten(&mut Vec::new(), &file, &mut settings);
+    Ok(settings
+        .into_iter()
+        .filter(|(path, value)| match Config::parse(&toml::to_string(&nested(path, (*value).clone())).unwrap_or_default()) {
+            Ok(config) => format!("{:?}", config) != defaults,
+            // e.g. a setting that needs others in its table
+            Err(_) => true,
+        })
+        .map(|(path, value)| {
+            let shown = match is_secret(&path) {
+                true => "<redacted>".to_string(),
+                false => value.to_string(),
+            };
+            (path.iter().map(|key| toml_key(key)).collect::<Vec<_>>().join("."), shown)
+        })
+        .collect())
+}
+
+// the leaves of `table`, arrays count as one setting
+fn flatten<'a>(path: &mut Vec<String>, table: &'a toml::Table, settings: &mut Vec<(Vec<String>, &'a toml::Value)>) {
+    for (key, value) in table {
+        path.push(key.clone());
+        match value {
+            toml::Value::Table(table) => flatten(path, table, settings),
+            value => settings.push((path.clone(), value)),
+        }
+        path.pop();
+    }
+}
+
+// `value` at `path` in an otherwise empty table
+fn nested(path: &[String], value: toml::Value) -> toml::Table {
+    let mut table = toml::Table::new();
+    match path {
+        [key] => {
+            table.insert(key.clone(), value);
+        }
+        [key, rest @ ..] => {
+            table.insert(key.clone(), toml::Value::Table(nested(rest, value)));
+        }
+        [] => {}
+    }
+    table
+}
+
+fn is_secret(path: &[String]) -> bool {
+    let Some(last) = path.last() else {
+        return false;
+    };
+    // API keys carry signing secrets and encryption keys are secrets themselves
+    last.ends_with("secret") || last.ends_with("_token") || last == "secret_access_key" || last == "api_keys" || last == "encryption_keys"
+}
+
+// keys such as route templates need quoting
+fn toml_key(key: &str) -> String {
+    match !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        true => key.to_string(),
+        false => format!("{:?}", key),
+    }
+}
+
+// the config file named by CONFIG_PATH, if any
+pub fn config_path() -> Option<String> {
+    non_empty_var("CONFIG_PATH")
+}
+
+// a secret file's contents, without the trailing newline editors leave
+fn read_secret(path: &str) -> Result<String, ConfigError> {
+    let secret = fs::read_to_string(path).map_err(ConfigError::Read)?;
+    Ok(secret.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn non_empty_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
@@ -0,0 +1,226 @@
+use std::fmt;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::metrics::Metrics;
+use crate::{UsageEvent, UsageObserver};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DecisionEventsConfig {
+    // only one sink is used, Kafka if both are set
+    pub kafka: Option<KafkaConfig>,
+    pub nats: Option<NatsConfig>,
+    // events waiting to be published, past this they're dropped rather than slow requests down
+    pub buffer: usize,
+}
+
+impl Default for DecisionEventsConfig {
+    fn default() -> Self {
+        DecisionEventsConfig { kafka: None, nats: None, buffer: 10_000 }
+    }
+}
+
+// needs the service built with the `kafka` feature
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct KafkaConfig {
+    // e.g. "kafka-1:9092,kafka-2:9092"
+    pub brokers: String,
+    pub topic: String,
+}
+
+// needs the service built with the `nats` feature
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct NatsConfig {
+    // e.g. "nats://nats:4222"
+    pub url: String,
+    pub subject: String,
+}
+
+impl DecisionEventsConfig {
+    // the feature the configured sink needs, if the service was built without it
+    pub fn missing_feature(&self) -> Option<&'static str> {
+        match (&self.kafka, &self.nats) {
+            (Some(_), _) if !cfg!(feature = "kafka") => Some("kafka"),
+            (None, Some(_)) if !cfg!(feature = "nats") => Some("nats"),
+            _ => None,
+        }
+    }
+}
+
+// One allow or deny decision, published as JSON
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DecisionEvent {
+    // sha256 of the client key, also the Kafka message key so a client's events stay in order
+    pub key_sha256: String,
+    // the route (plus any suffix) the request was counted against
+    pub route: String,
+    pub allowed: bool,
+    pub limit: u64,
+    pub remaining: u64,
+    pub resets_at: DateTime<Utc>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl DecisionEvent {
+    fn new(event: &UsageEvent, timestamp: DateTime<Utc>) -> Self {
+        DecisionEvent {
+            key_sha256: event.subject.clone(),
+            route: event.key.clone(),
+            allowed: event.allowed,
+            limit: event.limit,
+            remaining: event.remaining,
+            resets_at: event.resets_at,
+            timestamp,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DecisionEventsError {
+    // the configured sink's feature wasn't compiled in
+    NotCompiled(&'static str),
+    NoSink,
+    Connect(String),
+}
+
+impl fmt::Display for DecisionEventsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecisionEventsError::NotCompiled(feature) => write!(f, "decision_events.{} needs the service built with the {} feature", feature, feature),
+            DecisionEventsError::NoSink => write!(f, "decision_events needs a kafka or nats section"),
+            DecisionEventsError::Connect(err) => write!(f, "could not connect to the decision event sink: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for DecisionEventsError {}
+
+// Hands each decision to a background task publishing them, so a slow broker
+// only ever costs dropped events, counted in rate_limiter_decision_events_dropped_total.
+#[derive(Debug)]
+pub struct DecisionPublisher {
+    events: mpsc::Sender<DecisionEvent>,
+    metrics: Arc<Metrics>,
+}
+
+impl UsageObserver for DecisionPublisher {
+    fn on_usage(&self, event: &UsageEvent) {
+        if self.events.try_send(DecisionEvent::new(event, Utc::now())).is_err() {
+            self.metrics.decision_events_dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl DecisionPublisher {
+    // decisions go to the returned receiver, e.g. for embedders with a sink of their own
+    pub fn channel(buffer: usize, metrics: Arc<Metrics>) -> (Self, mpsc::Receiver<DecisionEvent>) {
+        let (events, receiver) = mpsc::channel(buffer.max(1));
+        (DecisionPublisher { events, metrics }, receiver)
+    }
+
+    // starts publishing to the configured sink
+    pub fn spawn(config: &DecisionEventsConfig, metrics: Arc<Metrics>) -> Result<Self, DecisionEventsError> {
+        if let Some(feature) = config.missing_feature() {
+            return Err(DecisionEventsError::NotCompiled(feature));
+        }
+        if config.kafka.is_none() && config.nats.is_none() {
+            return Err(DecisionEventsError::NoSink);
+        }
+        let (publisher, receiver) = DecisionPublisher::channel(config.buffer, metrics.clone());
+        tokio::spawn(sink::publish_all(config.clone(), receiver, metrics));
+        Ok(publisher)
+    }
+}
+
+#[cfg(any(feature = "kafka", feature = "nats"))]
+mod sink {
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    use tokio::sync::mpsc;
+
+    use super::{DecisionEvent, DecisionEventsConfig, DecisionEventsError};
+    use crate::metrics::Metrics;
+
+    enum Sink {
+        #[cfg(feature = "kafka")]
+        Kafka { producer: rdkafka::producer::FutureProducer, topic: String },
+        #[cfg(feature = "nats")]
+        Nats { client: async_nats::Client, subject: String },
+    }
+
+    // publishes events until every publisher is dropped, or not at all if the sink can't be set up
+    pub(super) async fn publish_all(config: DecisionEventsConfig, mut events: mpsc::Receiver<DecisionEvent>, metrics: Arc<Metrics>) {
+        let sink = match Sink::connect(&config).await {
+            Ok(sink) => sink,
+            Err(err) => {
+                tracing::error!(error = %err, "decision events won't be published");
+                return;
+            }
+        };
+        while let Some(event) = events.recv().await {
+            if let Err(err) = sink.publish(&event).await {
+                tracing::warn!(error = %err, "could not publish a decision event");
+                metrics.decision_events_dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    impl Sink {
+        async fn connect(config: &DecisionEventsConfig) -> Result<Sink, DecisionEventsError> {
+            #[cfg(feature = "kafka")]
+            if let Some(kafka) = &config.kafka {
+                let producer = rdkafka::ClientConfig::new()
+                    .set("bootstrap.servers", &kafka.brokers)
+                    .create()
+                    .map_err(|err: rdkafka::error::KafkaError| DecisionEventsError::Connect(err.to_string()))?;
+                return Ok(Sink::Kafka { producer, topic: kafka.topic.clone() });
+            }
+            #[cfg(feature = "nats")]
+            if let Some(nats) = &config.nats {
+                // keeps trying in the background, and reconnects whenever the connection drops
+                let client = async_nats::ConnectOptions::new()
+                    .retry_on_initial_connect()
+                    .connect(&nats.url)
+                    .await
+                    .map_err(|err| DecisionEventsError::Connect(err.to_string()))?;
+                return Ok(Sink::Nats { client, subject: nats.subject.clone() });
+            }
+            Err(DecisionEventsError::NoSink)
+        }
+
+        // queues the event with the client, which sends them in batches
+        async fn publish(&self, event: &DecisionEvent) -> Result<(), String> {
+            let payload = serde_json::to_vec(event).map_err(|err| err.to_string())?;
+            match self {
+                #[cfg(feature = "kafka")]
+                Sink::Kafka { producer, topic } => producer
+                    .send_result(rdkafka::producer::FutureRecord::to(topic).key(&event.key_sha256).payload(&payload))
+                    .map(|_| ())
+                    .map_err(|(err, _)| err.to_string()),
+                #[cfg(feature = "nats")]
+                Sink::Nats { client, subject } => client.publish(subject.clone(), payload.into()).await.map_err(|err| err.to_string()),
+            }
+        }
+    }
+}
+
+#[cfg(not(any(feature = "kafka", feature = "nats")))]
+mod sink {
+    use std::sync::Arc;
+
+    use tokio::sync::mpsc;
+
+    use super::{DecisionEvent, DecisionEventsConfig};
+    use crate::metrics::Metrics;
+
+    // without a sink compiled in, DecisionPublisher::spawn turns every config away before getting here
+    pub(super) async fn publish_all(_config: DecisionEventsConfig, _events: mpsc::Receiver<DecisionEvent>, _metrics: Arc<Metrics>) {}
+}
@@ -0,0 +1,140 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use rand::RngCore;
+use serde::{Deserialize, Deserializer};
+
+pub const KEY_LEN: usize = 32;
+// bumped if the layout below or the cipher ever changes
+const FORMAT_VERSION: u8 = 1;
+const NONCE_LEN: usize = 12;
+// format version, then the key version as a big endian u32, then the nonce
+const HEADER_LEN: usize = 1 + 4 + NONCE_LEN;
+
+// an AES-256 key, given in config as base64
+#[derive(Clone, PartialEq)]
+pub struct EncryptionKey([u8; KEY_LEN]);
+
+impl EncryptionKey {
+    pub fn new(key: [u8; KEY_LEN]) -> Self {
+        EncryptionKey(key)
+    }
+
+    pub fn from_base64(encoded: &str) -> Option<Self> {
+        let key = STANDARD.decode(encoded.trim()).ok()?;
+        Some(EncryptionKey(key.try_into().ok()?))
+    }
+}
+
+// keys never end up in logs
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+impl<'de> Deserialize<'de> for EncryptionKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        EncryptionKey::from_base64(&encoded)
+            .ok_or_else(|| serde::de::Error::custom(format!("expected {} bytes of base64", KEY_LEN)))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct KeyConfig {
+    pub version: u32,
+    pub key: EncryptionKey,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecryptError {
+    Malformed,
+    UnsupportedFormat(u8),
+    // the key this was encrypted with has been dropped from the keyring
+    UnknownKey(u32),
+    // tampered with, or stored under another id
+    Authentication,
+}
+
+// Encrypts with the highest versioned key and decrypts with whichever key the
+// ciphertext's header names, so keys can be rotated by adding a newer version
+// while older ciphertexts stay readable.
+#[derive(Clone)]
+pub struct Keyring {
+    keys: BTreeMap<u32, Aes256Gcm>,
+}
+
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keyring").field("versions", &self.keys.keys().collect::<Vec<_>>()).finish()
+    }
+}
+
+impl Keyring {
+    // None when there are no keys, i.e. encryption is off
+    pub fn new(keys: &[KeyConfig]) -> Option<Self> {
+        let keys: BTreeMap<_, _> = keys
+            .iter()
+            .map(|key| (key.version, Aes256Gcm::new((&key.key.0).into())))
+            .collect();
+        (!keys.is_empty()).then_some(Keyring { keys })
+    }
+
+    pub fn active_version(&self) -> u32 {
+        *self.keys.keys().next_back().expect("a keyring has at least one key")
+    }
+
+    // `aad` is authenticated but not encrypted, binding the ciphertext to e.g. the item id
+    pub fn encrypt(&self, plaintext: &[u8], aad: &[u8]) -> Vec<u8> {
+        let (version, cipher) = self.keys.iter().next_back().expect("a keyring has at least one key");
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let mut ciphertext = Vec::with_capacity(HEADER_LEN + plaintext.len() + 16);
+        ciphertext.push(FORMAT_VERSION);
+        ciphertext.extend_from_slice(&version.to_be_bytes());
+        ciphertext.extend_from_slice(&nonce);
+        let sealed = cipher
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad })
+            .expect("AES-GCM only fails for inputs far larger than a vault item");
+        ciphertext.extend_from_slice(&sealed);
+        ciphertext
+    }
+
+    pub fn decrypt(&self, ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>, DecryptError> {
+        if ciphertext.len() < HEADER_LEN {
+            return Err(DecryptError::Malformed);
+        }
+        if ciphertext[0] != FORMAT_VERSION {
+            return Err(DecryptError::UnsupportedFormat(ciphertext[0]));
+        }
+        let version = u32::from_be_bytes(ciphertext[1..5].try_into().unwrap());
+        let cipher = self.keys.get(&version).ok_or(DecryptError::UnknownKey(version))?;
+        cipher
+            .decrypt(Nonce::from_slice(&ciphertext[5..HEADER_LEN]), Payload { msg: &ciphertext[HEADER_LEN..], aad })
+            .map_err(|_| DecryptError::Authentication)
+    }
+
+    // the key version a ciphertext was encrypted with
+    pub fn key_version(ciphertext: &[u8]) -> Option<u32> {
+        ciphertext.get(1..5).map(|version| u32::from_be_bytes(version.try_into().unwrap()))
+    }
+}
+
+// Parses `VAULT_ENCRYPTION_KEYS`, comma separated `<version>:<base64 key>` pairs, e.g. as
+// injected by a secrets manager.
+pub fn parse_keys(value: &str) -> Option<Vec<KeyConfig>> {
+    value
+        .split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| {
+            let (version, key) = entry.split_once(':')?;
+            Some(KeyConfig { version: version.trim().parse().ok()?, key: EncryptionKey::from_base64(key)? })
+        })
+        .collect()
+}
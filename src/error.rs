@@ -0,0 +1,164 @@
+use std::sync::Arc;
+
+use warp::filters::body::BodyDeserializeError;
+use warp::http;
+use warp::hyper::StatusCode;
+use warp::reject::{InvalidHeader, InvalidQuery, LengthRequired, MethodNotAllowed, MissingHeader, PayloadTooLarge, UnsupportedMediaType};
+use warp::ws::MissingConnectionUpgrade;
+use warp::Rejection;
+
+use crate::config::{Config, RouteConfig};
+use crate::scopes::AuthError;
+use crate::signatures::SignatureError;
+use crate::store::StoreError;
+use crate::{replies, RateLimitedError, ReservationError, UsageError};
+
+// Everything that can turn a request away, with the status each one is answered
+// with in one place, see Error::reply
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Auth(#[from] AuthError),
+    #[error(transparent)]
+    Signature(#[from] SignatureError),
+    #[error(transparent)]
+    RateLimited(#[from] RateLimitedError),
+    #[error(transparent)]
+    Store(#[from] StoreError),
+    // a request that can't be served as it was sent
+    #[error("invalid request: {0}")]
+    Validation(String),
+    // more than a whole window's quota, so the request could never be allowed
+    #[error("a cost of {cost} is over the limit of {limit}")]
+    CostTooHigh { cost: u64, limit: u64 },
+    #[error("not found")]
+    NotFound,
+    #[error("method not allowed")]
+    MethodNotAllowed,
+    #[error("a content-length is required")]
+    LengthRequired,
+    #[error("the body is too large")]
+    PayloadTooLarge,
+    #[error("unsupported content-type")]
+    UnsupportedMediaType,
+    // something on our side, e.g. a reply that couldn't be built
+    #[error("internal error: {0}")]
+    Internal(String),
+}
+
+impl From<UsageError> for Error {
+    fn from(err: UsageError) -> Self {
+        match err {
+            UsageError::RateLimited(err) => Error::RateLimited(err),
+            UsageError::Store(err) => Error::Store(err),
+        }
+    }
+}
+
+impl From<ReservationError> for Error {
+    fn from(err: ReservationError) -> Self {
+        match err {
+            ReservationError::NotFound => Error::NotFound,
+            ReservationError::Store(err) => Error::Store(err),
+        }
+    }
+}
+
+impl From<http::Error> for Error {
+    fn from(err: http::Error) -> Self {
+        Error::Internal(err.to_string())
+    }
+}
+
+// A request turned away before any handler ran. One rejected by several routes is
+// answered for whichever got furthest, e.g. a malformed body rather than another route's method.
+impl From<Rejection> for Error {
+    fn from(rejection: Rejection) -> Self {
+        if rejection.is_not_found() {
+            return Error::NotFound;
+        }
+        if let Some(err) = rejection.find::<BodyDeserializeError>() {
+            return Error::Validation(err.to_string());
+        }
+        if let Some(err) = rejection.find::<InvalidQuery>() {
+            return Error::Validation(err.to_string());
+        }
+        if let Some(err) = rejection.find::<InvalidHeader>() {
+            return Error::Validation(err.to_string());
+        }
+        if let Some(err) = rejection.find::<MissingHeader>() {
+            return Error::Validation(err.to_string());
+        }
+        if let Some(err) = rejection.find::<MissingConnectionUpgrade>() {
+            return Error::Validation(err.to_string());
+        }
+        if rejection.find::<PayloadTooLarge>().is_some() {
+            return Error::PayloadTooLarge;
+        }
+        if rejection.find::<LengthRequired>().is_some() {
+            return Error::LengthRequired;
+        }
+        if rejection.find::<UnsupportedMediaType>().is_some() {
+            return Error::UnsupportedMediaType;
+        }
+        if rejection.find::<MethodNotAllowed>().is_some() {
+            return Error::MethodNotAllowed;
+        }
+        Error::Internal(format!("unexpected rejection: {:?}", rejection))
+    }
+}
+
+impl Error {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::Auth(AuthError::Forbidden) => StatusCode::FORBIDDEN,
+            Error::Auth(_) => StatusCode::UNAUTHORIZED,
+            Error::Signature(SignatureError::Replayed) => StatusCode::CONFLICT,
+            // a nonce that can't be checked could be a replay
+            Error::Signature(SignatureError::Store(_)) => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Signature(_) => StatusCode::UNAUTHORIZED,
+            Error::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            // the limiter couldn't decide, so the request can't be let through safely
+            Error::Store(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Validation(_) => StatusCode::BAD_REQUEST,
+            Error::CostTooHigh { .. } | Error::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::NotFound => StatusCode::NOT_FOUND,
+            Error::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            Error::LengthRequired => StatusCode::LENGTH_REQUIRED,
+            Error::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Error::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn reply(&self, config: &Config) -> Result<warp::reply::Response, http::Error> {
+        match self {
+            Error::Auth(err) => replies::auth_failure(&config.auth, *err),
+            // without a route to go by, the jitter is the service-wide one
+            Error::RateLimited(err) => replies::status(self.status())
+                .header(config.headers.retry_after.as_str(), replies::retry_after(err, config.retry_after_jitter(&RouteConfig::default())))
+                .header(config.headers.level.as_str(), err.level.as_str())
+                .body("".into()),
+            // clients can fix what they sent once they know what was wrong with it
+            Error::Validation(_) => replies::status(self.status()).header("Content-Type", "text/plain").body(self.to_string().into()),
+            Error::Internal(reason) => {
+                tracing::error!(reason = %reason, "request failed");
+                replies::empty(self.status())
+            }
+            _ => replies::empty(self.status()),
+        }
+    }
+}
+
+// a handler's reply, or the answer to the Error it failed with
+pub fn answer<F>(config: Arc<Config>, handler: F) -> Result<warp::reply::Response, http::Error>
+where
+    F: FnOnce(Arc<Config>) -> Result<warp::reply::Response, Error>,
+{
+    handler(config.clone()).or_else(|err| err.reply(&config))
+}
+
+// Answers every rejection through Error::reply, the same as the handlers' own errors.
+// Nothing is left to warp, the Rejection in the signature only keeps the filter's type.
+pub async fn recover(config: Arc<Config>, rejection: Rejection) -> Result<Result<warp::reply::Response, http::Error>, Rejection> {
+    Ok(Error::from(rejection).reply(&config))
+}
@@ -0,0 +1,49 @@
+use warp::hyper::HeaderMap;
+
+// strong validator derived from the exact bytes of the representation
+pub fn etag_for(body: &[u8]) -> String {
+    format!("\"{}\"", sha256::digest(body))
+}
+
+// If-None-Match uses the weak comparison function (RFC 9110 13.1.2), so a W/ prefix is ignored
+pub fn if_none_match(headers: &HeaderMap, etag: &str) -> bool {
+    let if_none_match = match headers.get("If-None-Match").map(|value| value.to_str()) {
+        Some(Ok(value)) => value,
+        _ => return false,
+    };
+
+    if_none_match
+        .split(',')
+        .map(|candidate| candidate.trim())
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn if_none_match_header(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("If-None-Match", value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn changes_only_with_the_body() {
+        assert_eq!(etag_for(b"[1, 2]"), etag_for(b"[1, 2]"));
+        assert_ne!(etag_for(b"[1, 2]"), etag_for(b"[1, 2, 3]"));
+        assert!(etag_for(b"").starts_with('"') && etag_for(b"").ends_with('"'));
+    }
+
+    #[test]
+    fn matches_if_none_match_weakly() {
+        let etag = etag_for(b"listing");
+
+        // the weak comparison ignores W/, and * matches any representation
+        for value in [etag.clone(), format!("W/{}", etag), "*".to_string(), format!("\"stale\", {}", etag)] {
+            assert!(if_none_match(&if_none_match_header(&value), &etag), "{}", value);
+        }
+        assert!(!if_none_match(&if_none_match_header("\"stale\""), &etag));
+        assert!(!if_none_match(&HeaderMap::new(), &etag));
+    }
+}
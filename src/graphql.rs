@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use async_graphql::parser::types::{BaseType, DocumentOperations, ExecutableDocument, Field, OperationType, Selection, SelectionSet, TypeKind, TypeSystemDefinition};
+use async_graphql::parser::{parse_query, parse_schema};
+use async_graphql::{Context, EmptySubscription, Json, Object, Request, Schema, SimpleObject, Value, Variables};
+use serde::Deserialize;
+
+use crate::server::{DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
+use crate::vault::{Vault, VaultItem};
+
+pub type VaultSchema = Schema<Query, Mutation, EmptySubscription>;
+
+// what a GraphQL query costs, instead of one unit per request
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct GraphqlConfig {
+    // charged for each field without a cost of its own
+    pub default_field_cost: u64,
+    // by "Type.field", e.g. "Query.items" = 5, 0 makes a field free
+    pub field_costs: HashMap<String, u64>,
+    // queries nesting fields deeper than this are rejected without being charged
+    pub max_depth: usize,
+}
+
+impl Default for GraphqlConfig {
+    fn default() -> Self {
+        GraphqlConfig {
+            default_field_cost: 1,
+            field_costs: HashMap::new(),
+            max_depth: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CostError {
+    Parse(String),
+    UnknownOperation,
+    // a fragment spread inside itself
+    FragmentCycle(String),
+    TooDeep { depth: usize, max_depth: usize },
+}
+
+impl fmt::Display for CostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CostError::Parse(err) => write!(f, "{}", err),
+            CostError::UnknownOperation => write!(f, "the document has no operation with that name"),
+            CostError::FragmentCycle(name) => write!(f, "fragment {} spreads itself", name),
+            CostError::TooDeep { depth, max_depth } => write!(f, "the query nests {} fields deep, at most {} are allowed", depth, max_depth),
+        }
+    }
+}
+
+#[derive(Debug, Clone, SimpleObject)]
+pub struct Item {
+    pub id: String,
+    pub data: Json<serde_json::Value>,
+}
+
+impl From<VaultItem> for Item {
+    fn from(item: VaultItem) -> Self {
+        Item { id: item.id, data: Json(item.data) }
+    }
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    // a page of items, like GET /vault/items
+    async fn items(&self, ctx: &Context<'_>, id_prefix: Option<String>, #[graphql(default)] offset: usize, limit: Option<usize>) -> Vec<Item> {
+        let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+        let (items, _) = ctx.data_unchecked::<Vault>().list(id_prefix.as_deref().unwrap_or(""), offset, limit);
+        items.into_iter().map(Item::from).collect()
+    }
+
+    async fn item(&self, ctx: &Context<'_>, id: String) -> Option<Item> {
+        ctx.data_unchecked::<Vault>().get(&id).map(Item::from)
+    }
+}
+
+pub struct Mutation;
+
+#[Object]
+impl Mutation {
+    async fn put_item(&self, ctx: &Context<'_>, id: String, data: Option<Json<serde_json::Value>>) -> Item {
+        let data = data.map_or(serde_json::Value::Null, |Json(data)| data);
+        ctx.data_unchecked::<Vault>().put(id, data).into()
+    }
+
+    // whether there was an item to delete
+    async fn delete_item(&self, ctx: &Context<'_>, id: String) -> bool {
+        ctx.data_unchecked::<Vault>().delete(&id).is_some()
+    }
+}
+
+// The vault schema, along with the field types a query's cost is worked out from.
+#[derive(Clone)]
+pub struct Graphql {
+    pub schema: VaultSchema,
+    // "Type.field" to the field's type, and whether it's a list
+    fields: Arc<HashMap<String, (String, bool)>>,
+}
+
+impl Graphql {
+    pub fn new(vault: Vault) -> Self {
+        let schema = Schema::build(Query, Mutation, EmptySubscription).data(vault).finish();
+        let sdl = parse_schema(schema.sdl()).expect("the schema's own SDL parses");
+
+        let mut fields = HashMap::new();
+        for definition in sdl.definitions {
+            let TypeSystemDefinition::Type(definition) = definition else {
+                continue;
+            };
+            let definition = definition.node;
+            let type_fields = match definition.kind {
+                TypeKind::Object(object) => object.fields,
+                TypeKind::Interface(interface) => interface.fields,
+                _ => continue,
+            };
+            for field in type_fields {
+                let (ty, list) = named_type(&field.node.ty.node.base);
+                fields.insert(format!("{}.{}", definition.name.node, field.node.name.node), (ty, list));
+            }
+        }
+        Graphql { schema, fields: Arc::new(fields) }
+    }
+
+    // Adds up the cost of each field the request's operation selects. The fields
+    // under a list count once per element, taking the list's `limit` argument as
+    // its length (or the default page size), so `items(limit: 50) { id data }`
+    // costs 1 + 50 * 2 at the default field cost.
+    pub fn cost(&self, request: &Request, config: &GraphqlConfig) -> Result<u64, CostError> {
+        let document = parse_query(&request.query).map_err(|err| CostError::Parse(err.to_string()))?;
+        let operation = match (&document.operations, request.operation_name.as_deref()) {
+            (DocumentOperations::Single(operation), _) => operation,
+            (DocumentOperations::Multiple(operations), Some(name)) => operations.get(name).ok_or(CostError::UnknownOperation)?,
+            (DocumentOperations::Multiple(operations), None) if operations.len() == 1 => operations.values().next().expect("one operation"),
+            (DocumentOperations::Multiple(_), None) => return Err(CostError::UnknownOperation),
+        };
+        let root = match operation.node.ty {
+            OperationType::Query => "Query",
+            OperationType::Mutation => "Mutation",
+            OperationType::Subscription => "Subscription",
+        };
+
+        let mut walk = Walk { graphql: self, config, document: &document, variables: &request.variables, fragments: Vec::new() };
+        walk.selection_set(&operation.node.selection_set.node, root, 1, 1)
+    }
+}
+
+// the named type under any list and non-null wrappers, and whether there was a list
+fn named_type(base: &BaseType) -> (String, bool) {
+    match base {
+        BaseType::Named(name) => (name.to_string(), false),
+        BaseType::List(inner) => (named_type(&inner.base).0, true),
+    }
+}
+
+struct Walk<'a> {
+    graphql: &'a Graphql,
+    config: &'a GraphqlConfig,
+    document: &'a ExecutableDocument,
+    variables: &'a Variables,
+    // fragments being walked, to catch cycles
+    fragments: Vec<&'a str>,
+}
+
+impl<'a> Walk<'a> {
+    fn selection_set(&mut self, selection_set: &'a SelectionSet, parent: &str, depth: usize, multiplier: u64) -> Result<u64, CostError> {
+        let mut cost = 0u64;
+        for selection in &selection_set.items {
+            let selection_cost = match &selection.node {
+                Selection::Field(field) => {
+                    let field = &field.node;
+                    if depth > self.config.max_depth {
+                        return Err(CostError::TooDeep { depth, max_depth: self.config.max_depth });
+                    }
+                    let name = format!("{}.{}", parent, field.name.node);
+                    let own = match field.name.node.as_str() {
+                        "__typename" => 0,
+                        _ => self.config.field_costs.get(&name).copied().unwrap_or(self.config.default_field_cost),
+                    };
+                    // introspection types aren't in the map, their fields are charged at the default cost
+                    let (ty, list) = self.graphql.fields.get(&name).cloned().unwrap_or_default();
+                    let length = match list {
+                        true => self.list_length(field),
+                        false => 1,
+                    };
+                    let children = self.selection_set(&field.selection_set.node, &ty, depth + 1, multiplier.saturating_mul(length))?;
+                    own.saturating_mul(multiplier).saturating_add(children)
+                }
+                Selection::FragmentSpread(spread) => {
+                    let name = spread.node.fragment_name.node.as_str();
+                    let Some(fragment) = self.document.fragments.get(name) else {
+                        return Err(CostError::Parse(format!("unknown fragment {}", name)));
+                    };
+                    if self.fragments.contains(&name) {
+                        return Err(CostError::FragmentCycle(name.to_string()));
+                    }
+                    self.fragments.push(name);
+                    let on = fragment.node.type_condition.node.on.node.to_string();
+                    let cost = self.selection_set(&fragment.node.selection_set.node, &on, depth, multiplier)?;
+                    self.fragments.pop();
+                    cost
+                }
+                Selection::InlineFragment(fragment) => {
+                    let on = fragment.node.type_condition.as_ref().map_or(parent.to_string(), |condition| condition.node.on.node.to_string());
+                    self.selection_set(&fragment.node.selection_set.node, &on, depth, multiplier)?
+                }
+            };
+            cost = cost.saturating_add(selection_cost);
+        }
+        Ok(cost)
+    }
+
+    // a list field's `limit` argument, given inline or as a variable
+    fn list_length(&self, field: &Field) -> u64 {
+        let limit = field
+            .get_argument("limit")
+            .and_then(|limit| limit.node.clone().into_const_with(|variable| self.variables.get(&variable).cloned().ok_or(())).ok())
+            .and_then(|limit| match limit {
+                Value::Number(number) => number.as_u64(),
+                _ => None,
+            });
+        limit.unwrap_or(DEFAULT_PAGE_SIZE as u64).clamp(1, MAX_PAGE_SIZE as u64)
+    }
+}
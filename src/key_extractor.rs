@@ -0,0 +1,232 @@
+use std::convert::Infallible;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use serde::Deserialize;
+use warp::{Filter, http::Method, hyper::HeaderMap, path::FullPath};
+
+const DEFAULT_API_KEY_HEADER: &str = "X-Api-Key";
+const DEFAULT_IPV4_PREFIX: u8 = 24;
+const DEFAULT_IPV6_PREFIX: u8 = 64;
+// hex digits of the User-Agent's sha256 kept in a fingerprint
+const USER_AGENT_HASH_LEN: usize = 16;
+
+// the parts of a request that limit keys can be derived from
+#[derive(Debug, Clone)]
+pub struct RequestInfo {
+    pub method: Method,
+    pub headers: HeaderMap,
+    pub remote_addr: Option<SocketAddr>,
+    pub path: String,
+}
+
+impl RequestInfo {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)?.to_str().ok().filter(|value| !value.is_empty())
+    }
+
+    // the raw Authorization header, "Bearer " prefix and all
+    pub fn authorization(&self) -> Option<&str> {
+        self.header("Authorization")
+    }
+}
+
+pub fn request_info() -> impl Filter<Extract = (RequestInfo,), Error = Infallible> + Clone {
+    warp::method()
+        .and(warp::header::headers_cloned())
+        .and(warp::addr::remote())
+        // servers other than warp's own (see listener) pass the peer's address as an extension
+        .and(warp::ext::optional::<SocketAddr>())
+        .and(warp::path::full())
+        .map(|method, headers, remote_addr: Option<SocketAddr>, peer_addr: Option<SocketAddr>, path: FullPath| RequestInfo {
+            method,
+            headers,
+            remote_addr: remote_addr.or(peer_addr),
+            path: path.as_str().to_string(),
+        })
+}
+
+// Derives the identity a request is counted under. Returning None means the
+// request doesn't carry one, and is rejected with a 401.
+pub trait KeyExtractor: fmt::Debug + Send + Sync {
+    fn extract(&self, request: &RequestInfo) -> Option<String>;
+}
+
+// the Authorization header, the default
+#[derive(Debug, Clone, Default)]
+pub struct BearerToken;
+
+impl KeyExtractor for BearerToken {
+    fn extract(&self, request: &RequestInfo) -> Option<String> {
+        request.authorization().map(str::to_string)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ApiKeyHeader {
+    pub header: String,
+}
+
+impl KeyExtractor for ApiKeyHeader {
+    fn extract(&self, request: &RequestInfo) -> Option<String> {
+        request.header(&self.header).map(|api_key| format!("api-key:{}", api_key))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ClientIp {
+    // only safe behind a proxy that overwrites X-Forwarded-For, otherwise clients can pick their own key
+    pub trust_forwarded_for: bool,
+}
+
+impl KeyExtractor for ClientIp {
+    fn extract(&self, request: &RequestInfo) -> Option<String> {
+        client_ip(request, self.trust_forwarded_for).map(|ip| format!("ip:{}", ip))
+    }
+}
+
+fn client_ip(request: &RequestInfo, trust_forwarded_for: bool) -> Option<String> {
+    let forwarded_for = trust_forwarded_for
+        .then(|| request.header("X-Forwarded-For"))
+        .flatten()
+        .and_then(|forwarded_for| forwarded_for.split(',').next())
+        .map(|ip| ip.trim().to_string());
+
+    forwarded_for.or_else(|| request.remote_addr.map(|addr| addr.ip().to_string()))
+}
+
+// one part of a fingerprint
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Signal {
+    // the Authorization header
+    Token,
+    // a hash of the User-Agent header, requests without one share "ua:none"
+    UserAgent,
+    // the network the client's address is in, e.g. 203.0.113.0/24
+    IpSubnet,
+}
+
+// Several signals in one key, in the order given, so a token is counted per device
+// using it, or a device is counted however many tokens it cycles through. A request
+// missing the token or an address has no key.
+#[derive(Debug, Clone)]
+pub struct Fingerprint {
+    pub signals: Vec<Signal>,
+    pub ipv4_prefix: u8,
+    pub ipv6_prefix: u8,
+    pub trust_forwarded_for: bool,
+}
+
+impl KeyExtractor for Fingerprint {
+    fn extract(&self, request: &RequestInfo) -> Option<String> {
+        let parts = self.signals.iter().map(|signal| match signal {
+            Signal::Token => request.authorization().map(str::to_string),
+            Signal::UserAgent => match request.header("User-Agent") {
+                Some(user_agent) => Some(format!("ua:{}", &sha256::digest(user_agent)[..USER_AGENT_HASH_LEN])),
+                None => Some("ua:none".to_string()),
+            },
+            Signal::IpSubnet => {
+                let ip: IpAddr = client_ip(request, self.trust_forwarded_for)?.parse().ok()?;
+                Some(format!("net:{}", subnet(ip, self.ipv4_prefix, self.ipv6_prefix)))
+            }
+        });
+        Some(format!("fp:{}", parts.collect::<Option<Vec<_>>>()?.join("|")))
+    }
+}
+
+// the network `ip` is in, as "<address>/<prefix>"
+fn subnet(ip: IpAddr, ipv4_prefix: u8, ipv6_prefix: u8) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let prefix = ipv4_prefix.min(32);
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+            format!("{}/{}", Ipv4Addr::from(u32::from(ip) & mask), prefix)
+        }
+        IpAddr::V6(ip) => {
+            let prefix = ipv6_prefix.min(128);
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+            format!("{}/{}", Ipv6Addr::from(u128::from(ip) & mask), prefix)
+        }
+    }
+}
+
+// the bearer token plus the concrete request path, so every resource behind a
+// templated route (e.g. each item id) gets its own counter
+#[derive(Debug, Clone, Default)]
+pub struct TokenAndRoute;
+
+impl KeyExtractor for TokenAndRoute {
+    fn extract(&self, request: &RequestInfo) -> Option<String> {
+        request.authorization().map(|token| format!("{}{}", token, request.path))
+    }
+}
+
+// the first extractor that finds a key wins, e.g. bearer token falling back to client ip
+#[derive(Debug)]
+pub struct Chain(pub Vec<Box<dyn KeyExtractor>>);
+
+impl KeyExtractor for Chain {
+    fn extract(&self, request: &RequestInfo) -> Option<String> {
+        self.0.iter().find_map(|extractor| extractor.extract(request))
+    }
+}
+
+// how a route's key extractor is written in config, e.g. `key = { type = "client_ip" }`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum KeyExtractorConfig {
+    #[default]
+    BearerToken,
+    ApiKeyHeader {
+        #[serde(default)]
+        header: Option<String>,
+    },
+    ClientIp {
+        #[serde(default)]
+        trust_forwarded_for: bool,
+    },
+    TokenAndRoute,
+    Chain {
+        extractors: Vec<KeyExtractorConfig>,
+    },
+    Fingerprint {
+        signals: Vec<Signal>,
+        // bits of the address kept by the ip_subnet signal, 24 and 64 by default
+        #[serde(default)]
+        ipv4_prefix: Option<u8>,
+        #[serde(default)]
+        ipv6_prefix: Option<u8>,
+        #[serde(default)]
+        trust_forwarded_for: bool,
+    },
+}
+
+impl KeyExtractorConfig {
+    // whether this is, or chains, a fingerprint without signals, which would give every client the key "fp:"
+    pub fn has_empty_fingerprint(&self) -> bool {
+        match self {
+            KeyExtractorConfig::Fingerprint { signals, .. } => signals.is_empty(),
+            KeyExtractorConfig::Chain { extractors } => extractors.iter().any(KeyExtractorConfig::has_empty_fingerprint),
+            _ => false,
+        }
+    }
+
+    pub fn build(&self) -> Box<dyn KeyExtractor> {
+        match self {
+            KeyExtractorConfig::BearerToken => Box::new(BearerToken),
+            KeyExtractorConfig::ApiKeyHeader { header } => Box::new(ApiKeyHeader {
+                header: header.clone().unwrap_or_else(|| DEFAULT_API_KEY_HEADER.to_string()),
+            }),
+            KeyExtractorConfig::ClientIp { trust_forwarded_for } => Box::new(ClientIp { trust_forwarded_for: *trust_forwarded_for }),
+            KeyExtractorConfig::TokenAndRoute => Box::new(TokenAndRoute),
+            KeyExtractorConfig::Chain { extractors } => Box::new(Chain(extractors.iter().map(KeyExtractorConfig::build).collect())),
+            KeyExtractorConfig::Fingerprint { signals, ipv4_prefix, ipv6_prefix, trust_forwarded_for } => Box::new(Fingerprint {
+                signals: signals.clone(),
+                ipv4_prefix: ipv4_prefix.unwrap_or(DEFAULT_IPV4_PREFIX),
+                ipv6_prefix: ipv6_prefix.unwrap_or(DEFAULT_IPV6_PREFIX),
+                trust_forwarded_for: *trust_forwarded_for,
+            }),
+        }
+    }
+}
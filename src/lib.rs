@@ -0,0 +1,46 @@
+// the routes are one warp filter chain, too deeply nested for the default limit in release builds
+#![recursion_limit = "256"]
+
+#[cfg(feature = "client")]
+pub mod admin;
+pub mod algorithms;
+pub mod bypass;
+pub mod circuit_breaker;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod compression;
+pub mod config;
+pub mod decision_events;
+pub mod encryption;
+pub mod error;
+pub mod etag;
+pub mod graphql;
+pub mod key_extractor;
+pub mod limiter;
+pub mod listener;
+pub mod metrics;
+pub mod middleware;
+pub mod notifications;
+pub mod proxy;
+pub mod region;
+pub mod replies;
+pub mod request_id;
+pub mod response_cache;
+pub mod router;
+pub mod scopes;
+pub mod server;
+pub mod sharded;
+pub mod signatures;
+pub mod simulate;
+pub mod startup;
+pub mod stats;
+pub mod store;
+pub mod stream;
+pub mod telemetry;
+pub mod usage_export;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod vault;
+pub mod write_behind;
+
+pub use limiter::{KeyHasher, LevelLimit, LimitLevel, MessageDirection, ParseRateLimitError, RateLimit, RateLimitedError, RateLimiter, Reservation, ReservationError, UsageError, UsageEvent, UsageObserver};
@@ -0,0 +1,732 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Deserializer};
+use sha2::Sha256;
+
+use crate::algorithms::Limit;
+use crate::bypass::{BypassClaims, BypassTokens};
+use crate::metrics::Metrics;
+use crate::region::{Region, CEILING_ROUTE};
+use crate::stats::UsageStats;
+use crate::store::{FailurePolicy, InMemoryStore, LimitOverride, StoreError, UsageCharge, UsageResult, UsageStore};
+use crate::usage_export::UsageLedger;
+
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    store: Arc<dyn UsageStore>,
+    // only used when the failure policy is FailurePolicy::Local
+    local_store: Arc<InMemoryStore>,
+    failure_policy: FailurePolicy,
+    // per connection message counters, these never leave the process the connection lives in
+    message_store: Arc<InMemoryStore>,
+    metrics: Arc<Metrics>,
+    stats: Arc<UsageStats>,
+    // only kept when usage is exported
+    usage_ledger: Option<Arc<UsageLedger>>,
+    key_hasher: KeyHasher,
+    bypass_tokens: Option<Arc<BypassTokens>>,
+    observers: Vec<Arc<dyn UsageObserver>>,
+    region: Option<Arc<Region>>,
+}
+
+// Hook called with the outcome of every counted request, e.g. to push quota
+// notifications. Observers run inline on the request path, so keep them cheap.
+pub trait UsageObserver: std::fmt::Debug + Send + Sync {
+    fn on_usage(&self, event: &UsageEvent);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsageEvent {
+    // sha256 of the bearer token, so observers never see the token itself
+    pub subject: String,
+    // the route (plus any suffix) the request was counted against
+    pub key: String,
+    pub limit: u64,
+    pub remaining: u64,
+    pub resets_at: DateTime<Utc>,
+    pub allowed: bool,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        RateLimiter::with_store(Arc::new(InMemoryStore::new()))
+    }
+
+    pub fn with_store(store: Arc<dyn UsageStore>) -> Self {
+        RateLimiter {
+            store,
+            local_store: Arc::new(InMemoryStore::new()),
+            failure_policy: FailurePolicy::default(),
+            message_store: Arc::new(InMemoryStore::new()),
+            metrics: Arc::new(Metrics::new()),
+            stats: Arc::new(UsageStats::new()),
+            usage_ledger: None,
+            key_hasher: KeyHasher::default(),
+            bypass_tokens: None,
+            observers: Vec::new(),
+            region: None,
+        }
+    }
+
+    pub fn with_failure_policy(mut self, failure_policy: FailurePolicy) -> Self {
+        self.failure_policy = failure_policy;
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    // per route usage over the last hour, shared by every clone of the limiter
+    pub fn stats(&self) -> &UsageStats {
+        &self.stats
+    }
+
+    pub fn with_usage_ledger(mut self, usage_ledger: Arc<UsageLedger>) -> Self {
+        self.usage_ledger = Some(usage_ledger);
+        self
+    }
+
+    pub fn usage_ledger(&self) -> Option<&UsageLedger> {
+        self.usage_ledger.as_deref()
+    }
+
+    pub fn with_key_hasher(mut self, key_hasher: KeyHasher) -> Self {
+        self.key_hasher = key_hasher;
+        self
+    }
+
+    pub fn with_bypass_tokens(mut self, bypass_tokens: BypassTokens) -> Self {
+        self.bypass_tokens = Some(Arc::new(bypass_tokens));
+        self
+    }
+
+    pub fn with_observer(mut self, observer: Arc<dyn UsageObserver>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    // counts usage in a deployment region, see region::Region
+    pub fn with_region(mut self, region: Region) -> Self {
+        self.region = Some(Arc::new(region));
+        self
+    }
+
+    pub fn region(&self) -> Option<&Region> {
+        self.region.as_deref()
+    }
+
+    // whether the usage store can be reached, see UsageStore::health_check
+    pub fn health_check(&self) -> Result<(), StoreError> {
+        self.store.health_check()
+    }
+
+    pub fn load_state(&self) -> Result<(), StoreError> {
+        self.store.load_state()
+    }
+
+    // whether the client's counter for `level` was evicted part way through its window, see UsageStore::was_evicted
+    pub fn was_evicted(&self, level: &LevelLimit) -> bool {
+        let route = self.region.as_ref().map_or_else(|| level.key.clone(), |region| region.scoped(&level.key));
+        self.store.was_evicted(&self.key_hasher.hash(&route, &level.client_key), Utc::now())
+    }
+
+    pub fn bypass_tokens(&self) -> Option<&BypassTokens> {
+        self.bypass_tokens.as_deref()
+    }
+
+    // every bypass attempt is audit logged, whether or not it is honoured
+    pub fn check_bypass(&self, route: &str, bypass_token: &str) -> Option<BypassClaims> {
+        let bypass_tokens = self.bypass_tokens.as_ref()?;
+
+        match bypass_tokens.verify(bypass_token) {
+            Ok(claims) => {
+                tracing::info!(target: "audit", subject = %claims.subject, route, expires_at = %claims.expires_at, "rate limit bypassed");
+                Some(claims)
+            }
+            Err(err) => {
+                tracing::warn!(target: "audit", route, error = ?err, "rejected rate limit bypass token");
+                None
+            }
+        }
+    }
+
+    pub fn log_usage(self, route: &str, bearer_token: String, rate_limit: RateLimit) -> Result<(u64, DateTime<Utc>), UsageError> {
+        self.log_weighted_usage(route, bearer_token, rate_limit, 1)
+    }
+
+    // counts the request as `cost` requests against the limit, e.g. one per item in a batch.
+    // callers are expected to reject costs larger than rate_limit.limit up front, since they can never fit in a window
+    pub fn log_weighted_usage(self, route: &str, bearer_token: String, rate_limit: RateLimit, cost: u64) -> Result<(u64, DateTime<Utc>), UsageError> {
+        let hashed_key = self.counter_key(route, &bearer_token);
+
+        let usage = match self.check_ceiling(&bearer_token, cost) {
+            Ok(()) => flatten_usage(self.store.log_usage(&hashed_key, &rate_limit, cost, Utc::now())),
+            Err(err) => Err(err),
+        };
+        if usage.is_ok() {
+            self.charge_ceiling(&bearer_token, cost);
+        }
+        // store failures are observed once the failure policy has decided them
+        if !matches!(usage, Err(UsageError::Store(_))) {
+            self.notify_observers(route, &bearer_token, &rate_limit, &usage);
+        }
+        usage
+    }
+
+    // decides a request the store failed to, according to the configured failure policy
+    pub fn apply_failure_policy(&self, route: &str, bearer_token: String, rate_limit: RateLimit, cost: u64, err: StoreError) -> Result<(u64, DateTime<Utc>), UsageError> {
+        tracing::warn!(error = %err, route, policy = ?self.failure_policy, "usage store failed, applying failure policy");
+        self.metrics.store_fallbacks.fetch_add(1, Ordering::Relaxed);
+
+        let now = Utc::now();
+        let usage = match self.failure_policy {
+            FailurePolicy::Allow => Ok((rate_limit.limit.saturating_sub(cost), now + rate_limit.duration)),
+            FailurePolicy::Reject => return Err(UsageError::Store(err)),
+            FailurePolicy::Local => flatten_usage(self.local_store.log_usage(&self.key_hasher.hash(route, &bearer_token), &rate_limit, cost, now)),
+        };
+        self.notify_observers(route, &bearer_token, &rate_limit, &usage);
+        usage
+    }
+
+    // Counts the request against every level of the limit hierarchy at once: it
+    // is only charged if all of them can afford `cost`, and otherwise the error
+    // names the level that couldn't. The first level is the one observers hear about.
+    pub fn log_usage_levels(&self, levels: &[LevelLimit], cost: u64) -> Result<(u64, DateTime<Utc>), UsageError> {
+        let client_key = levels.first().map_or("", |level| level.client_key.as_str());
+        // the cross-region ceiling is decided locally, so it's asked first
+        if let Err(err) = self.check_ceiling(client_key, cost) {
+            let usage = Err(err);
+            if let Some(level) = levels.first() {
+                self.notify_observers(&level.key, &level.client_key, &level.rate_limit, &usage);
+            }
+            return usage;
+        }
+
+        let keys: Vec<String> = levels.iter().map(|level| self.counter_key(&level.key, &level.client_key)).collect();
+        let charges: Vec<UsageCharge> = levels.iter().zip(&keys).map(|(level, key)| UsageCharge { key, rate_limit: &level.rate_limit }).collect();
+
+        let now = Utc::now();
+        let usage = match self.store.log_usage_many(&charges, cost, now) {
+            Ok(usage) => Ok(usage),
+            Err(err) => {
+                tracing::warn!(error = %err, route = levels[0].key, policy = ?self.failure_policy, "usage store failed, applying failure policy");
+                self.metrics.store_fallbacks.fetch_add(1, Ordering::Relaxed);
+                match self.failure_policy {
+                    FailurePolicy::Allow => Ok(Ok(levels.iter().map(|level| (level.rate_limit.limit.saturating_sub(cost), now + level.rate_limit.duration)).collect())),
+                    FailurePolicy::Reject => Err(err),
+                    FailurePolicy::Local => self.local_store.log_usage_many(&charges, cost, now),
+                }
+            }
+        };
+
+        let usage = match usage {
+            // report whichever level has the least left
+            Ok(Ok(usage)) => Ok(usage.into_iter().min_by_key(|(remaining, _)| *remaining).unwrap_or((0, now))),
+            Ok(Err((index, err))) => Err(UsageError::RateLimited(err.with_level(levels[index].level))),
+            Err(err) => return Err(UsageError::Store(err)),
+        };
+        if usage.is_ok() {
+            self.charge_ceiling(client_key, cost);
+        }
+        if let Some(level) = levels.first() {
+            self.notify_observers(&level.key, &level.client_key, &level.rate_limit, &usage);
+        }
+        usage
+    }
+
+    // Whether a request costing `cost` would be allowed at every level, without
+    // charging anything. Reports the tightest level's remaining requests, or the
+    // first level that would reject it. Store failures aren't put through the
+    // failure policy, the caller can't be told what a real request would get.
+    pub fn check_usage(&self, levels: &[LevelLimit], cost: u64) -> Result<(u64, DateTime<Utc>), UsageError> {
+        let now = Utc::now();
+        let mut tightest: Option<(u64, DateTime<Utc>)> = None;
+        for level in levels {
+            match flatten_usage(self.store.check_usage(&self.counter_key(&level.key, &level.client_key), &level.rate_limit, cost, now)) {
+                Ok(usage) if tightest.is_none_or(|(remaining, _)| usage.0 < remaining) => tightest = Some(usage),
+                Ok(_) => {}
+                Err(UsageError::RateLimited(err)) => return Err(UsageError::RateLimited(err.with_level(level.level))),
+                Err(err) => return Err(err),
+            }
+        }
+        if let Some(level) = levels.first() {
+            self.check_ceiling(&level.client_key, cost)?;
+        }
+        Ok(tightest.unwrap_or((0, now)))
+    }
+
+    // Sets `amount` of the route's quota aside for up to `ttl`, e.g. for a batch
+    // job that will only know its real cost at the end. Reservations only hold
+    // back the route level of the hierarchy.
+    pub fn reserve(&self, route: &str, client_key: &str, rate_limit: &RateLimit, amount: u64, ttl: Duration) -> Result<Reservation, UsageError> {
+        let now = Utc::now();
+        // the route is carried in the id so it can be committed or cancelled by id alone
+        let id = format!("{}.{}", URL_SAFE_NO_PAD.encode(route), hex::encode(rand::random::<[u8; 16]>()));
+        let expires_at = now + ttl;
+
+        let (remaining, resets_at) = flatten_usage(self.store.reserve(&self.counter_key(route, client_key), rate_limit, &id, amount, expires_at, now))?;
+        Ok(Reservation { id, route: route.to_string(), amount, expires_at, remaining, resets_at })
+    }
+
+    // Ends a reservation, charging what the work actually cost instead (which may be
+    // more or less than was reserved). Only the client that made the reservation can end it.
+    pub fn commit(&self, reservation_id: &str, client_key: &str, rate_limit: &RateLimit, cost: u64) -> Result<(u64, DateTime<Utc>), ReservationError> {
+        let route = Reservation::route_of(reservation_id).ok_or(ReservationError::NotFound)?;
+        match self.store.release(&self.counter_key(&route, client_key), rate_limit, reservation_id, cost, Utc::now()) {
+            Ok(Some(Ok(usage))) => Ok(usage),
+            // releasing never goes over the limit
+            Ok(Some(Err(_))) | Ok(None) => Err(ReservationError::NotFound),
+            Err(err) => Err(ReservationError::Store(err)),
+        }
+    }
+
+    pub fn cancel(&self, reservation_id: &str, client_key: &str, rate_limit: &RateLimit) -> Result<(), ReservationError> {
+        self.commit(reservation_id, client_key, rate_limit, 0).map(|_| ())
+    }
+
+    // Returns `cost` to every level a request was charged to, e.g. because the
+    // request then failed on our side. `charged_at` is any time after the charge,
+    // units charged in a window that has since ended aren't given back.
+    pub fn refund(&self, levels: &[LevelLimit], cost: u64, charged_at: DateTime<Utc>) {
+        for level in levels {
+            if let Err(err) = self.store.refund(&self.counter_key(&level.key, &level.client_key), &level.rate_limit, cost, charged_at) {
+                tracing::warn!(error = %err, route = level.key, "could not refund usage");
+            }
+        }
+        if let (Some(region), Some(level)) = (self.region.as_ref().filter(|region| region.has_ceiling()), levels.first()) {
+            region.refund_ceiling(&self.usage_key(CEILING_ROUTE, &level.client_key), cost, charged_at);
+        }
+    }
+
+    // Gives `level`'s client its whole quota back for the current window, e.g. after an incident.
+    // Refunds never take a counter past its limit, so refunding all of it is enough.
+    pub fn reset_usage(&self, level: &LevelLimit) -> Result<(), StoreError> {
+        self.store.refund(&self.counter_key(&level.key, &level.client_key), &level.rate_limit, level.rate_limit.limit, Utc::now())
+    }
+
+    // Counts a request the client got wrong against its abuse limit, see
+    // LevelLimit::abuse. Nothing is decided on it, so observers aren't told and
+    // a store failure only loses the count.
+    pub fn log_failure(&self, abuse: &LevelLimit) {
+        if let Err(err) = self.store.log_usage(&self.counter_key(&abuse.key, &abuse.client_key), &abuse.rate_limit, 1, Utc::now()) {
+            tracing::warn!(error = %err, route = abuse.key, "could not count a failed request");
+        }
+    }
+
+    // Overrides the limit of `client_key` on `route`, or on every route when
+    // `route` is None. None as the override removes it.
+    pub fn set_limit_override(&self, route: Option<&str>, client_key: &str, limit_override: Option<LimitOverride>) -> Result<(), StoreError> {
+        self.store.set_limit_override(&self.usage_key(&override_route(route), client_key), limit_override)
+    }
+
+    // the limit `client_key` has been given on `route`, an override for the route
+    // winning over one for every route. A store failure leaves the configured limits in place
+    pub fn limit_override(&self, route: &str, client_key: &str) -> Option<RateLimit> {
+        let now = Utc::now();
+        for key in [self.usage_key(&override_route(Some(route)), client_key), self.usage_key(&override_route(None), client_key)] {
+            match self.store.limit_override(&key, now) {
+                Ok(Some(limit_override)) => return Some(limit_override.rate_limit),
+                Ok(None) => {}
+                Err(err) => {
+                    tracing::warn!(error = %err, route, "could not look up limit override");
+                    return None;
+                }
+            }
+        }
+        None
+    }
+
+    // Remembers a nonce `client_key` sent until `expires_at`, false if the client
+    // already sent it. Nonces are kept per client, so one can't burn another's
+    pub fn record_nonce(&self, client_key: &str, nonce: &str, expires_at: DateTime<Utc>) -> Result<bool, StoreError> {
+        self.store.record_nonce(&self.key_hasher.hash(&nonce_route(nonce), client_key), expires_at, Utc::now())
+    }
+
+    // The key `client_key`'s usage of `route` is stored under. While a previous
+    // secret is being rotated out, whatever was stored under its hash is moved over first.
+    fn usage_key(&self, route: &str, client_key: &str) -> String {
+        let key = self.key_hasher.hash(route, client_key);
+        if let Some(previous) = &self.key_hasher.previous {
+            let previous_key = hash_key(previous.as_deref(), route, client_key);
+            if let Err(err) = self.store.migrate_key(&previous_key, &key) {
+                tracing::warn!(error = %err, route, "could not move usage hashed with the previous secret");
+            }
+        }
+        key
+    }
+
+    // the key a counter is stored under, kept apart per region when the limiter has one
+    fn counter_key(&self, route: &str, client_key: &str) -> String {
+        match &self.region {
+            Some(region) => self.usage_key(&region.scoped(route), client_key),
+            None => self.usage_key(route, client_key),
+        }
+    }
+
+    // whether `cost` more fits under the client's cross-region ceiling, if the region sets one
+    fn check_ceiling(&self, client_key: &str, cost: u64) -> Result<(), UsageError> {
+        match &self.region {
+            Some(region) if region.has_ceiling() => region
+                .check_ceiling(&self.usage_key(CEILING_ROUTE, client_key), cost)
+                .map_err(|err| UsageError::RateLimited(err.with_level(LimitLevel::Region))),
+            _ => Ok(()),
+        }
+    }
+
+    fn charge_ceiling(&self, client_key: &str, cost: u64) {
+        if let Some(region) = self.region.as_ref().filter(|region| region.has_ceiling()) {
+            region.charge_ceiling(&self.usage_key(CEILING_ROUTE, client_key), cost);
+        }
+    }
+
+    fn notify_observers(&self, route: &str, bearer_token: &str, rate_limit: &RateLimit, usage: &Result<(u64, DateTime<Utc>), UsageError>) {
+        if self.observers.is_empty() {
+            return;
+        }
+
+        let (remaining, resets_at, allowed) = match usage {
+            Ok((remaining, resets_at)) => (*remaining, *resets_at, true),
+            Err(UsageError::RateLimited(err)) => (0, err.time_when_refreshed, false),
+            Err(UsageError::Store(_)) => return,
+        };
+        let event = UsageEvent {
+            subject: sha256::digest(bearer_token),
+            key: route.to_string(),
+            limit: rate_limit.limit,
+            remaining,
+            resets_at,
+            allowed,
+        };
+        for observer in &self.observers {
+            observer.on_usage(&event);
+        }
+    }
+
+    // message-rate mode: counts one message in `direction` on a long lived connection, e.g. a websocket
+    pub fn log_message(&self, connection_id: &str, direction: MessageDirection, rate_limit: &RateLimit) -> Result<u64, RateLimitedError> {
+        match self.message_store.log_usage(&message_key(connection_id, direction), rate_limit, 1, Utc::now()) {
+            Ok(Ok((remaining, _))) => Ok(remaining),
+            Ok(Err(err)) => Err(err),
+            // the in memory store can't fail, but if it somehow did the message shouldn't be dropped over it
+            Err(_) => Ok(rate_limit.limit),
+        }
+    }
+
+    // forgets a closed connection's message counters
+    pub fn end_connection(&self, connection_id: &str) {
+        self.message_store.remove(&message_key(connection_id, MessageDirection::Inbound));
+        self.message_store.remove(&message_key(connection_id, MessageDirection::Outbound));
+    }
+}
+
+// each direction of a connection has its own message budget
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessageDirection {
+    Inbound,
+    Outbound,
+}
+
+fn message_key(connection_id: &str, direction: MessageDirection) -> String {
+    format!("{}:{:?}", connection_id, direction)
+}
+
+// overrides are stored like counters, under a prefix no route template starts with
+fn override_route(route: Option<&str>) -> String {
+    format!("limit-override {}", route.unwrap_or("*"))
+}
+
+// nonces are stored under a prefix of their own too
+fn nonce_route(nonce: &str) -> String {
+    format!("nonce {}", nonce)
+}
+
+// and failed requests under one of their own
+fn abuse_route(route: &str) -> String {
+    format!("abuse {}", route)
+}
+
+// and groups under their name with a prefix
+fn group_route(group: &str) -> String {
+    format!("group {}", group)
+}
+
+// Hashes the keys usage is stored under, since a bearer token can't be stored on
+// its own. With a secret the hash is an HMAC, so a leaked store can't be used to
+// guess tokens offline.
+#[derive(Clone, Default)]
+pub struct KeyHasher {
+    secret: Option<Vec<u8>>,
+    // Some(None) while moving off the unsalted hash
+    previous: Option<Option<Vec<u8>>>,
+}
+
+// secrets never end up in logs
+impl fmt::Debug for KeyHasher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyHasher").field("salted", &self.secret.is_some()).field("rotating", &self.previous.is_some()).finish()
+    }
+}
+
+impl KeyHasher {
+    // None keeps the unsalted sha256 of earlier releases
+    pub fn new(secret: Option<Vec<u8>>) -> Self {
+        KeyHasher { secret, previous: None }
+    }
+
+    // the secret being rotated out, None for the unsalted hash. Usage stored under
+    // its hashes is moved to the current secret's the first time it is touched
+    pub fn with_previous(mut self, previous: Option<Vec<u8>>) -> Self {
+        self.previous = Some(previous);
+        self
+    }
+
+    pub fn hash(&self, route: &str, client_key: &str) -> String {
+        hash_key(self.secret.as_deref(), route, client_key)
+    }
+}
+
+fn hash_key(secret: Option<&[u8]>, route: &str, client_key: &str) -> String {
+    match secret {
+        Some(secret) => {
+            let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any length");
+            mac.update(route.as_bytes());
+            mac.update(client_key.as_bytes());
+            hex::encode(mac.finalize().into_bytes())
+        }
+        None => sha256::digest(route.to_string() + client_key),
+    }
+}
+
+fn flatten_usage(result: Result<UsageResult, StoreError>) -> Result<(u64, DateTime<Utc>), UsageError> {
+    match result {
+        Ok(Ok(usage)) => Ok(usage),
+        Ok(Err(err)) => Err(UsageError::RateLimited(err)),
+        Err(err) => Err(UsageError::Store(err)),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimit {
+    pub limit: u64, 
+    pub duration: Duration,
+}
+
+impl RateLimit {
+    pub fn new(limit: u64) -> Self {
+        // duration defaults to 1 minute
+        RateLimit { 
+            limit, 
+            duration: Duration::minutes(1),
+        }
+    }
+
+    pub fn per_second(limit: u64) -> Self {
+        RateLimit::new(limit).with_window(Duration::seconds(1))
+    }
+
+    pub fn per_minute(limit: u64) -> Self {
+        RateLimit::new(limit)
+    }
+
+    pub fn per_hour(limit: u64) -> Self {
+        RateLimit::new(limit).with_window(Duration::hours(1))
+    }
+
+    // windows should be positive, parsing rejects any that aren't
+    pub fn with_window(mut self, window: Duration) -> Self {
+        self.duration = window;
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseRateLimitError {
+    // not "<limit>/<window>"
+    Format,
+    Limit,
+    // a window of zero, or a unit other than s, m, h or d
+    Window,
+}
+
+impl fmt::Display for ParseRateLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseRateLimitError::Format => write!(f, "expected <limit>/<window>, e.g. \"100/5m\""),
+            ParseRateLimitError::Limit => write!(f, "the limit should be a non-negative whole number"),
+            ParseRateLimitError::Window => write!(f, "the window should be a positive number of s, m, h or d, e.g. \"30s\" or \"h\""),
+        }
+    }
+}
+
+// "100/5m" is 100 per five minutes, the window's count can be left out ("10/s")
+impl FromStr for RateLimit {
+    type Err = ParseRateLimitError;
+
+    fn from_str(rate: &str) -> Result<Self, Self::Err> {
+        let (limit, window) = rate.split_once('/').ok_or(ParseRateLimitError::Format)?;
+        let limit = limit.trim().parse().map_err(|_| ParseRateLimitError::Limit)?;
+
+        let window = window.trim();
+        let (count, unit) = window.split_at(window.trim_end_matches(char::is_alphabetic).len());
+        let count: i64 = match count {
+            "" => 1,
+            count => count.parse().map_err(|_| ParseRateLimitError::Window)?,
+        };
+        let seconds = match unit {
+            "s" => Some(count),
+            "m" => count.checked_mul(60),
+            "h" => count.checked_mul(60 * 60),
+            "d" => count.checked_mul(24 * 60 * 60),
+            _ => None,
+        };
+        let window = seconds.filter(|seconds| *seconds > 0).and_then(Duration::try_seconds).ok_or(ParseRateLimitError::Window)?;
+        Ok(RateLimit::new(limit).with_window(window))
+    }
+}
+
+impl<'de> Deserialize<'de> for RateLimit {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let rate = String::deserialize(deserializer)?;
+        rate.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+// the same limit for the algorithms, which count in milliseconds
+impl From<&RateLimit> for Limit {
+    fn from(rate_limit: &RateLimit) -> Self {
+        Limit::new(rate_limit.limit, rate_limit.duration.num_milliseconds())
+    }
+}
+
+// quota set aside until it is committed, cancelled or expires
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reservation {
+    pub id: String,
+    pub route: String,
+    pub amount: u64,
+    pub expires_at: DateTime<Utc>,
+    // what's left for other requests once the reservation is set aside
+    pub remaining: u64,
+    pub resets_at: DateTime<Utc>,
+}
+
+impl Reservation {
+    // the route a reservation id was issued for, None if it isn't one of ours
+    pub fn route_of(reservation_id: &str) -> Option<String> {
+        let (route, _) = reservation_id.split_once('.')?;
+        String::from_utf8(URL_SAFE_NO_PAD.decode(route).ok()?).ok()
+    }
+}
+
+#[derive(Debug)]
+pub enum ReservationError {
+    // never issued, already committed or cancelled, or expired
+    NotFound,
+    Store(StoreError),
+}
+
+// levels of the limit hierarchy, narrowest first
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LimitLevel {
+    // the client's limit on one route, the only level unless a hierarchy is configured
+    #[default]
+    Route,
+    // the client across the routes of a group, counted in place of the route's own limit
+    Group,
+    // the client across every route
+    Token,
+    // every client of a tenant
+    Tenant,
+    Global,
+    // the client across every deployment region, see region::RegionConfig::global
+    Region,
+    // the client's failed requests on one route, see RouteConfig::abuse_limit
+    Abuse,
+}
+
+impl LimitLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LimitLevel::Route => "route",
+            LimitLevel::Group => "group",
+            LimitLevel::Token => "token",
+            LimitLevel::Tenant => "tenant",
+            LimitLevel::Global => "global",
+            LimitLevel::Region => "region",
+            LimitLevel::Abuse => "abuse",
+        }
+    }
+}
+
+// one level of the hierarchy a request is counted against
+#[derive(Debug, Clone)]
+pub struct LevelLimit {
+    pub level: LimitLevel,
+    pub key: String,
+    pub client_key: String,
+    pub rate_limit: RateLimit,
+}
+
+impl LevelLimit {
+    // failed requests on `route` are counted apart from the route's own usage
+    pub fn abuse(route: &str, client_key: &str, rate_limit: RateLimit) -> Self {
+        LevelLimit { level: LimitLevel::Abuse, key: abuse_route(route), client_key: client_key.to_string(), rate_limit }
+    }
+
+    // every route of the group is counted under the group's name, see config::GroupConfig
+    pub fn group(group: &str, client_key: &str, rate_limit: RateLimit) -> Self {
+        LevelLimit { level: LimitLevel::Group, key: group_route(group), client_key: client_key.to_string(), rate_limit }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitedError {
+    pub time_when_refreshed: DateTime<Utc>,
+    // the level of the hierarchy that was exhausted
+    pub level: LimitLevel,
+}
+
+impl RateLimitedError {
+    pub fn new(refresh_time: DateTime<Utc>) -> Self {
+        RateLimitedError { time_when_refreshed: refresh_time, level: LimitLevel::Route }
+    }
+
+    pub fn with_level(mut self, level: LimitLevel) -> Self {
+        self.level = level;
+        self
+    }
+}
+
+impl fmt::Display for RateLimitedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} limit reached, it resets at {}", self.level.as_str(), self.time_when_refreshed.to_rfc3339())
+    }
+}
+
+impl std::error::Error for RateLimitedError {}
+
+#[derive(Debug, Clone)]
+pub enum UsageError {
+    RateLimited(RateLimitedError),
+    // the limiter couldn't decide, so the request can't be let through safely
+    Store(StoreError),
+}
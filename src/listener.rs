@@ -0,0 +1,397 @@
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use std::{env, fs, io};
+
+use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tower_service::Service;
+use warp::hyper::server::accept;
+use warp::hyper::service::{make_service_fn, service_fn};
+use warp::hyper::body::Bytes;
+use warp::hyper::header::{HeaderValue, CONNECTION};
+use warp::hyper::{Body, Request, Response, Server, StatusCode, Version};
+
+use crate::config::Config;
+use crate::server;
+
+// the first socket systemd passes, see sd_listen_fds(3)
+const SD_LISTEN_FDS_START: RawFd = 3;
+// set once the socket systemd passed is owned by a listener
+static SYSTEMD_SOCKET_TAKEN: AtomicBool = AtomicBool::new(false);
+// how long to back off when accepting fails, e.g. because we're out of file descriptors
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+// how long open connections get to finish after a shutdown signal, event streams never would on their own
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(20);
+// and how long server::Shutdown gets after that, e.g. to export usage
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ListenConfig {
+    pub address: SocketAddr,
+    // serve on this Unix socket instead of `address`, e.g. behind a local nginx
+    pub unix_socket: Option<String>,
+    // e.g. 0o660 so only the proxy's group can connect, left to the umask otherwise
+    pub unix_socket_mode: Option<u32>,
+}
+
+impl Default for ListenConfig {
+    fn default() -> Self {
+        ListenConfig {
+            address: SocketAddr::from(([127, 0, 0, 1], 8080)),
+            unix_socket: None,
+            unix_socket_mode: None,
+        }
+    }
+}
+
+// connection handling, left at hyper's defaults unless set
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    // serve HTTP/2 with prior knowledge alongside HTTP/1.1, TLS and ALPN are left to whatever is in front
+    pub http2: bool,
+    pub http2_max_concurrent_streams: Option<u32>,
+    // ping HTTP/2 connections this often, and close them if a ping isn't answered within the timeout
+    pub http2_keep_alive_interval_seconds: Option<u64>,
+    pub http2_keep_alive_timeout_seconds: u64,
+    // reuse HTTP/1.1 connections for more than one request
+    pub http1_keep_alive: bool,
+    // close HTTP/1.1 connections that take longer than this to send a request's headers
+    pub http1_header_read_timeout_seconds: Option<u64>,
+    // further connections wait in the listen backlog until one closes
+    pub max_connections: Option<usize>,
+    // close connections sending more requests than this per window, whichever tokens they carry
+    pub max_requests_per_connection: Option<u64>,
+    pub connection_window_seconds: u64,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            http2: true,
+            http2_max_concurrent_streams: None,
+            http2_keep_alive_interval_seconds: None,
+            http2_keep_alive_timeout_seconds: 20,
+            http1_keep_alive: true,
+            http1_header_read_timeout_seconds: None,
+            max_connections: None,
+            max_requests_per_connection: None,
+            connection_window_seconds: 60,
+        }
+    }
+}
+
+type ResponseFuture = Pin<Box<dyn Future<Output = Result<Response<Body>, Infallible>> + Send>>;
+
+enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    fn local_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().ok(),
+            Listener::Unix(_) => None,
+        }
+    }
+
+    async fn accept(&self) -> io::Result<Connection> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, remote_addr) = listener.accept().await?;
+                Ok(Connection { stream: Box::new(stream), remote_addr: Some(remote_addr), closing: Arc::default(), _permit: None })
+            }
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                Ok(Connection { stream: Box::new(stream), remote_addr: None, closing: Arc::default(), _permit: None })
+            }
+        }
+    }
+}
+
+trait Stream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Stream for T {}
+
+// an accepted connection, holding its place under max_connections until it's closed
+struct Connection {
+    stream: Box<dyn Stream>,
+    // None for Unix sockets
+    remote_addr: Option<SocketAddr>,
+    closing: Arc<Closing>,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        if self.closing.flushed.load(Ordering::Relaxed) {
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.get_mut().stream).poll_read(cx, buf)
+    }
+}
+
+// A connection over its request limit reads as closed once the 429 telling it so
+// has been sent, i.e. once its body has been taken and the writes after that flushed.
+#[derive(Default)]
+struct Closing {
+    requested: AtomicBool,
+    flushed: AtomicBool,
+}
+
+// the requests a connection has sent in the current window
+struct ConnectionRequests {
+    window_started: Instant,
+    count: u64,
+}
+
+impl ConnectionRequests {
+    fn new() -> Self {
+        ConnectionRequests { window_started: Instant::now(), count: 0 }
+    }
+
+    // counts a request, returning whether the connection is still within `max`
+    fn count(&mut self, max: u64, window: Duration) -> bool {
+        if self.window_started.elapsed() >= window {
+            *self = ConnectionRequests::new();
+        }
+        self.count = self.count.saturating_add(1);
+        self.count <= max
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[io::IoSlice<'_>]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let connection = self.get_mut();
+        let flushed = Pin::new(&mut connection.stream).poll_flush(cx);
+        if matches!(flushed, Poll::Ready(Ok(()))) && connection.closing.requested.load(Ordering::Relaxed) {
+            connection.closing.flushed.store(true, Ordering::Relaxed);
+            // the pending read is waiting on the socket, poll again so it sees the end
+            cx.waker().wake_by_ref();
+        }
+        flushed
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+// Serves the service's routes until the server fails, or until SIGTERM or ctrl-c
+// once open connections have drained. A socket inherited through
+// systemd socket activation wins over the configured Unix socket, which wins over
+// the TCP address.
+pub async fn serve(config: Arc<Config>) -> io::Result<()> {
+    let (_, server) = bind(config).await?;
+    server.await
+}
+
+// Binds the listener without serving yet, returning its address (None for Unix
+// sockets) and the future that serves it, e.g. for tests binding port 0.
+pub async fn bind(config: Arc<Config>) -> io::Result<(Option<SocketAddr>, impl Future<Output = io::Result<()>>)> {
+    let listener = listen(&config.listen).await?;
+    let local_addr = listener.local_addr();
+    match local_addr {
+        Some(address) => tracing::info!(%address, "listening"),
+        None => tracing::info!("listening on a Unix socket"),
+    }
+
+    let max_connections = config.http.max_connections.map(|max| Arc::new(Semaphore::new(max)));
+    let connections = futures_util::stream::unfold((listener, max_connections), |(listener, max_connections)| async move {
+        // stop accepting while at the limit, the kernel queues new connections meanwhile
+        let permit = match &max_connections {
+            Some(semaphore) => semaphore.clone().acquire_owned().await.ok(),
+            None => None,
+        };
+        loop {
+            match listener.accept().await {
+                Ok(connection) => return Some((Ok::<_, io::Error>(Connection { _permit: permit, ..connection }), (listener, max_connections))),
+                // failing to accept one connection shouldn't take the server down
+                Err(err) => {
+                    tracing::warn!(error = %err, "failed to accept connection");
+                    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                }
+            }
+        }
+    });
+
+    let http = &config.http;
+    let mut builder = Server::builder(accept::from_stream(connections))
+        .http1_keepalive(http.http1_keep_alive)
+        .http1_only(!http.http2)
+        .http2_keep_alive_interval(http.http2_keep_alive_interval_seconds.map(Duration::from_secs))
+        .http2_keep_alive_timeout(Duration::from_secs(http.http2_keep_alive_timeout_seconds));
+    if let Some(max) = http.http2_max_concurrent_streams {
+        builder = builder.http2_max_concurrent_streams(max);
+    }
+    if let Some(seconds) = http.http1_header_read_timeout_seconds {
+        builder = builder.http1_header_read_timeout(Duration::from_secs(seconds));
+    }
+
+    let (routes, shutdown) = server::routes_with_shutdown(config.clone());
+    let service = warp::service(routes);
+    let max_requests = http.max_requests_per_connection;
+    let window = Duration::from_secs(http.connection_window_seconds);
+    let server = builder.serve(make_service_fn(move |connection: &Connection| {
+        // warp only knows the peer's address when it runs the server itself, so it's passed along as an extension
+        let remote_addr = connection.remote_addr;
+        let closing = connection.closing.clone();
+        let requests = Arc::new(Mutex::new(ConnectionRequests::new()));
+        let service = service.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |mut request: Request<Body>| {
+                // counted per connection rather than per token, so rotating credentials over one connection doesn't help
+                let over_limit = max_requests.is_some_and(|max| !requests.lock().unwrap().count(max, window));
+                if over_limit {
+                    tracing::warn!(remote_addr = ?remote_addr, "closing a connection over its request limit");
+                    let response = close_connection(&request, &closing);
+                    return Box::pin(async move { Ok(response) }) as ResponseFuture;
+                }
+                if let Some(remote_addr) = remote_addr {
+                    request.extensions_mut().insert(remote_addr);
+                }
+                Box::pin(service.clone().call(request))
+            }))
+        }
+    }));
+
+    let (signalled, stopping) = tokio::sync::oneshot::channel();
+    let server = server.with_graceful_shutdown(async move {
+        shutdown_signal().await;
+        let _ = signalled.send(());
+    });
+    let served = async move {
+        let drained = async move {
+            match stopping.await {
+                Ok(()) => tokio::time::sleep(DRAIN_TIMEOUT).await,
+                Err(_) => std::future::pending::<()>().await,
+            }
+        };
+        let served = tokio::select! {
+            served = server => served.map_err(io::Error::other),
+            _ = drained => {
+                tracing::warn!("closing connections still open after draining");
+                Ok(())
+            }
+        };
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, shutdown.run()).await.is_err() {
+            tracing::error!("gave up on shutting down cleanly");
+        }
+        served
+    };
+    Ok((local_addr, served))
+}
+
+// ctrl-c, or SIGTERM as sent by systemd and Kubernetes
+async fn shutdown_signal() {
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(err) => {
+                tracing::warn!(error = %err, "can't listen for SIGTERM, only ctrl-c shuts down cleanly");
+                std::future::pending::<()>().await
+            }
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate => {}
+    }
+    tracing::info!("shutting down once open connections have drained");
+}
+
+// A 429 for a request over its connection's limit, closing the connection once
+// it's sent. HTTP/1.1 has a header for that, HTTP/2 connections stop being read.
+fn close_connection(request: &Request<Body>, closing: &Arc<Closing>) -> Response<Body> {
+    let mut response = match request.version() {
+        Version::HTTP_2 => {
+            let closing = closing.clone();
+            Response::new(Body::wrap_stream(futures_util::stream::poll_fn(move |_| {
+                closing.requested.store(true, Ordering::Relaxed);
+                Poll::Ready(None::<Result<Bytes, Infallible>>)
+            })))
+        }
+        _ => {
+            let mut response = Response::new(Body::empty());
+            response.headers_mut().insert(CONNECTION, HeaderValue::from_static("close"));
+            response
+        }
+    };
+    *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+    response
+}
+
+async fn listen(config: &ListenConfig) -> io::Result<Listener> {
+    if let Some(listener) = systemd_listener()? {
+        return Ok(listener);
+    }
+
+    match &config.unix_socket {
+        Some(path) => {
+            // a socket left behind by a previous run would make bind fail, anything else at the path is left alone
+            if fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+                fs::remove_file(path)?;
+            }
+            let listener = UnixListener::bind(path)?;
+            if let Some(mode) = config.unix_socket_mode {
+                fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+            }
+            Ok(Listener::Unix(listener))
+        }
+        None => Ok(Listener::Tcp(TcpListener::bind(config.address).await?)),
+    }
+}
+
+// the listener systemd passed us, if we were socket activated
+fn systemd_listener() -> io::Result<Option<Listener>> {
+    let for_us = env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok()) == Some(std::process::id());
+    let count: usize = env::var("LISTEN_FDS").ok().and_then(|count| count.parse().ok()).unwrap_or(0);
+    // The variables are left set, removing them races with other threads reading the
+    // environment. A child process can't mistake the sockets for its own since
+    // LISTEN_PID names this one, but binding again here mustn't take the descriptor twice
+    if !for_us || count == 0 || SYSTEMD_SOCKET_TAKEN.swap(true, Ordering::SeqCst) {
+        return Ok(None);
+    }
+    if count > 1 {
+        tracing::warn!(count, "systemd passed more than one socket, only the first is used");
+    }
+
+    // SAFETY: systemd hands the process this descriptor and nothing else in it owns it
+    let unix = unsafe { std::os::unix::net::UnixListener::from_raw_fd(SD_LISTEN_FDS_START) };
+    // only a Unix socket has a Unix socket address
+    if unix.local_addr().is_ok() {
+        unix.set_nonblocking(true)?;
+        return Ok(Some(Listener::Unix(UnixListener::from_std(unix)?)));
+    }
+
+    // SAFETY: the descriptor was just released by the UnixListener above
+    let tcp = unsafe { std::net::TcpListener::from_raw_fd(unix.into_raw_fd()) };
+    tcp.set_nonblocking(true)?;
+    Ok(Some(Listener::Tcp(TcpListener::from_std(tcp)?)))
+}
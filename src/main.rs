@@ -1,174 +1,161 @@
-use std::sync::{Arc};
+// serving the routes instantiates their filter chain here too, see lib.rs
+#![recursion_limit = "256"]
 
-use chrono::{DateTime, Duration, Utc};
-use warp::{Filter, hyper::{Response, HeaderMap, StatusCode}};
-use sha256;
-use dashmap::DashMap;
+use std::sync::Arc;
 
-const POST_VAULT_ROUTE: &str = "POST /vault";
-const GET_VAULT_ITEMS_ROUTE: &str = "GET /vault/items";
-const PUT_VAULT_ITEM_ROUTE: &str = "PUT /vault/items/<:id>";
-
-const POST_VAULT_RATE_LIMIT: i32 = 3;
-const GET_VAULT_ITEMS_RATE_LIMIT: i32 = 1200;
-const PUT_VAULT_ITEM_RATE_LIMIT: i32 = 60;
+use rate_limited_service::config::{self, Config};
+use rate_limited_service::simulate::{self, SimulateError};
+use rate_limited_service::{listener, telemetry};
 
 #[tokio::main]
 async fn main() {
-    let rate_limiter = RateLimiter::new();
-    let rate_limiter_filter = warp::any().map(move || rate_limiter.clone());
-
-    let post_vault_route = warp::path("vault")
-        .and(warp::path::end())
-        .and(warp::post())
-        .and(warp::header::headers_cloned())
-        .and(rate_limiter_filter.clone())
-        .map(|headers, rate_limiter| post_vault(rate_limiter, headers));
-    
-    let get_vault_items_route = warp::path!("vault" / "items")
-        .and(warp::path::end())
-        .and(warp::get())
-        .and(warp::header::headers_cloned())
-        .and(rate_limiter_filter.clone())
-        .map(|headers, rate_limiter| get_vault_items(rate_limiter, headers));
-
-    let put_vault_item_route = warp::path!("vault" / "items" / String)
-        .and(warp::path::end())
-        .and(warp::put())
-        .and(warp::header::headers_cloned())
-        .and(rate_limiter_filter.clone())
-        .map(|id, headers, rate_limiter| put_vault_item(rate_limiter, headers, id));
-
-    let routes = post_vault_route
-        .or(get_vault_items_route)
-        .or(put_vault_item_route);
-
-    warp::serve(routes)
-        .run(([127,0,0,1], 8080))
-        .await;
-}
+    // validates the config and exits, e.g. in CI before a deploy
+    if std::env::args().skip(1).any(|arg| arg == "--check-config") {
+        std::process::exit(check_config());
+    }
+    // replays a traffic trace offline and reports what each algorithm would have limited
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("simulate") {
+        std::process::exit(simulate(&args[1..]));
+    }
+    // talks to a running service's admin API, e.g. during an incident
+    if args.first().map(String::as_str) == Some("admin") {
+        std::process::exit(admin(&args[1..]).await);
+    }
 
-// POST "/vault"
-pub fn post_vault(rate_limiter: RateLimiter, headers: HeaderMap) -> Result<warp::reply::Response, warp::http::Error> {
-    let bearer_token = match headers.get("Authorization").map(|token| token.to_str()) {
-        Some(Ok(token)) => token.to_string(),
-        _ => return unauthorized_reply(),
+    let config = match Config::load() {
+        Ok(config) => Arc::new(config),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
     };
 
-    match rate_limiter.log_usage(POST_VAULT_ROUTE, bearer_token, RateLimit::new(POST_VAULT_RATE_LIMIT)) {
-        Ok((requests_remaining, _)) => ok_reply(requests_remaining),
-        Err(err) => rate_limited_reply(err),
-    }
-}
-
-// GET "/vault/items"
-pub fn get_vault_items(rate_limiter: RateLimiter, headers: HeaderMap) -> Result<warp::reply::Response, warp::http::Error> {
-    let bearer_token = match headers.get("Authorization").map(|token| token.to_str()) {
-        Some(Ok(token)) => token.to_string(),
-        _ => return unauthorized_reply(),
+    let telemetry = match telemetry::init(&config.telemetry) {
+        Ok(telemetry) => telemetry,
+        Err(err) => {
+            eprintln!("could not set up trace export: {}", err);
+            std::process::exit(1);
+        }
     };
 
-    match rate_limiter.log_usage(GET_VAULT_ITEMS_ROUTE, bearer_token, RateLimit::new(GET_VAULT_ITEMS_RATE_LIMIT)) {
-        Ok((requests_remaining, _)) => ok_reply(requests_remaining),
-        Err(err) => rate_limited_reply(err),
+    if let Err(err) = listener::serve(config).await {
+        eprintln!("{}", err);
+        drop(telemetry);
+        std::process::exit(1);
     }
 }
 
-// PUT "/vault/items/<:id>
-pub fn put_vault_item(rate_limiter: RateLimiter, headers: HeaderMap, id: String) -> Result<warp::reply::Response, warp::http::Error> {
-    let bearer_token = match headers.get("Authorization").map(|token| token.to_str()) {
-        Some(Ok(token)) => token.to_string(),
-        _ => return unauthorized_reply(),
+// prints what the config changes from the defaults and anything wrong with it, the exit code is 1 if something is
+fn check_config() -> i32 {
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return 1;
+        }
     };
 
-    match rate_limiter.log_usage(&(PUT_VAULT_ITEM_ROUTE.to_owned() + &id), bearer_token, RateLimit::new(PUT_VAULT_ITEM_RATE_LIMIT)) {
-        Ok((requests_remaining, _)) => ok_reply(requests_remaining),
-        Err(err) => rate_limited_reply(err),
+    match config::config_path() {
+        Some(path) => {
+            let changed = std::fs::read_to_string(&path).map_err(config::ConfigError::Read).and_then(|contents| config::changed_settings(&contents));
+            match changed {
+                Ok(changed) if changed.is_empty() => println!("{} leaves every setting at its default", path),
+                Ok(changed) => {
+                    println!("{} changes these settings from their defaults:", path);
+                    for (setting, value) in changed {
+                        println!("  {} = {}", setting, value);
+                    }
+                }
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    return 1;
+                }
+            }
+        }
+        None => println!("CONFIG_PATH isn't set, every setting is at its default"),
     }
-}
-
-fn unauthorized_reply() -> Result<warp::reply::Response, http::Error> {
-    Response::builder()
-        .status(StatusCode::UNAUTHORIZED)
-        .body("".into())
-}
-
-fn ok_reply(requests_remaining: i32) -> Result<warp::reply::Response, http::Error> {
-    Response::builder()
-        .status(StatusCode::OK)
-        .header("X-Ratelimit-Remaining", requests_remaining)
-        .body("".into())
-}
 
-fn rate_limited_reply(err: RateLimitedError) -> Result<warp::reply::Response, http::Error> {
-    Response::builder()
-        .status(StatusCode::TOO_MANY_REQUESTS)
-        .header("X-Ratelimit-Retry-After", (err.time_when_refreshed - Utc::now()).num_seconds())
-        .body("".into())
-}
-
-#[derive(Debug, Clone)]
-pub struct RateLimiter {
-    usage_counter: Arc<DashMap<String, (i32, DateTime<Utc>)>>
-}
-
-impl RateLimiter {
-    pub fn new() -> Self {
-        RateLimiter { usage_counter: Arc::new(DashMap::new()) }
+    let problems = config.problems();
+    for problem in &problems {
+        eprintln!("error: {}", problem);
     }
+    match problems.len() {
+        0 => {
+            println!("config OK");
+            0
+        }
+        count => {
+            eprintln!("{} problem(s) found", count);
+            1
+        }
+    }
+}
 
-    pub fn log_usage(self, route: &str, bearer_token: String, rate_limit: RateLimit) -> Result<(i32, DateTime<Utc>), RateLimitedError> {
-        // bearer token cannot be stored on it's own as it is a security issue
-        let hashed_key = sha256::digest(route.to_string() + &bearer_token);
-        let now = Utc::now();
-        
-        if let Some(mut pair) = self.usage_counter.get_mut(&hashed_key) {
-            let count = pair.0;
-            let refresh_time = pair.1;
+// `simulate <trace.csv> [config.toml]`, the config defaults to CONFIG_PATH as when serving
+fn simulate(args: &[String]) -> i32 {
+    let Some(trace_path) = args.first() else {
+        eprintln!("usage: rate_limited_service simulate <trace.csv> [config.toml]");
+        return 2;
+    };
+    let config = match args.get(1) {
+        Some(path) => std::fs::read_to_string(path).map_err(config::ConfigError::Read).and_then(|contents| Config::parse(&contents)),
+        None => Config::load(),
+    };
+    let config = match config {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return 1;
+        }
+    };
 
-            if refresh_time < now { 
-                // rate limiting interval has passed and needs to be refreshed
-                *pair = (rate_limit.limit - 1, now + rate_limit.duration);
-                return Ok((rate_limit.limit - 1, now + rate_limit.duration)) 
-            } else if count > 0 { 
-                // rate limiting interval does not need to be refreshed, but this request should count against the allowable requests
-                *pair = (count - 1, refresh_time);
-                return Ok((count - 1, refresh_time)) 
-            } else { 
-                // rate limit has been reached
-                return Err(RateLimitedError::new(refresh_time))
-            }
-        } else { 
-            // token / endpoint is being used for the first time, so we should add it to the usage counter
-            self.usage_counter.insert(hashed_key, (rate_limit.limit - 1, now + rate_limit.duration));
-            Ok((rate_limit.limit - 1, now + rate_limit.duration))
+    let trace = std::fs::read_to_string(trace_path).map_err(SimulateError::Read).and_then(|contents| simulate::parse_trace(&contents));
+    match trace {
+        Ok(trace) => {
+            print!("{}", simulate::run(&config, &trace));
+            0
+        }
+        Err(err) => {
+            eprintln!("error: {}", err);
+            1
         }
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct RateLimit {
-    pub limit: i32, 
-    pub duration: Duration,
-}
+// `admin <command>`, see admin::USAGE
+#[cfg(feature = "client")]
+async fn admin(args: &[String]) -> i32 {
+    use rate_limited_service::admin;
+    use rate_limited_service::client::AdminClient;
+
+    let command = match admin::parse_args(args) {
+        Ok(command) => command,
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, admin::USAGE);
+            return 2;
+        }
+    };
+    let Ok(admin_token) = std::env::var("ADMIN_TOKEN") else {
+        eprintln!("error: ADMIN_TOKEN isn't set");
+        return 2;
+    };
+    let base_url = std::env::var("ADMIN_URL").unwrap_or_else(|_| "http://localhost:8080".to_string());
 
-impl RateLimit {
-    pub fn new(limit: i32) -> Self {
-        // duration defaults to 1 minute
-        RateLimit { 
-            limit, 
-            duration: Duration::minutes(1),
+    match admin::run(&AdminClient::new(base_url, admin_token), &command).await {
+        Ok(output) => {
+            print!("{}", output);
+            0
+        }
+        Err(err) => {
+            eprintln!("error: {}", err);
+            1
         }
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct RateLimitedError {
-    pub time_when_refreshed: DateTime<Utc>,
+#[cfg(not(feature = "client"))]
+async fn admin(_args: &[String]) -> i32 {
+    eprintln!("error: the admin subcommand needs the client feature, build with --features client");
+    2
 }
-
-impl RateLimitedError {
-    pub fn new(refresh_time: DateTime<Utc>) -> Self {
-        RateLimitedError { time_when_refreshed: refresh_time }
-    }
-}
\ No newline at end of file
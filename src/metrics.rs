@@ -0,0 +1,58 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Process-wide counters and gauges, rendered in the Prometheus text format by GET /metrics.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    // 0 = closed, 1 = open, 2 = half open
+    pub store_circuit_breaker_state: AtomicU64,
+    pub store_circuit_breaker_trips: AtomicU64,
+    pub store_errors: AtomicU64,
+    pub store_fallbacks: AtomicU64,
+    // only kept up to date while the in-memory store has max_keys set
+    pub store_keys: AtomicU64,
+    pub store_memory_bytes: AtomicU64,
+    pub store_evictions: AtomicU64,
+    pub proxy_upstream_errors: AtomicU64,
+    pub proxy_upstream_timeouts: AtomicU64,
+    pub proxy_retries: AtomicU64,
+    pub soft_limit_warnings: AtomicU64,
+    pub decision_events_dropped: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        gauge(&mut out, "rate_limiter_store_circuit_breaker_state", "Store circuit breaker state (0 closed, 1 open, 2 half open)", &self.store_circuit_breaker_state);
+        counter(&mut out, "rate_limiter_store_circuit_breaker_trips_total", "Times the store circuit breaker has opened", &self.store_circuit_breaker_trips);
+        counter(&mut out, "rate_limiter_store_errors_total", "Failed or slow calls to the usage store", &self.store_errors);
+        counter(&mut out, "rate_limiter_store_fallbacks_total", "Requests decided by the failure policy because the store could not be used", &self.store_fallbacks);
+        gauge(&mut out, "rate_limiter_store_keys", "Counters held by the in-memory usage store", &self.store_keys);
+        gauge(&mut out, "rate_limiter_store_memory_bytes", "Estimated memory used by the in-memory usage store's counters", &self.store_memory_bytes);
+        counter(&mut out, "rate_limiter_store_evictions_total", "Counters evicted part way through their window because the in-memory store was full", &self.store_evictions);
+        counter(&mut out, "rate_limiter_proxy_upstream_errors_total", "Proxied requests that failed with a 502 after any retries", &self.proxy_upstream_errors);
+        counter(&mut out, "rate_limiter_proxy_upstream_timeouts_total", "Proxied requests that timed out with a 504 after any retries", &self.proxy_upstream_timeouts);
+        counter(&mut out, "rate_limiter_proxy_retries_total", "Retries of idempotent proxied requests", &self.proxy_retries);
+        counter(&mut out, "rate_limiter_soft_limit_warnings_total", "Allowed requests past their route's soft limit", &self.soft_limit_warnings);
+        counter(&mut out, "rate_limiter_decision_events_dropped_total", "Decision events dropped because the publish buffer was full or the sink failed", &self.decision_events_dropped);
+        out
+    }
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: &AtomicU64) {
+    metric(out, "gauge", name, help, value)
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: &AtomicU64) {
+    metric(out, "counter", name, help, value)
+}
+
+fn metric(out: &mut String, kind: &str, name: &str, help: &str, value: &AtomicU64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "{} {}", name, value.load(Ordering::Relaxed));
+}
@@ -0,0 +1,106 @@
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use tower_layer::Layer;
+use tower_service::Service;
+use warp::http::{Request, Response};
+use warp::hyper::{Body, StatusCode};
+
+use crate::config::Config;
+use crate::key_extractor::RequestInfo;
+use crate::replies;
+use crate::server::{self, LimitedRoute, RateLimitDecision, PROXY_ROUTE};
+use crate::RateLimiter;
+
+const DEFAULT_LIMIT: u64 = 600;
+
+// Applies the service's rate limiting to any tower service answering with hyper
+// bodies, e.g. a warp app via `warp::service(routes)`, so it can be embedded as
+// middleware instead of running this binary in front. Requests are counted
+// against the most specific route template in config, or the layer's own route.
+//
+// The client ip key extractor needs the peer's SocketAddr in the request's extensions.
+#[derive(Debug, Clone)]
+pub struct RateLimitLayer {
+    rate_limiter: RateLimiter,
+    config: Arc<Config>,
+    route: String,
+    default_limit: u64,
+}
+
+impl RateLimitLayer {
+    pub fn new(rate_limiter: RateLimiter, config: Arc<Config>) -> Self {
+        RateLimitLayer { rate_limiter, config, route: PROXY_ROUTE.to_string(), default_limit: DEFAULT_LIMIT }
+    }
+
+    // the route requests no configured template matches are counted against, and its limit unless config sets one
+    pub fn with_route(mut self, route: &str, default_limit: u64) -> Self {
+        self.route = route.to_string();
+        self.default_limit = default_limit;
+        self
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService { inner, layer: self.clone() }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    layer: RateLimitLayer,
+}
+
+impl<S, B> Service<Request<B>> for RateLimitService<S>
+where
+    S: Service<Request<B>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Future: Send,
+    B: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let request_info = RequestInfo {
+            method: request.method().clone(),
+            headers: request.headers().clone(),
+            remote_addr: request.extensions().get::<SocketAddr>().copied(),
+            path: request.uri().path().to_string(),
+        };
+        let RateLimitLayer { rate_limiter, config, route, default_limit } = self.layer.clone();
+        let limited_route = LimitedRoute::new(&route, config.rate_limit(&route, default_limit));
+
+        let (reply, charge) = match server::check_rate_limit(rate_limiter.clone(), &config, &request_info, limited_route) {
+            RateLimitDecision::Allowed(reply, charge) => (reply, charge),
+            RateLimitDecision::Rejected(reply) => return Box::pin(async move { Ok(or_internal_server_error(reply)) }),
+        };
+
+        // the clone might not be ready, so call the instance poll_ready was called on and keep the clone
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            let response = replies::with_builder_headers(reply, inner.call(request).await?);
+            Ok(or_internal_server_error(server::settle_charge(&rate_limiter, &config, charge, response)))
+        })
+    }
+}
+
+fn or_internal_server_error(response: Result<Response<Body>, warp::http::Error>) -> Response<Body> {
+    response.unwrap_or_else(|_| {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+        response
+    })
+}
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::{broadcast, mpsc};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{UsageEvent, UsageObserver};
+
+const EVENT_CAPACITY: usize = 1024;
+// used when a subscriber doesn't pick a threshold, as a fraction of the limit (but always at least 1)
+const DEFAULT_THRESHOLD_PERCENT: u64 = 10;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum QuotaNotification {
+    // remaining quota for `key` dropped below the subscriber's threshold
+    QuotaLow { key: String, limit: u64, remaining: u64, resets_at: String },
+    // the window for `key` has passed, so its full limit is available again
+    QuotaReset { key: String, limit: u64 },
+}
+
+impl QuotaNotification {
+    pub fn name(&self) -> &'static str {
+        match self {
+            QuotaNotification::QuotaLow { .. } => "quota_low",
+            QuotaNotification::QuotaReset { .. } => "quota_reset",
+        }
+    }
+}
+
+// Fans usage events out to quota subscribers, see `subscribe`
+#[derive(Debug, Clone)]
+pub struct QuotaNotifier {
+    events: broadcast::Sender<UsageEvent>,
+}
+
+impl Default for QuotaNotifier {
+    fn default() -> Self {
+        QuotaNotifier { events: broadcast::channel(EVENT_CAPACITY).0 }
+    }
+}
+
+impl UsageObserver for QuotaNotifier {
+    fn on_usage(&self, event: &UsageEvent) {
+        // sending only fails when nobody is subscribed
+        let _ = self.events.send(event.clone());
+    }
+}
+
+impl QuotaNotifier {
+    pub fn new() -> Self {
+        QuotaNotifier::default()
+    }
+
+    // Notifications for one subject (the sha256 of a bearer token): a QuotaLow the
+    // first time a window's remaining quota drops below `threshold` (10% of the
+    // limit if not given), then a QuotaReset once that window is over.
+    // Dropping the returned receiver ends the subscription.
+    pub fn subscribe(&self, subject: String, threshold: Option<u64>) -> mpsc::Receiver<QuotaNotification> {
+        let (sender, receiver) = mpsc::channel(EVENT_CAPACITY);
+        let mut events = self.events.subscribe();
+
+        tokio::spawn(async move {
+            // windows already reported as low, by key, so each is reported once
+            let mut low_windows: HashMap<String, (u64, DateTime<Utc>)> = HashMap::new();
+
+            loop {
+                let next_reset = low_windows.values().map(|(_, resets_at)| *resets_at).min();
+                let until_next_reset = next_reset
+                    .map(|resets_at| (resets_at - Utc::now()).to_std().unwrap_or_default())
+                    .unwrap_or(std::time::Duration::from_secs(3600));
+
+                let event = tokio::select! {
+                    _ = sender.closed() => break,
+                    _ = tokio::time::sleep(until_next_reset), if next_reset.is_some() => {
+                        let now = Utc::now();
+                        let reset: Vec<String> = low_windows.iter().filter(|(_, (_, resets_at))| *resets_at <= now).map(|(key, _)| key.clone()).collect();
+                        for key in reset {
+                            if let Some((limit, _)) = low_windows.remove(&key) {
+                                if sender.send(QuotaNotification::QuotaReset { key, limit }).await.is_err() {
+                                    return;
+                                }
+                            }
+                        }
+                        continue;
+                    }
+                    event = events.recv() => match event {
+                        Ok(event) if event.subject == subject => event,
+                        Ok(_) => continue,
+                        Err(RecvError::Lagged(missed)) => {
+                            tracing::warn!(missed, "quota subscriber fell behind, usage events were dropped");
+                            continue;
+                        }
+                        Err(RecvError::Closed) => break,
+                    },
+                };
+
+                let threshold = threshold.unwrap_or(((u128::from(event.limit) * u128::from(DEFAULT_THRESHOLD_PERCENT) / 100) as u64).max(1));
+                let already_reported = low_windows.get(&event.key).is_some_and(|(_, resets_at)| *resets_at == event.resets_at);
+                if event.remaining >= threshold || already_reported {
+                    continue;
+                }
+
+                low_windows.insert(event.key.clone(), (event.limit, event.resets_at));
+                let low = QuotaNotification::QuotaLow { key: event.key, limit: event.limit, remaining: event.remaining, resets_at: event.resets_at.to_rfc3339() };
+                if sender.send(low).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        receiver
+    }
+}
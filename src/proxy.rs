@@ -0,0 +1,232 @@
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use futures_util::{stream, Stream, StreamExt, TryStreamExt};
+use rand::Rng;
+use serde::Deserialize;
+use warp::http::{HeaderValue, Method, Request, Uri, header};
+use warp::hyper::body::{Buf, Bytes};
+use warp::hyper::client::HttpConnector;
+use warp::hyper::{Body, Client, HeaderMap};
+
+use crate::key_extractor::RequestInfo;
+use crate::metrics::Metrics;
+
+// backoff before a retry never grows past this, however many retries are configured
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(5);
+
+// headers that only describe the connection they arrived on, so are never forwarded (RFC 9110 7.6.1)
+const HOP_BY_HOP_HEADERS: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ProxyConfig {
+    // e.g. "http://127.0.0.1:9000", requests are forwarded here with their path and query intact
+    pub upstream: String,
+    pub connect_timeout_ms: u64,
+    // how long to wait for the upstream's response headers, the body is streamed without a deadline
+    pub read_timeout_ms: u64,
+    // extra attempts for idempotent requests that failed to connect, timed out or got a 502/503/504
+    pub retries: u32,
+    // base for the jittered exponential backoff between attempts
+    pub retry_backoff_ms: u64,
+    // idempotent requests are only retried if their body fits in this, larger ones are streamed through once
+    pub max_retry_body_bytes: usize,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        ProxyConfig {
+            upstream: String::new(),
+            connect_timeout_ms: 1000,
+            read_timeout_ms: 30_000,
+            retries: 2,
+            retry_backoff_ms: 100,
+            max_retry_body_bytes: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ProxyError {
+    InvalidUri(String),
+    // the request body couldn't be read from the client
+    Body(warp::Error),
+    Upstream(warp::hyper::Error),
+    Timeout,
+}
+
+impl ProxyError {
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, ProxyError::Timeout)
+    }
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProxyError::InvalidUri(uri) => write!(f, "invalid upstream uri: {}", uri),
+            ProxyError::Body(err) => write!(f, "could not read request body: {}", err),
+            ProxyError::Upstream(err) => write!(f, "upstream request failed: {}", err),
+            ProxyError::Timeout => write!(f, "upstream timed out"),
+        }
+    }
+}
+
+// Forwards requests to the configured upstream and streams its responses back
+#[derive(Debug, Clone)]
+pub struct Proxy {
+    client: Client<HttpConnector>,
+    config: ProxyConfig,
+    upstream: String,
+    metrics: Arc<Metrics>,
+}
+
+impl Proxy {
+    pub fn new(config: &ProxyConfig, metrics: Arc<Metrics>) -> Self {
+        let mut connector = HttpConnector::new();
+        connector.set_connect_timeout(Some(Duration::from_millis(config.connect_timeout_ms)));
+
+        Proxy {
+            client: Client::builder().build(connector),
+            config: config.clone(),
+            upstream: config.upstream.trim_end_matches('/').to_string(),
+            metrics,
+        }
+    }
+
+    pub async fn forward<S, B>(&self, request: &RequestInfo, query: &str, body: S) -> Result<warp::reply::Response, ProxyError>
+    where
+        S: Stream<Item = Result<B, warp::Error>> + Send + 'static,
+        B: Buf + Send + 'static,
+    {
+        let uri = match query {
+            "" => format!("{}{}", self.upstream, request.path),
+            query => format!("{}{}?{}", self.upstream, request.path, query),
+        };
+        let uri: Uri = uri.parse().map_err(|_| ProxyError::InvalidUri(uri))?;
+        let body = body.map_ok(|mut chunk| -> Bytes { chunk.copy_to_bytes(chunk.remaining()) });
+
+        // only idempotent requests are retried, and only they need their body buffered so it can be sent again
+        if self.config.retries == 0 || !is_idempotent(&request.method) {
+            let response = self.send(request, &uri, Body::wrap_stream(body)).await;
+            return self.record(response);
+        }
+
+        // a body too large to hold on to is streamed through without retries, so no client can make the proxy buffer it all
+        let declared_length = request.header("Content-Length").and_then(|length| length.parse::<usize>().ok());
+        if declared_length.is_some_and(|length| length > self.config.max_retry_body_bytes) {
+            let response = self.send(request, &uri, Body::wrap_stream(body)).await;
+            return self.record(response);
+        }
+        let mut body = Box::pin(body);
+        let (mut buffered, mut buffered_bytes) = (Vec::new(), 0);
+        while let Some(chunk) = body.try_next().await.map_err(ProxyError::Body)? {
+            buffered_bytes += chunk.len();
+            buffered.push(chunk);
+            if buffered_bytes > self.config.max_retry_body_bytes {
+                // what's been read so far goes first
+                let body = stream::iter(buffered.into_iter().map(Ok)).chain(body);
+                let response = self.send(request, &uri, Body::wrap_stream(body)).await;
+                return self.record(response);
+            }
+        }
+        let body = Bytes::from(buffered.concat());
+        let mut attempt = 0;
+        loop {
+            let response = self.send(request, &uri, Body::from(body.clone())).await;
+            let retryable = match &response {
+                Ok(response) => matches!(response.status().as_u16(), 502..=504),
+                Err(ProxyError::Upstream(_)) | Err(ProxyError::Timeout) => true,
+                Err(_) => false,
+            };
+            if !retryable || attempt >= self.config.retries {
+                return self.record(response);
+            }
+
+            attempt += 1;
+            self.metrics.proxy_retries.fetch_add(1, Ordering::Relaxed);
+            tokio::time::sleep(self.backoff(attempt)).await;
+        }
+    }
+
+    async fn send(&self, request: &RequestInfo, uri: &Uri, body: Body) -> Result<warp::reply::Response, ProxyError> {
+        let mut upstream_request = Request::builder().method(request.method.clone()).uri(uri.clone());
+        if let Some(headers) = upstream_request.headers_mut() {
+            *headers = forwarded_headers(request);
+        }
+        let upstream_request = upstream_request.body(body).map_err(|_| ProxyError::InvalidUri(uri.to_string()))?;
+
+        let read_timeout = Duration::from_millis(self.config.read_timeout_ms);
+        let mut response = match tokio::time::timeout(read_timeout, self.client.request(upstream_request)).await {
+            Ok(response) => response.map_err(|err| if err.is_timeout() { ProxyError::Timeout } else { ProxyError::Upstream(err) })?,
+            Err(_) => return Err(ProxyError::Timeout),
+        };
+        strip_hop_by_hop(response.headers_mut());
+        Ok(response)
+    }
+
+    fn record(&self, response: Result<warp::reply::Response, ProxyError>) -> Result<warp::reply::Response, ProxyError> {
+        if let Err(err) = &response {
+            let counter = if err.is_timeout() { &self.metrics.proxy_upstream_timeouts } else { &self.metrics.proxy_upstream_errors };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+        response
+    }
+
+    // "full jitter": a random wait up to an exponentially growing cap, so retrying clients spread out
+    fn backoff(&self, attempt: u32) -> Duration {
+        let cap = Duration::from_millis(self.config.retry_backoff_ms)
+            .saturating_mul(2u32.saturating_pow(attempt - 1))
+            .min(MAX_RETRY_BACKOFF);
+        Duration::from_millis(rand::thread_rng().gen_range(0..=cap.as_millis() as u64))
+    }
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS | Method::PUT | Method::DELETE | Method::TRACE)
+}
+
+fn forwarded_headers(request: &RequestInfo) -> HeaderMap {
+    let mut headers = request.headers.clone();
+    strip_hop_by_hop(&mut headers);
+    // the client picks the upstream's Host from the uri
+    headers.remove(header::HOST);
+
+    if let Some(remote_addr) = request.remote_addr {
+        let forwarded_for = match headers.get("X-Forwarded-For").and_then(|value| value.to_str().ok()) {
+            Some(existing) => format!("{}, {}", existing, remote_addr.ip()),
+            None => remote_addr.ip().to_string(),
+        };
+        if let Ok(value) = HeaderValue::from_str(&forwarded_for) {
+            headers.insert("X-Forwarded-For", value);
+        }
+    }
+    headers
+}
+
+fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    // headers named in Connection are hop-by-hop too
+    let named: Vec<String> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .collect();
+
+    for name in HOP_BY_HOP_HEADERS.iter().copied().chain(named.iter().map(String::as_str)) {
+        headers.remove(name);
+    }
+}
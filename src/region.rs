@@ -0,0 +1,107 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::config::LevelConfig;
+use crate::store::UsageStore;
+use crate::write_behind::{WriteBehindConfig, WriteBehindStore};
+use crate::{RateLimit, RateLimitedError};
+
+// a client's usage across regions is counted under this, never under a region's name
+pub const CEILING_ROUTE: &str = "region-ceiling";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RegionConfig {
+    // the region this instance is deployed in, e.g. "eu-west-1", also read from REGION.
+    // Every limit is counted per region, so each region has the whole quota to itself
+    pub name: String,
+    // a client's ceiling across every region sharing the store, on top of each region's own limits
+    pub global: Option<LevelConfig>,
+    // how often usage counted against the ceiling here is written to the shared store, and the other regions' read back
+    pub sync_interval_ms: u64,
+    // the most a client's ceiling usage here can get ahead of the shared store, 0 writes every request through
+    pub max_unsynced_cost: u64,
+}
+
+impl Default for RegionConfig {
+    fn default() -> Self {
+        RegionConfig { name: String::new(), global: None, sync_interval_ms: 1000, max_unsynced_cost: 100 }
+    }
+}
+
+// The deployment region an instance counts usage in. Counters are kept apart
+// per region, and the optional ceiling is decided against a local copy of each
+// client's cross-region usage that is reconciled with the shared store in the
+// background (see WriteBehindStore), so checking it doesn't cost a round trip
+// to another region. Between syncs each region can overspend the ceiling by up
+// to max_unsynced_cost per client.
+#[derive(Debug)]
+pub struct Region {
+    name: String,
+    ceiling: Option<Ceiling>,
+}
+
+#[derive(Debug)]
+struct Ceiling {
+    rate_limit: RateLimit,
+    store: WriteBehindStore<Arc<dyn UsageStore>>,
+}
+
+impl Region {
+    // `store` is the one shared by every region, the ceiling is reconciled through it
+    pub fn new(config: &RegionConfig, store: Arc<dyn UsageStore>) -> Self {
+        let ceiling = config.global.as_ref().map(|global| Ceiling {
+            rate_limit: global.rate_limit(),
+            store: WriteBehindStore::new(store, WriteBehindConfig { flush_interval_ms: config.sync_interval_ms, max_pending_cost: config.max_unsynced_cost }),
+        });
+        Region { name: config.name.clone(), ceiling }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // the route a counter is kept under in this region
+    pub fn scoped(&self, route: &str) -> String {
+        format!("{} @{}", route, self.name)
+    }
+
+    pub fn has_ceiling(&self) -> bool {
+        self.ceiling.is_some()
+    }
+
+    // Whether `cost` more fits under the ceiling stored under `key`, as far as this
+    // region knows. The shared store failing only loses the ceiling, each region's
+    // own limits still apply.
+    pub fn check_ceiling(&self, key: &str, cost: u64) -> Result<(), RateLimitedError> {
+        let Some(ceiling) = &self.ceiling else {
+            return Ok(());
+        };
+        match ceiling.store.check_usage(key, &ceiling.rate_limit, cost, Utc::now()) {
+            Ok(usage) => usage.map(|_| ()),
+            Err(err) => {
+                tracing::warn!(error = %err, region = self.name, "could not check the cross-region ceiling");
+                Ok(())
+            }
+        }
+    }
+
+    // counts `cost` against the ceiling once a request is allowed here
+    pub fn charge_ceiling(&self, key: &str, cost: u64) {
+        if let Some(ceiling) = &self.ceiling {
+            if let Err(err) = ceiling.store.log_usage(key, &ceiling.rate_limit, cost, Utc::now()) {
+                tracing::warn!(error = %err, region = self.name, "could not count usage against the cross-region ceiling");
+            }
+        }
+    }
+
+    pub fn refund_ceiling(&self, key: &str, cost: u64, charged_at: DateTime<Utc>) {
+        if let Some(ceiling) = &self.ceiling {
+            if let Err(err) = ceiling.store.refund(key, &ceiling.rate_limit, cost, charged_at) {
+                tracing::warn!(error = %err, region = self.name, "could not refund usage of the cross-region ceiling");
+            }
+        }
+    }
+}
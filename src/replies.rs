@@ -0,0 +1,194 @@
+use chrono::Utc;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use warp::http::header::HeaderName;
+use warp::http::{self, response::Builder};
+use warp::hyper::{Response, StatusCode};
+
+use crate::bypass::BYPASS_TOKEN_HEADER;
+use crate::config::{DeprecationConfig, RouteConfig};
+use crate::scopes::AuthError;
+use crate::{LimitLevel, RateLimit, RateLimitedError};
+
+// the names of the rate limiting headers, for gateways that expect different ones
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HeaderNames {
+    pub remaining: String,
+    // the units an allowed request was charged
+    pub cost: String,
+    pub retry_after: String,
+    pub level: String,
+    // names the group whose pool ran out, on 429s from a limit group
+    pub group: String,
+    // on allowed responses past the route's soft limit
+    pub warning: String,
+    // on allowed responses whose count started over because the store evicted the client's counter
+    pub evicted: String,
+    // read from requests as well as echoed on bypassed responses
+    pub bypass: String,
+}
+
+impl Default for HeaderNames {
+    fn default() -> Self {
+        HeaderNames {
+            remaining: "X-Ratelimit-Remaining".to_string(),
+            cost: "X-Ratelimit-Cost".to_string(),
+            retry_after: "X-Ratelimit-Retry-After".to_string(),
+            level: "X-Ratelimit-Level".to_string(),
+            group: "X-Ratelimit-Group".to_string(),
+            warning: "X-Ratelimit-Warning".to_string(),
+            evicted: "X-Ratelimit-Evicted".to_string(),
+            bypass: BYPASS_TOKEN_HEADER.to_string(),
+        }
+    }
+}
+
+impl HeaderNames {
+    // the settings whose names aren't valid header names, which every reply carrying them would fail on
+    pub fn invalid(&self) -> Vec<(&'static str, &str)> {
+        let names = [
+            ("remaining", &self.remaining),
+            ("cost", &self.cost),
+            ("retry_after", &self.retry_after),
+            ("level", &self.level),
+            ("group", &self.group),
+            ("warning", &self.warning),
+            ("evicted", &self.evicted),
+            ("bypass", &self.bypass),
+        ];
+        names
+            .into_iter()
+            .filter(|(_, name)| HeaderName::from_bytes(name.as_bytes()).is_err())
+            .map(|(setting, name)| (setting, name.as_str()))
+            .collect()
+    }
+}
+
+// how requests are answered when their credentials are missing, invalid or lack a scope
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AuthPolicy {
+    // named in the WWW-Authenticate challenge sent when credentials are missing
+    pub realm: String,
+    // answer every failure with a bare 401 as earlier releases did, so clients can't tell a bad token from a missing scope
+    pub uniform: bool,
+}
+
+impl Default for AuthPolicy {
+    fn default() -> Self {
+        AuthPolicy { realm: "rate_limited_service".to_string(), uniform: false }
+    }
+}
+
+pub fn status(status: StatusCode) -> Builder {
+    Response::builder().status(status)
+}
+
+// the builder an allowed request's reply starts from
+pub fn allowed(names: &HeaderNames, requests_remaining: u64) -> Builder {
+    Response::builder().header(names.remaining.as_str(), requests_remaining)
+}
+
+pub fn bypassed(names: &HeaderNames) -> Builder {
+    Response::builder().header(names.bypass.as_str(), "accepted").header(names.cost.as_str(), 0)
+}
+
+// for replies built by warp (websockets, event streams) that still need the rate limiting headers
+pub fn with_builder_headers(reply: Builder, mut response: warp::reply::Response) -> Result<warp::reply::Response, http::Error> {
+    if let Some(headers) = reply.headers_ref() {
+        response.headers_mut().extend(headers.clone());
+    }
+    Ok(response)
+}
+
+pub fn json<T: Serialize>(reply: Builder, value: &T) -> Result<warp::reply::Response, http::Error> {
+    match serde_json::to_vec(value) {
+        Ok(json) => reply.header("Content-Type", "application/json").body(json.into()),
+        Err(_) => internal_server_error(),
+    }
+}
+
+pub fn empty(status: StatusCode) -> Result<warp::reply::Response, http::Error> {
+    Response::builder()
+        .status(status)
+        .body("".into())
+}
+
+pub fn unauthorized() -> Result<warp::reply::Response, http::Error> {
+    empty(StatusCode::UNAUTHORIZED)
+}
+
+pub fn forbidden() -> Result<warp::reply::Response, http::Error> {
+    empty(StatusCode::FORBIDDEN)
+}
+
+// missing credentials get a challenge saying how to authenticate, invalid ones a plain 401 and valid ones without the scope a 403
+pub fn auth_failure(policy: &AuthPolicy, err: AuthError) -> Result<warp::reply::Response, http::Error> {
+    match (err, policy.uniform) {
+        (_, true) => unauthorized(),
+        (AuthError::Missing, false) => status(StatusCode::UNAUTHORIZED)
+            .header("WWW-Authenticate", format!("Bearer realm=\"{}\"", policy.realm))
+            .body("".into()),
+        (AuthError::Invalid, false) => unauthorized(),
+        (AuthError::Forbidden, false) => forbidden(),
+    }
+}
+
+pub fn internal_server_error() -> Result<warp::reply::Response, http::Error> {
+    empty(StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+// `group` is the route's limit group, if the request was counted under one
+pub fn rate_limited(names: &HeaderNames, err: RateLimitedError, rate_limit: &RateLimit, route_config: &RouteConfig, group: Option<&str>, jitter_seconds: u64) -> Result<warp::reply::Response, http::Error> {
+    let retry_after = retry_after(&err, jitter_seconds);
+    let mut reply = Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header(names.retry_after.as_str(), retry_after)
+        .header(names.level.as_str(), err.level.as_str());
+    let group = group.filter(|_| err.level == LimitLevel::Group);
+    if let Some(group) = group {
+        reply = reply.header(names.group.as_str(), group);
+    }
+    if let Some(deprecation) = &route_config.deprecation {
+        reply = deprecated(reply, deprecation);
+    }
+
+    match &route_config.rate_limited_body {
+        Some(template) => reply
+            .header("Content-Type", route_config.rate_limited_content_type.as_deref().unwrap_or("text/plain; charset=utf-8"))
+            .body(render_rate_limited_body(template, retry_after, rate_limit, err.level, group.unwrap_or_default()).into()),
+        None => reply.body("".into()),
+    }
+}
+
+// the Deprecation, Sunset and Link headers of a route being retired
+pub fn deprecated(mut reply: Builder, deprecation: &DeprecationConfig) -> Builder {
+    reply = reply.header("Deprecation", format!("@{}", deprecation.since.timestamp()));
+    if let Some(sunset) = deprecation.sunset {
+        reply = reply.header("Sunset", sunset.format("%a, %d %b %Y %H:%M:%S GMT").to_string());
+    }
+    if let Some(link) = &deprecation.link {
+        reply = reply.header("Link", format!("<{}>; rel=\"deprecation\"; type=\"text/html\"", link));
+    }
+    reply
+}
+
+// Seconds until `err`'s window resets, plus up to `jitter_seconds` at random. Only ever
+// later than the reset, since a client coming back early would just be limited again.
+pub fn retry_after(err: &RateLimitedError, jitter_seconds: u64) -> i64 {
+    let jitter = match jitter_seconds {
+        0 => 0,
+        max => rand::thread_rng().gen_range(0..=max),
+    };
+    (err.time_when_refreshed - Utc::now()).num_seconds().saturating_add(i64::try_from(jitter).unwrap_or(i64::MAX))
+}
+
+fn render_rate_limited_body(template: &str, retry_after: i64, rate_limit: &RateLimit, level: LimitLevel, group: &str) -> String {
+    template
+        .replace("{level}", level.as_str())
+        .replace("{group}", group)
+        .replace("{retry_after}", &retry_after.to_string())
+        .replace("{limit}", &rate_limit.limit.to_string())
+        .replace("{window_seconds}", &rate_limit.duration.num_seconds().to_string())
+}
@@ -0,0 +1,53 @@
+use std::convert::Infallible;
+
+use uuid::Uuid;
+use warp::{Filter, Reply, hyper::HeaderMap, trace::Info};
+
+use crate::telemetry;
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+// Span wrapping each request. The request id is filled in once it is known, and
+// the rate limiting fields once the request has been counted (see server::check_rate_limit).
+pub fn span(info: Info) -> tracing::Span {
+    let span = tracing::info_span!(
+        "request",
+        method = %info.method(),
+        path = %info.path(),
+        request_id = tracing::field::Empty,
+        route = tracing::field::Empty,
+        decision = tracing::field::Empty,
+        remaining = tracing::field::Empty,
+        store_latency_ms = tracing::field::Empty,
+    );
+    telemetry::continue_trace(&span, info.request_headers());
+    span
+}
+
+// honours the caller's X-Request-Id if it looks sane, otherwise generates one
+pub fn request_id() -> impl Filter<Extract = (String,), Error = Infallible> + Clone {
+    warp::header::headers_cloned().map(|headers: HeaderMap| {
+        let request_id = headers
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| is_valid(value))
+            .map(|value| value.to_string())
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+        tracing::Span::current().record("request_id", request_id.as_str());
+        request_id
+    })
+}
+
+pub fn echo(request_id: String, reply: impl Reply) -> impl Reply {
+    warp::reply::with_header(reply, REQUEST_ID_HEADER, request_id)
+}
+
+// ids end up in logs, so only accept short values that can't smuggle in anything odd
+fn is_valid(request_id: &str) -> bool {
+    !request_id.is_empty()
+        && request_id.len() <= MAX_REQUEST_ID_LENGTH
+        && request_id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+}
@@ -0,0 +1,78 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use serde::Deserialize;
+
+// "hit" or "miss" on GET /vault/items responses while the cache is on
+pub const CACHE_STATUS_HEADER: &str = "X-Cache";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ResponseCacheConfig {
+    // how long a client's listing is served from the cache
+    pub ttl_ms: u64,
+    // quota charged for a response served from the cache, 0 makes them free
+    pub cost: u64,
+    // listings cached at once, further ones aren't cached until expired ones are swept out
+    pub max_entries: usize,
+}
+
+impl Default for ResponseCacheConfig {
+    fn default() -> Self {
+        ResponseCacheConfig { ttl_ms: 1000, cost: 0, max_entries: 10_000 }
+    }
+}
+
+// a listing as it was sent, before any compression
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    pub body: Vec<u8>,
+    pub etag: String,
+    pub link: String,
+}
+
+#[derive(Debug)]
+struct Entry {
+    response: CachedResponse,
+    // the vault's version the listing was read at, see Vault::version
+    version: u64,
+    expires_at: Instant,
+}
+
+// Per client GET /vault/items listings, so a client polling the same page is
+// answered without reading the vault again. Entries expire after ttl_ms, and
+// any write to the vault makes every entry stale straight away.
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    config: ResponseCacheConfig,
+    entries: Arc<DashMap<String, Entry>>,
+}
+
+impl ResponseCache {
+    pub fn new(config: ResponseCacheConfig) -> Self {
+        ResponseCache { config, entries: Arc::new(DashMap::new()) }
+    }
+
+    pub fn cost(&self) -> u64 {
+        self.config.cost
+    }
+
+    // `key` names the client and the exact listing, e.g. its query
+    pub fn get(&self, key: &str, version: u64) -> Option<CachedResponse> {
+        let entry = self.entries.get(key)?;
+        (entry.version == version && entry.expires_at > Instant::now()).then(|| entry.response.clone())
+    }
+
+    pub fn insert(&self, key: String, version: u64, response: CachedResponse) {
+        let now = Instant::now();
+        if self.entries.len() >= self.config.max_entries {
+            self.entries.retain(|_, entry| entry.expires_at > now);
+            if self.entries.len() >= self.config.max_entries {
+                return;
+            }
+        }
+        let expires_at = now + Duration::from_millis(self.config.ttl_ms);
+        self.entries.insert(key, Entry { response, version, expires_at });
+    }
+}
@@ -0,0 +1,118 @@
+use std::cmp::Reverse;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    // `{name}` (or the older `<:name>`), matches any single segment
+    Param,
+    // a trailing `*`, matches the rest of the path
+    Rest,
+}
+
+// A route template such as `PUT /vault/items/{id}` or `* /reports/*`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoutePattern {
+    template: String,
+    // None for `*`, any method
+    method: Option<String>,
+    segments: Vec<Segment>,
+}
+
+impl RoutePattern {
+    // None if `template` isn't of the form "<METHOD> <path>"
+    pub fn parse(template: &str) -> Option<Self> {
+        let (method, path) = template.trim().split_once(' ')?;
+        let path = path.trim();
+        if !path.starts_with('/') {
+            return None;
+        }
+
+        let segments: Vec<Segment> = path_segments(path)
+            .map(|segment| match segment {
+                "*" => Segment::Rest,
+                _ if is_param(segment) => Segment::Param,
+                literal => Segment::Literal(literal.to_string()),
+            })
+            .collect();
+        // `*` only makes sense as the last segment
+        if segments.iter().rev().skip(1).any(|segment| *segment == Segment::Rest) {
+            return None;
+        }
+
+        Some(RoutePattern {
+            template: template.trim().to_string(),
+            method: (method != "*").then(|| method.to_ascii_uppercase()),
+            segments,
+        })
+    }
+
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+
+    // whether both match exactly the same requests, e.g. `PUT /items/{id}` and `put /items/<:item>`
+    pub fn same_requests(&self, other: &RoutePattern) -> bool {
+        self.method == other.method && self.segments == other.segments
+    }
+
+    pub fn matches(&self, method: &str, path: &str) -> bool {
+        if self.method.as_deref().is_some_and(|expected| !expected.eq_ignore_ascii_case(method)) {
+            return false;
+        }
+
+        let mut path_segments = path_segments(path);
+        for segment in &self.segments {
+            match (segment, path_segments.next()) {
+                (Segment::Rest, _) => return true,
+                (Segment::Param, Some(_)) => {}
+                (Segment::Literal(literal), Some(actual)) if literal == actual => {}
+                _ => return false,
+            }
+        }
+        path_segments.next().is_none()
+    }
+
+    // more literal segments beat parameters, and any pattern beats a catch-all
+    fn specificity(&self) -> (usize, bool, bool, usize) {
+        let literals = self.segments.iter().filter(|segment| matches!(segment, Segment::Literal(_))).count();
+        let exact = !self.segments.contains(&Segment::Rest);
+        (literals, exact, self.method.is_some(), self.segments.len())
+    }
+}
+
+// Maps a request's method and path to the most specific matching route template
+#[derive(Debug, Clone, Default)]
+pub struct Router {
+    patterns: Vec<RoutePattern>,
+}
+
+impl Router {
+    // templates that don't parse are skipped with a warning
+    pub fn new<'a>(templates: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut patterns: Vec<RoutePattern> = templates
+            .into_iter()
+            .filter_map(|template| {
+                let pattern = RoutePattern::parse(template);
+                if pattern.is_none() {
+                    tracing::warn!(template, "ignoring route template, expected \"<METHOD> <path>\"");
+                }
+                pattern
+            })
+            .collect();
+        // ties are broken by template so the winner doesn't depend on config ordering
+        patterns.sort_by(|a, b| Reverse(a.specificity()).cmp(&Reverse(b.specificity())).then_with(|| a.template.cmp(&b.template)));
+        Router { patterns }
+    }
+
+    pub fn match_route(&self, method: &str, path: &str) -> Option<&str> {
+        self.patterns.iter().find(|pattern| pattern.matches(method, path)).map(RoutePattern::template)
+    }
+}
+
+fn path_segments(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+fn is_param(segment: &str) -> bool {
+    (segment.starts_with('{') && segment.ends_with('}')) || (segment.starts_with("<:") && segment.ends_with('>'))
+}
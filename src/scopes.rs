@@ -0,0 +1,165 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::config::Config;
+use crate::key_extractor::RequestInfo;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// on responses to requests made with an expired token still inside its grace period,
+// carrying the time it stops being accepted
+pub const TOKEN_EXPIRING_HEADER: &str = "X-Token-Expiring";
+
+// an API key from config, identified by the sha256 of the token so the file doesn't hold live secrets
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ApiKeyConfig {
+    pub token_sha256: String,
+    pub scopes: Vec<String>,
+    // tokens sharing a tenant share its tenant level limit
+    pub tenant: Option<String>,
+    // when set, the key's PUT and POST requests have to be signed with it, see signatures
+    pub signing_secret: Option<String>,
+}
+
+// what a bearer token is entitled to
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TokenClaims {
+    pub scopes: HashSet<String>,
+    pub tenant: Option<String>,
+    // set for an expired token that is still accepted, see Config::jwt_expiry_grace_seconds
+    pub grace_ends_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JwtHeader {
+    alg: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct JwtClaims {
+    exp: Option<i64>,
+    // OAuth style, space separated
+    scope: Option<String>,
+    scopes: Option<Vec<String>>,
+    tenant: Option<String>,
+}
+
+// why a request was turned away, each answered differently, see replies::AuthPolicy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthError {
+    // no credentials at all
+    Missing,
+    // credentials that aren't accepted, e.g. an unknown token or an expired JWT
+    Invalid,
+    // good credentials without the scopes the route requires
+    Forbidden,
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Missing => write!(f, "the request carries no credentials"),
+            AuthError::Invalid => write!(f, "the request's credentials aren't accepted"),
+            AuthError::Forbidden => write!(f, "the request's credentials lack a required scope"),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+// Works out what a request's credentials are entitled to. Set
+// Config::authenticator to check them against something other than config.
+pub trait Authenticator: fmt::Debug + Send + Sync {
+    // Missing or Invalid when the credentials can't be accepted, scopes are checked by the caller
+    fn authenticate(&self, config: &Config, request: &RequestInfo) -> Result<TokenClaims, AuthError>;
+}
+
+// The default: the scopes and tenant of the matching configured API key,
+// otherwise the claims of an HS256 JWT signed with `jwt_secret`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConfiguredTokens;
+
+impl Authenticator for ConfiguredTokens {
+    fn authenticate(&self, config: &Config, request: &RequestInfo) -> Result<TokenClaims, AuthError> {
+        let bearer_token = request.authorization().ok_or(AuthError::Missing)?;
+        if let Some(api_key) = api_key(config, bearer_token) {
+            return Ok(TokenClaims { scopes: api_key.scopes.iter().cloned().collect(), tenant: api_key.tenant.clone(), grace_ends_at: None });
+        }
+
+        let token = bearer_token.trim_start_matches("Bearer ");
+        // parse turns away a grace too long for a Duration, one set some other way is just ignored
+        let grace = Duration::try_seconds(config.jwt_expiry_grace_seconds.max(0)).unwrap_or_default();
+        config.jwt_secret.as_deref()
+            .and_then(|secret| jwt_claims(secret.as_bytes(), token, grace))
+            .ok_or(AuthError::Invalid)
+    }
+}
+
+// a request's claims, from Config::authenticator if one is set
+pub fn authenticate(config: &Config, request: &RequestInfo) -> Result<TokenClaims, AuthError> {
+    match &config.authenticator {
+        Some(authenticator) => authenticator.authenticate(config, request),
+        None => ConfiguredTokens.authenticate(config, request),
+    }
+}
+
+// a request's claims, as long as they include every one of `required_scopes`
+pub fn authorize(config: &Config, request: &RequestInfo, required_scopes: &[String]) -> Result<TokenClaims, AuthError> {
+    let claims = authenticate(config, request)?;
+    match required_scopes.iter().all(|scope| claims.scopes.contains(scope)) {
+        true => Ok(claims),
+        false => Err(AuthError::Forbidden),
+    }
+}
+
+// the configured API key a bearer token belongs to
+pub fn api_key<'a>(config: &'a Config, bearer_token: &str) -> Option<&'a ApiKeyConfig> {
+    let digest = sha256::digest(bearer_token.trim_start_matches("Bearer "));
+    config.api_keys.iter().find(|api_key| api_key.token_sha256.eq_ignore_ascii_case(&digest))
+}
+
+fn jwt_claims(secret: &[u8], token: &str, grace: Duration) -> Option<TokenClaims> {
+    let mut parts = token.split('.');
+    let (header, payload, signature) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some() {
+        return None;
+    }
+
+    // only accept the algorithm we can verify, never "none"
+    let jwt_header: JwtHeader = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(header).ok()?).ok()?;
+    if jwt_header.alg != "HS256" {
+        return None;
+    }
+
+    let mut mac = HmacSha256::new_from_slice(secret).ok()?;
+    mac.update(header.as_bytes());
+    mac.update(b".");
+    mac.update(payload.as_bytes());
+    mac.verify_slice(&URL_SAFE_NO_PAD.decode(signature).ok()?).ok()?;
+
+    let claims: JwtClaims = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()?;
+    let now = Utc::now();
+    let grace_ends_at = match claims.exp.and_then(|exp| Utc.timestamp_opt(exp, 0).single()) {
+        // an expiry the grace can't be added to is rejected rather than overflowing
+        Some(expires_at) => match expires_at.checked_add_signed(grace)? {
+            grace_ends_at if grace_ends_at <= now => return None,
+            // long running clients get a little while to swap in a fresh token
+            grace_ends_at if expires_at <= now => Some(grace_ends_at),
+            _ => None,
+        },
+        None => None,
+    };
+
+    let mut scopes: HashSet<String> = claims.scope.unwrap_or_default().split_whitespace().map(str::to_string).collect();
+    scopes.extend(claims.scopes.unwrap_or_default());
+    Some(TokenClaims { scopes, tenant: claims.tenant, grace_ends_at })
+}
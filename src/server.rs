@@ -0,0 +1,1522 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+use chrono::{DateTime, Duration, Utc};
+use futures_util::TryStreamExt;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use warp::http::header::{HeaderName, HeaderValue};
+use warp::{Filter, Rejection, Reply, hyper::{body::Bytes, Body, HeaderMap, StatusCode}};
+
+use crate::bypass::BypassTokens;
+use crate::circuit_breaker::CircuitBreakerStore;
+use crate::config::{Config, RouteConfig, StoreConfig};
+use crate::decision_events::DecisionPublisher;
+use crate::encryption::Keyring;
+use crate::error::{self, Error};
+use crate::graphql::Graphql;
+use crate::key_extractor::{self, RequestInfo};
+use crate::metrics::Metrics;
+use crate::notifications::QuotaNotifier;
+use crate::proxy::Proxy;
+use crate::region::Region;
+use crate::response_cache::{self, CachedResponse, ResponseCache};
+use crate::stats::{self, RouteStats};
+use crate::store::{InMemoryStore, LimitOverride, UsageStore};
+#[cfg(feature = "testing")]
+use crate::testing;
+use crate::usage_export::{ExportHandle, UsageExporter, UsageLedger};
+use crate::vault::{Vault, VaultItem};
+use crate::write_behind::WriteBehindStore;
+use crate::{compression, etag, replies, request_id, scopes, signatures, stream};
+use crate::scopes::{AuthError, TokenClaims};
+use crate::startup::{self, Readiness};
+use crate::{LevelLimit, LimitLevel, RateLimit, RateLimiter, Reservation, UsageError};
+
+pub const POST_VAULT_ROUTE: &str = "POST /vault";
+pub const GET_VAULT_ITEMS_ROUTE: &str = "GET /vault/items";
+pub const PUT_VAULT_ITEM_ROUTE: &str = "PUT /vault/items/<:id>";
+pub const DELETE_VAULT_ITEM_ROUTE: &str = "DELETE /vault/items/<:id>";
+pub const POST_VAULT_ITEMS_BATCH_ROUTE: &str = "POST /vault/items:batch";
+pub const GET_VAULT_STREAM_ROUTE: &str = "GET /vault/stream";
+pub const GET_QUOTA_EVENTS_ROUTE: &str = "GET /quota/events";
+pub const GET_METRICS_ROUTE: &str = "GET /metrics";
+pub const POST_GRAPHQL_ROUTE: &str = "POST /graphql";
+// in proxy mode, requests no configured route template matches are counted against this one
+pub const PROXY_ROUTE: &str = "* /*";
+// the routes config can be given for outside proxy mode, besides templates matching them
+pub const LIMITED_ROUTES: &[&str] = &[
+    POST_VAULT_ROUTE,
+    GET_VAULT_ITEMS_ROUTE,
+    PUT_VAULT_ITEM_ROUTE,
+    DELETE_VAULT_ITEM_ROUTE,
+    POST_VAULT_ITEMS_BATCH_ROUTE,
+    GET_VAULT_STREAM_ROUTE,
+    GET_QUOTA_EVENTS_ROUTE,
+    GET_METRICS_ROUTE,
+    POST_GRAPHQL_ROUTE,
+];
+
+const POST_VAULT_RATE_LIMIT: u64 = 3;
+const GET_VAULT_ITEMS_RATE_LIMIT: u64 = 1200;
+const PUT_VAULT_ITEM_RATE_LIMIT: u64 = 60;
+const DELETE_VAULT_ITEM_RATE_LIMIT: u64 = 60;
+// batch requests are charged one unit per item, so this is items per minute rather than requests
+const POST_VAULT_ITEMS_BATCH_RATE_LIMIT: u64 = 600;
+// new connections per minute, messages on an open connection are limited separately
+const GET_VAULT_STREAM_RATE_LIMIT: u64 = 10;
+const DEFAULT_STREAM_MESSAGES_PER_SECOND: u64 = 10;
+// subscriptions per minute
+const GET_QUOTA_EVENTS_RATE_LIMIT: u64 = 10;
+const PROXY_RATE_LIMIT: u64 = 600;
+// queries are charged by cost, so this is cost units per minute rather than requests
+const POST_GRAPHQL_RATE_LIMIT: u64 = 6000;
+
+const DEFAULT_RESERVATION_TTL_SECONDS: i64 = 5 * 60;
+const MAX_RESERVATION_TTL_SECONDS: i64 = 60 * 60;
+
+const MAX_BATCH_BODY_BYTES: u64 = 1024 * 1024;
+const MAX_ITEM_BODY_BYTES: u64 = 64 * 1024;
+const MAX_GRAPHQL_BODY_BYTES: u64 = 64 * 1024;
+const MAX_ADMIN_BODY_BYTES: u64 = 16 * 1024;
+
+const DEFAULT_TOP_OFFENDERS: usize = 10;
+const MAX_TOP_OFFENDERS: usize = 1000;
+const DEFAULT_TOP_OFFENDERS_WINDOW_SECONDS: i64 = 5 * 60;
+
+pub(crate) const DEFAULT_PAGE_SIZE: usize = 100;
+pub(crate) const MAX_PAGE_SIZE: usize = 1000;
+
+// what has to happen once the routes stop being served, before the process exits
+#[derive(Debug, Default)]
+pub struct Shutdown {
+    usage_export: Option<ExportHandle>,
+}
+
+impl Shutdown {
+    pub async fn run(self) {
+        if let Some(usage_export) = self.usage_export {
+            usage_export.shutdown().await;
+        }
+    }
+}
+
+// every route the service serves, with request ids and tracing applied
+pub fn routes(config: Arc<Config>) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    routes_with_shutdown(config).0
+}
+
+// the routes, along with what to run once they've stopped being served
+pub fn routes_with_shutdown(config: Arc<Config>) -> (impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone, Shutdown) {
+    let mut shutdown = Shutdown::default();
+    let metrics = Arc::new(Metrics::new());
+    // faults are injected closest to the store, so the circuit breaker and failure policy see them like real ones
+    #[cfg(feature = "testing")]
+    let chaos = testing::Chaos::new();
+    let mut memory_store = InMemoryStore::with_capacity(config.startup.expected_keys);
+    if let Some(max_keys) = config.store.max_keys {
+        memory_store = memory_store.with_max_keys(max_keys, config.store.eviction_policy, metrics.clone());
+    }
+    #[cfg(feature = "testing")]
+    let base_store = testing::ChaosStore::new(memory_store, chaos.clone());
+    #[cfg(not(feature = "testing"))]
+    let base_store = memory_store;
+    let store = match &config.store.circuit_breaker {
+        Some(circuit_breaker) => with_write_behind(CircuitBreakerStore::new(base_store, circuit_breaker.clone(), metrics.clone()), &config.store),
+        None => with_write_behind(base_store, &config.store),
+    };
+    let quota_notifier = QuotaNotifier::new();
+    let mut rate_limiter = RateLimiter::with_store(store.clone())
+        .with_failure_policy(config.store.failure_policy)
+        .with_key_hasher(config.key_hasher())
+        .with_metrics(metrics.clone())
+        .with_observer(Arc::new(quota_notifier.clone()));
+    if let Some(region) = &config.region {
+        rate_limiter = rate_limiter.with_region(Region::new(region, store));
+    }
+    if let Some(secret) = &config.bypass_token_secret {
+        // a day is already far longer than an emergency needs, and keeps expiry times representable
+        let max_ttl = Duration::seconds(config.bypass_token_max_ttl_seconds.clamp(0, 24 * 60 * 60));
+        rate_limiter = rate_limiter.with_bypass_tokens(BypassTokens::new(secret.as_bytes(), max_ttl));
+    }
+    if let Some(usage_export) = &config.usage_export {
+        let usage_ledger = Arc::new(UsageLedger::new());
+        rate_limiter = rate_limiter.with_usage_ledger(usage_ledger.clone());
+        shutdown.usage_export = Some(UsageExporter::new(usage_export, usage_ledger).spawn());
+    }
+    if let Some(decision_events) = &config.decision_events {
+        match DecisionPublisher::spawn(decision_events, metrics.clone()) {
+            Ok(publisher) => rate_limiter = rate_limiter.with_observer(Arc::new(publisher)),
+            Err(err) => tracing::error!(error = %err, "decision events won't be published"),
+        }
+    }
+    // requests are served straight away, /readyz tells load balancers when they'll be served without cold start delays
+    let readiness = Readiness::new();
+    let warmup_routes = LIMITED_ROUTES
+        .iter()
+        .copied()
+        .chain(config.routes.keys().map(String::as_str))
+        .filter_map(|route| Some((route.to_string(), route_rate_limit(&config, route)?)))
+        .collect();
+    tokio::spawn(startup::run(config.startup.clone(), rate_limiter.clone(), warmup_routes, readiness.clone()));
+
+    let proxy = config.proxy.as_ref().map(|proxy| Proxy::new(proxy, metrics.clone()));
+    let rate_limiter_filter = warp::any().map(move || rate_limiter.clone());
+    let vault = Vault::with_keyring(Keyring::new(&config.encryption_keys));
+    let graphql = Graphql::new(vault.clone());
+    #[cfg(feature = "testing")]
+    let testing_routes = testing::routes(chaos, config.clone());
+    let recover_config = config.clone();
+    let response_cache = config.response_cache.clone().map(ResponseCache::new);
+    let config_filter = warp::any().map(move || config.clone());
+    let vault_filter = warp::any().map(move || vault.clone());
+    let response_cache_filter = warp::any().map(move || response_cache.clone());
+    let metrics_filter = warp::any().map(move || metrics.clone());
+    let quota_notifier_filter = warp::any().map(move || quota_notifier.clone());
+    let graphql_filter = warp::any().map(move || graphql.clone());
+
+    let post_vault_route = warp::path("vault")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(key_extractor::request_info())
+        // the body is only there to be signed, requests without a length are read as empty
+        .and(warp::body::content_length_limit(MAX_ITEM_BODY_BYTES).and(warp::body::bytes()).or(warp::any().map(Bytes::new)).unify())
+        .and(config_filter.clone())
+        .and(rate_limiter_filter.clone())
+        .map(|request_info, body, config, rate_limiter| error::answer(config, |config| post_vault(rate_limiter, config, request_info, body)));
+    
+    let get_vault_items_route = warp::path!("vault" / "items")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(key_extractor::request_info())
+        .and(warp::query())
+        .and(config_filter.clone())
+        .and(vault_filter.clone())
+        .and(response_cache_filter)
+        .and(rate_limiter_filter.clone())
+        .map(|request_info, query, config, vault, response_cache, rate_limiter| error::answer(config, |config| get_vault_items(rate_limiter, config, vault, response_cache, request_info, query)));
+
+    let put_vault_item_route = warp::path!("vault" / "items" / String)
+        .and(warp::path::end())
+        .and(warp::put())
+        .and(key_extractor::request_info())
+        .and(warp::body::content_length_limit(MAX_ITEM_BODY_BYTES))
+        .and(warp::body::bytes())
+        .and(config_filter.clone())
+        .and(vault_filter.clone())
+        .and(rate_limiter_filter.clone())
+        .map(|id, request_info, body, config, vault, rate_limiter| error::answer(config, |config| put_vault_item(rate_limiter, config, vault, request_info, id, body)));
+
+    let delete_vault_item_route = warp::path!("vault" / "items" / String)
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(key_extractor::request_info())
+        .and(config_filter.clone())
+        .and(vault_filter.clone())
+        .and(rate_limiter_filter.clone())
+        .map(|id, request_info, config, vault, rate_limiter| error::answer(config, |config| delete_vault_item(rate_limiter, config, vault, request_info, id)));
+
+    let post_vault_items_batch_route = warp::path!("vault" / "items:batch")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(key_extractor::request_info())
+        .and(warp::body::content_length_limit(MAX_BATCH_BODY_BYTES))
+        .and(warp::body::bytes())
+        .and(config_filter.clone())
+        .and(vault_filter.clone())
+        .and(rate_limiter_filter.clone())
+        .map(|request_info, body, config, vault, rate_limiter| error::answer(config, |config| post_vault_items_batch(rate_limiter, config, vault, request_info, body)));
+
+    let get_vault_stream_route = warp::path!("vault" / "stream")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(key_extractor::request_info())
+        .and(warp::ws())
+        .and(config_filter.clone())
+        .and(vault_filter.clone())
+        .and(rate_limiter_filter.clone())
+        .map(|request_info, ws, config, vault, rate_limiter| error::answer(config, |config| get_vault_stream(rate_limiter, config, vault, request_info, ws)));
+
+    let get_quota_events_route = warp::path!("quota" / "events")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(key_extractor::request_info())
+        .and(warp::query())
+        .and(config_filter.clone())
+        .and(quota_notifier_filter)
+        .and(rate_limiter_filter.clone())
+        .map(|request_info, query, config, quota_notifier, rate_limiter| error::answer(config, |config| get_quota_events(rate_limiter, config, quota_notifier, request_info, query)));
+
+    let get_vault_limits_route = warp::path!("vault" / "limits" / String)
+        .and(warp::path::end())
+        .and(warp::get().or(warp::head()).unify())
+        .and(key_extractor::request_info())
+        .and(warp::query())
+        .and(config_filter.clone())
+        .and(rate_limiter_filter.clone())
+        .map(|route, request_info, query, config, rate_limiter| error::answer(config, |config| get_vault_limits(rate_limiter, config, request_info, route, query)));
+
+    let post_vault_reservation_route = warp::path!("vault" / "reservations")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(key_extractor::request_info())
+        .and(warp::body::content_length_limit(MAX_ITEM_BODY_BYTES))
+        .and(warp::body::bytes())
+        .and(config_filter.clone())
+        .and(rate_limiter_filter.clone())
+        .map(|request_info, body, config, rate_limiter| error::answer(config, |config| post_vault_reservation(rate_limiter, config, request_info, body)));
+
+    let commit_vault_reservation_route = warp::path!("vault" / "reservations" / String / "commit")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(key_extractor::request_info())
+        .and(warp::body::content_length_limit(MAX_ITEM_BODY_BYTES))
+        .and(warp::body::bytes())
+        .and(config_filter.clone())
+        .and(rate_limiter_filter.clone())
+        .map(|id, request_info, body, config, rate_limiter| error::answer(config, |config| commit_vault_reservation(rate_limiter, config, request_info, id, body)));
+
+    let delete_vault_reservation_route = warp::path!("vault" / "reservations" / String)
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(key_extractor::request_info())
+        .and(config_filter.clone())
+        .and(rate_limiter_filter.clone())
+        .map(|id, request_info, config, rate_limiter| error::answer(config, |config| delete_vault_reservation(rate_limiter, config, request_info, id)));
+
+    let issue_bypass_token_route = warp::path!("admin" / "bypass-tokens")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::header::headers_cloned())
+        .and(warp::body::content_length_limit(MAX_ADMIN_BODY_BYTES))
+        .and(warp::body::stream())
+        .and(config_filter.clone())
+        .and(rate_limiter_filter.clone())
+        .and_then(|headers, body, config: Arc<Config>, rate_limiter| async move {
+            let reply = match admin_json(&config, &headers, body).await {
+                Ok(request) => issue_bypass_token(rate_limiter, config.clone(), headers, request),
+                Err(err) => Err(err),
+            };
+            Ok::<_, Rejection>(reply.or_else(|err| err.reply(&config)))
+        });
+
+    let put_limit_override_route = warp::path!("admin" / "limits" / String)
+        .and(warp::path::end())
+        .and(warp::put())
+        .and(warp::header::headers_cloned())
+        .and(warp::body::content_length_limit(MAX_ADMIN_BODY_BYTES))
+        .and(warp::body::stream())
+        .and(config_filter.clone())
+        .and(rate_limiter_filter.clone())
+        .and_then(|key, headers, body, config: Arc<Config>, rate_limiter| async move {
+            let reply = match admin_json(&config, &headers, body).await {
+                Ok(request) => put_limit_override(rate_limiter, config.clone(), headers, key, request),
+                Err(err) => Err(err),
+            };
+            Ok::<_, Rejection>(reply.or_else(|err| err.reply(&config)))
+        });
+
+    let delete_limit_override_route = warp::path!("admin" / "limits" / String)
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(warp::header::headers_cloned())
+        .and(warp::query())
+        .and(config_filter.clone())
+        .and(rate_limiter_filter.clone())
+        .map(|key, headers, query, config, rate_limiter| error::answer(config, |config| delete_limit_override(rate_limiter, config, headers, key, query)));
+
+    let get_client_limits_route = warp::path!("admin" / "limits" / String)
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::header::headers_cloned())
+        .and(config_filter.clone())
+        .and(rate_limiter_filter.clone())
+        .map(|key, headers, config, rate_limiter| error::answer(config, |config| get_client_limits(rate_limiter, config, headers, key)));
+
+    let reset_client_limits_route = warp::path!("admin" / "limits" / String / "reset")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::header::headers_cloned())
+        .and(config_filter.clone())
+        .and(rate_limiter_filter.clone())
+        .map(|key, headers, config, rate_limiter| error::answer(config, |config| reset_client_limits(rate_limiter, config, headers, key)));
+
+    let get_admin_stats_route = warp::path!("admin" / "stats")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::header::headers_cloned())
+        .and(config_filter.clone())
+        .and(rate_limiter_filter.clone())
+        .map(|headers, config, rate_limiter| error::answer(config, |config| get_admin_stats(rate_limiter, config, headers)));
+
+    let get_top_offenders_route = warp::path!("admin" / "top-offenders")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::header::headers_cloned())
+        .and(warp::query())
+        .and(config_filter.clone())
+        .and(rate_limiter_filter.clone())
+        .map(|headers, query, config, rate_limiter| error::answer(config, |config| get_top_offenders(rate_limiter, config, headers, query)));
+
+    let post_graphql_route = warp::path("graphql")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(key_extractor::request_info())
+        .and(warp::body::content_length_limit(MAX_GRAPHQL_BODY_BYTES))
+        .and(warp::body::bytes())
+        .and(config_filter.clone())
+        .and(graphql_filter)
+        .and(rate_limiter_filter.clone())
+        .and_then(|request_info, body, config: Arc<Config>, graphql, rate_limiter| async move {
+            let reply = post_graphql(rate_limiter, config.clone(), graphql, request_info, body).await;
+            Ok::<_, Rejection>(reply.or_else(|err| err.reply(&config)))
+        });
+
+    let readyz_route = warp::path("readyz")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || readiness.reply());
+
+    let get_metrics_route = warp::path("metrics")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::header::headers_cloned())
+        .and(config_filter.clone())
+        .and(metrics_filter.clone())
+        .map(|headers, config, metrics| error::answer(config, |config| get_metrics(metrics, config, headers)));
+
+    // in proxy mode everything but the service's own admin, metrics and quota endpoints goes upstream
+    let proxy_route = warp::any()
+        .and_then(move || {
+            let proxy = proxy.clone();
+            async move { proxy.ok_or_else(warp::reject::not_found) }
+        })
+        .and(key_extractor::request_info())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .and(warp::body::stream())
+        .and(config_filter.clone())
+        .and(rate_limiter_filter.clone())
+        .and_then(|proxy, request_info, query, body, config: Arc<Config>, rate_limiter| async move {
+            let reply = proxy_request(rate_limiter, config.clone(), proxy, request_info, query, body).await;
+            Ok::<_, Rejection>(reply.or_else(|err| err.reply(&config)))
+        });
+
+    let routes = issue_bypass_token_route
+        .or(put_limit_override_route)
+        .or(delete_limit_override_route)
+        .or(get_client_limits_route)
+        .or(reset_client_limits_route)
+        .or(get_admin_stats_route)
+        .or(get_top_offenders_route)
+        .or(get_metrics_route)
+        .or(readyz_route)
+        .or(get_quota_events_route)
+        .or(get_vault_limits_route)
+        .or(post_vault_reservation_route)
+        .or(commit_vault_reservation_route)
+        .or(delete_vault_reservation_route)
+        .or(proxy_route)
+        .or(post_vault_route)
+        .or(get_vault_items_route)
+        .or(put_vault_item_route)
+        .or(delete_vault_item_route)
+        .or(post_vault_items_batch_route)
+        .or(get_vault_stream_route)
+        .or(post_graphql_route);
+    #[cfg(feature = "testing")]
+    let routes = routes.or(testing_routes);
+
+    // rejections are answered here, inside request_id so their responses carry the id too
+    let routes = routes.recover(move |rejection| error::recover(recover_config.clone(), rejection));
+
+    let routes = request_id::request_id()
+        .and(routes)
+        .map(request_id::echo)
+        .with(warp::trace(request_id::span));
+    (routes, shutdown)
+}
+
+fn with_write_behind<S: UsageStore + 'static>(store: S, config: &StoreConfig) -> Arc<dyn UsageStore> {
+    match &config.write_behind {
+        Some(write_behind) => Arc::new(WriteBehindStore::new(store, write_behind.clone())),
+        None => Arc::new(store),
+    }
+}
+
+// POST "/vault"
+pub fn post_vault(rate_limiter: RateLimiter, config: Arc<Config>, request_info: RequestInfo, body: Bytes) -> Result<warp::reply::Response, Error> {
+    check_signature(&config, &rate_limiter, &request_info, &body)?;
+    rate_limited_request(rate_limiter, &config, request_info, LimitedRoute::new(POST_VAULT_ROUTE, config.rate_limit(POST_VAULT_ROUTE, POST_VAULT_RATE_LIMIT)))
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ListItemsQuery {
+    #[serde(default)]
+    pub offset: usize,
+    pub limit: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_prefix: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListItemsResponse {
+    pub items: Vec<VaultItem>,
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+// GET "/vault/items"
+pub fn get_vault_items(rate_limiter: RateLimiter, config: Arc<Config>, vault: Vault, response_cache: Option<ResponseCache>, request_info: RequestInfo, query: ListItemsQuery) -> Result<warp::reply::Response, Error> {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    // listings are cached per client and page, a request without a key is turned away anyway
+    let cache_key = response_cache.as_ref().and_then(|_| {
+        let client_key = config.route(GET_VAULT_ITEMS_ROUTE).key.build().extract(&request_info)?;
+        let page = serde_urlencoded::to_string(ListItemsQuery { offset: query.offset, limit: Some(limit), id_prefix: query.id_prefix.clone() }).ok()?;
+        Some(sha256::digest(format!("{}\n{}", client_key, page)))
+    });
+    let version = vault.version();
+    let cached = response_cache.as_ref().zip(cache_key.as_deref()).and_then(|(cache, key)| cache.get(key, version));
+    let cache_status = response_cache.as_ref().map(|_| if cached.is_some() { "hit" } else { "miss" });
+
+    // the limit is decided before the vault is read, so a client over it can't make the service list anything.
+    // A listing from the cache didn't touch the vault and one the client already has only needs a 304, so both can cost less.
+    let rate_limit = config.rate_limit(GET_VAULT_ITEMS_ROUTE, GET_VAULT_ITEMS_RATE_LIMIT);
+    let limited_route = match (&cached, &response_cache) {
+        (Some(cached), _) if etag::if_none_match(&request_info.headers, &cached.etag) => LimitedRoute::new(GET_VAULT_ITEMS_ROUTE, rate_limit).with_cost(config.not_modified_cost),
+        (Some(_), Some(response_cache)) => LimitedRoute::new(GET_VAULT_ITEMS_ROUTE, rate_limit).with_cost(response_cache.cost()),
+        _ => LimitedRoute::new(GET_VAULT_ITEMS_ROUTE, rate_limit),
+    };
+    let headers = request_info.headers.clone();
+    let (mut reply, mut charge) = match check_rate_limit(rate_limiter.clone(), &config, &request_info, limited_route) {
+        RateLimitDecision::Allowed(reply, charge) => (reply, charge),
+        RateLimitDecision::Rejected(reply) => return Ok(reply?),
+    };
+
+    let CachedResponse { body, etag, link } = match cached {
+        Some(response) => response,
+        None => {
+            let (items, total) = vault.list(query.id_prefix.as_deref().unwrap_or(""), query.offset, limit);
+            let body = match serde_json::to_vec(&ListItemsResponse { items, total, offset: query.offset, limit }) {
+                Ok(body) => body,
+                Err(_) => return Ok(settle_charge(&rate_limiter, &config, charge, replies::internal_server_error())?),
+            };
+            let response = CachedResponse { etag: etag::etag_for(&body), link: pagination_links(&query, limit, total), body };
+            if let (Some(response_cache), Some(cache_key)) = (&response_cache, cache_key) {
+                response_cache.insert(cache_key, version, response.clone());
+            }
+            // only now is it known that the client already has the listing
+            if let (true, Some(charge)) = (etag::if_none_match(&headers, &response.etag), charge.as_mut()) {
+                reduce_charge(&rate_limiter, &config, &mut reply, charge, config.not_modified_cost);
+            }
+            response
+        }
+    };
+    if let Some(cache_status) = cache_status {
+        reply = reply.header(response_cache::CACHE_STATUS_HEADER, cache_status);
+    }
+
+    let response = if etag::if_none_match(&headers, &etag) {
+        reply.status(StatusCode::NOT_MODIFIED).header("ETag", &etag).body(Body::empty())
+    } else {
+        if !link.is_empty() {
+            reply = reply.header("Link", link);
+        }
+        reply = reply.status(StatusCode::OK).header("Content-Type", "application/json");
+        if !config.route(GET_VAULT_ITEMS_ROUTE).compression {
+            reply.header("ETag", &etag).body(body.into())
+        } else {
+            match compression::encode_body(&headers, body) {
+                // the encoded bytes differ from what the etag was computed over, so it can only be a weak validator
+                (Some(encoding), body) => reply
+                    .header("ETag", format!("W/{}", etag))
+                    .header("Content-Encoding", encoding.as_str())
+                    .header("Vary", "Accept-Encoding")
+                    .body(body.into()),
+                (None, body) => reply.header("ETag", &etag).header("Vary", "Accept-Encoding").body(body.into()),
+            }
+        }
+    };
+    Ok(settle_charge(&rate_limiter, &config, charge, response)?)
+}
+
+// builds the Link header (RFC 8288) pointing at the neighbouring pages of a listing
+fn pagination_links(query: &ListItemsQuery, limit: usize, total: usize) -> String {
+    let page_link = |offset: usize, rel: &str| {
+        let page = ListItemsQuery { offset, limit: Some(limit), id_prefix: query.id_prefix.clone() };
+        serde_urlencoded::to_string(&page)
+            .map(|query_string| format!("</vault/items?{}>; rel=\"{}\"", query_string, rel))
+            .ok()
+    };
+
+    let mut links = Vec::new();
+    // the offset comes straight from the query string, so it can be anything up to usize::MAX
+    let next_offset = query.offset.saturating_add(limit);
+    if next_offset < total {
+        links.extend(page_link(next_offset, "next"));
+    }
+    if query.offset > 0 {
+        links.extend(page_link(query.offset.saturating_sub(limit), "prev"));
+    }
+    links.join(", ")
+}
+
+// PUT "/vault/items/<:id>
+pub fn put_vault_item(rate_limiter: RateLimiter, config: Arc<Config>, vault: Vault, request_info: RequestInfo, id: String, body: Bytes) -> Result<warp::reply::Response, Error> {
+    check_signature(&config, &rate_limiter, &request_info, &body)?;
+    // an empty body stores an item with no data, so the endpoint keeps working without a payload
+    let data = if body.is_empty() {
+        serde_json::Value::Null
+    } else {
+        serde_json::from_slice(&body).map_err(|err| Error::Validation(err.to_string()))?
+    };
+
+    let limited_route = LimitedRoute::new(PUT_VAULT_ITEM_ROUTE, config.rate_limit(PUT_VAULT_ITEM_ROUTE, PUT_VAULT_ITEM_RATE_LIMIT)).with_key_suffix(&id);
+    rate_limited_request_with(rate_limiter, &config, request_info, limited_route, |reply| {
+        replies::json(reply.status(StatusCode::OK), &vault.put(id.clone(), data))
+    })
+}
+
+// DELETE "/vault/items/<:id>"
+pub fn delete_vault_item(rate_limiter: RateLimiter, config: Arc<Config>, vault: Vault, request_info: RequestInfo, id: String) -> Result<warp::reply::Response, Error> {
+    let limited_route = LimitedRoute::new(DELETE_VAULT_ITEM_ROUTE, config.rate_limit(DELETE_VAULT_ITEM_ROUTE, DELETE_VAULT_ITEM_RATE_LIMIT)).with_key_suffix(&id);
+    rate_limited_request_with(rate_limiter, &config, request_info, limited_route, |reply| {
+        match vault.delete(&id) {
+            Some(_) => reply.status(StatusCode::NO_CONTENT).body(Body::empty()),
+            None => reply.status(StatusCode::NOT_FOUND).body(Body::empty()),
+        }
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BatchCreateItemsRequest {
+    pub items: Vec<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchCreateItemsResponse {
+    pub items: Vec<VaultItem>,
+}
+
+// POST "/vault/items:batch"
+pub fn post_vault_items_batch(rate_limiter: RateLimiter, config: Arc<Config>, vault: Vault, request_info: RequestInfo, body: Bytes) -> Result<warp::reply::Response, Error> {
+    let request: BatchCreateItemsRequest = signed_json(&config, &rate_limiter, &request_info, &body)?;
+    let rate_limit = config.rate_limit(POST_VAULT_ITEMS_BATCH_ROUTE, POST_VAULT_ITEMS_BATCH_RATE_LIMIT);
+    let cost = match u64::try_from(request.items.len()) {
+        Ok(0) => return Err(Error::Validation("the batch has no items".to_string())),
+        Ok(cost) => cost,
+        Err(_) => return Err(Error::PayloadTooLarge),
+    };
+
+    let limited_route = LimitedRoute::new(POST_VAULT_ITEMS_BATCH_ROUTE, rate_limit).with_cost(cost);
+    rate_limited_request_with(rate_limiter, &config, request_info, limited_route, |reply| {
+        let items = request.items.into_iter().map(|data| vault.create(data)).collect();
+        replies::json(reply.status(StatusCode::CREATED), &BatchCreateItemsResponse { items })
+    })
+}
+
+// GET "/vault/stream"
+pub fn get_vault_stream(rate_limiter: RateLimiter, config: Arc<Config>, vault: Vault, request_info: RequestInfo, ws: warp::ws::Ws) -> Result<warp::reply::Response, Error> {
+    let messages_per_second = config.route(GET_VAULT_STREAM_ROUTE).messages_per_second.unwrap_or(DEFAULT_STREAM_MESSAGES_PER_SECOND).max(1);
+    let message_rate_limit = RateLimit::per_second(messages_per_second);
+    let connection_rate_limiter = rate_limiter.clone();
+
+    let limited_route = LimitedRoute::new(GET_VAULT_STREAM_ROUTE, config.rate_limit(GET_VAULT_STREAM_ROUTE, GET_VAULT_STREAM_RATE_LIMIT));
+    rate_limited_request_with(rate_limiter, &config, request_info, limited_route, move |reply| {
+        let response = ws.on_upgrade(move |socket| stream::vault_events(socket, vault, connection_rate_limiter, message_rate_limit));
+        replies::with_builder_headers(reply, response.into_response())
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QuotaEventsQuery {
+    // notify once remaining quota drops below this, defaults to 10% of the limit
+    pub threshold: Option<u64>,
+}
+
+// GET "/quota/events"
+pub fn get_quota_events(rate_limiter: RateLimiter, config: Arc<Config>, quota_notifier: QuotaNotifier, request_info: RequestInfo, query: QuotaEventsQuery) -> Result<warp::reply::Response, Error> {
+    // usage events identify the caller by the digest of the same header the limiter keys on
+    let subject = request_info.authorization().map(sha256::digest).unwrap_or_default();
+
+    let limited_route = LimitedRoute::new(GET_QUOTA_EVENTS_ROUTE, config.rate_limit(GET_QUOTA_EVENTS_ROUTE, GET_QUOTA_EVENTS_RATE_LIMIT));
+    rate_limited_request_with(rate_limiter, &config, request_info, limited_route, move |reply| {
+        let notifications = quota_notifier.subscribe(subject, query.threshold);
+        let events = futures_util::stream::unfold(notifications, |mut notifications| async move {
+            let notification = notifications.recv().await?;
+            Some((warp::sse::Event::default().event(notification.name()).json_data(&notification), notifications))
+        });
+        replies::with_builder_headers(reply, warp::sse::reply(warp::sse::keep_alive().stream(events)).into_response())
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LimitsQuery {
+    // for routes counted per item, e.g. PUT /vault/items/<:id>
+    pub id: Option<String>,
+    // checks a request costing this much, e.g. a batch of that many items, rather than a plain request
+    pub cost: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LimitStatusResponse {
+    pub remaining: u64,
+    pub resets_at: String,
+    pub cost: RouteCost,
+}
+
+// what requests to a route are charged
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RouteCost {
+    // units a plain request costs
+    pub per_request: u64,
+    // what a request is charged by instead, if not always per_request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub charged_by: Option<&'static str>,
+    // for routes answering conditional requests, a 304 costs this instead
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub not_modified: Option<u64>,
+    // for routes with a response cache, a response served from it costs this instead
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cached: Option<u64>,
+}
+
+fn route_cost(config: &Config, route: &str) -> RouteCost {
+    let (charged_by, not_modified) = match route {
+        POST_VAULT_ITEMS_BATCH_ROUTE => (Some("items"), None),
+        POST_GRAPHQL_ROUTE => (Some("query_cost"), None),
+        GET_VAULT_ITEMS_ROUTE => (None, Some(config.not_modified_cost)),
+        _ => (None, None),
+    };
+    let cached = config.response_cache.as_ref().filter(|_| route == GET_VAULT_ITEMS_ROUTE).map(|response_cache| response_cache.cost);
+    RouteCost { per_request: 1, charged_by, not_modified, cached }
+}
+
+// GET or HEAD "/vault/limits/{route}", with the route template percent-encoded
+// Reports whether a request to the route would be allowed right now, without charging for it.
+// Answers with the same headers (and 429) the real request would get.
+pub fn get_vault_limits(rate_limiter: RateLimiter, config: Arc<Config>, request_info: RequestInfo, route: String, query: LimitsQuery) -> Result<warp::reply::Response, Error> {
+    let route = match percent_encoding::percent_decode_str(&route).decode_utf8() {
+        Ok(route) => route.into_owned(),
+        Err(_) => return Err(Error::Validation("the route should be a percent-encoded route template".to_string())),
+    };
+    let rate_limit = route_rate_limit(&config, &route).ok_or(Error::NotFound)?;
+
+    let cost = query.cost.unwrap_or(1);
+    let mut limited_route = LimitedRoute::new(&route, rate_limit).with_cost(cost);
+    if let Some(id) = &query.id {
+        limited_route = limited_route.with_key_suffix(id);
+    }
+    let ResolvedLimits { route_config, levels, .. } = resolve_limits(&rate_limiter, &config, &request_info, limited_route)?;
+
+    match rate_limiter.check_usage(&levels, cost) {
+        Ok((remaining, resets_at)) => {
+            let reply = replies::allowed(&config.headers, remaining).status(StatusCode::OK);
+            Ok(replies::json(reply, &LimitStatusResponse { remaining, resets_at: resets_at.to_rfc3339(), cost: route_cost(&config, &route) })?)
+        }
+        Err(UsageError::RateLimited(err)) => {
+            let rate_limit = levels.iter().find(|level| level.level == err.level).unwrap_or(&levels[0]).rate_limit.clone();
+            Ok(replies::rate_limited(&config.headers, err, &rate_limit, &route_config, group_name(&config, &route), config.retry_after_jitter(&route_config))?)
+        }
+        Err(UsageError::Store(err)) => Err(Error::Store(err)),
+    }
+}
+
+// a route's limit by template, None for routes that are neither built in nor given a limit in config
+pub fn route_rate_limit(config: &Config, route: &str) -> Option<RateLimit> {
+    let limit = built_in_limit(route).or_else(|| config.routes.get(route).and_then(|route_config| route_config.limit))?;
+    Some(config.rate_limit(route, limit))
+}
+
+// the limits routes have when config doesn't set one
+fn built_in_limit(route: &str) -> Option<u64> {
+    match route {
+        POST_VAULT_ROUTE => Some(POST_VAULT_RATE_LIMIT),
+        GET_VAULT_ITEMS_ROUTE => Some(GET_VAULT_ITEMS_RATE_LIMIT),
+        PUT_VAULT_ITEM_ROUTE => Some(PUT_VAULT_ITEM_RATE_LIMIT),
+        DELETE_VAULT_ITEM_ROUTE => Some(DELETE_VAULT_ITEM_RATE_LIMIT),
+        POST_VAULT_ITEMS_BATCH_ROUTE => Some(POST_VAULT_ITEMS_BATCH_RATE_LIMIT),
+        GET_VAULT_STREAM_ROUTE => Some(GET_VAULT_STREAM_RATE_LIMIT),
+        GET_QUOTA_EVENTS_ROUTE => Some(GET_QUOTA_EVENTS_RATE_LIMIT),
+        PROXY_ROUTE => Some(PROXY_RATE_LIMIT),
+        POST_GRAPHQL_ROUTE => Some(POST_GRAPHQL_RATE_LIMIT),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReserveRequest {
+    // the route template to reserve quota on, e.g. "POST /vault/items:batch"
+    pub route: String,
+    pub amount: u64,
+    pub ttl_seconds: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReservationResponse {
+    pub id: String,
+    pub amount: u64,
+    pub expires_at: String,
+    pub remaining: u64,
+}
+
+// POST "/vault/reservations"
+pub fn post_vault_reservation(rate_limiter: RateLimiter, config: Arc<Config>, request_info: RequestInfo, body: Bytes) -> Result<warp::reply::Response, Error> {
+    let request: ReserveRequest = signed_json(&config, &rate_limiter, &request_info, &body)?;
+    let ttl_seconds = request.ttl_seconds.unwrap_or(DEFAULT_RESERVATION_TTL_SECONDS);
+    if request.amount == 0 || ttl_seconds <= 0 {
+        return Err(Error::Validation("amount and ttl_seconds should be positive".to_string()));
+    }
+    // a grouped route has no quota of its own to set aside
+    let rate_limit = route_rate_limit(&config, &request.route).filter(|_| config.group(&request.route).is_none()).ok_or(Error::NotFound)?;
+
+    // an amount over the limit is a 413, like any request that could never fit in a window
+    let limited_route = LimitedRoute::new(&request.route, rate_limit).with_cost(request.amount);
+    let ResolvedLimits { limited_route, route_config, client_key, .. } = resolve_limits(&rate_limiter, &config, &request_info, limited_route)?;
+
+    let ttl = Duration::seconds(ttl_seconds.min(MAX_RESERVATION_TTL_SECONDS));
+    match rate_limiter.reserve(&limited_route.key, &client_key, &limited_route.rate_limit, request.amount, ttl) {
+        Ok(reservation) => {
+            let reply = replies::allowed(&config.headers, reservation.remaining).status(StatusCode::CREATED);
+            Ok(replies::json(reply, &ReservationResponse {
+                id: reservation.id,
+                amount: reservation.amount,
+                expires_at: reservation.expires_at.to_rfc3339(),
+                remaining: reservation.remaining,
+            })?)
+        }
+        Err(UsageError::RateLimited(err)) => Ok(replies::rate_limited(&config.headers, err, &limited_route.rate_limit, &route_config, None, config.retry_after_jitter(&route_config))?),
+        Err(UsageError::Store(err)) => Err(Error::Store(err)),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CommitReservationRequest {
+    // what the work actually cost, charged in place of the reserved amount
+    pub cost: u64,
+}
+
+// POST "/vault/reservations/{id}/commit"
+pub fn commit_vault_reservation(rate_limiter: RateLimiter, config: Arc<Config>, request_info: RequestInfo, id: String, body: Bytes) -> Result<warp::reply::Response, Error> {
+    let request: CommitReservationRequest = signed_json(&config, &rate_limiter, &request_info, &body)?;
+    let (client_key, rate_limit) = reservation_owner(&rate_limiter, &config, &request_info, &id)?;
+
+    let (remaining, resets_at) = rate_limiter.commit(&id, &client_key, &rate_limit, request.cost)?;
+    let reply = replies::allowed(&config.headers, remaining).status(StatusCode::OK);
+    let route = Reservation::route_of(&id).unwrap_or_default();
+    Ok(replies::json(reply, &LimitStatusResponse { remaining, resets_at: resets_at.to_rfc3339(), cost: route_cost(&config, &route) })?)
+}
+
+// DELETE "/vault/reservations/{id}"
+pub fn delete_vault_reservation(rate_limiter: RateLimiter, config: Arc<Config>, request_info: RequestInfo, id: String) -> Result<warp::reply::Response, Error> {
+    let (client_key, rate_limit) = reservation_owner(&rate_limiter, &config, &request_info, &id)?;
+    rate_limiter.cancel(&id, &client_key, &rate_limit)?;
+    Ok(replies::empty(StatusCode::NO_CONTENT)?)
+}
+
+// the key of the client making the request and the limit of the reservation's route.
+// Another client's key simply won't find the reservation.
+fn reservation_owner(rate_limiter: &RateLimiter, config: &Config, request_info: &RequestInfo, id: &str) -> Result<(String, RateLimit), Error> {
+    let route = Reservation::route_of(id).ok_or(Error::NotFound)?;
+    let rate_limit = route_rate_limit(config, &route).ok_or(Error::NotFound)?;
+    let resolved = resolve_limits(rate_limiter, config, request_info, LimitedRoute::new(&route, rate_limit).with_cost(0))?;
+    Ok((resolved.client_key, resolved.limited_route.rate_limit))
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProxyErrorResponse {
+    pub error: String,
+}
+
+// any request, in proxy mode
+pub async fn proxy_request<S, B>(rate_limiter: RateLimiter, config: Arc<Config>, proxy: Proxy, request_info: RequestInfo, query: String, body: S) -> Result<warp::reply::Response, Error>
+where
+    S: futures_util::Stream<Item = Result<B, warp::Error>> + Send + 'static,
+    B: warp::hyper::body::Buf + Send + 'static,
+{
+    // check_rate_limit swaps in whichever configured template matches the request
+    let limited_route = LimitedRoute::new(PROXY_ROUTE, config.rate_limit(PROXY_ROUTE, PROXY_RATE_LIMIT));
+    let (reply, charge) = match check_rate_limit(rate_limiter.clone(), &config, &request_info, limited_route) {
+        RateLimitDecision::Allowed(reply, charge) => (reply, charge),
+        RateLimitDecision::Rejected(reply) => return Ok(reply?),
+    };
+
+    let response = match proxy.forward(&request_info, &query, body).await {
+        Ok(response) => replies::with_builder_headers(reply, response),
+        Err(err) => {
+            tracing::warn!(error = %err, "proxied request failed");
+            let status = if err.is_timeout() { StatusCode::GATEWAY_TIMEOUT } else { StatusCode::BAD_GATEWAY };
+            replies::json(reply.status(status), &ProxyErrorResponse { error: err.to_string() })
+        }
+    };
+    Ok(settle_charge(&rate_limiter, &config, charge, response)?)
+}
+
+// POST "/graphql"
+pub async fn post_graphql(rate_limiter: RateLimiter, config: Arc<Config>, graphql: Graphql, request_info: RequestInfo, body: Bytes) -> Result<warp::reply::Response, Error> {
+    let request: async_graphql::Request = signed_json(&config, &rate_limiter, &request_info, &body)?;
+    // queries that can't be costed are turned away before they're charged anything, with the
+    // errors in the body where GraphQL clients look for them
+    let cost = match graphql.cost(&request, &config.graphql) {
+        Ok(cost) => cost,
+        Err(err) => {
+            let errors = async_graphql::Response::from_errors(vec![async_graphql::ServerError::new(err.to_string(), None)]);
+            return Ok(replies::json(replies::status(StatusCode::BAD_REQUEST), &errors)?);
+        }
+    };
+
+    let limited_route = LimitedRoute::new(POST_GRAPHQL_ROUTE, config.rate_limit(POST_GRAPHQL_ROUTE, POST_GRAPHQL_RATE_LIMIT)).with_cost(cost);
+    let (reply, charge) = match check_rate_limit(rate_limiter.clone(), &config, &request_info, limited_route) {
+        RateLimitDecision::Allowed(reply, charge) => (reply, charge),
+        RateLimitDecision::Rejected(reply) => return Ok(reply?),
+    };
+    let response = graphql.schema.execute(request).await;
+    Ok(settle_charge(&rate_limiter, &config, charge, replies::json(reply.status(StatusCode::OK), &response))?)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IssueBypassTokenRequest {
+    pub subject: String,
+    pub ttl_seconds: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IssueBypassTokenResponse {
+    pub token: String,
+    pub expires_at: String,
+}
+
+// POST "/admin/bypass-tokens"
+pub fn issue_bypass_token(rate_limiter: RateLimiter, config: Arc<Config>, headers: HeaderMap, request: IssueBypassTokenRequest) -> Result<warp::reply::Response, Error> {
+    // the endpoint only exists when both an admin token and a signing secret are configured
+    let bypass_tokens = rate_limiter.bypass_tokens().ok_or(Error::NotFound)?;
+    authorize_admin(&config, &headers)?;
+
+    // ttl_seconds comes from the body, so it can be too large for a Duration
+    let ttl = match Duration::try_seconds(request.ttl_seconds) {
+        Some(ttl) if !request.subject.is_empty() && request.ttl_seconds > 0 => ttl,
+        _ => return Err(Error::Validation("subject should be set and ttl_seconds positive and in range".to_string())),
+    };
+
+    let (token, expires_at) = bypass_tokens.issue(&request.subject, ttl);
+    tracing::info!(target: "audit", subject = %request.subject, expires_at = %expires_at, "issued rate limit bypass token");
+
+    let response = IssueBypassTokenResponse { token, expires_at: expires_at.to_rfc3339() };
+    Ok(replies::json(replies::status(StatusCode::CREATED), &response)?)
+}
+
+// An admin request's JSON body. It's only read once the request is known to come from
+// the admin, so anyone else can't make the service buffer and parse what they send.
+async fn admin_json<T, S, B>(config: &Config, headers: &HeaderMap, body: S) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+    S: futures_util::Stream<Item = Result<B, warp::Error>>,
+    B: warp::hyper::body::Buf,
+{
+    authorize_admin(config, headers)?;
+    let body = body
+        .try_fold(Vec::new(), |mut body, mut chunk| async move {
+            body.extend_from_slice(&chunk.copy_to_bytes(chunk.remaining()));
+            Ok(body)
+        })
+        .await
+        .map_err(|err| Error::Validation(err.to_string()))?;
+    serde_json::from_slice(&body).map_err(|err| Error::Validation(err.to_string()))
+}
+
+// the admin endpoints only exist when an admin token is configured, and only answer to it
+pub(crate) fn authorize_admin(config: &Config, headers: &HeaderMap) -> Result<(), Error> {
+    let admin_token = config.admin_token.as_deref().ok_or(Error::NotFound)?;
+    Ok(check_admin(admin_token, headers)?)
+}
+
+// whether the request's bearer token is the admin token
+pub(crate) fn check_admin(admin_token: &str, headers: &HeaderMap) -> Result<(), AuthError> {
+    let bearer_token = match headers.get("Authorization").map(|token| token.to_str()) {
+        Some(Ok(token)) => token.trim_start_matches("Bearer "),
+        Some(Err(_)) => return Err(AuthError::Invalid),
+        None => return Err(AuthError::Missing),
+    };
+    // compare digests so the comparison time doesn't depend on how much of the admin token matched
+    match sha256::digest(bearer_token) == sha256::digest(admin_token) {
+        true => Ok(()),
+        false => Err(AuthError::Invalid),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LimitOverrideRequest {
+    pub limit: u64,
+    // defaults to a one minute window
+    pub window_seconds: Option<i64>,
+    // the route template the override applies to, every route if unset
+    pub route: Option<String>,
+    // the override lasts until it's removed if unset
+    pub ttl_seconds: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LimitOverrideResponse {
+    pub key: String,
+    pub route: Option<String>,
+    pub limit: u64,
+    pub window_seconds: i64,
+    pub expires_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LimitOverrideQuery {
+    pub route: Option<String>,
+}
+
+// PUT "/admin/limits/{key}", with the client key (e.g. "Bearer abc" or "api-key:abc") percent-encoded
+pub fn put_limit_override(rate_limiter: RateLimiter, config: Arc<Config>, headers: HeaderMap, key: String, request: LimitOverrideRequest) -> Result<warp::reply::Response, Error> {
+    let key = admin_client_key(&config, &headers, &key)?;
+    // both come from the body, so they have to be positive and fit in a Duration, as a parsed rate's window does
+    let positive = |seconds: Option<i64>| match seconds {
+        Some(seconds) => Duration::try_seconds(seconds).filter(|_| seconds > 0).map(Some),
+        None => Some(None),
+    };
+    let out_of_range = || Error::Validation("window_seconds and ttl_seconds should be positive and in range".to_string());
+    let (Some(window), Some(ttl)) = (positive(request.window_seconds), positive(request.ttl_seconds)) else {
+        return Err(out_of_range());
+    };
+    let expires_at = match ttl {
+        Some(ttl) => Some(Utc::now().checked_add_signed(ttl).ok_or_else(out_of_range)?),
+        None => None,
+    };
+
+    let mut rate_limit = RateLimit::new(request.limit);
+    if let Some(window) = window {
+        rate_limit.duration = window;
+    }
+    let limit_override = LimitOverride { rate_limit: rate_limit.clone(), expires_at };
+    rate_limiter.set_limit_override(request.route.as_deref(), &key, Some(limit_override))?;
+    tracing::info!(target: "audit", subject = %sha256::digest(key.as_str()), route = ?request.route, limit = request.limit, expires_at = ?expires_at, "set limit override");
+
+    Ok(replies::json(replies::status(StatusCode::OK), &LimitOverrideResponse {
+        key,
+        route: request.route,
+        limit: rate_limit.limit,
+        window_seconds: rate_limit.duration.num_seconds(),
+        expires_at: expires_at.map(|expires_at| expires_at.to_rfc3339()),
+    })?)
+}
+
+// DELETE "/admin/limits/{key}", with `?route=` for an override set on one route
+pub fn delete_limit_override(rate_limiter: RateLimiter, config: Arc<Config>, headers: HeaderMap, key: String, query: LimitOverrideQuery) -> Result<warp::reply::Response, Error> {
+    let key = admin_client_key(&config, &headers, &key)?;
+    rate_limiter.set_limit_override(query.route.as_deref(), &key, None)?;
+    tracing::info!(target: "audit", subject = %sha256::digest(key.as_str()), route = ?query.route, "removed limit override");
+    Ok(replies::empty(StatusCode::NO_CONTENT)?)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClientLimitsResponse {
+    pub key: String,
+    pub routes: Vec<ClientRouteLimits>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClientRouteLimits {
+    // a route template, or "group <name>" for the pool of a limit group
+    pub route: String,
+    pub limit: u64,
+    pub window_seconds: i64,
+    pub remaining: u64,
+    pub resets_at: String,
+    // whether the limit is one set through PUT /admin/limits/{key}
+    pub overridden: bool,
+}
+
+// GET "/admin/limits/{key}"
+// The client's quota on each limited route (a group's routes sharing one entry) as the
+// route level counts it, leaving out scope limits, schedules and deprecation.
+pub fn get_client_limits(rate_limiter: RateLimiter, config: Arc<Config>, headers: HeaderMap, key: String) -> Result<warp::reply::Response, Error> {
+    let key = admin_client_key(&config, &headers, &key)?;
+
+    let mut routes = Vec::new();
+    for (route, level, overridden) in client_route_levels(&rate_limiter, &config, &key) {
+        let (remaining, resets_at) = match rate_limiter.check_usage(std::slice::from_ref(&level), 0) {
+            Ok(usage) => usage,
+            Err(UsageError::RateLimited(err)) => (0, err.time_when_refreshed),
+            Err(UsageError::Store(err)) => return Err(Error::Store(err)),
+        };
+        routes.push(ClientRouteLimits {
+            route,
+            limit: level.rate_limit.limit,
+            window_seconds: level.rate_limit.duration.num_seconds(),
+            remaining,
+            resets_at: resets_at.to_rfc3339(),
+            overridden,
+        });
+    }
+    Ok(replies::json(replies::status(StatusCode::OK), &ClientLimitsResponse { key, routes })?)
+}
+
+// POST "/admin/limits/{key}/reset"
+// Gives the client its whole quota back on every route for the current window, e.g. after an
+// incident left it limited through no fault of its own. Overrides are left as they are.
+pub fn reset_client_limits(rate_limiter: RateLimiter, config: Arc<Config>, headers: HeaderMap, key: String) -> Result<warp::reply::Response, Error> {
+    let key = admin_client_key(&config, &headers, &key)?;
+    for (_, level, _) in client_route_levels(&rate_limiter, &config, &key) {
+        rate_limiter.reset_usage(&level)?;
+    }
+    tracing::info!(target: "audit", subject = %sha256::digest(key.as_str()), "reset usage");
+    Ok(replies::empty(StatusCode::NO_CONTENT)?)
+}
+
+// the route level counter of `client_key` on each limited route, by the name it's reported under,
+// and whether an override set its limit. Routes of a group share their group's counter.
+fn client_route_levels(rate_limiter: &RateLimiter, config: &Config, client_key: &str) -> Vec<(String, LevelLimit, bool)> {
+    let mut levels = BTreeMap::new();
+    for route in LIMITED_ROUTES.iter().copied().chain(config.routes.keys().map(String::as_str)) {
+        let Some(rate_limit) = route_rate_limit(config, route) else {
+            continue;
+        };
+        if let Some((name, group)) = config.group(route) {
+            levels.entry(format!("group {}", name)).or_insert_with(|| (LevelLimit::group(name, client_key, group.rate_limit()), false));
+            continue;
+        }
+        let limit_override = rate_limiter.limit_override(route, client_key);
+        let level = LevelLimit {
+            level: LimitLevel::Route,
+            key: route.to_string(),
+            client_key: client_key.to_string(),
+            rate_limit: limit_override.clone().unwrap_or(rate_limit),
+        };
+        levels.insert(route.to_string(), (level, limit_override.is_some()));
+    }
+    levels.into_iter().map(|(route, (level, overridden))| (route, level, overridden)).collect()
+}
+
+// the decoded client key of an admin request, the endpoints only exist when an admin token is configured
+fn admin_client_key(config: &Config, headers: &HeaderMap, key: &str) -> Result<String, Error> {
+    authorize_admin(config, headers)?;
+    match percent_encoding::percent_decode_str(key).decode_utf8() {
+        Ok(key) if !key.is_empty() => Ok(key.into_owned()),
+        _ => Err(Error::Validation("the key should be a non-empty, percent-encoded client key".to_string())),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatsResponse {
+    pub generated_at: String,
+    pub routes: BTreeMap<String, RouteStats>,
+}
+
+// GET "/admin/stats"
+pub fn get_admin_stats(rate_limiter: RateLimiter, config: Arc<Config>, headers: HeaderMap) -> Result<warp::reply::Response, Error> {
+    authorize_admin(&config, &headers)?;
+
+    let now = Utc::now();
+    Ok(replies::json(replies::status(StatusCode::OK), &StatsResponse { generated_at: now.to_rfc3339(), routes: rate_limiter.stats().snapshot(now) })?)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TopOffendersQuery {
+    // how many clients to list in each ranking
+    pub limit: Option<usize>,
+    // how far back to look, at most an hour
+    pub window_seconds: Option<i64>,
+}
+
+// GET "/admin/top-offenders"
+pub fn get_top_offenders(rate_limiter: RateLimiter, config: Arc<Config>, headers: HeaderMap, query: TopOffendersQuery) -> Result<warp::reply::Response, Error> {
+    authorize_admin(&config, &headers)?;
+
+    let limit = query.limit.unwrap_or(DEFAULT_TOP_OFFENDERS).min(MAX_TOP_OFFENDERS);
+    // only the last hour is kept, so clamping also keeps a huge or negative query from overflowing the Duration
+    let window_seconds = query.window_seconds.unwrap_or(DEFAULT_TOP_OFFENDERS_WINDOW_SECONDS).clamp(1, stats::RETENTION_SECONDS);
+    let window = Duration::seconds(window_seconds);
+    Ok(replies::json(replies::status(StatusCode::OK), &rate_limiter.stats().top_offenders(Utc::now(), window, limit))?)
+}
+
+// GET "/metrics"
+pub fn get_metrics(metrics: Arc<Metrics>, config: Arc<Config>, headers: HeaderMap) -> Result<warp::reply::Response, Error> {
+    let body = metrics.render().into_bytes();
+    let reply = replies::status(StatusCode::OK).header("Content-Type", "text/plain; version=0.0.4");
+    if !config.route(GET_METRICS_ROUTE).compression {
+        return Ok(reply.body(body.into())?);
+    }
+
+    let response = match compression::encode_body(&headers, body) {
+        (Some(encoding), body) => reply
+            .header("Content-Encoding", encoding.as_str())
+            .header("Vary", "Accept-Encoding")
+            .body(body.into()),
+        (None, body) => reply.header("Vary", "Accept-Encoding").body(body.into()),
+    };
+    Ok(response?)
+}
+
+// what a request is counted against
+#[derive(Debug, Clone)]
+pub struct LimitedRoute {
+    // route template, used to look up per-route config
+    pub route: String,
+    // usage is counted per key (and client key), which is the route unless a suffix narrows it down
+    pub key: String,
+    pub rate_limit: RateLimit,
+    pub cost: u64,
+}
+
+impl LimitedRoute {
+    pub fn new(route: &str, rate_limit: RateLimit) -> Self {
+        LimitedRoute { route: route.to_string(), key: route.to_string(), rate_limit, cost: 1 }
+    }
+
+    pub fn with_key_suffix(mut self, suffix: &str) -> Self {
+        self.key.push_str(suffix);
+        self
+    }
+
+    pub fn with_cost(mut self, cost: u64) -> Self {
+        self.cost = cost;
+        self
+    }
+
+    // reads and writes to a template matching any method are counted apart
+    pub fn with_method_default(mut self, class: &str, rate_limit: RateLimit) -> Self {
+        self.key = format!("{} {}", self.key, class);
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    // counts the request against another route template instead, keeping any key suffix
+    pub fn with_route(mut self, route: &str, rate_limit: RateLimit) -> Self {
+        self.key = format!("{}{}", route, &self.key[self.route.len()..]);
+        self.route = route.to_string();
+        self.rate_limit = rate_limit;
+        self
+    }
+}
+
+// requests from API keys with a signing secret have to carry a valid signature, checked before they cost anything
+fn check_signature(config: &Config, rate_limiter: &RateLimiter, request_info: &RequestInfo, body: &[u8]) -> Result<(), Error> {
+    signatures::check(config, rate_limiter, request_info, body).map_err(|err| {
+        tracing::info!(error = %err, "rejected an unsigned, badly signed or replayed request");
+        Error::Signature(err)
+    })
+}
+
+// a JSON body, once its signature has been checked
+fn signed_json<T: DeserializeOwned>(config: &Config, rate_limiter: &RateLimiter, request_info: &RequestInfo, body: &[u8]) -> Result<T, Error> {
+    check_signature(config, rate_limiter, request_info, body)?;
+    serde_json::from_slice(body).map_err(|err| Error::Validation(err.to_string()))
+}
+
+fn rate_limited_request(rate_limiter: RateLimiter, config: &Config, request_info: RequestInfo, limited_route: LimitedRoute) -> Result<warp::reply::Response, Error> {
+    rate_limited_request_with(rate_limiter, config, request_info, limited_route, |reply| {
+        reply.status(StatusCode::OK).body(Body::empty())
+    })
+}
+
+// `respond` only runs if the request is allowed, and is handed a builder that already carries the rate limiting headers
+fn rate_limited_request_with<F>(rate_limiter: RateLimiter, config: &Config, request_info: RequestInfo, limited_route: LimitedRoute, respond: F) -> Result<warp::reply::Response, Error>
+where
+    F: FnOnce(http::response::Builder) -> Result<warp::reply::Response, warp::http::Error>,
+{
+    let response = match check_rate_limit(rate_limiter.clone(), config, &request_info, limited_route) {
+        RateLimitDecision::Allowed(reply, charge) => settle_charge(&rate_limiter, config, charge, respond(reply)),
+        RateLimitDecision::Rejected(reply) => reply,
+    };
+    Ok(response?)
+}
+
+pub(crate) enum RateLimitDecision {
+    // carries the rate limiting headers for the reply, and what was charged for it unless the request bypassed the limits
+    Allowed(http::response::Builder, Option<Charge>),
+    Rejected(Result<warp::reply::Response, http::Error>),
+}
+
+// what an allowed request was charged, so it can be refunded
+pub(crate) struct Charge {
+    levels: Vec<LevelLimit>,
+    cost: u64,
+    charged_at: DateTime<Utc>,
+    // where the request is counted if it fails on the client's side
+    abuse: Option<LevelLimit>,
+}
+
+// A request that failed on our side (or upstream's) shouldn't cost the client
+// anything, and one the client got wrong counts against the route's abuse limit.
+// A 429 from upstream isn't the client's fault, so heavy users aren't punished twice.
+pub(crate) fn settle_charge(rate_limiter: &RateLimiter, config: &Config, charge: Option<Charge>, response: Result<warp::reply::Response, http::Error>) -> Result<warp::reply::Response, http::Error> {
+    let status = response.as_ref().map_or(StatusCode::INTERNAL_SERVER_ERROR, |response| response.status());
+    let Some(charge) = charge else {
+        return response;
+    };
+    if config.refund_server_errors && status.is_server_error() {
+        rate_limiter.refund(&charge.levels, charge.cost, charge.charged_at);
+    }
+    if let (true, Some(abuse)) = (status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS, &charge.abuse) {
+        rate_limiter.log_failure(abuse);
+    }
+    response
+}
+
+// Charges an allowed request `cost` in place of what it was charged, once it turns out to be cheaper
+// to answer than expected. The reply's remaining and cost headers are brought in line.
+fn reduce_charge(rate_limiter: &RateLimiter, config: &Config, reply: &mut http::response::Builder, charge: &mut Charge, cost: u64) {
+    let refund = charge.cost.saturating_sub(cost);
+    if refund == 0 {
+        return;
+    }
+    rate_limiter.refund(&charge.levels, refund, charge.charged_at);
+    charge.cost = cost;
+
+    let Some(headers) = reply.headers_mut() else {
+        return;
+    };
+    if let Ok(name) = HeaderName::from_bytes(config.headers.remaining.as_bytes()) {
+        let remaining = headers.get(&name).and_then(|remaining| remaining.to_str().ok()?.parse::<u64>().ok());
+        if let Some(remaining) = remaining {
+            headers.insert(name, HeaderValue::from(remaining.saturating_add(refund)));
+        }
+    }
+    if let Ok(name) = HeaderName::from_bytes(config.headers.cost.as_bytes()) {
+        headers.insert(name, HeaderValue::from(cost));
+    }
+}
+
+// counts the request, for handlers that can't respond synchronously
+pub(crate) fn check_rate_limit(rate_limiter: RateLimiter, config: &Config, request_info: &RequestInfo, mut limited_route: LimitedRoute) -> RateLimitDecision {
+    // requests the service has no limit of its own for, i.e. proxied ones or those passed through the middleware
+    let catch_all = limited_route.route == PROXY_ROUTE || built_in_limit(&limited_route.route).is_none();
+    // a template in config that matches this request more specifically than the handler's own route takes over its limits
+    if let Some(route) = config.match_route(request_info.method.as_str(), &request_info.path) {
+        if route != limited_route.route {
+            let rate_limit = config.rate_limit(route, limited_route.rate_limit.limit);
+            limited_route = limited_route.with_route(route, rate_limit);
+        }
+    }
+    // without a limit in config either, the method's class has a default in place of the catch-all's
+    if catch_all && !config.sets_limit(&limited_route.route) {
+        if let Some((class, rate_limit)) = config.method_defaults.for_method(request_info.method.as_str()) {
+            limited_route = limited_route.with_method_default(class, rate_limit);
+        }
+    }
+
+    // the request's span carries the outcome, e.g. to an OTLP backend
+    let span = tracing::Span::current();
+    span.record("route", limited_route.route.as_str());
+
+    // a client that has used up the abuse limit is turned away before its credentials are even looked at
+    let route_config = config.route(&limited_route.route);
+    let abuse = abuse_level(&route_config, &limited_route.route, request_info);
+    if let Some(abuse) = &abuse {
+        // the route's own limit still applies if the store can't tell
+        if let Err(UsageError::RateLimited(err)) = rate_limiter.check_usage(std::slice::from_ref(abuse), 1) {
+            span.record("decision", "rate_limited");
+            return RateLimitDecision::Rejected(replies::rate_limited(&config.headers, err, &abuse.rate_limit, &route_config, None, config.retry_after_jitter(&route_config)));
+        }
+    }
+
+    let ResolvedLimits { limited_route, route_config, client_key, levels, grace_ends_at, tenant } = match resolve_limits(&rate_limiter, config, request_info, limited_route) {
+        Ok(resolved) => resolved,
+        Err(err) => {
+            if let (true, Some(abuse)) = (err.status().is_client_error(), &abuse) {
+                rate_limiter.log_failure(abuse);
+            }
+            span.record("decision", "rejected");
+            return RateLimitDecision::Rejected(err.reply(config));
+        }
+    };
+    span.record("route", limited_route.route.as_str());
+
+    if let Some(bypass_token) = request_info.header(&config.headers.bypass) {
+        if rate_limiter.check_bypass(&limited_route.route, bypass_token).is_some() {
+            span.record("decision", "bypassed");
+            // a bypass lifts the limit, not the bill
+            if let Some(usage_ledger) = rate_limiter.usage_ledger() {
+                usage_ledger.record(tenant.as_deref(), &limited_route.route, false, Utc::now());
+            }
+            return RateLimitDecision::Allowed(replies::bypassed(&config.headers), None);
+        }
+    }
+
+    let LimitedRoute { route, cost, .. } = limited_route;
+    // the route's own limit, or its group's
+    let LevelLimit { level, key, rate_limit, .. } = levels[0].clone();
+    let started = std::time::Instant::now();
+    let usage = match levels.len() {
+        1 => match rate_limiter.clone().log_weighted_usage(&key, client_key.clone(), rate_limit.clone(), cost) {
+            Err(UsageError::Store(err)) => rate_limiter.apply_failure_policy(&key, client_key.clone(), rate_limit.clone(), cost, err),
+            Err(UsageError::RateLimited(err)) => Err(UsageError::RateLimited(err.with_level(level))),
+            usage => usage,
+        },
+        _ => rate_limiter.log_usage_levels(&levels, cost),
+    };
+    span.record("store_latency_ms", started.elapsed().as_secs_f64() * 1000.0);
+
+    match &usage {
+        Ok((requests_remaining, _)) => rate_limiter.stats().record(&route, &client_key, tenant.as_deref(), Some(*requests_remaining), Utc::now()),
+        Err(UsageError::RateLimited(_)) => rate_limiter.stats().record(&route, &client_key, tenant.as_deref(), None, Utc::now()),
+        Err(UsageError::Store(_)) => {}
+    }
+    if let (Some(usage_ledger), Ok(_) | Err(UsageError::RateLimited(_))) = (rate_limiter.usage_ledger(), &usage) {
+        usage_ledger.record(tenant.as_deref(), &route, usage.is_err(), Utc::now());
+    }
+
+    match usage {
+        Ok((requests_remaining, _)) => {
+            span.record("decision", "allowed");
+            span.record("remaining", requests_remaining);
+            let mut reply = replies::allowed(&config.headers, requests_remaining).header(config.headers.cost.as_str(), cost);
+            if let Some(warning) = soft_limit_warning(&route_config, &rate_limit, requests_remaining) {
+                rate_limiter.metrics().soft_limit_warnings.fetch_add(1, Ordering::Relaxed);
+                reply = reply.header(config.headers.warning.as_str(), warning);
+            }
+            if let Some(grace_ends_at) = grace_ends_at {
+                reply = reply.header(scopes::TOKEN_EXPIRING_HEADER, grace_ends_at.to_rfc3339());
+            }
+            if let Some(deprecation) = &route_config.deprecation {
+                reply = replies::deprecated(reply, deprecation);
+            }
+            if config.store.max_keys.is_some() {
+                // every level is asked, so none is left to be reported on a later request
+                let evicted: Vec<bool> = levels.iter().map(|level| rate_limiter.was_evicted(level)).collect();
+                if evicted.contains(&true) {
+                    reply = reply.header(config.headers.evicted.as_str(), "true");
+                }
+            }
+            let charge = Charge { levels, cost, charged_at: Utc::now(), abuse };
+            RateLimitDecision::Allowed(reply, Some(charge))
+        }
+        Err(UsageError::RateLimited(err)) => {
+            span.record("decision", "rate_limited");
+            span.record("remaining", 0);
+            let rate_limit = levels.iter().find(|level| level.level == err.level).map_or(&rate_limit, |level| &level.rate_limit);
+            RateLimitDecision::Rejected(replies::rate_limited(&config.headers, err, rate_limit, &route_config, group_name(config, &route), config.retry_after_jitter(&route_config)))
+        }
+        Err(UsageError::Store(err)) => {
+            span.record("decision", "store_unavailable");
+            RateLimitDecision::Rejected(Error::Store(err).reply(config))
+        }
+    }
+}
+
+// the route's abuse limit for the client making the request, if the route has one and the client can be told apart
+fn abuse_level(route_config: &RouteConfig, route: &str, request_info: &RequestInfo) -> Option<LevelLimit> {
+    let abuse_limit = route_config.abuse_limit.clone()?;
+    let client_key = route_config.key.build().extract(request_info)?;
+    Some(LevelLimit::abuse(route, &client_key, abuse_limit))
+}
+
+// the name of the group `route` is counted under, if any
+fn group_name<'a>(config: &'a Config, route: &str) -> Option<&'a str> {
+    config.group(route).map(|(name, _)| name)
+}
+
+// what to tell a client that has used up the route's soft limit, going by the tightest level's remaining quota
+fn soft_limit_warning(route_config: &RouteConfig, rate_limit: &RateLimit, requests_remaining: u64) -> Option<String> {
+    let percent = u128::from(route_config.soft_limit_percent?);
+    let used = rate_limit.limit.saturating_sub(requests_remaining);
+    (u128::from(used) * 100 >= u128::from(rate_limit.limit) * percent).then(|| format!("{} of {} used, slow down", used, rate_limit.limit))
+}
+
+// the limits a request answers to, once its key and scopes are known
+struct ResolvedLimits {
+    limited_route: LimitedRoute,
+    route_config: RouteConfig,
+    client_key: String,
+    // the route's own limit (or its group's) first
+    levels: Vec<LevelLimit>,
+    // when an expired token in its grace period stops being accepted
+    grace_ends_at: Option<DateTime<Utc>>,
+    // only known when something needed the token's claims
+    tenant: Option<String>,
+}
+
+// everything about a request's limits short of counting it, or why a request that could never be allowed is refused
+fn resolve_limits(rate_limiter: &RateLimiter, config: &Config, request_info: &RequestInfo, mut limited_route: LimitedRoute) -> Result<ResolvedLimits, Error> {
+    let route_config = config.route(&limited_route.route);
+    let client_key = match route_config.key.build().extract(request_info) {
+        Some(client_key) => client_key,
+        None => return Err(Error::Auth(AuthError::Missing)),
+    };
+
+    // scopes are checked before anything else so a forbidden request never costs quota
+    let scoped = !route_config.required_scopes.is_empty() || !route_config.scope_limits.is_empty();
+    let graced = config.jwt_secret.is_some() && config.jwt_expiry_grace_seconds > 0;
+    let claims = if !route_config.required_scopes.is_empty() {
+        scopes::authorize(config, request_info, &route_config.required_scopes)?
+    } else if scoped || graced || config.limits.tenant.is_some() || config.usage_export.is_some() {
+        // credentials that aren't accepted just go without scopes on routes that don't require any
+        scopes::authenticate(config, request_info).unwrap_or_default()
+    } else {
+        TokenClaims::default()
+    };
+    if scoped {
+        if let Some(limit) = claims.scopes.iter().filter_map(|scope| route_config.scope_limits.get(scope)).max() {
+            limited_route.rate_limit.limit = *limit;
+        }
+    }
+    // a deprecated route's own limit gives way to a stricter one, though an override can still exempt a client
+    if let Some(rate_limit) = route_config.deprecation.as_ref().and_then(|deprecation| deprecation.rate_limit(Utc::now())) {
+        limited_route.rate_limit = rate_limit;
+    }
+    // an override set through the admin API takes precedence over the configured limits
+    if let Some(rate_limit) = rate_limiter.limit_override(&limited_route.route, &client_key) {
+        limited_route.rate_limit = rate_limit;
+    }
+    // a schedule in effect, e.g. a maintenance window, applies whatever the token's scopes or overrides
+    if let Some(limit) = route_config.scheduled_limit(Utc::now()) {
+        limited_route.rate_limit.limit = limit;
+    }
+
+    let levels = limit_levels(config, &limited_route, &client_key, &claims);
+
+    // a request costing more than the whole window's quota could never be accepted, so don't make the client wait to find out
+    if let Some(level) = levels.iter().find(|level| limited_route.cost > level.rate_limit.limit) {
+        return Err(Error::CostTooHigh { cost: limited_route.cost, limit: level.rate_limit.limit });
+    }
+
+    Ok(ResolvedLimits { limited_route, route_config, client_key, levels, grace_ends_at: claims.grace_ends_at, tenant: claims.tenant })
+}
+
+// the route's own limit followed by whichever levels of the hierarchy are configured.
+// A grouped route's own limit is replaced by its group's, whatever scopes, overrides and schedules made it.
+fn limit_levels(config: &Config, limited_route: &LimitedRoute, client_key: &str, claims: &TokenClaims) -> Vec<LevelLimit> {
+    let mut levels = vec![match config.group(&limited_route.route) {
+        Some((name, group)) => LevelLimit::group(name, client_key, group.rate_limit()),
+        None => LevelLimit {
+            level: LimitLevel::Route,
+            key: limited_route.key.clone(),
+            client_key: client_key.to_string(),
+            rate_limit: limited_route.rate_limit.clone(),
+        },
+    }];
+    if let Some(token) = &config.limits.token {
+        levels.push(LevelLimit { level: LimitLevel::Token, key: "token".to_string(), client_key: client_key.to_string(), rate_limit: token.rate_limit() });
+    }
+    // tokens without a tenant only answer to the other levels
+    if let (Some(tenant_limit), Some(tenant)) = (&config.limits.tenant, &claims.tenant) {
+        levels.push(LevelLimit { level: LimitLevel::Tenant, key: "tenant".to_string(), client_key: tenant.clone(), rate_limit: tenant_limit.rate_limit() });
+    }
+    if let Some(global) = &config.limits.global {
+        levels.push(LevelLimit { level: LimitLevel::Global, key: "global".to_string(), client_key: String::new(), rate_limit: global.rate_limit() });
+    }
+    levels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(offset: usize) -> ListItemsQuery {
+        ListItemsQuery { offset, limit: Some(2), id_prefix: None }
+    }
+
+    #[test]
+    fn links_only_to_pages_that_exist() {
+        assert_eq!(pagination_links(&query(0), 2, 3), "</vault/items?offset=2&limit=2>; rel=\"next\"");
+        assert_eq!(pagination_links(&query(2), 2, 3), "</vault/items?offset=0&limit=2>; rel=\"prev\"");
+        assert_eq!(pagination_links(&query(0), 2, 2), "");
+
+        // an offset that would overflow past the last page just has nothing after it
+        assert!(!pagination_links(&query(usize::MAX), 2, 3).contains("next"));
+    }
+}
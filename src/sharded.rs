@@ -0,0 +1,258 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::store::{LimitOverride, MultiUsageResult, StoreError, UsageCharge, UsageResult, UsageStore};
+use crate::RateLimit;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ShardingConfig {
+    // points each shard gets on the hash ring, more spread keys more evenly
+    pub virtual_nodes: usize,
+    // how often every shard's health is checked, a shard that fails a call is marked down straight away
+    pub health_check_interval_ms: u64,
+}
+
+impl Default for ShardingConfig {
+    fn default() -> Self {
+        ShardingConfig {
+            virtual_nodes: 160,
+            health_check_interval_ms: 1000,
+        }
+    }
+}
+
+// Spreads keys over several stores (e.g. one per Redis endpoint) with a consistent
+// hash ring, so adding or removing a shard only moves the keys next to it. A key
+// whose shard is down is served by the next healthy shard on the ring until the
+// health check sees its own shard come back. Counters don't move with the key,
+// so a rerouted key starts its window afresh. This is for embedding the limiter
+// with several remote stores, the service only runs the in-memory store and
+// has no config for shards.
+#[derive(Debug)]
+pub struct ShardedStore<S: UsageStore> {
+    shared: Arc<Shards<S>>,
+}
+
+#[derive(Debug)]
+struct Shards<S> {
+    shards: Vec<Shard<S>>,
+    // hash of each virtual node to the index of its shard
+    ring: BTreeMap<u64, usize>,
+}
+
+#[derive(Debug)]
+struct Shard<S> {
+    name: String,
+    store: S,
+    healthy: AtomicBool,
+}
+
+impl<S: UsageStore + 'static> ShardedStore<S> {
+    // shards are named (e.g. by endpoint) so a shard keeps its place on the ring
+    // whatever order they're listed in. Starts a thread checking their health,
+    // which stops once the store is dropped
+    pub fn new(shards: Vec<(String, S)>, config: ShardingConfig) -> Self {
+        let mut ring = BTreeMap::new();
+        for (index, (name, _)) in shards.iter().enumerate() {
+            for node in 0..config.virtual_nodes.max(1) {
+                ring.insert(hash(&format!("{}#{}", name, node)), index);
+            }
+        }
+        let shards = shards.into_iter().map(|(name, store)| Shard { name, store, healthy: AtomicBool::new(true) }).collect();
+        let shared = Arc::new(Shards { shards, ring });
+
+        let checker = Arc::downgrade(&shared);
+        let interval = Duration::from_millis(config.health_check_interval_ms.max(1));
+        thread::Builder::new()
+            .name("shard-health".to_string())
+            .spawn(move || check_every(checker, interval))
+            .expect("could not start the shard health check thread");
+        ShardedStore { shared }
+    }
+}
+
+impl<S: UsageStore> ShardedStore<S> {
+    // the shard `key` is stored on right now
+    pub fn shard_for(&self, key: &str) -> Option<&str> {
+        let index = self.shared.candidates(key).next()?;
+        Some(&self.shared.shards[index].name)
+    }
+
+    // checks every shard now, returning the names of those that are down
+    pub fn check_health(&self) -> Vec<&str> {
+        self.shared.check_health();
+        self.shared.shards.iter().filter(|shard| !shard.healthy.load(Ordering::Relaxed)).map(|shard| shard.name.as_str()).collect()
+    }
+}
+
+fn check_every<S: UsageStore>(shards: Weak<Shards<S>>, interval: Duration) {
+    loop {
+        thread::sleep(interval);
+        let Some(shards) = shards.upgrade() else {
+            return;
+        };
+        shards.check_health();
+    }
+}
+
+impl<S: UsageStore> Shards<S> {
+    fn check_health(&self) {
+        for shard in &self.shards {
+            let healthy = shard.store.health_check().is_ok();
+            if shard.healthy.swap(healthy, Ordering::Relaxed) != healthy {
+                match healthy {
+                    true => tracing::info!(shard = %shard.name, "usage store shard is back up, its keys move back to it"),
+                    false => tracing::warn!(shard = %shard.name, "usage store shard is down, its keys move to the next shard"),
+                }
+            }
+        }
+    }
+
+    // healthy shards in the order `key` falls to them, starting with its own
+    fn candidates(&self, key: &str) -> impl Iterator<Item = usize> + '_ {
+        let point = hash(key);
+        let mut seen = vec![false; self.shards.len()];
+        self.ring
+            .range(point..)
+            .chain(self.ring.range(..point))
+            .map(|(_, index)| *index)
+            .filter(move |index| !std::mem::replace(&mut seen[*index], true))
+            .filter(|index| self.shards[*index].healthy.load(Ordering::Relaxed))
+    }
+
+    // runs `call` on the shard for `key`, moving on to the next one if it fails
+    fn call<T>(&self, key: &str, call: impl Fn(&S) -> Result<T, StoreError>) -> Result<T, StoreError> {
+        self.call_on(key, true, call)
+    }
+
+    // like `call` for calls that change a counter: a shard that timed out may have
+    // applied the change anyway, so it isn't made again on the next shard
+    fn charge<T>(&self, key: &str, call: impl Fn(&S) -> Result<T, StoreError>) -> Result<T, StoreError> {
+        self.call_on(key, false, call)
+    }
+
+    fn call_on<T>(&self, key: &str, retry_timeouts: bool, call: impl Fn(&S) -> Result<T, StoreError>) -> Result<T, StoreError> {
+        let mut last_err = StoreError::Unavailable("every usage store shard is down".to_string());
+        for index in self.candidates(key) {
+            let shard = &self.shards[index];
+            match call(&shard.store) {
+                Ok(result) => return Ok(result),
+                Err(err) => {
+                    tracing::warn!(shard = %shard.name, %err, "usage store shard failed, marking it down");
+                    shard.healthy.store(false, Ordering::Relaxed);
+                    if matches!(err, StoreError::Timeout) && !retry_timeouts {
+                        return Err(err);
+                    }
+                    last_err = err;
+                }
+            }
+        }
+        Err(last_err)
+    }
+}
+
+fn hash(value: &str) -> u64 {
+    let digest = Sha256::digest(value.as_bytes());
+    u64::from_be_bytes(digest[..8].try_into().expect("sha256 is 32 bytes"))
+}
+
+impl<S: UsageStore> UsageStore for ShardedStore<S> {
+    fn log_usage(&self, key: &str, rate_limit: &RateLimit, cost: u64, now: DateTime<Utc>) -> Result<UsageResult, StoreError> {
+        self.shared.charge(key, |shard| shard.log_usage(key, rate_limit, cost, now))
+    }
+
+    // The keys can live on different shards, so they can't be charged atomically.
+    // Every key is checked first and the charges already made are refunded if a
+    // later one is rejected, but concurrent requests can still slip in between
+    fn log_usage_many(&self, charges: &[UsageCharge<'_>], cost: u64, now: DateTime<Utc>) -> Result<MultiUsageResult, StoreError> {
+        for (index, charge) in charges.iter().enumerate() {
+            if let Err(err) = self.check_usage(charge.key, charge.rate_limit, cost, now)? {
+                return Ok(Err((index, err)));
+            }
+        }
+
+        let refund = |charged: &[UsageCharge<'_>]| charged.iter().try_for_each(|charge| self.refund(charge.key, charge.rate_limit, cost, now));
+        let mut usage = Vec::with_capacity(charges.len());
+        for (index, charge) in charges.iter().enumerate() {
+            match self.log_usage(charge.key, charge.rate_limit, cost, now) {
+                Ok(Ok(charged)) => usage.push(charged),
+                Ok(Err(err)) => {
+                    refund(&charges[..index])?;
+                    return Ok(Err((index, err)));
+                }
+                Err(err) => {
+                    refund(&charges[..index])?;
+                    return Err(err);
+                }
+            }
+        }
+        Ok(Ok(usage))
+    }
+
+    fn check_usage(&self, key: &str, rate_limit: &RateLimit, cost: u64, now: DateTime<Utc>) -> Result<UsageResult, StoreError> {
+        self.shared.call(key, |shard| shard.check_usage(key, rate_limit, cost, now))
+    }
+
+    fn refund(&self, key: &str, rate_limit: &RateLimit, cost: u64, charged_at: DateTime<Utc>) -> Result<(), StoreError> {
+        self.shared.charge(key, |shard| shard.refund(key, rate_limit, cost, charged_at))
+    }
+
+    fn reserve(&self, key: &str, rate_limit: &RateLimit, id: &str, amount: u64, expires_at: DateTime<Utc>, now: DateTime<Utc>) -> Result<UsageResult, StoreError> {
+        self.shared.charge(key, |shard| shard.reserve(key, rate_limit, id, amount, expires_at, now))
+    }
+
+    fn release(&self, key: &str, rate_limit: &RateLimit, id: &str, cost: u64, now: DateTime<Utc>) -> Result<Option<UsageResult>, StoreError> {
+        self.shared.charge(key, |shard| shard.release(key, rate_limit, id, cost, now))
+    }
+
+    fn set_limit_override(&self, key: &str, limit_override: Option<LimitOverride>) -> Result<(), StoreError> {
+        self.shared.call(key, |shard| shard.set_limit_override(key, limit_override.clone()))
+    }
+
+    fn limit_override(&self, key: &str, now: DateTime<Utc>) -> Result<Option<LimitOverride>, StoreError> {
+        self.shared.call(key, |shard| shard.limit_override(key, now))
+    }
+
+    // a key moving to another shard only takes its override along, its counter starts afresh there
+    fn migrate_key(&self, from: &str, to: &str) -> Result<(), StoreError> {
+        if self.shard_for(from) == self.shard_for(to) {
+            return self.shared.call(from, |shard| shard.migrate_key(from, to));
+        }
+        if let Some(limit_override) = self.limit_override(from, Utc::now())? {
+            if self.limit_override(to, Utc::now())?.is_none() {
+                self.set_limit_override(to, Some(limit_override))?;
+            }
+            self.set_limit_override(from, None)?;
+        }
+        Ok(())
+    }
+
+    fn record_nonce(&self, key: &str, expires_at: DateTime<Utc>, now: DateTime<Utc>) -> Result<bool, StoreError> {
+        self.shared.call(key, |shard| shard.record_nonce(key, expires_at, now))
+    }
+
+    // healthy as long as some shard is
+    fn health_check(&self) -> Result<(), StoreError> {
+        match self.shared.shards.iter().any(|shard| shard.store.health_check().is_ok()) {
+            true => Ok(()),
+            false => Err(StoreError::Unavailable("every usage store shard is down".to_string())),
+        }
+    }
+
+    // every shard has to load its own state, a down shard's keys would otherwise come back without it
+    fn load_state(&self) -> Result<(), StoreError> {
+        self.shared.shards.iter().try_for_each(|shard| shard.store.load_state())
+    }
+
+    fn was_evicted(&self, key: &str, now: DateTime<Utc>) -> bool {
+        self.shared.call(key, |shard| Ok(shard.was_evicted(key, now))).unwrap_or(false)
+    }
+}
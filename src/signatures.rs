@@ -0,0 +1,104 @@
+use std::fmt;
+
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::config::Config;
+use crate::key_extractor::RequestInfo;
+use crate::scopes;
+use crate::store::StoreError;
+use crate::RateLimiter;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// hex HMAC-SHA256 of "<timestamp>.<body>", or "<timestamp>.<nonce>.<body>" when
+// a nonce is sent, under the API key's signing secret
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+// unix seconds when the request was signed
+pub const TIMESTAMP_HEADER: &str = "X-Timestamp";
+// optional, a value the client never sends twice so a signed request can't be replayed even within the tolerance
+pub const NONCE_HEADER: &str = "X-Nonce";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SignatureError {
+    Missing,
+    // signed too long ago (or too far in the future), so possibly a replay
+    Stale,
+    Invalid,
+    // the nonce was already used
+    Replayed,
+    // the nonce couldn't be checked
+    Store(StoreError),
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::Missing => write!(f, "the request isn't signed"),
+            SignatureError::Stale => write!(f, "the request's timestamp is too far from now"),
+            SignatureError::Invalid => write!(f, "the request's signature doesn't match"),
+            SignatureError::Replayed => write!(f, "the request's nonce was already used"),
+            SignatureError::Store(err) => write!(f, "the request's nonce couldn't be checked: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+// what a client puts in X-Signature for a body sent with X-Timestamp set to `timestamp`, and X-Nonce to `nonce` if any
+pub fn sign(secret: &[u8], timestamp: i64, nonce: Option<&str>, body: &[u8]) -> String {
+    hex::encode(mac(secret, timestamp, nonce, body).finalize().into_bytes())
+}
+
+fn mac(secret: &[u8], timestamp: i64, nonce: Option<&str>, body: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    if let Some(nonce) = nonce {
+        mac.update(nonce.as_bytes());
+        mac.update(b".");
+    }
+    mac.update(body);
+    mac
+}
+
+pub fn verify(secret: &[u8], timestamp: Option<&str>, nonce: Option<&str>, signature: Option<&str>, body: &[u8], now: DateTime<Utc>, tolerance: Duration) -> Result<(), SignatureError> {
+    let (Some(timestamp), Some(signature)) = (timestamp, signature) else {
+        return Err(SignatureError::Missing);
+    };
+    let timestamp: i64 = timestamp.trim().parse().map_err(|_| SignatureError::Invalid)?;
+    let signed_at = DateTime::from_timestamp(timestamp, 0).ok_or(SignatureError::Invalid)?;
+    if (now - signed_at).abs() > tolerance {
+        return Err(SignatureError::Stale);
+    }
+
+    // GitHub style "sha256=" prefixes are accepted too
+    let signature = signature.trim().trim_start_matches("sha256=");
+    let signature = hex::decode(signature).map_err(|_| SignatureError::Invalid)?;
+    mac(secret, timestamp, nonce, body).verify_slice(&signature).map_err(|_| SignatureError::Invalid)
+}
+
+// Requests made with an API key that has a signing secret have to be signed,
+// anything else passes unchecked. A signed nonce is recorded, so the request
+// it came with is only accepted once.
+pub fn check(config: &Config, rate_limiter: &RateLimiter, request_info: &RequestInfo, body: &[u8]) -> Result<(), SignatureError> {
+    let token = request_info.authorization().unwrap_or_default();
+    let Some(secret) = scopes::api_key(config, token).and_then(|api_key| api_key.signing_secret.as_deref()) else {
+        return Ok(());
+    };
+    let now = Utc::now();
+    let tolerance = Duration::seconds(config.signature_tolerance_seconds.max(0));
+    let nonce = request_info.header(NONCE_HEADER);
+    verify(secret.as_bytes(), request_info.header(TIMESTAMP_HEADER), nonce, request_info.header(SIGNATURE_HEADER), body, now, tolerance)?;
+
+    let Some(nonce) = nonce else {
+        return Ok(());
+    };
+    // the timestamp can be up to `tolerance` early, so the request stays acceptable for at most twice that
+    match rate_limiter.record_nonce(token, nonce, now + tolerance * 2) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(SignatureError::Replayed),
+        Err(err) => Err(SignatureError::Store(err)),
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+
+use chrono::DateTime;
+
+use crate::algorithms::{FixedWindow, Limit, SlidingWindow, TokenBucket};
+use crate::config::Config;
+use crate::server::{self, PROXY_ROUTE};
+use crate::RateLimit;
+
+// the algorithms a trace is replayed under, in the order they're reported
+pub const ALGORITHMS: [Algorithm; 3] = [Algorithm::FixedWindow, Algorithm::SlidingWindow, Algorithm::TokenBucket];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    FixedWindow,
+    SlidingWindow,
+    TokenBucket,
+}
+
+impl Algorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Algorithm::FixedWindow => "fixed_window",
+            Algorithm::SlidingWindow => "sliding_window",
+            Algorithm::TokenBucket => "token_bucket",
+        }
+    }
+}
+
+// one request of a traffic trace
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceRecord {
+    pub timestamp_ms: i64,
+    // the client the request was counted under, e.g. its bearer token
+    pub key: String,
+    // a route template such as "POST /vault", or a request such as "GET /reports/7"
+    pub route: String,
+}
+
+#[derive(Debug)]
+pub enum SimulateError {
+    Read(io::Error),
+    // a line of the trace that isn't timestamp,key,route, numbered from 1
+    Parse { line: usize, reason: String },
+}
+
+impl fmt::Display for SimulateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimulateError::Read(err) => write!(f, "could not read trace: {}", err),
+            SimulateError::Parse { line, reason } => write!(f, "line {} of the trace: {}", line, reason),
+        }
+    }
+}
+
+impl std::error::Error for SimulateError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SimulateError::Read(err) => Some(err),
+            SimulateError::Parse { .. } => None,
+        }
+    }
+}
+
+// Reads a CSV trace of timestamp,key,route lines, with or without a header line.
+// Timestamps are milliseconds since the Unix epoch or RFC 3339.
+pub fn parse_trace(contents: &str) -> Result<Vec<TraceRecord>, SimulateError> {
+    let mut records = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let fields: Vec<&str> = line.split(',').map(|field| field.trim().trim_matches('"')).collect();
+        if line.trim().is_empty() || (index == 0 && fields[0] == "timestamp") {
+            continue;
+        }
+        let parse_error = |reason: &str| SimulateError::Parse { line: index + 1, reason: reason.to_string() };
+        let &[timestamp, key, route] = &fields[..] else {
+            return Err(parse_error("expected timestamp,key,route"));
+        };
+        let timestamp_ms = timestamp
+            .parse::<i64>()
+            .ok()
+            .or_else(|| DateTime::parse_from_rfc3339(timestamp).ok().map(|timestamp| timestamp.timestamp_millis()))
+            .ok_or_else(|| parse_error("timestamp should be milliseconds since the epoch or RFC 3339"))?;
+        if !route.contains(' ') {
+            return Err(parse_error("route should be a method and path, e.g. \"POST /vault\""));
+        }
+        records.push(TraceRecord { timestamp_ms, key: key.to_string(), route: route.to_string() });
+    }
+    Ok(records)
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RouteReport {
+    pub requests: u64,
+    // indexed like ALGORITHMS
+    pub limited: [u64; 3],
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    pub requests: u64,
+    // requests to routes nothing limits, allowed under every algorithm
+    pub unlimited: u64,
+    // keyed by what requests were counted under: a route template, or "group <name>"
+    pub routes: BTreeMap<String, RouteReport>,
+}
+
+impl Report {
+    pub fn limited(&self, algorithm: Algorithm) -> u64 {
+        self.routes.values().map(|route| route.limited[algorithm as usize]).sum()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "replayed {} requests, {} to routes without a limit", self.requests, self.unlimited)?;
+        let width = self.routes.keys().map(String::len).chain(["route".len()]).max().unwrap_or_default();
+        write!(f, "{:<width$}  {:>10}", "route", "requests")?;
+        for algorithm in ALGORITHMS {
+            write!(f, "  {:>22}", format!("{} limited", algorithm.as_str()))?;
+        }
+        writeln!(f)?;
+
+        let (total_route, total) = ("total".to_string(), RouteReport { requests: self.requests - self.unlimited, limited: ALGORITHMS.map(|algorithm| self.limited(algorithm)) });
+        for (route, report) in self.routes.iter().chain([(&total_route, &total)]) {
+            write!(f, "{:<width$}  {:>10}", route, report.requests)?;
+            for limited in report.limited {
+                let percent = if report.requests == 0 { 0.0 } else { limited as f64 * 100.0 / report.requests as f64 };
+                write!(f, "  {:>22}", format!("{} ({:.1}%)", limited, percent))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+// a client's state under one algorithm
+enum Counter {
+    FixedWindow(FixedWindow),
+    SlidingWindow(SlidingWindow),
+    TokenBucket(TokenBucket),
+}
+
+impl Counter {
+    fn new(algorithm: Algorithm, limit: &Limit, now_ms: i64) -> Self {
+        match algorithm {
+            Algorithm::FixedWindow => Counter::FixedWindow(FixedWindow::new(limit, now_ms)),
+            Algorithm::SlidingWindow => Counter::SlidingWindow(SlidingWindow::new(limit, now_ms)),
+            Algorithm::TokenBucket => Counter::TokenBucket(TokenBucket::new(limit, now_ms)),
+        }
+    }
+
+    fn try_acquire(&mut self, limit: &Limit, now_ms: i64) -> bool {
+        let decision = match self {
+            Counter::FixedWindow(window) => window.try_acquire(limit, 1, now_ms),
+            Counter::SlidingWindow(window) => window.try_acquire(limit, 1, now_ms),
+            Counter::TokenBucket(bucket) => bucket.try_acquire(limit, 1, now_ms),
+        };
+        decision.is_allowed()
+    }
+}
+
+// Replays `trace` in timestamp order against each algorithm, offline with nothing
+// shared with a running service. Each request costs 1 and is counted against its
+// route's limit, or its group's, from `config`. The token, tenant and global levels,
+// scopes, schedules and admin overrides aren't replayed.
+pub fn run(config: &Config, trace: &[TraceRecord]) -> Report {
+    let mut records: Vec<&TraceRecord> = trace.iter().collect();
+    records.sort_by_key(|record| record.timestamp_ms);
+
+    let mut report = Report::default();
+    let mut counters: HashMap<(String, &str), [Counter; 3]> = HashMap::new();
+    let mut limits: HashMap<&str, Option<(String, Limit)>> = HashMap::new();
+    for record in records {
+        report.requests += 1;
+        let limit = limits.entry(record.route.as_str()).or_insert_with(|| limit_for(config, &record.route).map(|(key, rate_limit)| (key, Limit::from(&rate_limit))));
+        let Some((route, limit)) = limit.as_ref() else {
+            report.unlimited += 1;
+            continue;
+        };
+
+        let counters = counters
+            .entry((route.clone(), record.key.as_str()))
+            .or_insert_with(|| ALGORITHMS.map(|algorithm| Counter::new(algorithm, limit, record.timestamp_ms)));
+        let route_report = report.routes.entry(route.clone()).or_default();
+        route_report.requests += 1;
+        for (counter, limited) in counters.iter_mut().zip(route_report.limited.iter_mut()) {
+            if !counter.try_acquire(limit, record.timestamp_ms) {
+                *limited += 1;
+            }
+        }
+    }
+    report
+}
+
+// what a request to `route` is counted under and its limit, None if nothing limits it
+fn limit_for(config: &Config, route: &str) -> Option<(String, RateLimit)> {
+    let (method, path) = route.split_once(' ')?;
+    let limit_of = |route: &str| match config.sets_limit(route) {
+        true => Some(config.rate_limit(route, 0)),
+        false => server::route_rate_limit(config, route),
+    };
+    // a request is counted under the configured template it matches
+    let known = limit_of(route).is_some() || config.group(route).is_some();
+    let route = if known { route } else { config.match_route(method, path).unwrap_or(route) };
+
+    if let Some((name, group)) = config.group(route) {
+        return Some((format!("group {}", name), group.rate_limit()));
+    }
+    if let Some(rate_limit) = limit_of(route) {
+        return Some((route.to_string(), rate_limit));
+    }
+    // anything else falls back to the method defaults, as unconfigured paths do in proxy mode
+    let (class, rate_limit) = config.method_defaults.for_method(method)?;
+    Some((format!("{} {}", PROXY_ROUTE, class), rate_limit))
+}
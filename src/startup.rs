@@ -0,0 +1,122 @@
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use warp::hyper::StatusCode;
+
+use crate::{replies, LevelLimit, LimitLevel, RateLimit, RateLimiter, UsageError};
+
+// synthetic requests checked between yields, so a long warmup doesn't hold up the runtime
+const WARMUP_BATCH: u64 = 1000;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StartupConfig {
+    // counters the in-memory store makes room for up front, so the first rush of clients doesn't grow its maps
+    pub expected_keys: usize,
+    // synthetic requests checked against every limited route before reporting ready, 0 skips the warmup.
+    // They're checked rather than charged, so no client's quota or usage figures are touched
+    pub warmup_requests: u64,
+    // how long to wait before trying the store again while it can't be reached
+    pub store_retry_ms: u64,
+}
+
+impl Default for StartupConfig {
+    fn default() -> Self {
+        StartupConfig { expected_keys: 0, warmup_requests: 0, store_retry_ms: 1000 }
+    }
+}
+
+// what the service is doing before it can take traffic, in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    #[default]
+    CheckingStore,
+    LoadingState,
+    WarmingUp,
+    Ready,
+}
+
+// Shared between the startup task and GET /readyz, which answers 503 until the phase is Ready.
+#[derive(Debug, Clone, Default)]
+pub struct Readiness {
+    phase: Arc<RwLock<Phase>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReadinessResponse {
+    pub phase: Phase,
+}
+
+impl Readiness {
+    pub fn new() -> Self {
+        Readiness::default()
+    }
+
+    pub fn phase(&self) -> Phase {
+        *self.phase.read().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn set(&self, phase: Phase) {
+        *self.phase.write().unwrap_or_else(|err| err.into_inner()) = phase;
+    }
+
+    // GET "/readyz"
+    pub fn reply(&self) -> Result<warp::reply::Response, warp::http::Error> {
+        let phase = self.phase();
+        let status = match phase {
+            Phase::Ready => StatusCode::OK,
+            _ => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        replies::json(replies::status(status), &ReadinessResponse { phase })
+    }
+}
+
+// Gets the limiter ready to serve, then flips `readiness` to Ready: waits for the
+// store to be reachable, has it load whatever state it persists, and optionally
+// runs synthetic traffic through the limiter so the first real requests don't
+// pay for cold caches and connection pools. `routes` are the limits warmed up.
+pub async fn run(config: StartupConfig, rate_limiter: RateLimiter, routes: Vec<(String, RateLimit)>, readiness: Readiness) {
+    let started = Instant::now();
+    let retry = Duration::from_millis(config.store_retry_ms.max(1));
+
+    readiness.set(Phase::CheckingStore);
+    while let Err(err) = rate_limiter.health_check() {
+        tracing::warn!(error = %err, "usage store can't be reached yet, not ready");
+        tokio::time::sleep(retry).await;
+    }
+
+    readiness.set(Phase::LoadingState);
+    while let Err(err) = rate_limiter.load_state() {
+        tracing::warn!(error = %err, "usage store couldn't load its state yet, not ready");
+        tokio::time::sleep(retry).await;
+    }
+
+    if config.warmup_requests > 0 && !routes.is_empty() {
+        readiness.set(Phase::WarmingUp);
+        warm_up(&rate_limiter, &routes, config.warmup_requests).await;
+    }
+
+    readiness.set(Phase::Ready);
+    tracing::info!(elapsed_ms = started.elapsed().as_millis() as u64, "ready to serve");
+}
+
+// checks `requests` made up requests spread over `routes`, each from its own client
+async fn warm_up(rate_limiter: &RateLimiter, routes: &[(String, RateLimit)], requests: u64) {
+    let mut failed = 0u64;
+    for request in 0..requests {
+        let (route, rate_limit) = &routes[(request % routes.len() as u64) as usize];
+        let level = LevelLimit { level: LimitLevel::Route, key: route.clone(), client_key: format!("warmup-{}", request), rate_limit: rate_limit.clone() };
+        if let Err(UsageError::Store(_)) = rate_limiter.check_usage(std::slice::from_ref(&level), 1) {
+            failed += 1;
+        }
+        if (request + 1) % WARMUP_BATCH == 0 {
+            tokio::task::yield_now().await;
+        }
+    }
+    // the store was reachable moments ago, so a warmup that fails is only worth a warning
+    if failed > 0 {
+        tracing::warn!(failed, requests, "some warmup requests failed");
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, Utc};
+use dashmap::DashMap;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+const BUCKET_SECONDS: i64 = 10;
+// an hour of buckets
+const BUCKETS: usize = 360;
+const BUCKETS_PER_MINUTE: usize = 6;
+// how far back top_offenders can look
+pub const RETENTION_SECONDS: i64 = BUCKETS as i64 * BUCKET_SECONDS;
+// remaining quota samples kept per bucket for the percentile
+const SAMPLES_PER_BUCKET: usize = 128;
+
+// Usage per route and client over the last hour, in ten second buckets, for
+// GET /admin/stats and /admin/top-offenders. Clients are only kept as the sha256 of their keys.
+#[derive(Debug, Default)]
+pub struct UsageStats {
+    routes: DashMap<String, Mutex<VecDeque<Bucket>>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    // unix time divided by BUCKET_SECONDS
+    index: i64,
+    requests: u64,
+    limited: u64,
+    clients: HashMap<String, ClientCounts>,
+    // a uniform sample of the remaining quota allowed requests were left with
+    remaining: Vec<u64>,
+    allowed: u64,
+}
+
+impl Bucket {
+    fn new(index: i64) -> Self {
+        Bucket { index, requests: 0, limited: 0, clients: HashMap::new(), remaining: Vec::new(), allowed: 0 }
+    }
+}
+
+#[derive(Debug, Default)]
+struct ClientCounts {
+    requests: u64,
+    limited: u64,
+    tenant: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RouteStats {
+    pub requests_last_minute: u64,
+    pub requests_last_hour: u64,
+    // distinct clients over the last hour
+    pub active_keys: usize,
+    // requests rejected with a 429 over the last hour
+    pub limited_requests: u64,
+    // None until an allowed request has been seen in the last hour
+    pub p95_remaining: Option<u64>,
+}
+
+// a client's usage across every route
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Offender {
+    pub key_sha256: String,
+    pub tenant: Option<String>,
+    pub requests: u64,
+    pub limited_requests: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TopOffenders {
+    // clients with the most 429s, leaving out those without any
+    pub by_limited_requests: Vec<Offender>,
+    pub by_requests: Vec<Offender>,
+}
+
+impl UsageStats {
+    pub fn new() -> Self {
+        UsageStats::default()
+    }
+
+    // counts a request to `route`, with the quota it left or None if it was rate limited
+    pub fn record(&self, route: &str, client_key: &str, tenant: Option<&str>, remaining: Option<u64>, now: DateTime<Utc>) {
+        let index = now.timestamp().div_euclid(BUCKET_SECONDS);
+        let series = self.routes.entry(route.to_string()).or_default();
+        let mut buckets = series.lock().unwrap();
+        if buckets.back().is_none_or(|bucket| bucket.index != index) {
+            buckets.push_back(Bucket::new(index));
+        }
+        while buckets.front().is_some_and(|bucket| bucket.index <= index - BUCKETS as i64) {
+            buckets.pop_front();
+        }
+
+        let bucket = buckets.back_mut().expect("pushed above");
+        bucket.requests += 1;
+        let client = bucket.clients.entry(sha256::digest(client_key)).or_default();
+        client.requests += 1;
+        client.limited += u64::from(remaining.is_none());
+        if client.tenant.is_none() {
+            client.tenant = tenant.map(str::to_string);
+        }
+        match remaining {
+            Some(remaining) => {
+                bucket.allowed += 1;
+                // reservoir sampling keeps every allowed request equally likely to be in the sample
+                if bucket.remaining.len() < SAMPLES_PER_BUCKET {
+                    bucket.remaining.push(remaining);
+                } else {
+                    let slot = rand::thread_rng().gen_range(0..bucket.allowed) as usize;
+                    if slot < SAMPLES_PER_BUCKET {
+                        bucket.remaining[slot] = remaining;
+                    }
+                }
+            }
+            None => bucket.limited += 1,
+        }
+    }
+
+    // every route seen in the last hour
+    pub fn snapshot(&self, now: DateTime<Utc>) -> BTreeMap<String, RouteStats> {
+        let index = now.timestamp().div_euclid(BUCKET_SECONDS);
+        self.routes
+            .iter()
+            .filter_map(|series| {
+                let buckets = series.value().lock().unwrap();
+                let hour: Vec<&Bucket> = buckets.iter().filter(|bucket| bucket.index > index - BUCKETS as i64).collect();
+                if hour.is_empty() {
+                    return None;
+                }
+
+                let mut remaining: Vec<u64> = hour.iter().flat_map(|bucket| bucket.remaining.iter().copied()).collect();
+                remaining.sort_unstable();
+                let stats = RouteStats {
+                    requests_last_minute: hour.iter().filter(|bucket| bucket.index > index - BUCKETS_PER_MINUTE as i64).map(|bucket| bucket.requests).sum(),
+                    requests_last_hour: hour.iter().map(|bucket| bucket.requests).sum(),
+                    active_keys: hour.iter().flat_map(|bucket| bucket.clients.keys()).collect::<HashSet<_>>().len(),
+                    limited_requests: hour.iter().map(|bucket| bucket.limited).sum(),
+                    p95_remaining: percentile(&remaining, 95),
+                };
+                Some((series.key().clone(), stats))
+            })
+            .collect()
+    }
+
+    // the `count` clients with the most 429s and with the most requests over the last `window`, up to an hour
+    pub fn top_offenders(&self, now: DateTime<Utc>, window: Duration, count: usize) -> TopOffenders {
+        let index = now.timestamp().div_euclid(BUCKET_SECONDS);
+        let buckets = (window.num_seconds() / BUCKET_SECONDS).clamp(1, BUCKETS as i64);
+
+        let mut clients: HashMap<String, Offender> = HashMap::new();
+        for series in self.routes.iter() {
+            let series = series.value().lock().unwrap();
+            for bucket in series.iter().filter(|bucket| bucket.index > index - buckets) {
+                for (key_sha256, counts) in &bucket.clients {
+                    let offender = clients.entry(key_sha256.clone()).or_insert_with(|| Offender {
+                        key_sha256: key_sha256.clone(),
+                        tenant: None,
+                        requests: 0,
+                        limited_requests: 0,
+                    });
+                    offender.requests += counts.requests;
+                    offender.limited_requests += counts.limited;
+                    if offender.tenant.is_none() {
+                        offender.tenant = counts.tenant.clone();
+                    }
+                }
+            }
+        }
+
+        let mut by_limited_requests: Vec<Offender> = clients.values().filter(|offender| offender.limited_requests > 0).cloned().collect();
+        by_limited_requests.sort_by(|a, b| b.limited_requests.cmp(&a.limited_requests).then_with(|| a.key_sha256.cmp(&b.key_sha256)));
+        by_limited_requests.truncate(count);
+        let mut by_requests: Vec<Offender> = clients.into_values().collect();
+        by_requests.sort_by(|a, b| b.requests.cmp(&a.requests).then_with(|| a.key_sha256.cmp(&b.key_sha256)));
+        by_requests.truncate(count);
+        TopOffenders { by_limited_requests, by_requests }
+    }
+}
+
+// nearest rank percentile of sorted values
+fn percentile(sorted: &[u64], percent: usize) -> Option<u64> {
+    let rank = (sorted.len() * percent).div_ceil(100);
+    sorted.get(rank.max(1) - 1).copied()
+}
@@ -0,0 +1,486 @@
+use std::fmt;
+use std::mem::size_of;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use serde::Deserialize;
+
+use crate::algorithms::{FixedWindow, Limit};
+use crate::metrics::Metrics;
+use crate::{RateLimit, RateLimitedError};
+
+// expired nonces are swept out once every this many are recorded
+const NONCE_SWEEP_INTERVAL: usize = 1024;
+// a full store evicts this share of max_keys at once, so it isn't scanned again for every new key
+const EVICTION_BATCH_DIVISOR: usize = 100;
+// roughly what a counter costs, keys are hex sha256 hashes. Reservations and the map's own overhead aren't counted
+const COUNTER_BYTES: usize = 64 + size_of::<String>() + size_of::<Counter>();
+
+// requests remaining and when the window resets, or the error saying when it will
+pub type UsageResult = Result<(u64, DateTime<Utc>), RateLimitedError>;
+// the usage of every key in order, or the index of the first key that couldn't afford the cost
+pub type MultiUsageResult = Result<Vec<(u64, DateTime<Utc>)>, (usize, RateLimitedError)>;
+
+// one of the counters a multi-key call charges
+#[derive(Debug, Clone, Copy)]
+pub struct UsageCharge<'a> {
+    pub key: &'a str,
+    pub rate_limit: &'a RateLimit,
+}
+
+// a limit set for one client through the admin API, in place of the route's own
+#[derive(Debug, Clone)]
+pub struct LimitOverride {
+    pub rate_limit: RateLimit,
+    // None for overrides that last until they're removed
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+// Where usage counters live. Keys are already hashed by the RateLimiter, and
+// implementations must apply each call atomically per key.
+pub trait UsageStore: fmt::Debug + Send + Sync {
+    fn log_usage(&self, key: &str, rate_limit: &RateLimit, cost: u64, now: DateTime<Utc>) -> Result<UsageResult, StoreError>;
+
+    // charges `cost` to every key as one transaction: either all of them are
+    // charged, or (if any would go over its limit) none are
+    fn log_usage_many(&self, charges: &[UsageCharge<'_>], cost: u64, now: DateTime<Utc>) -> Result<MultiUsageResult, StoreError>;
+
+    // whether `cost` would be allowed right now, without charging it. Reports
+    // the requests currently remaining, which a fresh window counts as the full limit
+    fn check_usage(&self, key: &str, rate_limit: &RateLimit, cost: u64, now: DateTime<Utc>) -> Result<UsageResult, StoreError>;
+
+    // gives back `cost` charged at `charged_at`, but only to the window it was
+    // charged in and never past the limit, so a refund can't carry into a fresh window
+    fn refund(&self, key: &str, rate_limit: &RateLimit, cost: u64, charged_at: DateTime<Utc>) -> Result<(), StoreError>;
+
+    // sets `amount` aside for reservation `id` until `expires_at` unless it is
+    // released first. Held quota can't be spent by other requests, in this window or the next
+    fn reserve(&self, key: &str, rate_limit: &RateLimit, id: &str, amount: u64, expires_at: DateTime<Utc>, now: DateTime<Utc>) -> Result<UsageResult, StoreError>;
+
+    // releases reservation `id` and charges `cost` in its place, 0 cancels it.
+    // None if the key holds no such reservation, e.g. because it expired
+    fn release(&self, key: &str, rate_limit: &RateLimit, id: &str, cost: u64, now: DateTime<Utc>) -> Result<Option<UsageResult>, StoreError>;
+
+    // replaces the limit override stored under `key`, None removes it. Overrides
+    // live with the counters so every instance sharing the store applies them
+    fn set_limit_override(&self, key: &str, limit_override: Option<LimitOverride>) -> Result<(), StoreError>;
+
+    // the override stored under `key`, unless it has expired by `now`
+    fn limit_override(&self, key: &str, now: DateTime<Utc>) -> Result<Option<LimitOverride>, StoreError>;
+
+    // moves the counter and override stored under `from` to `to`, e.g. once keys
+    // are hashed with a new secret. Anything already under `to` wins, and `from` is dropped either way
+    fn migrate_key(&self, from: &str, to: &str) -> Result<(), StoreError>;
+
+    // remembers nonce `key` until `expires_at`. False if it's already remembered,
+    // i.e. the request carrying it is a replay
+    fn record_nonce(&self, key: &str, expires_at: DateTime<Utc>, now: DateTime<Utc>) -> Result<bool, StoreError>;
+
+    // whether the store can be reached, for stores that can go down independently of the service
+    fn health_check(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    // whether `key`'s counter was evicted part way through its window since this
+    // was last asked, i.e. its count started over early. Only stores with a cap on keys evict
+    fn was_evicted(&self, _key: &str, _now: DateTime<Utc>) -> bool {
+        false
+    }
+
+    // loads whatever state the store persists, e.g. from a snapshot, before the
+    // service reports ready. Called once at startup, after health_check passes
+    fn load_state(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+}
+
+// so a store shared through an Arc can be wrapped again, e.g. by region::Region
+impl<S: UsageStore + ?Sized> UsageStore for Arc<S> {
+    fn log_usage(&self, key: &str, rate_limit: &RateLimit, cost: u64, now: DateTime<Utc>) -> Result<UsageResult, StoreError> {
+        (**self).log_usage(key, rate_limit, cost, now)
+    }
+
+    fn log_usage_many(&self, charges: &[UsageCharge<'_>], cost: u64, now: DateTime<Utc>) -> Result<MultiUsageResult, StoreError> {
+        (**self).log_usage_many(charges, cost, now)
+    }
+
+    fn check_usage(&self, key: &str, rate_limit: &RateLimit, cost: u64, now: DateTime<Utc>) -> Result<UsageResult, StoreError> {
+        (**self).check_usage(key, rate_limit, cost, now)
+    }
+
+    fn refund(&self, key: &str, rate_limit: &RateLimit, cost: u64, charged_at: DateTime<Utc>) -> Result<(), StoreError> {
+        (**self).refund(key, rate_limit, cost, charged_at)
+    }
+
+    fn reserve(&self, key: &str, rate_limit: &RateLimit, id: &str, amount: u64, expires_at: DateTime<Utc>, now: DateTime<Utc>) -> Result<UsageResult, StoreError> {
+        (**self).reserve(key, rate_limit, id, amount, expires_at, now)
+    }
+
+    fn release(&self, key: &str, rate_limit: &RateLimit, id: &str, cost: u64, now: DateTime<Utc>) -> Result<Option<UsageResult>, StoreError> {
+        (**self).release(key, rate_limit, id, cost, now)
+    }
+
+    fn set_limit_override(&self, key: &str, limit_override: Option<LimitOverride>) -> Result<(), StoreError> {
+        (**self).set_limit_override(key, limit_override)
+    }
+
+    fn limit_override(&self, key: &str, now: DateTime<Utc>) -> Result<Option<LimitOverride>, StoreError> {
+        (**self).limit_override(key, now)
+    }
+
+    fn migrate_key(&self, from: &str, to: &str) -> Result<(), StoreError> {
+        (**self).migrate_key(from, to)
+    }
+
+    fn record_nonce(&self, key: &str, expires_at: DateTime<Utc>, now: DateTime<Utc>) -> Result<bool, StoreError> {
+        (**self).record_nonce(key, expires_at, now)
+    }
+
+    fn health_check(&self) -> Result<(), StoreError> {
+        (**self).health_check()
+    }
+
+    fn was_evicted(&self, key: &str, now: DateTime<Utc>) -> bool {
+        (**self).was_evicted(key, now)
+    }
+
+    fn load_state(&self) -> Result<(), StoreError> {
+        (**self).load_state()
+    }
+}
+
+// what happens to a request when the limiter can't decide it, e.g. because the store is down
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailurePolicy {
+    // fail open, the request is allowed without being counted
+    Allow,
+    // fail closed, the request is rejected with a 503
+    #[default]
+    Reject,
+    // count the request in a process-local store until the backing store recovers
+    Local,
+}
+
+// which counters make room once the in-memory store holds max_keys
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionPolicy {
+    // the counters used longest ago
+    #[default]
+    Lru,
+    // the counters whose windows reset soonest, so the fewest clients lose much of a window's count
+    SoonestReset,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StoreError {
+    Unavailable(String),
+    Timeout,
+    // the circuit breaker is open, so the store wasn't called at all
+    CircuitOpen,
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Unavailable(reason) => write!(f, "store unavailable: {}", reason),
+            StoreError::Timeout => write!(f, "store timed out"),
+            StoreError::CircuitOpen => write!(f, "store circuit breaker is open"),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+#[derive(Debug, Default)]
+pub struct InMemoryStore {
+    usage_counter: DashMap<String, Counter>,
+    // single key calls share this, multi-key calls take it exclusively so nothing changes between checking and charging their keys
+    transaction: RwLock<()>,
+    limit_overrides: DashMap<String, LimitOverride>,
+    // when each nonce can be forgotten
+    nonces: DashMap<String, DateTime<Utc>>,
+    nonces_recorded: AtomicUsize,
+    // unbounded unless set, see with_max_keys
+    max_keys: Option<usize>,
+    eviction_policy: EvictionPolicy,
+    // when each counter evicted part way through its window would have reset, until the key is next asked about
+    evicted: DashMap<String, i64>,
+    metrics: Option<Arc<Metrics>>,
+}
+
+#[derive(Debug, Clone)]
+struct Counter {
+    window: FixedWindow,
+    // for EvictionPolicy::Lru
+    used_at_ms: i64,
+    // reservations outlive the window they were made in, they hold back quota until released or expired
+    holds: Vec<Hold>,
+}
+
+#[derive(Debug, Clone)]
+struct Hold {
+    id: String,
+    amount: u64,
+    expires_at: DateTime<Utc>,
+}
+
+impl Counter {
+    fn new(rate_limit: &RateLimit, now: DateTime<Utc>) -> Self {
+        Counter { window: FixedWindow::new(&Limit::from(rate_limit), now.timestamp_millis()), used_at_ms: now.timestamp_millis(), holds: Vec::new() }
+    }
+
+    // starts a new window if the current one is over, and lets go of expired reservations
+    fn refresh(&mut self, rate_limit: &RateLimit, now: DateTime<Utc>) {
+        self.window.refresh(&Limit::from(rate_limit), now.timestamp_millis());
+        self.holds.retain(|hold| hold.expires_at >= now);
+        self.used_at_ms = now.timestamp_millis();
+    }
+
+    // what requests can still spend, once reservations are set aside
+    fn available(&self) -> u64 {
+        let held = self.holds.iter().fold(0u64, |held, hold| held.saturating_add(hold.amount));
+        self.window.remaining.saturating_sub(held)
+    }
+
+    fn resets_at(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp_millis(self.window.resets_at_ms).unwrap_or(DateTime::<Utc>::MAX_UTC)
+    }
+}
+
+impl InMemoryStore {
+    pub fn new() -> Self {
+        InMemoryStore::default()
+    }
+
+    // makes room for `keys` counters up front, see StartupConfig::expected_keys
+    pub fn with_capacity(keys: usize) -> Self {
+        InMemoryStore { usage_counter: DashMap::with_capacity(keys), ..InMemoryStore::default() }
+    }
+
+    // Caps the counters kept at `max_keys`. Once full, counters whose windows are
+    // over are dropped first, then `policy` picks which to evict. An evicted
+    // client starts a fresh window, which `metrics` count along with the store's size.
+    pub fn with_max_keys(mut self, max_keys: usize, policy: EvictionPolicy, metrics: Arc<Metrics>) -> Self {
+        self.max_keys = Some(max_keys.max(1));
+        self.eviction_policy = policy;
+        self.metrics = Some(metrics);
+        self
+    }
+
+    // drops a key's counter, for keys that will never be used again
+    pub fn remove(&self, key: &str) {
+        self.usage_counter.remove(key);
+    }
+
+    // makes room before `key` gets a counter, if it would go over max_keys
+    fn make_room(&self, key: &str, now: DateTime<Utc>) {
+        let Some(max_keys) = self.max_keys else {
+            return;
+        };
+        let mut keys = self.usage_counter.len();
+        if keys >= max_keys && !self.usage_counter.contains_key(key) {
+            keys = self.evict(max_keys, now.timestamp_millis());
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.store_keys.store(keys as u64, Ordering::Relaxed);
+            metrics.store_memory_bytes.store((keys * COUNTER_BYTES) as u64, Ordering::Relaxed);
+        }
+    }
+
+    // brings the counters kept down below max_keys, returning how many are left
+    fn evict(&self, max_keys: usize, now_ms: i64) -> usize {
+        // a counter whose window is over holds nothing a new one wouldn't, unless it carries reservations
+        self.usage_counter.retain(|_, counter| counter.window.resets_at_ms >= now_ms || !counter.holds.is_empty());
+        let target = max_keys.saturating_sub(max_keys.div_ceil(EVICTION_BATCH_DIVISOR));
+        let keys = self.usage_counter.len();
+        if keys <= target {
+            return keys;
+        }
+
+        let mut candidates: Vec<(i64, String)> = self
+            .usage_counter
+            .iter()
+            .map(|counter| {
+                let rank = match self.eviction_policy {
+                    EvictionPolicy::Lru => counter.used_at_ms,
+                    EvictionPolicy::SoonestReset => counter.window.resets_at_ms,
+                };
+                (rank, counter.key().clone())
+            })
+            .collect();
+        let count = keys - target;
+        candidates.select_nth_unstable(count - 1);
+        for (_, key) in candidates.into_iter().take(count) {
+            if let Some((key, counter)) = self.usage_counter.remove(&key) {
+                self.evicted.insert(key, counter.window.resets_at_ms);
+            }
+        }
+        // the record of evictions is bounded too, by forgetting those whose windows are over
+        if self.evicted.len() > max_keys {
+            self.evicted.retain(|_, resets_at_ms| *resets_at_ms >= now_ms);
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.store_evictions.fetch_add(count as u64, Ordering::Relaxed);
+        }
+        tracing::warn!(evicted = count, max_keys, "usage store is full, evicted counters part way through their windows");
+        target
+    }
+
+    // a key's counter as of `now`, without changing it
+    fn peek(&self, key: &str, rate_limit: &RateLimit, now: DateTime<Utc>) -> Counter {
+        let mut counter = match self.usage_counter.get(key) {
+            Some(counter) => counter.clone(),
+            None => Counter::new(rate_limit, now),
+        };
+        counter.refresh(rate_limit, now);
+        counter
+    }
+
+    fn charge(&self, key: &str, rate_limit: &RateLimit, cost: u64, now: DateTime<Utc>) -> UsageResult {
+        self.make_room(key, now);
+        // the entry guard holds the shard lock, so concurrent requests for a key can't both spend the same unit
+        let mut counter = self.usage_counter
+            .entry(key.to_string())
+            .or_insert_with(|| Counter::new(rate_limit, now));
+        counter.refresh(rate_limit, now);
+
+        let available = counter.available();
+        if available >= cost {
+            counter.window.remaining -= cost;
+            Ok((available - cost, counter.resets_at()))
+        } else {
+            // rate limit has been reached
+            Err(RateLimitedError::new(counter.resets_at()))
+        }
+    }
+}
+
+impl UsageStore for InMemoryStore {
+    fn log_usage(&self, key: &str, rate_limit: &RateLimit, cost: u64, now: DateTime<Utc>) -> Result<UsageResult, StoreError> {
+        let _shared = self.transaction.read().unwrap();
+        Ok(self.charge(key, rate_limit, cost, now))
+    }
+
+    fn log_usage_many(&self, charges: &[UsageCharge<'_>], cost: u64, now: DateTime<Utc>) -> Result<MultiUsageResult, StoreError> {
+        let _exclusive = self.transaction.write().unwrap();
+
+        for (index, charge) in charges.iter().enumerate() {
+            let counter = self.peek(charge.key, charge.rate_limit, now);
+            if counter.available() < cost {
+                return Ok(Err((index, RateLimitedError::new(counter.resets_at()))));
+            }
+        }
+
+        // every key can afford it, and nothing else can run until we're done
+        let usage = charges
+            .iter()
+            .map(|charge| self.charge(charge.key, charge.rate_limit, cost, now).expect("checked above"))
+            .collect();
+        Ok(Ok(usage))
+    }
+
+    fn check_usage(&self, key: &str, rate_limit: &RateLimit, cost: u64, now: DateTime<Utc>) -> Result<UsageResult, StoreError> {
+        let counter = self.peek(key, rate_limit, now);
+        if counter.available() < cost {
+            return Ok(Err(RateLimitedError::new(counter.resets_at())));
+        }
+        Ok(Ok((counter.available(), counter.resets_at())))
+    }
+
+    fn refund(&self, key: &str, rate_limit: &RateLimit, cost: u64, charged_at: DateTime<Utc>) -> Result<(), StoreError> {
+        let _shared = self.transaction.read().unwrap();
+        if let Some(mut counter) = self.usage_counter.get_mut(key) {
+            counter.window.refund(&Limit::from(rate_limit), cost, charged_at.timestamp_millis());
+        }
+        Ok(())
+    }
+
+    fn reserve(&self, key: &str, rate_limit: &RateLimit, id: &str, amount: u64, expires_at: DateTime<Utc>, now: DateTime<Utc>) -> Result<UsageResult, StoreError> {
+        let _shared = self.transaction.read().unwrap();
+        self.make_room(key, now);
+        let mut counter = self.usage_counter
+            .entry(key.to_string())
+            .or_insert_with(|| Counter::new(rate_limit, now));
+        counter.refresh(rate_limit, now);
+
+        let available = counter.available();
+        if available < amount {
+            return Ok(Err(RateLimitedError::new(counter.resets_at())));
+        }
+        counter.holds.push(Hold { id: id.to_string(), amount, expires_at });
+        Ok(Ok((available - amount, counter.resets_at())))
+    }
+
+    fn release(&self, key: &str, rate_limit: &RateLimit, id: &str, cost: u64, now: DateTime<Utc>) -> Result<Option<UsageResult>, StoreError> {
+        let _shared = self.transaction.read().unwrap();
+        let mut counter = match self.usage_counter.get_mut(key) {
+            Some(counter) => counter,
+            None => return Ok(None),
+        };
+        counter.refresh(rate_limit, now);
+
+        let index = match counter.holds.iter().position(|hold| hold.id == id) {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        counter.holds.remove(index);
+        // the work is already done, so a cost over what's left is charged anyway and the window stays exhausted
+        counter.window.remaining = counter.window.remaining.saturating_sub(cost);
+        Ok(Some(Ok((counter.available(), counter.resets_at()))))
+    }
+
+    fn set_limit_override(&self, key: &str, limit_override: Option<LimitOverride>) -> Result<(), StoreError> {
+        match limit_override {
+            Some(limit_override) => {
+                self.limit_overrides.insert(key.to_string(), limit_override);
+            }
+            None => {
+                self.limit_overrides.remove(key);
+            }
+        }
+        Ok(())
+    }
+
+    fn limit_override(&self, key: &str, now: DateTime<Utc>) -> Result<Option<LimitOverride>, StoreError> {
+        // expired overrides are dropped the first time they're looked up
+        let expired = self.limit_overrides.remove_if(key, |_, limit_override| limit_override.expires_at.is_some_and(|expires_at| expires_at <= now));
+        if expired.is_some() {
+            return Ok(None);
+        }
+        Ok(self.limit_overrides.get(key).map(|limit_override| limit_override.clone()))
+    }
+
+    fn migrate_key(&self, from: &str, to: &str) -> Result<(), StoreError> {
+        if let Some((_, counter)) = self.usage_counter.remove(from) {
+            self.usage_counter.entry(to.to_string()).or_insert(counter);
+        }
+        if let Some((_, limit_override)) = self.limit_overrides.remove(from) {
+            self.limit_overrides.entry(to.to_string()).or_insert(limit_override);
+        }
+        Ok(())
+    }
+
+    fn record_nonce(&self, key: &str, expires_at: DateTime<Utc>, now: DateTime<Utc>) -> Result<bool, StoreError> {
+        if self.nonces_recorded.fetch_add(1, Ordering::Relaxed) % NONCE_SWEEP_INTERVAL == NONCE_SWEEP_INTERVAL - 1 {
+            self.nonces.retain(|_, expires_at| *expires_at > now);
+        }
+
+        // the entry guard holds the shard lock, so only one of two concurrent requests with a nonce gets through
+        match self.nonces.entry(key.to_string()) {
+            Entry::Occupied(nonce) if *nonce.get() > now => Ok(false),
+            entry => {
+                entry.insert(expires_at);
+                Ok(true)
+            }
+        }
+    }
+
+    fn was_evicted(&self, key: &str, now: DateTime<Utc>) -> bool {
+        self.evicted.remove(key).is_some_and(|(_, resets_at_ms)| resets_at_ms >= now.timestamp_millis())
+    }
+}
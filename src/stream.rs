@@ -0,0 +1,57 @@
+use chrono::Utc;
+use futures_util::{SinkExt, StreamExt};
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+use warp::ws::{Message, WebSocket};
+
+use crate::vault::Vault;
+use crate::{MessageDirection, RateLimit, RateLimiter};
+
+// close code for a client that broke the message rate (RFC 6455 policy violation)
+const POLICY_VIOLATION: u16 = 1008;
+
+// Pushes every vault change to `socket` until either side hangs up. Both
+// directions are held to `message_rate_limit`: outgoing events wait for the
+// next window, while a client sending too fast is disconnected.
+pub async fn vault_events(socket: WebSocket, vault: Vault, rate_limiter: RateLimiter, message_rate_limit: RateLimit) {
+    let connection_id = Uuid::new_v4().to_string();
+    let (mut sender, mut receiver) = socket.split();
+    let mut events = vault.subscribe();
+
+    loop {
+        tokio::select! {
+            message = receiver.next() => match message {
+                Some(Ok(message)) if message.is_close() => break,
+                // pings and pongs are answered by the websocket layer and aren't counted
+                Some(Ok(message)) if message.is_text() || message.is_binary() => {
+                    if rate_limiter.log_message(&connection_id, MessageDirection::Inbound, &message_rate_limit).is_err() {
+                        let _ = sender.send(Message::close_with(POLICY_VIOLATION, "message rate exceeded")).await;
+                        break;
+                    }
+                }
+                Some(Ok(_)) => {}
+                _ => break,
+            },
+            event = events.recv() => match event {
+                Ok(event) => {
+                    while let Err(err) = rate_limiter.log_message(&connection_id, MessageDirection::Outbound, &message_rate_limit) {
+                        // the window only refreshes once it is strictly in the past
+                        let wait = (err.time_when_refreshed - Utc::now()).to_std().unwrap_or_default();
+                        tokio::time::sleep(wait + std::time::Duration::from_millis(1)).await;
+                    }
+                    let json = match serde_json::to_string(&event) {
+                        Ok(json) => json,
+                        Err(_) => continue,
+                    };
+                    if sender.send(Message::text(json)).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(missed)) => tracing::warn!(connection_id, missed, "vault stream subscriber fell behind, events were dropped"),
+                Err(RecvError::Closed) => break,
+            },
+        }
+    }
+
+    rate_limiter.end_connection(&connection_id);
+}
@@ -0,0 +1,99 @@
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Sampler, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use serde::Deserialize;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use warp::hyper::HeaderMap;
+
+// where request spans are exported, logs always go to stdout
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    // an OTLP/HTTP traces endpoint, e.g. "http://tempo:4318/v1/traces", spans aren't exported unless set
+    pub otlp_endpoint: Option<String>,
+    // the fraction of requests traced, a sampled parent is always followed
+    pub sampling_ratio: f64,
+    pub service_name: String,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        TelemetryConfig {
+            otlp_endpoint: None,
+            sampling_ratio: 1.0,
+            service_name: "rate_limited_service".to_string(),
+        }
+    }
+}
+
+// Flushes spans still waiting to be exported once dropped, so keep it alive for as
+// long as the process serves requests.
+#[derive(Debug)]
+pub struct Telemetry {
+    provider: Option<TracerProvider>,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Some(provider) = self.provider.take() {
+            if let Err(err) = provider.shutdown() {
+                eprintln!("could not flush traces: {}", err);
+            }
+        }
+    }
+}
+
+// Installs the global tracing subscriber, logging to stdout and exporting spans over
+// OTLP when an endpoint is configured. Needs a tokio runtime to export on.
+pub fn init(config: &TelemetryConfig) -> Result<Telemetry, opentelemetry::trace::TraceError> {
+    let provider = match &config.otlp_endpoint {
+        Some(endpoint) => {
+            let exporter = SpanExporter::builder().with_http().with_endpoint(endpoint).build()?;
+            Some(
+                TracerProvider::builder()
+                    .with_batch_exporter(exporter, runtime::Tokio)
+                    .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sampling_ratio.clamp(0.0, 1.0)))))
+                    .with_resource(Resource::new([KeyValue::new("service.name", config.service_name.clone())]))
+                    .build(),
+            )
+        }
+        None => None,
+    };
+
+    // continue traces started upstream, from their W3C traceparent header
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let otlp = provider
+        .as_ref()
+        .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer("rate_limited_service")));
+    tracing_subscriber::registry()
+        .with(LevelFilter::INFO)
+        .with(tracing_subscriber::fmt::layer())
+        .with(otlp)
+        .init();
+    Ok(Telemetry { provider })
+}
+
+// makes `span` a child of the trace the request's traceparent header names, if any
+pub fn continue_trace(span: &tracing::Span, headers: &HeaderMap) {
+    let parent = global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+    span.set_parent(parent);
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key)?.to_str().ok()
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
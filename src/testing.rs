@@ -0,0 +1,251 @@
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use warp::hyper::{HeaderMap, StatusCode};
+use warp::{Filter, Rejection, Reply};
+
+use crate::config::Config;
+use crate::error::{self, Error};
+use crate::store::{LimitOverride, MultiUsageResult, StoreError, UsageCharge, UsageResult, UsageStore};
+use crate::{replies, server, RateLimit, RateLimitedError};
+
+// Faults to inject into the usage store, shared between a ChaosStore and the
+// endpoints controlling it. Only compiled in with the `testing` feature.
+#[derive(Debug, Clone, Default)]
+pub struct Chaos {
+    state: Arc<ChaosState>,
+}
+
+#[derive(Debug, Default)]
+struct ChaosState {
+    // how far the store's clock is ahead of the real one
+    clock_offset_ms: AtomicI64,
+    latency_ms: AtomicU64,
+    failing: AtomicBool,
+}
+
+impl Chaos {
+    pub fn new() -> Self {
+        Chaos::default()
+    }
+
+    // moves the limiter's clock forward, e.g. to the end of a window without waiting it out
+    pub fn advance_clock(&self, by: Duration) -> Duration {
+        let offset = self.state.clock_offset_ms.fetch_add(by.num_milliseconds(), Ordering::SeqCst) + by.num_milliseconds();
+        Duration::milliseconds(offset)
+    }
+
+    pub fn clock_offset(&self) -> Duration {
+        Duration::milliseconds(self.state.clock_offset_ms.load(Ordering::SeqCst))
+    }
+
+    // every store call takes at least this long
+    pub fn set_latency(&self, latency: std::time::Duration) {
+        self.state.latency_ms.store(u64::try_from(latency.as_millis()).unwrap_or(u64::MAX), Ordering::SeqCst);
+    }
+
+    // every store call fails while set, as if the store were down
+    pub fn set_failing(&self, failing: bool) {
+        self.state.failing.store(failing, Ordering::SeqCst);
+    }
+
+    // back to the real clock and a healthy store
+    pub fn reset(&self) {
+        self.state.clock_offset_ms.store(0, Ordering::SeqCst);
+        self.state.latency_ms.store(0, Ordering::SeqCst);
+        self.state.failing.store(false, Ordering::SeqCst);
+    }
+
+    fn snapshot(&self) -> ChaosResponse {
+        ChaosResponse {
+            clock_offset_seconds: self.clock_offset().num_seconds(),
+            latency_ms: self.state.latency_ms.load(Ordering::SeqCst),
+            failing: self.state.failing.load(Ordering::SeqCst),
+        }
+    }
+}
+
+// Wraps a store to apply the injected faults. The inner store sees times moved
+// forward by the clock offset, and the times it answers with are moved back, so
+// a fast-forwarded window resets early without Retry-After going wrong.
+#[derive(Debug)]
+pub struct ChaosStore<S> {
+    inner: S,
+    chaos: Chaos,
+}
+
+impl<S: UsageStore> ChaosStore<S> {
+    pub fn new(inner: S, chaos: Chaos) -> Self {
+        ChaosStore { inner, chaos }
+    }
+
+    // applies latency and errors, then calls the store with the clock offset
+    fn call<T>(&self, call: impl FnOnce(Duration) -> Result<T, StoreError>) -> Result<T, StoreError> {
+        let latency_ms = self.chaos.state.latency_ms.load(Ordering::SeqCst);
+        if latency_ms > 0 {
+            std::thread::sleep(std::time::Duration::from_millis(latency_ms));
+        }
+        if self.chaos.state.failing.load(Ordering::SeqCst) {
+            return Err(StoreError::Unavailable("fault injected for testing".to_string()));
+        }
+        call(self.chaos.clock_offset())
+    }
+}
+
+fn shift_usage(usage: UsageResult, offset: Duration) -> UsageResult {
+    usage
+        .map(|(remaining, resets_at)| (remaining, resets_at - offset))
+        .map_err(|err| shift_error(err, offset))
+}
+
+fn shift_error(err: RateLimitedError, offset: Duration) -> RateLimitedError {
+    RateLimitedError { time_when_refreshed: err.time_when_refreshed - offset, ..err }
+}
+
+fn shift_override(limit_override: LimitOverride, offset: Duration) -> LimitOverride {
+    LimitOverride { expires_at: limit_override.expires_at.map(|expires_at| expires_at + offset), ..limit_override }
+}
+
+impl<S: UsageStore> UsageStore for ChaosStore<S> {
+    fn log_usage(&self, key: &str, rate_limit: &RateLimit, cost: u64, now: DateTime<Utc>) -> Result<UsageResult, StoreError> {
+        self.call(|offset| Ok(shift_usage(self.inner.log_usage(key, rate_limit, cost, now + offset)?, offset)))
+    }
+
+    fn log_usage_many(&self, charges: &[UsageCharge<'_>], cost: u64, now: DateTime<Utc>) -> Result<MultiUsageResult, StoreError> {
+        self.call(|offset| {
+            let usage = self.inner.log_usage_many(charges, cost, now + offset)?;
+            Ok(usage
+                .map(|usage| usage.into_iter().map(|(remaining, resets_at)| (remaining, resets_at - offset)).collect())
+                .map_err(|(index, err)| (index, shift_error(err, offset))))
+        })
+    }
+
+    fn check_usage(&self, key: &str, rate_limit: &RateLimit, cost: u64, now: DateTime<Utc>) -> Result<UsageResult, StoreError> {
+        self.call(|offset| Ok(shift_usage(self.inner.check_usage(key, rate_limit, cost, now + offset)?, offset)))
+    }
+
+    fn refund(&self, key: &str, rate_limit: &RateLimit, cost: u64, charged_at: DateTime<Utc>) -> Result<(), StoreError> {
+        self.call(|offset| self.inner.refund(key, rate_limit, cost, charged_at + offset))
+    }
+
+    fn reserve(&self, key: &str, rate_limit: &RateLimit, id: &str, amount: u64, expires_at: DateTime<Utc>, now: DateTime<Utc>) -> Result<UsageResult, StoreError> {
+        self.call(|offset| Ok(shift_usage(self.inner.reserve(key, rate_limit, id, amount, expires_at + offset, now + offset)?, offset)))
+    }
+
+    fn release(&self, key: &str, rate_limit: &RateLimit, id: &str, cost: u64, now: DateTime<Utc>) -> Result<Option<UsageResult>, StoreError> {
+        self.call(|offset| Ok(self.inner.release(key, rate_limit, id, cost, now + offset)?.map(|usage| shift_usage(usage, offset))))
+    }
+
+    fn set_limit_override(&self, key: &str, limit_override: Option<LimitOverride>) -> Result<(), StoreError> {
+        self.call(|offset| self.inner.set_limit_override(key, limit_override.map(|limit_override| shift_override(limit_override, offset))))
+    }
+
+    fn limit_override(&self, key: &str, now: DateTime<Utc>) -> Result<Option<LimitOverride>, StoreError> {
+        self.call(|offset| Ok(self.inner.limit_override(key, now + offset)?.map(|limit_override| shift_override(limit_override, -offset))))
+    }
+
+    fn migrate_key(&self, from: &str, to: &str) -> Result<(), StoreError> {
+        self.call(|_| self.inner.migrate_key(from, to))
+    }
+
+    fn record_nonce(&self, key: &str, expires_at: DateTime<Utc>, now: DateTime<Utc>) -> Result<bool, StoreError> {
+        self.call(|offset| self.inner.record_nonce(key, expires_at + offset, now + offset))
+    }
+
+    fn health_check(&self) -> Result<(), StoreError> {
+        self.call(|_| self.inner.health_check())
+    }
+
+    fn load_state(&self) -> Result<(), StoreError> {
+        self.call(|_| self.inner.load_state())
+    }
+
+    fn was_evicted(&self, key: &str, now: DateTime<Utc>) -> bool {
+        self.inner.was_evicted(key, now + self.chaos.clock_offset())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AdvanceClockRequest {
+    pub seconds: i64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct StoreFaultsRequest {
+    pub latency_ms: u64,
+    pub failing: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChaosResponse {
+    pub clock_offset_seconds: i64,
+    pub latency_ms: u64,
+    pub failing: bool,
+}
+
+// The endpoints controlling `chaos`, for the admin token only. They don't exist
+// unless an admin token is configured.
+pub fn routes(chaos: Chaos, config: Arc<Config>) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let chaos_filter = warp::any().map(move || chaos.clone());
+    let config_filter = warp::any().map(move || config.clone());
+
+    let advance_clock_route = warp::path!("testing" / "clock" / "advance")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::header::headers_cloned())
+        .and(warp::body::json())
+        .and(config_filter.clone())
+        .and(chaos_filter.clone())
+        .map(|headers, request, config, chaos| error::answer(config, |config| advance_clock(chaos, config, headers, request)));
+
+    let put_store_faults_route = warp::path!("testing" / "store")
+        .and(warp::path::end())
+        .and(warp::put())
+        .and(warp::header::headers_cloned())
+        .and(warp::body::json())
+        .and(config_filter.clone())
+        .and(chaos_filter.clone())
+        .map(|headers, request, config, chaos| error::answer(config, |config| put_store_faults(chaos, config, headers, request)));
+
+    let delete_faults_route = warp::path!("testing")
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(warp::header::headers_cloned())
+        .and(config_filter)
+        .and(chaos_filter)
+        .map(|headers, config, chaos| error::answer(config, |config| delete_faults(chaos, config, headers)));
+
+    advance_clock_route.or(put_store_faults_route).or(delete_faults_route)
+}
+
+// POST "/testing/clock/advance"
+pub fn advance_clock(chaos: Chaos, config: Arc<Config>, headers: HeaderMap, request: AdvanceClockRequest) -> Result<warp::reply::Response, Error> {
+    server::authorize_admin(&config, &headers)?;
+    // the clock only moves forward, windows that have been reset can't be un-reset
+    if request.seconds <= 0 {
+        return Err(Error::Validation("seconds should be positive".to_string()));
+    }
+    chaos.advance_clock(Duration::seconds(request.seconds));
+    tracing::warn!(target: "audit", seconds = request.seconds, "advanced the limiter clock");
+    Ok(replies::json(replies::status(StatusCode::OK), &chaos.snapshot())?)
+}
+
+// PUT "/testing/store"
+pub fn put_store_faults(chaos: Chaos, config: Arc<Config>, headers: HeaderMap, request: StoreFaultsRequest) -> Result<warp::reply::Response, Error> {
+    server::authorize_admin(&config, &headers)?;
+    chaos.set_latency(std::time::Duration::from_millis(request.latency_ms));
+    chaos.set_failing(request.failing);
+    tracing::warn!(target: "audit", latency_ms = request.latency_ms, failing = request.failing, "injected usage store faults");
+    Ok(replies::json(replies::status(StatusCode::OK), &chaos.snapshot())?)
+}
+
+// DELETE "/testing"
+pub fn delete_faults(chaos: Chaos, config: Arc<Config>, headers: HeaderMap) -> Result<warp::reply::Response, Error> {
+    server::authorize_admin(&config, &headers)?;
+    chaos.reset();
+    tracing::warn!(target: "audit", "cleared injected faults");
+    Ok(replies::empty(StatusCode::NO_CONTENT)?)
+}
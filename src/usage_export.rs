@@ -0,0 +1,286 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use hmac::{Hmac, Mac};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use warp::http::{Method, Request, Uri};
+use warp::hyper::client::HttpConnector;
+use warp::hyper::{Body, Client};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const CSV_HEADER: &str = "hour,tenant,route,requests,limited_requests\n";
+// what SigV4 leaves unencoded in a path, '/' is kept apart as the segment separator
+const UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct UsageExportConfig {
+    // e.g. "https://s3.eu-west-1.amazonaws.com" or a MinIO server, objects are PUT to <endpoint>/<bucket>/<key>
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    // objects are written as <prefix><YYYY>/<MM>/<DD>/<HH>-<instance>.csv
+    pub prefix: String,
+    // tells apart the objects of instances counting the same hour, a random id if unset
+    pub instance: Option<String>,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    // how often hours that have ended are written out, and failed writes retried
+    pub interval_seconds: u64,
+}
+
+impl Default for UsageExportConfig {
+    fn default() -> Self {
+        UsageExportConfig {
+            endpoint: String::new(),
+            bucket: String::new(),
+            region: "us-east-1".to_string(),
+            prefix: "usage/".to_string(),
+            instance: None,
+            access_key_id: String::new(),
+            secret_access_key: String::new(),
+            interval_seconds: 60,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    InvalidUri(String),
+    Upload(warp::hyper::Error),
+    // the bucket answered, but not with a 2xx
+    Rejected(u16),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::InvalidUri(uri) => write!(f, "invalid object uri: {}", uri),
+            ExportError::Upload(err) => write!(f, "could not upload usage: {}", err),
+            ExportError::Rejected(status) => write!(f, "the bucket rejected the usage upload with a {}", status),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct UsageCounts {
+    pub requests: u64,
+    // of which rejected with a 429
+    pub limited_requests: u64,
+}
+
+// counts by tenant then route, clients without a tenant are counted under ""
+type HourUsage = BTreeMap<(String, String), UsageCounts>;
+
+// Request counts per tenant and route for each UTC hour, until they're exported
+#[derive(Debug, Default)]
+pub struct UsageLedger {
+    hours: Mutex<BTreeMap<DateTime<Utc>, HourUsage>>,
+}
+
+impl UsageLedger {
+    pub fn new() -> Self {
+        UsageLedger::default()
+    }
+
+    pub fn record(&self, tenant: Option<&str>, route: &str, limited: bool, now: DateTime<Utc>) {
+        let mut hours = self.hours.lock().unwrap();
+        let counts = hours.entry(hour_of(now)).or_default().entry((tenant.unwrap_or("").to_string(), route.to_string())).or_default();
+        counts.requests += 1;
+        counts.limited_requests += u64::from(limited);
+    }
+
+    // removes every hour that has ended by `now`
+    fn take_finished(&self, now: DateTime<Utc>) -> Vec<(DateTime<Utc>, HourUsage)> {
+        let mut hours = self.hours.lock().unwrap();
+        let current = hours.split_off(&hour_of(now));
+        std::mem::replace(&mut *hours, current).into_iter().collect()
+    }
+
+    // puts back an hour that couldn't be written, adding anything counted since
+    fn restore(&self, hour: DateTime<Utc>, usage: HourUsage) {
+        let mut hours = self.hours.lock().unwrap();
+        let restored = hours.entry(hour).or_default();
+        for (key, counts) in usage {
+            let restored = restored.entry(key).or_default();
+            restored.requests += counts.requests;
+            restored.limited_requests += counts.limited_requests;
+        }
+    }
+}
+
+fn hour_of(now: DateTime<Utc>) -> DateTime<Utc> {
+    now.duration_trunc(Duration::hours(1)).unwrap_or(now)
+}
+
+// Writes each hour's counts to an S3 compatible bucket as CSV once the hour is
+// over, one object per instance and hour. An instance shut down gracefully
+// writes out the hour it was stopped in as well, see ExportHandle::shutdown.
+#[derive(Debug)]
+pub struct UsageExporter {
+    ledger: Arc<UsageLedger>,
+    config: UsageExportConfig,
+    instance: String,
+    client: Client<hyper_rustls::HttpsConnector<HttpConnector>>,
+}
+
+impl UsageExporter {
+    pub fn new(config: &UsageExportConfig, ledger: Arc<UsageLedger>) -> Self {
+        let connector = hyper_rustls::HttpsConnectorBuilder::new().with_webpki_roots().https_or_http().enable_http1().build();
+        let instance = config.instance.clone().unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string());
+        UsageExporter { ledger, config: config.clone(), instance, client: Client::builder().build(connector) }
+    }
+
+    // exports finished hours every interval_seconds until the handle is shut down
+    pub fn spawn(self) -> ExportHandle {
+        let stop = Arc::new(Notify::new());
+        let task = tokio::spawn(self.run(stop.clone()));
+        ExportHandle { stop, task }
+    }
+
+    async fn run(self, stop: Arc<Notify>) {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(self.config.interval_seconds.max(1)));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    if let Err(err) = self.export(Utc::now()).await {
+                        tracing::warn!(error = %err, "usage export failed, retrying next interval");
+                    }
+                }
+                _ = stop.notified() => break,
+            }
+        }
+        if let Err(err) = self.export_all(Utc::now()).await {
+            tracing::error!(error = %err, "usage counted before shutting down couldn't be exported");
+        }
+    }
+
+    // Writes every hour that ended by `now`, returning how many objects were
+    // written. Hours that fail are kept to be tried again.
+    pub async fn export(&self, now: DateTime<Utc>) -> Result<usize, ExportError> {
+        self.write(self.ledger.take_finished(now), now).await
+    }
+
+    // Writes everything counted so far, for when the instance stops. The hour
+    // still running gets an object named after the minute and second it was
+    // stopped, since an instance restarted under the same name writes that hour again.
+    pub async fn export_all(&self, now: DateTime<Utc>) -> Result<usize, ExportError> {
+        self.write(self.ledger.take_finished(hour_of(now) + Duration::hours(1)), now).await
+    }
+
+    async fn write(&self, hours: Vec<(DateTime<Utc>, HourUsage)>, now: DateTime<Utc>) -> Result<usize, ExportError> {
+        let mut finished = VecDeque::from(hours);
+        let mut written = 0;
+        while let Some((hour, usage)) = finished.pop_front() {
+            let key = match hour < hour_of(now) {
+                true => format!("{}{}-{}.csv", self.config.prefix, hour.format("%Y/%m/%d/%H"), self.instance),
+                false => format!("{}{}-{}-{}.csv", self.config.prefix, hour.format("%Y/%m/%d/%H"), self.instance, now.format("%M%S")),
+            };
+            if let Err(err) = self.put(&key, to_csv(hour, &usage)).await {
+                self.ledger.restore(hour, usage);
+                for (hour, usage) in finished {
+                    self.ledger.restore(hour, usage);
+                }
+                return Err(err);
+            }
+            written += 1;
+        }
+        Ok(written)
+    }
+
+    async fn put(&self, key: &str, body: Vec<u8>) -> Result<(), ExportError> {
+        let path = format!("/{}/{}", self.config.bucket, key).split('/').map(|segment| percent_encoding::utf8_percent_encode(segment, UNRESERVED).to_string()).collect::<Vec<_>>().join("/");
+        let url = format!("{}{}", self.config.endpoint.trim_end_matches('/'), path);
+        let uri: Uri = url.parse().map_err(|_| ExportError::InvalidUri(url.clone()))?;
+        let host = uri.authority().map(|authority| authority.as_str().to_string()).ok_or_else(|| ExportError::InvalidUri(url.clone()))?;
+
+        let amz_date = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let payload_hash = hex::encode(Sha256::digest(&body));
+        let authorization = self.authorization(&path, &host, &amz_date, &payload_hash);
+
+        let request = Request::builder()
+            .method(Method::PUT)
+            .uri(uri)
+            .header("Content-Type", "text/csv")
+            .header("Host", host)
+            .header("X-Amz-Content-Sha256", payload_hash)
+            .header("X-Amz-Date", amz_date)
+            .header("Authorization", authorization)
+            .body(Body::from(body))
+            .map_err(|_| ExportError::InvalidUri(url))?;
+        let response = self.client.request(request).await.map_err(ExportError::Upload)?;
+        match response.status().is_success() {
+            true => Ok(()),
+            false => Err(ExportError::Rejected(response.status().as_u16())),
+        }
+    }
+
+    // an AWS Signature Version 4 Authorization header for a PUT of `path`
+    fn authorization(&self, path: &str, host: &str, amz_date: &str, payload_hash: &str) -> String {
+        let signed_headers = "content-type;host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "PUT\n{}\n\ncontent-type:text/csv\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            path, host, payload_hash, amz_date, signed_headers, payload_hash
+        );
+        let date = &amz_date[..8];
+        let scope = format!("{}/{}/s3/aws4_request", date, self.config.region);
+        let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", amz_date, scope, hex::encode(Sha256::digest(canonical_request.as_bytes())));
+
+        let mut key = format!("AWS4{}", self.config.secret_access_key).into_bytes();
+        for part in [date, self.config.region.as_str(), "s3", "aws4_request"] {
+            key = sign(&key, part.as_bytes());
+        }
+        let signature = hex::encode(sign(&key, string_to_sign.as_bytes()));
+        format!("AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}", self.config.access_key_id, scope, signed_headers, signature)
+    }
+}
+
+// stops a spawned exporter, see UsageExporter::spawn
+#[derive(Debug)]
+pub struct ExportHandle {
+    stop: Arc<Notify>,
+    task: JoinHandle<()>,
+}
+
+impl ExportHandle {
+    // stops exporting on the interval and writes out everything counted so far,
+    // including the hour still running, returning once that's been tried
+    pub async fn shutdown(self) {
+        self.stop.notify_one();
+        if let Err(err) = self.task.await {
+            tracing::error!(error = %err, "usage export stopped unexpectedly");
+        }
+    }
+}
+
+fn sign(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn to_csv(hour: DateTime<Utc>, usage: &HourUsage) -> Vec<u8> {
+    let mut csv = CSV_HEADER.to_string();
+    for ((tenant, route), counts) in usage {
+        csv.push_str(&format!("{},{},{},{},{}\n", hour.to_rfc3339(), csv_field(tenant), csv_field(route), counts.requests, counts.limited_requests));
+    }
+    csv.into_bytes()
+}
+
+// quoted if it holds anything that would break the row up
+fn csv_field(field: &str) -> String {
+    match field.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::encryption::{DecryptError, Keyring};
+
+// how many change events a slow subscriber can fall behind by before it starts missing them
+const EVENT_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VaultItem {
+    pub id: String,
+    pub data: serde_json::Value,
+}
+
+// a change to the vault, as pushed to /vault/stream subscribers
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum VaultEvent {
+    Put { item: VaultItem },
+    Deleted { id: String },
+}
+
+// in-memory item storage, ordered by id so paging through it is stable
+#[derive(Debug, Clone)]
+pub struct Vault {
+    items: Arc<RwLock<Items>>,
+    events: broadcast::Sender<VaultEvent>,
+}
+
+// item data as serialized JSON, encrypted with the item's id as associated data when there's a keyring
+#[derive(Debug, Default)]
+struct Items {
+    data: BTreeMap<String, Vec<u8>>,
+    keyring: Option<Keyring>,
+    // bumped by every put and delete
+    version: u64,
+}
+
+impl Items {
+    fn seal(&self, id: &str, data: &serde_json::Value) -> Vec<u8> {
+        let json = serde_json::to_vec(data).expect("JSON values always serialize");
+        match &self.keyring {
+            Some(keyring) => keyring.encrypt(&json, id.as_bytes()),
+            None => json,
+        }
+    }
+
+    fn open(&self, id: &str, sealed: &[u8]) -> Result<VaultItem, DecryptError> {
+        let json = match &self.keyring {
+            Some(keyring) => keyring.decrypt(sealed, id.as_bytes())?,
+            None => sealed.to_vec(),
+        };
+        let data = serde_json::from_slice(&json).map_err(|_| DecryptError::Malformed)?;
+        Ok(VaultItem { id: id.to_string(), data })
+    }
+
+    // items are only ever sealed with the current keyring, rotate re-encrypts them all when it changes
+    fn open_stored(&self, id: &str, sealed: &[u8]) -> VaultItem {
+        self.open(id, sealed).expect("vault items decrypt with the vault's own keyring")
+    }
+}
+
+impl Default for Vault {
+    fn default() -> Self {
+        Vault {
+            items: Arc::new(RwLock::new(Items::default())),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+        }
+    }
+}
+
+impl Vault {
+    pub fn new() -> Self {
+        Vault::default()
+    }
+
+    // a vault that encrypts item data at rest
+    pub fn with_keyring(keyring: Option<Keyring>) -> Self {
+        let vault = Vault::default();
+        vault.items.write().unwrap().keyring = keyring;
+        vault
+    }
+
+    // Switches to `keyring`, re-encrypting every item under its active key. Items are
+    // decrypted with the current keyring, so the new one can leave retired keys out.
+    // Nothing changes if any item fails to decrypt.
+    pub fn rotate(&self, keyring: Keyring) -> Result<usize, DecryptError> {
+        let mut items = self.items.write().unwrap();
+        let opened = items
+            .data
+            .iter()
+            .map(|(id, sealed)| items.open(id, sealed))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        items.keyring = Some(keyring);
+        for item in &opened {
+            let sealed = items.seal(&item.id, &item.data);
+            items.data.insert(item.id.clone(), sealed);
+        }
+        Ok(opened.len())
+    }
+
+    // what's actually held for an item, for checking it isn't stored in the clear
+    pub fn stored(&self, id: &str) -> Option<Vec<u8>> {
+        self.items.read().unwrap().data.get(id).cloned()
+    }
+
+    // changes whenever an item is put or deleted, so anything read from the vault can tell it's out of date
+    pub fn version(&self) -> u64 {
+        self.items.read().unwrap().version
+    }
+
+    // receives every change made after subscribing
+    pub fn subscribe(&self) -> broadcast::Receiver<VaultEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn put(&self, id: String, data: serde_json::Value) -> VaultItem {
+        let mut items = self.items.write().unwrap();
+        let sealed = items.seal(&id, &data);
+        items.data.insert(id.clone(), sealed);
+        items.version += 1;
+        drop(items);
+        let item = VaultItem { id, data };
+        // sending only fails when nobody is subscribed
+        let _ = self.events.send(VaultEvent::Put { item: item.clone() });
+        item
+    }
+
+    pub fn get(&self, id: &str) -> Option<VaultItem> {
+        let items = self.items.read().unwrap();
+        items.data.get(id).map(|sealed| items.open_stored(id, sealed))
+    }
+
+    pub fn create(&self, data: serde_json::Value) -> VaultItem {
+        self.put(Uuid::new_v4().to_string(), data)
+    }
+
+    pub fn delete(&self, id: &str) -> Option<VaultItem> {
+        let mut items = self.items.write().unwrap();
+        let deleted = items.data.remove(id).map(|sealed| items.open_stored(id, &sealed));
+        if deleted.is_some() {
+            items.version += 1;
+        }
+        drop(items);
+        if deleted.is_some() {
+            let _ = self.events.send(VaultEvent::Deleted { id: id.to_string() });
+        }
+        deleted
+    }
+
+    // returns one page of the items whose id starts with `id_prefix`, along with how many items matched in total
+    pub fn list(&self, id_prefix: &str, offset: usize, limit: usize) -> (Vec<VaultItem>, usize) {
+        let items = self.items.read().unwrap();
+        let matching = || items
+            .data
+            .range(id_prefix.to_string()..)
+            .take_while(|(id, _)| id.starts_with(id_prefix));
+
+        let page = matching().skip(offset).take(limit).map(|(id, sealed)| items.open_stored(id, sealed)).collect();
+        (page, matching().count())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_pages_of_the_items_under_a_prefix() {
+        let vault = Vault::new();
+        for id in ["a1", "a2", "a3", "b1"] {
+            vault.put(id.to_string(), serde_json::Value::Null);
+        }
+        let ids = |(page, total): (Vec<VaultItem>, usize)| (page.into_iter().map(|item| item.id).collect::<Vec<_>>(), total);
+
+        assert_eq!(ids(vault.list("a", 0, 2)), (vec!["a1".to_string(), "a2".to_string()], 3));
+        assert_eq!(ids(vault.list("a", 2, 2)), (vec!["a3".to_string()], 3));
+        assert_eq!(ids(vault.list("", 3, 10)), (vec!["b1".to_string()], 4));
+        assert_eq!(ids(vault.list("a", usize::MAX, 2)), (vec![], 3));
+    }
+}
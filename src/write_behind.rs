@@ -0,0 +1,260 @@
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::Deserialize;
+
+use crate::store::{LimitOverride, MultiUsageResult, StoreError, UsageCharge, UsageResult, UsageStore};
+use crate::{RateLimit, RateLimitedError};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WriteBehindConfig {
+    // how often usage decided locally is written to the backing store
+    pub flush_interval_ms: u64,
+    // the most a key's usage can get ahead of the backing store, a request that would
+    // take it past this waits for the write instead. 0 writes every request through
+    pub max_pending_cost: u64,
+}
+
+impl Default for WriteBehindConfig {
+    fn default() -> Self {
+        WriteBehindConfig {
+            flush_interval_ms: 100,
+            max_pending_cost: 100,
+        }
+    }
+}
+
+// Wraps a (typically remote) store so requests are decided against a local copy of
+// each key's counter, with their usage batched to the backing store in the background.
+// A key's first request in a window goes to the store to learn what others have used,
+// and every flush catches the copy up again. Between flushes other instances sharing
+// the store can't see this one's usage, so a window can be overspent by up to
+// max_pending_cost per instance. Calls other than plain charges and checks write
+// through.
+#[derive(Debug)]
+pub struct WriteBehindStore<S: UsageStore> {
+    shared: Arc<Shared<S>>,
+}
+
+#[derive(Debug)]
+struct Shared<S> {
+    inner: S,
+    config: WriteBehindConfig,
+    counters: DashMap<String, LocalCounter>,
+}
+
+#[derive(Debug)]
+struct LocalCounter {
+    rate_limit: RateLimit,
+    remaining: u64,
+    resets_at: DateTime<Utc>,
+    // charged here but not yet in the backing store
+    pending: u64,
+}
+
+impl<S: UsageStore + 'static> WriteBehindStore<S> {
+    // starts a thread flushing every flush_interval_ms, which stops once the store is dropped
+    pub fn new(inner: S, config: WriteBehindConfig) -> Self {
+        let interval = Duration::from_millis(config.flush_interval_ms.max(1));
+        let shared = Arc::new(Shared { inner, config, counters: DashMap::new() });
+        let flusher = Arc::downgrade(&shared);
+        thread::Builder::new()
+            .name("write-behind".to_string())
+            .spawn(move || flush_every(flusher, interval))
+            .expect("could not start the write-behind thread");
+        WriteBehindStore { shared }
+    }
+}
+
+impl<S: UsageStore> WriteBehindStore<S> {
+    // writes every key's pending usage to the backing store now
+    pub fn flush(&self) -> Result<(), StoreError> {
+        self.shared.flush_all(Utc::now())
+    }
+}
+
+fn flush_every<S: UsageStore>(shared: Weak<Shared<S>>, interval: Duration) {
+    loop {
+        thread::sleep(interval);
+        let Some(shared) = shared.upgrade() else {
+            return;
+        };
+        if let Err(err) = shared.flush_all(Utc::now()) {
+            tracing::warn!(%err, "could not write usage to the store, retrying on the next flush");
+        }
+    }
+}
+
+impl<S: UsageStore> Drop for WriteBehindStore<S> {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            tracing::warn!(%err, "usage not yet written to the store was lost");
+        }
+    }
+}
+
+impl<S: UsageStore> Shared<S> {
+    fn flush_all(&self, now: DateTime<Utc>) -> Result<(), StoreError> {
+        let keys: Vec<String> = self.counters.iter().filter(|counter| counter.pending > 0).map(|counter| counter.key().clone()).collect();
+        let mut result = Ok(());
+        for key in keys {
+            if let Err(err) = self.flush(&key, now) {
+                result = Err(err);
+            }
+        }
+        // counters for windows that are over have nothing left to tell us
+        self.counters.retain(|_, counter| counter.resets_at >= now || counter.pending > 0);
+        result
+    }
+
+    // writes a key's pending usage, then catches its counter up with what the store says is left
+    fn flush(&self, key: &str, now: DateTime<Utc>) -> Result<(), StoreError> {
+        let (rate_limit, pending) = match self.counters.get_mut(key) {
+            // usage from a window that's over would be charged to the next one
+            Some(mut counter) if counter.resets_at < now => {
+                counter.pending = 0;
+                return Ok(());
+            }
+            Some(mut counter) => (counter.rate_limit.clone(), std::mem::take(&mut counter.pending)),
+            None => return Ok(()),
+        };
+        if pending == 0 {
+            return Ok(());
+        }
+
+        // the counter isn't locked while the store is called, requests keep being decided against it
+        let usage = self.inner.log_usage(key, &rate_limit, pending, now);
+        let Some(mut counter) = self.counters.get_mut(key) else {
+            return usage.map(|_| ());
+        };
+        match usage {
+            Ok(Ok((remaining, resets_at))) => {
+                counter.remaining = remaining.saturating_sub(counter.pending);
+                counter.resets_at = resets_at;
+            }
+            // other instances used up the window first, the usage is dropped as it can't be charged
+            Ok(Err(err)) => {
+                counter.remaining = 0;
+                counter.resets_at = err.time_when_refreshed;
+            }
+            Err(err) => {
+                counter.pending = counter.pending.saturating_add(pending);
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    // charges the backing store directly and starts the key's local counter from its answer
+    fn write_through(&self, key: &str, rate_limit: &RateLimit, cost: u64, now: DateTime<Utc>) -> Result<UsageResult, StoreError> {
+        self.flush(key, now)?;
+        let usage = self.inner.log_usage(key, rate_limit, cost, now)?;
+        let (remaining, resets_at) = match &usage {
+            Ok(usage) => *usage,
+            Err(err) => (0, err.time_when_refreshed),
+        };
+        let pending = self.counters.get(key).map_or(0, |counter| counter.pending);
+        self.counters.insert(key.to_string(), LocalCounter { rate_limit: rate_limit.clone(), remaining, resets_at, pending });
+        Ok(usage)
+    }
+
+    // drops a key's local counter once its pending usage is written, so the next request asks the store
+    fn forget(&self, key: &str, now: DateTime<Utc>) -> Result<(), StoreError> {
+        self.flush(key, now)?;
+        self.counters.remove_if(key, |_, counter| counter.pending == 0);
+        Ok(())
+    }
+}
+
+impl<S: UsageStore> UsageStore for WriteBehindStore<S> {
+    fn log_usage(&self, key: &str, rate_limit: &RateLimit, cost: u64, now: DateTime<Utc>) -> Result<UsageResult, StoreError> {
+        let shared = &self.shared;
+        if let Some(mut counter) = shared.counters.get_mut(key) {
+            let current = counter.resets_at >= now && counter.rate_limit == *rate_limit;
+            if current && counter.pending.saturating_add(cost) <= shared.config.max_pending_cost {
+                if counter.remaining < cost {
+                    return Ok(Err(RateLimitedError::new(counter.resets_at)));
+                }
+                counter.remaining -= cost;
+                counter.pending += cost;
+                return Ok(Ok((counter.remaining, counter.resets_at)));
+            }
+        }
+        shared.write_through(key, rate_limit, cost, now)
+    }
+
+    fn log_usage_many(&self, charges: &[UsageCharge<'_>], cost: u64, now: DateTime<Utc>) -> Result<MultiUsageResult, StoreError> {
+        // the keys have to be charged together, so this one goes to the store
+        for charge in charges {
+            self.shared.forget(charge.key, now)?;
+        }
+        self.shared.inner.log_usage_many(charges, cost, now)
+    }
+
+    fn check_usage(&self, key: &str, rate_limit: &RateLimit, cost: u64, now: DateTime<Utc>) -> Result<UsageResult, StoreError> {
+        if let Some(counter) = self.shared.counters.get(key).filter(|counter| counter.resets_at >= now && counter.rate_limit == *rate_limit) {
+            if counter.remaining < cost {
+                return Ok(Err(RateLimitedError::new(counter.resets_at)));
+            }
+            return Ok(Ok((counter.remaining, counter.resets_at)));
+        }
+        self.shared.inner.check_usage(key, rate_limit, cost, now)
+    }
+
+    fn refund(&self, key: &str, rate_limit: &RateLimit, cost: u64, charged_at: DateTime<Utc>) -> Result<(), StoreError> {
+        // usage that hasn't reached the store yet can simply be taken back
+        if let Some(mut counter) = self.shared.counters.get_mut(key) {
+            if counter.pending >= cost && counter.rate_limit == *rate_limit && charged_at <= counter.resets_at {
+                counter.pending -= cost;
+                counter.remaining = counter.remaining.saturating_add(cost).min(rate_limit.limit);
+                return Ok(());
+            }
+        }
+        self.shared.forget(key, Utc::now())?;
+        self.shared.inner.refund(key, rate_limit, cost, charged_at)
+    }
+
+    fn reserve(&self, key: &str, rate_limit: &RateLimit, id: &str, amount: u64, expires_at: DateTime<Utc>, now: DateTime<Utc>) -> Result<UsageResult, StoreError> {
+        self.shared.forget(key, now)?;
+        self.shared.inner.reserve(key, rate_limit, id, amount, expires_at, now)
+    }
+
+    fn release(&self, key: &str, rate_limit: &RateLimit, id: &str, cost: u64, now: DateTime<Utc>) -> Result<Option<UsageResult>, StoreError> {
+        self.shared.forget(key, now)?;
+        self.shared.inner.release(key, rate_limit, id, cost, now)
+    }
+
+    fn set_limit_override(&self, key: &str, limit_override: Option<LimitOverride>) -> Result<(), StoreError> {
+        self.shared.inner.set_limit_override(key, limit_override)
+    }
+
+    fn limit_override(&self, key: &str, now: DateTime<Utc>) -> Result<Option<LimitOverride>, StoreError> {
+        self.shared.inner.limit_override(key, now)
+    }
+
+    fn migrate_key(&self, from: &str, to: &str) -> Result<(), StoreError> {
+        self.shared.forget(from, Utc::now())?;
+        self.shared.inner.migrate_key(from, to)
+    }
+
+    // nonces aren't usage, every instance has to see them straight away
+    fn record_nonce(&self, key: &str, expires_at: DateTime<Utc>, now: DateTime<Utc>) -> Result<bool, StoreError> {
+        self.shared.inner.record_nonce(key, expires_at, now)
+    }
+
+    fn health_check(&self) -> Result<(), StoreError> {
+        self.shared.inner.health_check()
+    }
+
+    fn load_state(&self) -> Result<(), StoreError> {
+        self.shared.inner.load_state()
+    }
+
+    fn was_evicted(&self, key: &str, now: DateTime<Utc>) -> bool {
+        self.shared.inner.was_evicted(key, now)
+    }
+}
@@ -0,0 +1,81 @@
+#![cfg(feature = "client")]
+
+use std::net::SocketAddr;
+
+use rate_limited_service::admin::{self, Command};
+use rate_limited_service::client::{AdminClient, ClientError};
+use rate_limited_service::config::Config;
+use rate_limited_service::server::POST_VAULT_ROUTE;
+use rate_limited_service::RateLimit;
+use reqwest::StatusCode;
+
+mod common;
+
+fn spawn() -> SocketAddr {
+    let mut config = Config::default();
+    config.admin_token = Some("admin".to_string());
+    common::spawn(config)
+}
+
+async fn post_vault(addr: SocketAddr, bearer_token: &str) -> StatusCode {
+    reqwest::Client::new().post(format!("http://{}/vault", addr)).header("Authorization", bearer_token).send().await.unwrap().status()
+}
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[test]
+fn parses_commands_and_their_options() {
+    assert_eq!(admin::parse_args(&args(&["limits", "show", "Bearer abc"])).unwrap(), Command::ShowLimits { key: "Bearer abc".to_string() });
+    assert_eq!(
+        admin::parse_args(&args(&["limits", "set", "Bearer abc", "5000/1h", "--ttl", "600"])).unwrap(),
+        Command::SetOverride { key: "Bearer abc".to_string(), rate_limit: RateLimit::per_hour(5000), route: None, ttl_seconds: Some(600) },
+    );
+    assert_eq!(admin::parse_args(&args(&["stats", "top", "--limit", "5"])).unwrap(), Command::TopOffenders { limit: Some(5), window_seconds: None });
+
+    assert!(admin::parse_args(&args(&["limits", "show", "Bearer abc", "--route", "POST /vault"])).is_err());
+    assert!(admin::parse_args(&args(&["limits", "set", "Bearer abc", "lots"])).is_err());
+    assert!(admin::parse_args(&args(&["stats", "top", "--limit"])).is_err());
+}
+
+#[tokio::test]
+async fn shows_and_resets_a_clients_quota() {
+    let addr = spawn();
+    let client = AdminClient::new(format!("http://{}", addr), "admin");
+    for _ in 0..3 {
+        post_vault(addr, "Bearer abc").await;
+    }
+    assert_eq!(post_vault(addr, "Bearer abc").await, StatusCode::TOO_MANY_REQUESTS);
+
+    let limits = client.client_limits("Bearer abc").await.unwrap();
+    let post_vault_limits = limits.routes.iter().find(|route| route.route == POST_VAULT_ROUTE).unwrap();
+    assert_eq!((post_vault_limits.limit, post_vault_limits.remaining, post_vault_limits.overridden), (3, 0, false));
+
+    client.reset_limits("Bearer abc").await.unwrap();
+    assert_eq!(post_vault(addr, "Bearer abc").await, StatusCode::OK);
+
+    let output = admin::run(&client, &Command::ShowLimits { key: "Bearer abc".to_string() }).await.unwrap();
+    let row = output.lines().find(|line| line.starts_with(POST_VAULT_ROUTE)).unwrap();
+    // the route, its limit, then what's remaining
+    assert_eq!(row.split_whitespace().nth(3), Some("2"), "{}", row);
+}
+
+#[tokio::test]
+async fn marks_overridden_limits() {
+    let addr = spawn();
+    let client = AdminClient::new(format!("http://{}", addr), "admin");
+    let command = admin::parse_args(&args(&["limits", "set", "Bearer partner", "10/1m", "--route", POST_VAULT_ROUTE])).unwrap();
+    admin::run(&client, &command).await.unwrap();
+
+    let output = admin::run(&client, &Command::ShowLimits { key: "Bearer partner".to_string() }).await.unwrap();
+    let row = output.lines().find(|line| line.starts_with(POST_VAULT_ROUTE)).unwrap();
+    assert!(row.contains("10/60s (override)"), "{}", row);
+}
+
+#[tokio::test]
+async fn rejects_tokens_other_than_the_admin_token() {
+    let addr = spawn();
+    let client = AdminClient::new(format!("http://{}", addr), "not-admin");
+    assert!(matches!(client.stats().await, Err(ClientError::Unauthorized)));
+}
@@ -0,0 +1,38 @@
+use rate_limited_service::algorithms::{Decision, FixedWindow, Limit, SlidingWindow, TokenBucket};
+
+const LIMIT: Limit = Limit::new(10, 1000);
+
+#[test]
+fn fixed_window_resets_a_window_after_its_first_request() {
+    let mut window = FixedWindow::new(&LIMIT, 500);
+
+    assert_eq!(window.try_acquire(&LIMIT, 10, 500), Decision::Allowed { remaining: 0, resets_at_ms: 1500 });
+    assert_eq!(window.try_acquire(&LIMIT, 1, 1500), Decision::Limited { retry_at_ms: 1500 });
+    assert_eq!(window.try_acquire(&LIMIT, 1, 1501), Decision::Allowed { remaining: 9, resets_at_ms: 2501 });
+}
+
+#[test]
+fn sliding_window_counts_what_is_left_of_the_previous_window() {
+    let mut window = SlidingWindow::new(&LIMIT, 0);
+    assert!(window.try_acquire(&LIMIT, 10, 900).is_allowed());
+
+    // a fixed window would allow another 10 here, but 3/4 of the previous window still overlaps
+    assert_eq!(window.try_acquire(&LIMIT, 3, 1250), Decision::Limited { retry_at_ms: 2000 });
+    assert_eq!(window.try_acquire(&LIMIT, 2, 1250), Decision::Allowed { remaining: 0, resets_at_ms: 3000 });
+    assert_eq!(window.used(&LIMIT, 1900), 3);
+    // nothing carries over a gap of a whole window
+    assert_eq!(window.used(&LIMIT, 3100), 0);
+}
+
+#[test]
+fn token_bucket_refills_a_token_at_a_time() {
+    let mut bucket = TokenBucket::new(&LIMIT, 0);
+    assert_eq!(bucket.try_acquire(&LIMIT, 10, 0), Decision::Allowed { remaining: 0, resets_at_ms: 1000 });
+    assert_eq!(bucket.try_acquire(&LIMIT, 1, 50), Decision::Limited { retry_at_ms: 100 });
+
+    // 250ms is two and a half tokens, the half isn't lost
+    assert_eq!(bucket.try_acquire(&LIMIT, 2, 250), Decision::Allowed { remaining: 0, resets_at_ms: 1200 });
+    assert_eq!(bucket.try_acquire(&LIMIT, 1, 300), Decision::Allowed { remaining: 0, resets_at_ms: 1300 });
+    // it never holds more than the limit
+    assert_eq!(bucket.try_acquire(&LIMIT, 1, 60_000), Decision::Allowed { remaining: 9, resets_at_ms: 60_100 });
+}
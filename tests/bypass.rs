@@ -0,0 +1,27 @@
+use std::net::SocketAddr;
+
+use rate_limited_service::config::Config;
+use reqwest::StatusCode;
+
+mod common;
+
+// the service with bypass tokens enabled
+fn spawn() -> SocketAddr {
+    common::spawn(Config::parse("admin_token = \"admin\"\nbypass_token_secret = \"bypass-secret\"").unwrap())
+}
+
+#[tokio::test]
+async fn rejects_ttls_that_are_not_positive_or_out_of_range() {
+    let addr = spawn();
+    let issue = |ttl_seconds: i64| {
+        reqwest::Client::new()
+            .post(format!("http://{}/admin/bypass-tokens", addr))
+            .bearer_auth("admin")
+            .json(&serde_json::json!({ "subject": "incident-1234", "ttl_seconds": ttl_seconds }))
+            .send()
+    };
+
+    assert_eq!(issue(300).await.unwrap().status(), StatusCode::CREATED);
+    assert_eq!(issue(0).await.unwrap().status(), StatusCode::BAD_REQUEST);
+    assert_eq!(issue(i64::MAX).await.unwrap().status(), StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,173 @@
+use rate_limited_service::config::{self, Config, ConfigProblem};
+
+#[test]
+fn finds_nothing_wrong_with_routes_the_service_serves() {
+    let config = Config::parse(
+        r#"
+        [routes."POST /vault"]
+        limit = 5
+
+        [routes."PUT /vault/items/{id}"]
+        rate = "10/s"
+
+        [limits.global]
+        limit = 1000
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(config.problems(), Vec::new());
+}
+
+#[test]
+fn reports_unknown_and_duplicate_routes_and_zero_limits() {
+    let config = Config::parse(
+        r#"
+        [routes."GET /nowhere"]
+        limit = 5
+
+        [routes."not a template"]
+        limit = 5
+
+        [routes."PUT /vault/items/{id}"]
+        limit = 5
+
+        [routes."put /vault/items/<:item>"]
+        limit = 0
+
+        [routes."POST /vault"]
+        limit = 5
+        schedules = [{ limit = 1, starts_at = "2026-01-02T00:00:00Z", ends_at = "2026-01-01T00:00:00Z" }]
+
+        [limits.token]
+        rate = "0/m"
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(config.problems(), vec![
+        ConfigProblem::UnknownRoute("GET /nowhere".to_string()),
+        ConfigProblem::UnknownRoute("not a template".to_string()),
+        ConfigProblem::DuplicateRoute("PUT /vault/items/{id}".to_string(), "put /vault/items/<:item>".to_string()),
+        ConfigProblem::EmptySchedule("POST /vault".to_string()),
+        ConfigProblem::ZeroLimit("put /vault/items/<:item>".to_string()),
+        ConfigProblem::ZeroLimit("limits.token".to_string()),
+    ]);
+}
+
+#[test]
+fn reports_groups_with_unknown_or_shared_routes() {
+    let config = Config::parse(
+        r#"
+        [routes."PUT /vault/items/{id}"]
+        limit = 5
+
+        [groups.reads]
+        routes = ["GET /vault/items", "GET /vault/items/{id}"]
+        limit = 0
+
+        [groups.writes]
+        routes = ["POST /vault", "PUT /vault/items/{id}", "GET /vault/items"]
+        rate = "100/m"
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(config.problems(), vec![
+        ConfigProblem::ZeroLimit("groups.reads".to_string()),
+        ConfigProblem::UnknownGroupRoute("reads".to_string(), "GET /vault/items/{id}".to_string()),
+        ConfigProblem::GroupedTwice("GET /vault/items".to_string(), "reads".to_string(), "writes".to_string()),
+    ]);
+    assert_eq!(config.group("GET /vault/items").map(|(name, _)| name), Some("reads"));
+}
+
+#[test]
+fn reports_unnamed_regions() {
+    let config = Config::parse(
+        r#"
+        [region.global]
+        rate = "1000/m"
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(config.problems(), vec![ConfigProblem::UnnamedRegion]);
+}
+
+#[test]
+fn reports_header_names_replies_couldnt_carry() {
+    let config = Config::parse(
+        r#"
+        [headers]
+        remaining = "X-Rate-Limit-Remaining"
+        retry_after = "Retry After"
+        level = ""
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(config.problems(), vec![
+        ConfigProblem::InvalidHeaderName("retry_after", "Retry After".to_string()),
+        ConfigProblem::InvalidHeaderName("level", String::new()),
+    ]);
+}
+
+#[test]
+fn reports_fingerprints_without_signals() {
+    let config = Config::parse(
+        r#"
+        [routes."POST /vault"]
+        key = { type = "fingerprint", signals = [] }
+
+        [routes."GET /vault/items"]
+        key = { type = "chain", extractors = [{ type = "bearer_token" }, { type = "fingerprint", signals = [] }] }
+
+        [routes."PUT /vault/items/{id}"]
+        key = { type = "fingerprint", signals = ["user_agent"] }
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(config.problems(), vec![
+        ConfigProblem::EmptyFingerprint("GET /vault/items".to_string()),
+        ConfigProblem::EmptyFingerprint("POST /vault".to_string()),
+    ]);
+}
+
+#[test]
+fn accepts_any_route_in_proxy_mode() {
+    let config = Config::parse(
+        r#"
+        [proxy]
+        upstream = "http://localhost:9000"
+
+        [routes."GET /customers/{id}"]
+        limit = 5
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(config.problems(), Vec::new());
+}
+
+#[test]
+fn lists_the_settings_that_change_a_default() {
+    let changed = config::changed_settings(
+        r#"
+        admin_token = "hunter2"
+        not_modified_cost = 1
+        refund_server_errors = false
+
+        [routes."POST /vault"]
+        limit = 5
+        "#,
+    )
+    .unwrap();
+
+    // not_modified_cost is already 1
+    assert_eq!(changed, vec![
+        ("admin_token".to_string(), "<redacted>".to_string()),
+        ("refund_server_errors".to_string(), "false".to_string()),
+        ("routes.\"POST /vault\".limit".to_string(), "5".to_string()),
+    ]);
+}
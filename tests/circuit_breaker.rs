@@ -0,0 +1,116 @@
+#![cfg(feature = "testing")]
+
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use chrono::Utc;
+use rate_limited_service::circuit_breaker::{CircuitBreakerConfig, CircuitBreakerStore};
+use rate_limited_service::metrics::Metrics;
+use rate_limited_service::store::{InMemoryStore, StoreError, UsageStore};
+use rate_limited_service::testing::{Chaos, ChaosStore};
+use rate_limited_service::RateLimit;
+
+const CLOSED: u64 = 0;
+const OPEN: u64 = 1;
+const HALF_OPEN: u64 = 2;
+
+fn breaker(chaos: &Chaos) -> (CircuitBreakerStore<ChaosStore<InMemoryStore>>, Arc<Metrics>) {
+    let metrics = Arc::new(Metrics::new());
+    let config = CircuitBreakerConfig { failure_threshold: 3, open_seconds: 1, slow_call_ms: 1_000 };
+    (CircuitBreakerStore::new(ChaosStore::new(InMemoryStore::new(), chaos.clone()), config, metrics.clone()), metrics)
+}
+
+fn log_usage(store: &impl UsageStore) -> Result<(), StoreError> {
+    store.log_usage("token", &RateLimit::new(1_000), 1, Utc::now()).map(|_| ())
+}
+
+fn state(metrics: &Metrics) -> u64 {
+    metrics.store_circuit_breaker_state.load(Ordering::SeqCst)
+}
+
+#[test]
+fn opens_after_consecutive_failures() {
+    let chaos = Chaos::new();
+    let (store, metrics) = breaker(&chaos);
+
+    // a success in between starts the count again
+    chaos.set_failing(true);
+    assert!(matches!(log_usage(&store), Err(StoreError::Unavailable(_))));
+    assert!(matches!(log_usage(&store), Err(StoreError::Unavailable(_))));
+    chaos.set_failing(false);
+    log_usage(&store).unwrap();
+    assert_eq!(state(&metrics), CLOSED);
+
+    chaos.set_failing(true);
+    for _ in 0..3 {
+        assert!(matches!(log_usage(&store), Err(StoreError::Unavailable(_))));
+    }
+    assert_eq!(state(&metrics), OPEN);
+    assert_eq!(metrics.store_circuit_breaker_trips.load(Ordering::SeqCst), 1);
+
+    // once open it fails fast, even though the store has recovered
+    chaos.set_failing(false);
+    assert!(matches!(log_usage(&store), Err(StoreError::CircuitOpen)));
+    assert_eq!(metrics.store_errors.load(Ordering::SeqCst), 5);
+}
+
+#[test]
+fn lets_a_single_probe_through_once_the_open_period_is_over() {
+    let chaos = Chaos::new();
+    let (store, metrics) = breaker(&chaos);
+    chaos.set_failing(true);
+    for _ in 0..3 {
+        let _ = log_usage(&store);
+    }
+    chaos.set_failing(false);
+    thread::sleep(Duration::from_millis(1_100));
+
+    // the probe is held up in the store, so everything else still fails fast
+    chaos.set_latency(Duration::from_millis(300));
+    thread::scope(|scope| {
+        let probe = scope.spawn(|| log_usage(&store));
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(state(&metrics), HALF_OPEN);
+        assert!(matches!(log_usage(&store), Err(StoreError::CircuitOpen)));
+        probe.join().unwrap().unwrap();
+    });
+
+    // and its success closes the breaker again
+    assert_eq!(state(&metrics), CLOSED);
+    chaos.reset();
+    log_usage(&store).unwrap();
+}
+
+#[test]
+fn a_failed_probe_reopens_the_breaker() {
+    let chaos = Chaos::new();
+    let (store, metrics) = breaker(&chaos);
+    chaos.set_failing(true);
+    for _ in 0..3 {
+        let _ = log_usage(&store);
+    }
+    thread::sleep(Duration::from_millis(1_100));
+
+    assert!(matches!(log_usage(&store), Err(StoreError::Unavailable(_))));
+    assert_eq!(state(&metrics), OPEN);
+    assert_eq!(metrics.store_circuit_breaker_trips.load(Ordering::SeqCst), 2);
+    chaos.set_failing(false);
+    assert!(matches!(log_usage(&store), Err(StoreError::CircuitOpen)));
+}
+
+#[test]
+fn counts_slow_calls_as_failures() {
+    let chaos = Chaos::new();
+    let metrics = Arc::new(Metrics::new());
+    let config = CircuitBreakerConfig { failure_threshold: 2, open_seconds: 30, slow_call_ms: 10 };
+    let store = CircuitBreakerStore::new(ChaosStore::new(InMemoryStore::new(), chaos.clone()), config, metrics.clone());
+
+    // slow answers still count the request
+    chaos.set_latency(Duration::from_millis(50));
+    log_usage(&store).unwrap();
+    log_usage(&store).unwrap();
+    assert_eq!(state(&metrics), OPEN);
+    assert!(matches!(log_usage(&store), Err(StoreError::CircuitOpen)));
+}
@@ -0,0 +1,91 @@
+#![cfg(feature = "client")]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rate_limited_service::client::{ClientError, VaultClient, GET_VAULT_ITEMS_ROUTE, POST_VAULT_ROUTE};
+use rate_limited_service::replies::HeaderNames;
+use warp::http::{Response, StatusCode};
+use warp::Filter;
+
+type Script = Vec<(StatusCode, Vec<(&'static str, &'static str)>)>;
+
+// answers each request with the next status and headers in `script`, repeating the
+// last one once it runs out, and counts the requests it got
+fn spawn_scripted(script: Script) -> (String, Arc<AtomicUsize>) {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counted = requests.clone();
+    let server = warp::any().map(move || {
+        let (status, headers) = &script[counted.fetch_add(1, Ordering::SeqCst).min(script.len() - 1)];
+        let mut response = Response::builder().status(*status);
+        for (name, value) in headers {
+            response = response.header(*name, *value);
+        }
+        response.body(String::new()).unwrap()
+    });
+    let (addr, server) = warp::serve(server).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    (format!("http://{}", addr), requests)
+}
+
+fn limited(retry_after: &'static str) -> (StatusCode, Vec<(&'static str, &'static str)>) {
+    (StatusCode::TOO_MANY_REQUESTS, vec![("X-Ratelimit-Retry-After", retry_after), ("X-Ratelimit-Remaining", "0")])
+}
+
+#[tokio::test]
+async fn retries_once_the_window_resets() {
+    let (url, requests) = spawn_scripted(vec![limited("0"), (StatusCode::OK, vec![("X-Ratelimit-Remaining", "4")])]);
+    let client = VaultClient::new(url, "Bearer abc");
+
+    client.create_vault().await.unwrap();
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+    assert_eq!(client.remaining(POST_VAULT_ROUTE), Some(4));
+    assert_eq!(client.remaining(GET_VAULT_ITEMS_ROUTE), None);
+}
+
+#[tokio::test]
+async fn returns_429s_asking_to_wait_past_max_backoff() {
+    let (url, requests) = spawn_scripted(vec![limited("120")]);
+    let client = VaultClient::new(url, "Bearer abc").with_max_backoff(Duration::from_secs(60));
+
+    let err = client.create_vault().await.unwrap_err();
+    // a second is added for the rounding down of the header
+    assert!(matches!(err, ClientError::RateLimited { retry_after } if retry_after == Duration::from_secs(121)), "{}", err);
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+    assert_eq!(client.remaining(POST_VAULT_ROUTE), Some(0));
+}
+
+#[tokio::test]
+async fn falls_back_to_a_plain_retry_after() {
+    let (url, requests) = spawn_scripted(vec![(StatusCode::TOO_MANY_REQUESTS, vec![("Retry-After", "30")])]);
+    let client = VaultClient::new(url, "Bearer abc").with_max_backoff(Duration::from_secs(10));
+    let err = client.create_vault().await.unwrap_err();
+    assert!(matches!(err, ClientError::RateLimited { retry_after } if retry_after == Duration::from_secs(31)), "{}", err);
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+    // the service's own header wins
+    let both = (StatusCode::TOO_MANY_REQUESTS, vec![("X-Ratelimit-Retry-After", "0"), ("Retry-After", "30")]);
+    let (url, requests) = spawn_scripted(vec![both, (StatusCode::OK, vec![])]);
+    VaultClient::new(url, "Bearer abc").with_max_backoff(Duration::from_secs(10)).create_vault().await.unwrap();
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn gives_up_after_max_retries() {
+    let (url, requests) = spawn_scripted(vec![limited("0")]);
+    let client = VaultClient::new(url, "Bearer abc").with_max_retries(2);
+
+    assert!(matches!(client.create_vault().await, Err(ClientError::RateLimited { .. })));
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn reads_remaining_under_configured_header_names() {
+    let (url, _) = spawn_scripted(vec![(StatusCode::OK, vec![("X-Rate-Limit-Remaining", "7"), ("X-Ratelimit-Remaining", "1")])]);
+    let header_names = HeaderNames { remaining: "X-Rate-Limit-Remaining".to_string(), ..HeaderNames::default() };
+    let client = VaultClient::new(url, "Bearer abc").with_header_names(header_names);
+
+    client.create_vault().await.unwrap();
+    assert_eq!(client.remaining(POST_VAULT_ROUTE), Some(7));
+}
@@ -0,0 +1,12 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use rate_limited_service::config::Config;
+use rate_limited_service::server;
+
+// starts the service on an ephemeral port, it runs until the test's runtime shuts down
+pub fn spawn(config: Config) -> SocketAddr {
+    let (addr, server) = warp::serve(server::routes(Arc::new(config))).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    addr
+}
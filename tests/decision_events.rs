@@ -0,0 +1,38 @@
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use rate_limited_service::decision_events::DecisionPublisher;
+use rate_limited_service::metrics::Metrics;
+use rate_limited_service::{RateLimit, RateLimiter};
+
+#[tokio::test]
+async fn publishes_each_allow_and_deny_decision() {
+    let (publisher, mut events) = DecisionPublisher::channel(16, Arc::new(Metrics::new()));
+    let rate_limiter = RateLimiter::new().with_observer(Arc::new(publisher));
+
+    assert!(rate_limiter.clone().log_usage("POST /vault", "Bearer fraud".to_string(), RateLimit::new(1)).is_ok());
+    assert!(rate_limiter.clone().log_usage("POST /vault", "Bearer fraud".to_string(), RateLimit::new(1)).is_err());
+
+    let allowed = events.recv().await.unwrap();
+    assert_eq!(allowed.key_sha256, sha256::digest("Bearer fraud"));
+    assert_eq!(allowed.route, "POST /vault");
+    assert!(allowed.allowed);
+    assert_eq!((allowed.limit, allowed.remaining), (1, 0));
+
+    let denied = events.recv().await.unwrap();
+    assert!(!denied.allowed);
+    assert_eq!(denied.resets_at, allowed.resets_at);
+}
+
+#[tokio::test]
+async fn drops_events_rather_than_wait_for_a_full_buffer() {
+    let metrics = Arc::new(Metrics::new());
+    let (publisher, _events) = DecisionPublisher::channel(1, metrics.clone());
+    let rate_limiter = RateLimiter::new().with_observer(Arc::new(publisher));
+
+    for _ in 0..3 {
+        let _ = rate_limiter.clone().log_usage("POST /vault", "Bearer busy".to_string(), RateLimit::new(10));
+    }
+
+    assert_eq!(metrics.decision_events_dropped.load(Ordering::Relaxed), 2);
+}
@@ -0,0 +1,69 @@
+use rate_limited_service::config::Config;
+use rate_limited_service::encryption::{parse_keys, DecryptError, EncryptionKey, KeyConfig, Keyring};
+use rate_limited_service::vault::Vault;
+use serde_json::json;
+
+fn key(version: u32, byte: u8) -> KeyConfig {
+    KeyConfig { version, key: EncryptionKey::new([byte; 32]) }
+}
+
+#[test]
+fn decrypts_with_the_key_named_in_the_header() {
+    let old = Keyring::new(&[key(1, 1)]).unwrap();
+    let rotated = Keyring::new(&[key(1, 1), key(2, 2)]).unwrap();
+    let sealed = old.encrypt(b"secret", b"item-1");
+
+    assert_eq!(rotated.active_version(), 2);
+    assert_eq!(Keyring::key_version(&sealed), Some(1));
+    assert_eq!(rotated.decrypt(&sealed, b"item-1").unwrap(), b"secret");
+    assert_eq!(Keyring::key_version(&rotated.encrypt(b"secret", b"item-1")), Some(2));
+
+    let retired = Keyring::new(&[key(2, 2)]).unwrap();
+    assert_eq!(retired.decrypt(&sealed, b"item-1"), Err(DecryptError::UnknownKey(1)));
+}
+
+#[test]
+fn rejects_tampered_or_moved_ciphertexts() {
+    let keyring = Keyring::new(&[key(1, 1)]).unwrap();
+    let mut sealed = keyring.encrypt(b"secret", b"item-1");
+
+    assert_eq!(keyring.decrypt(&sealed, b"item-2"), Err(DecryptError::Authentication));
+    *sealed.last_mut().unwrap() ^= 1;
+    assert_eq!(keyring.decrypt(&sealed, b"item-1"), Err(DecryptError::Authentication));
+    assert_eq!(keyring.decrypt(b"short", b"item-1"), Err(DecryptError::Malformed));
+}
+
+#[test]
+fn stores_items_encrypted_and_rotates_them() {
+    let vault = Vault::with_keyring(Keyring::new(&[key(1, 1)]));
+    vault.put("item-1".to_string(), json!({"card": "4111111111111111"}));
+
+    let stored = vault.stored("item-1").unwrap();
+    assert!(!String::from_utf8_lossy(&stored).contains("4111"));
+    assert_eq!(Keyring::key_version(&stored), Some(1));
+
+    // the old key isn't needed once everything is re-encrypted
+    assert_eq!(vault.rotate(Keyring::new(&[key(2, 2)]).unwrap()), Ok(1));
+    assert_eq!(Keyring::key_version(&vault.stored("item-1").unwrap()), Some(2));
+
+    let (items, _) = vault.list("", 0, 10);
+    assert_eq!(items[0].data, json!({"card": "4111111111111111"}));
+}
+
+#[test]
+fn reads_keys_from_config_and_the_environment_format() {
+    let config = Config::parse(
+        r#"
+        [[encryption_keys]]
+        version = 1
+        key = "AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE="
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.encryption_keys[0].key, EncryptionKey::new([1; 32]));
+    assert!(Config::parse("[[encryption_keys]]\nversion = 1\nkey = \"dG9vIHNob3J0\"").is_err());
+
+    let keys = parse_keys("1:AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=, 2:AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=").unwrap();
+    assert_eq!(Keyring::new(&keys).unwrap().active_version(), 2);
+    assert!(parse_keys("1:not-base64").is_none());
+}
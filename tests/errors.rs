@@ -0,0 +1,53 @@
+use chrono::{Duration, Utc};
+use rate_limited_service::config::Config;
+use rate_limited_service::error::Error;
+use rate_limited_service::scopes::AuthError;
+use rate_limited_service::signatures::SignatureError;
+use rate_limited_service::store::StoreError;
+use rate_limited_service::{LimitLevel, RateLimitedError, ReservationError, UsageError};
+use warp::http::StatusCode;
+
+#[test]
+fn answers_each_error_with_its_status() {
+    let config = Config::default();
+    let rate_limited = RateLimitedError::new(Utc::now() + Duration::seconds(30)).with_level(LimitLevel::Token);
+    let cases = [
+        (Error::Auth(AuthError::Missing), StatusCode::UNAUTHORIZED),
+        (Error::Auth(AuthError::Invalid), StatusCode::UNAUTHORIZED),
+        (Error::Auth(AuthError::Forbidden), StatusCode::FORBIDDEN),
+        (Error::Signature(SignatureError::Invalid), StatusCode::UNAUTHORIZED),
+        (Error::Signature(SignatureError::Replayed), StatusCode::CONFLICT),
+        (Error::Signature(SignatureError::Store(StoreError::Timeout)), StatusCode::SERVICE_UNAVAILABLE),
+        (Error::RateLimited(rate_limited.clone()), StatusCode::TOO_MANY_REQUESTS),
+        (Error::Store(StoreError::Timeout), StatusCode::SERVICE_UNAVAILABLE),
+        (Error::Validation("offset should be a number".to_string()), StatusCode::BAD_REQUEST),
+        (Error::CostTooHigh { cost: 10, limit: 5 }, StatusCode::PAYLOAD_TOO_LARGE),
+        (Error::PayloadTooLarge, StatusCode::PAYLOAD_TOO_LARGE),
+        (Error::NotFound, StatusCode::NOT_FOUND),
+        (Error::MethodNotAllowed, StatusCode::METHOD_NOT_ALLOWED),
+        (Error::LengthRequired, StatusCode::LENGTH_REQUIRED),
+        (Error::UnsupportedMediaType, StatusCode::UNSUPPORTED_MEDIA_TYPE),
+        (Error::Internal("the reply couldn't be built".to_string()), StatusCode::INTERNAL_SERVER_ERROR),
+    ];
+    for (err, status) in cases {
+        assert_eq!(err.status(), status, "{}", err);
+        assert_eq!(err.reply(&config).unwrap().status(), status, "{}", err);
+    }
+
+    let reply = Error::RateLimited(rate_limited.clone()).reply(&config).unwrap();
+    assert_eq!(reply.headers()["X-Ratelimit-Level"], "token");
+    assert!(reply.headers().contains_key("X-Ratelimit-Retry-After"));
+
+    // the jitter is capped at an hour here too
+    let config = Config::parse(&format!("retry_after_jitter_seconds = {}", i64::MAX)).unwrap();
+    let reply = Error::RateLimited(rate_limited).reply(&config).unwrap();
+    let retry_after: i64 = reply.headers()["X-Ratelimit-Retry-After"].to_str().unwrap().parse().unwrap();
+    assert!(retry_after <= 30 + 60 * 60, "{}", retry_after);
+}
+
+#[test]
+fn converts_limiter_errors() {
+    assert!(matches!(Error::from(UsageError::Store(StoreError::CircuitOpen)), Error::Store(StoreError::CircuitOpen)));
+    assert_eq!(Error::from(ReservationError::NotFound).status(), StatusCode::NOT_FOUND);
+    assert_eq!(Error::from(ReservationError::Store(StoreError::Timeout)).status(), StatusCode::SERVICE_UNAVAILABLE);
+}
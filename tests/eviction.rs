@@ -0,0 +1,79 @@
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use chrono::{Duration, Utc};
+use rate_limited_service::config::Config;
+use rate_limited_service::metrics::Metrics;
+use rate_limited_service::store::{EvictionPolicy, InMemoryStore, UsageStore};
+use rate_limited_service::RateLimit;
+
+mod common;
+
+use common::spawn;
+
+#[test]
+fn evicts_the_least_recently_used_counter_once_full() {
+    let metrics = Arc::new(Metrics::new());
+    let store = InMemoryStore::new().with_max_keys(100, EvictionPolicy::Lru, metrics.clone());
+    let rate_limit = RateLimit::new(10);
+    let now = Utc::now();
+    for key in 0..100 {
+        store.log_usage(&key.to_string(), &rate_limit, 1, now + Duration::milliseconds(key)).unwrap().unwrap();
+    }
+    store.log_usage("0", &rate_limit, 1, now + Duration::milliseconds(200)).unwrap().unwrap();
+
+    // 1 has gone longest without a request now
+    store.log_usage("new", &rate_limit, 1, now + Duration::milliseconds(300)).unwrap().unwrap();
+    assert!(store.was_evicted("1", now + Duration::milliseconds(400)));
+    assert!(!store.was_evicted("0", now + Duration::milliseconds(400)));
+    assert_eq!(metrics.store_evictions.load(Ordering::Relaxed), 1);
+
+    // its count started over, and it's only reported once
+    assert_eq!(store.log_usage("1", &rate_limit, 1, now + Duration::milliseconds(500)).unwrap().unwrap().0, 9);
+    assert!(!store.was_evicted("1", now + Duration::milliseconds(500)));
+}
+
+#[test]
+fn evicts_the_counter_resetting_soonest_once_full() {
+    let store = InMemoryStore::new().with_max_keys(2, EvictionPolicy::SoonestReset, Arc::new(Metrics::new()));
+    let now = Utc::now();
+    store.log_usage("hourly", &RateLimit::per_hour(10), 1, now).unwrap().unwrap();
+    store.log_usage("minutely", &RateLimit::new(10), 1, now).unwrap().unwrap();
+
+    store.log_usage("new", &RateLimit::new(10), 1, now).unwrap().unwrap();
+    assert!(store.was_evicted("minutely", now));
+    assert!(!store.was_evicted("hourly", now));
+}
+
+#[test]
+fn drops_finished_windows_before_evicting_anything() {
+    let metrics = Arc::new(Metrics::new());
+    let store = InMemoryStore::new().with_max_keys(1, EvictionPolicy::Lru, metrics.clone());
+    let now = Utc::now();
+    store.log_usage("old", &RateLimit::new(10), 1, now).unwrap().unwrap();
+
+    store.log_usage("new", &RateLimit::new(10), 1, now + Duration::minutes(2)).unwrap().unwrap();
+    assert!(!store.was_evicted("old", now + Duration::minutes(2)));
+    assert_eq!(metrics.store_evictions.load(Ordering::Relaxed), 0);
+}
+
+async fn post_vault(addr: SocketAddr, bearer_token: &str) -> reqwest::Response {
+    reqwest::Client::new().post(format!("http://{}/vault", addr)).header("Authorization", bearer_token).send().await.unwrap()
+}
+
+#[tokio::test]
+async fn tells_clients_their_count_started_over() {
+    let mut config = Config::default();
+    config.store.max_keys = Some(1);
+    let addr = spawn(config);
+
+    post_vault(addr, "Bearer first").await;
+    post_vault(addr, "Bearer first").await;
+    assert!(post_vault(addr, "Bearer first").await.headers().get("X-Ratelimit-Evicted").is_none());
+    post_vault(addr, "Bearer second").await;
+
+    let response = post_vault(addr, "Bearer first").await;
+    assert_eq!(response.headers()["X-Ratelimit-Evicted"], "true");
+    assert_eq!(response.headers()["X-Ratelimit-Remaining"], "2");
+}
@@ -0,0 +1,54 @@
+#![cfg(feature = "testing")]
+
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use rate_limited_service::store::{FailurePolicy, InMemoryStore, StoreError};
+use rate_limited_service::testing::{Chaos, ChaosStore};
+use rate_limited_service::{RateLimit, RateLimiter, UsageError};
+
+const ROUTE: &str = "POST /vault";
+
+// a limiter whose store is down
+fn failing(failure_policy: FailurePolicy) -> RateLimiter {
+    let chaos = Chaos::new();
+    chaos.set_failing(true);
+    RateLimiter::with_store(Arc::new(ChaosStore::new(InMemoryStore::new(), chaos))).with_failure_policy(failure_policy)
+}
+
+// logs usage the way the server does, leaving what the store can't decide to the failure policy
+fn log_usage(rate_limiter: &RateLimiter, cost: u64) -> Result<u64, UsageError> {
+    let rate_limit = RateLimit::new(2);
+    let usage = match rate_limiter.clone().log_weighted_usage(ROUTE, "token".to_string(), rate_limit.clone(), cost) {
+        Err(UsageError::Store(err)) => rate_limiter.apply_failure_policy(ROUTE, "token".to_string(), rate_limit, cost, err),
+        usage => usage,
+    };
+    usage.map(|(remaining, _)| remaining)
+}
+
+#[test]
+fn fails_open_without_counting() {
+    let rate_limiter = failing(FailurePolicy::Allow);
+
+    for _ in 0..5 {
+        assert_eq!(log_usage(&rate_limiter, 1).unwrap(), 1);
+    }
+    assert_eq!(rate_limiter.metrics().store_fallbacks.load(Ordering::SeqCst), 5);
+}
+
+#[test]
+fn fails_closed_with_the_store_error() {
+    let rate_limiter = failing(FailurePolicy::Reject);
+
+    assert!(matches!(log_usage(&rate_limiter, 1), Err(UsageError::Store(StoreError::Unavailable(_)))));
+    assert_eq!(rate_limiter.metrics().store_fallbacks.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn counts_locally_while_the_store_is_down() {
+    let rate_limiter = failing(FailurePolicy::Local);
+
+    assert_eq!(log_usage(&rate_limiter, 1).unwrap(), 1);
+    assert_eq!(log_usage(&rate_limiter, 1).unwrap(), 0);
+    assert!(matches!(log_usage(&rate_limiter, 1), Err(UsageError::RateLimited(_))));
+}
@@ -0,0 +1,87 @@
+use std::net::SocketAddr;
+
+use async_graphql::{Request, Variables};
+use rate_limited_service::config::{Config, RouteConfig};
+use rate_limited_service::graphql::{CostError, Graphql, GraphqlConfig};
+use rate_limited_service::server::POST_GRAPHQL_ROUTE;
+use rate_limited_service::vault::Vault;
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+
+mod common;
+
+fn cost(query: &str, config: &GraphqlConfig) -> Result<u64, CostError> {
+    Graphql::new(Vault::new()).cost(&Request::new(query), config)
+}
+
+#[test]
+fn costs_queries_by_the_fields_they_select() {
+    let config = GraphqlConfig::default();
+
+    assert_eq!(cost("{ item(id: \"a\") { id data } }", &config), Ok(3));
+    // fields under a list count once per element
+    assert_eq!(cost("{ items(limit: 50) { id data } }", &config), Ok(101));
+    assert_eq!(cost("{ items { id } }", &config), Ok(101));
+    assert_eq!(cost("{ items(limit: 5) { ...fields } __typename } fragment fields on Item { id data }", &config), Ok(11));
+    assert_eq!(cost("mutation { putItem(id: \"a\", data: 1) { id } deleteItem(id: \"b\") }", &config), Ok(3));
+
+    let request = Request::new("query Page($limit: Int) { items(limit: $limit) { id } }").variables(Variables::from_json(json!({"limit": 20})));
+    assert_eq!(Graphql::new(Vault::new()).cost(&request, &config), Ok(21));
+}
+
+#[test]
+fn applies_configured_field_costs_and_depth() {
+    let config = GraphqlConfig {
+        field_costs: [("Query.items".to_string(), 10), ("Item.data".to_string(), 0)].into(),
+        max_depth: 1,
+        ..GraphqlConfig::default()
+    };
+
+    assert_eq!(cost("{ items(limit: 3) { data } }", &config), Err(CostError::TooDeep { depth: 2, max_depth: 1 }));
+    assert_eq!(cost("{ item(id: \"a\") }", &config), Ok(1));
+
+    let config = GraphqlConfig { max_depth: 2, ..config };
+    assert_eq!(cost("{ items(limit: 3) { id data } }", &config), Ok(13));
+    assert!(matches!(cost("{ items { ...a } } fragment a on Item { ...a }", &config), Err(CostError::FragmentCycle(_))));
+    assert!(matches!(cost("{ items {", &config), Err(CostError::Parse(_))));
+}
+
+fn spawn(limit: u64) -> SocketAddr {
+    let mut config = Config::default();
+    config.routes.insert(POST_GRAPHQL_ROUTE.to_string(), RouteConfig { limit: Some(limit), ..RouteConfig::default() });
+    common::spawn(config)
+}
+
+async fn graphql(addr: SocketAddr, query: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("http://{}/graphql", addr))
+        .header("Authorization", "Bearer graphql")
+        .json(&json!({ "query": query }))
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn charges_graphql_requests_by_query_cost() {
+    let addr = spawn(30);
+
+    let response = graphql(addr, "mutation { putItem(id: \"a\", data: {secret: 1}) { id } }").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["X-Ratelimit-Remaining"], "28");
+
+    let response = graphql(addr, "{ items(limit: 10) { id data } }").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["X-Ratelimit-Remaining"], "7");
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["data"]["items"], json!([{"id": "a", "data": {"secret": 1}}]));
+
+    // too expensive for what's left, while a cheaper query still fits
+    assert_eq!(graphql(addr, "{ items(limit: 10) { id } }").await.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(graphql(addr, "{ item(id: \"a\") { id } }").await.status(), StatusCode::OK);
+
+    let response = graphql(addr, "{ items { id data { nope } } ").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = response.json().await.unwrap();
+    assert!(body["errors"][0]["message"].is_string());
+}
@@ -0,0 +1,23 @@
+use std::sync::Arc;
+
+use rate_limited_service::store::InMemoryStore;
+use rate_limited_service::{KeyHasher, RateLimit, RateLimiter};
+
+#[test]
+fn moves_usage_over_when_the_secret_is_rotated() {
+    let store = Arc::new(InMemoryStore::new());
+    let unsalted = RateLimiter::with_store(store.clone());
+    assert_eq!(unsalted.clone().log_usage("POST /vault", "Bearer token".to_string(), RateLimit::new(3)).unwrap().0, 2);
+
+    // without the previous secret the old counter isn't found
+    let salted = RateLimiter::with_store(store.clone()).with_key_hasher(KeyHasher::new(Some(b"first".to_vec())));
+    assert_eq!(salted.clone().log_usage("POST /vault", "Bearer other".to_string(), RateLimit::new(3)).unwrap().0, 2);
+
+    let rotating = RateLimiter::with_store(store.clone()).with_key_hasher(KeyHasher::new(Some(b"first".to_vec())).with_previous(None));
+    assert_eq!(rotating.clone().log_usage("POST /vault", "Bearer token".to_string(), RateLimit::new(3)).unwrap().0, 1);
+    assert_eq!(salted.clone().log_usage("POST /vault", "Bearer token".to_string(), RateLimit::new(3)).unwrap().0, 0);
+
+    let rotated = RateLimiter::with_store(store).with_key_hasher(KeyHasher::new(Some(b"second".to_vec())).with_previous(Some(b"first".to_vec())));
+    assert!(rotated.clone().log_usage("POST /vault", "Bearer token".to_string(), RateLimit::new(3)).is_err());
+    assert!(unsalted.log_usage("POST /vault", "Bearer token".to_string(), RateLimit::new(3)).is_ok());
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use proptest::prelude::*;
+use rate_limited_service::algorithms::{Decision, Limit, SlidingWindow, TokenBucket};
+use rate_limited_service::circuit_breaker::{CircuitBreakerConfig, CircuitBreakerStore};
+use rate_limited_service::metrics::Metrics;
+use rate_limited_service::store::{InMemoryStore, UsageStore};
+use rate_limited_service::RateLimit;
+
+const KEYS: [&str; 3] = ["alpha", "bravo", "charlie"];
+
+#[derive(Debug, Clone)]
+enum Op {
+    Request { key: usize, cost: u64 },
+    AdvanceClock { millis: i64 },
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        4 => (0..KEYS.len(), 1..=3u64).prop_map(|(key, cost)| Op::Request { key, cost }),
+        1 => (0..5_000i64).prop_map(|millis| Op::AdvanceClock { millis }),
+    ]
+}
+
+// every store under test, they all implement the fixed window algorithm
+fn stores() -> Vec<(&'static str, Box<dyn UsageStore>)> {
+    vec![
+        ("in_memory", Box::new(InMemoryStore::new())),
+        (
+            "circuit_breaker(in_memory)",
+            Box::new(CircuitBreakerStore::new(InMemoryStore::new(), CircuitBreakerConfig::default(), Arc::new(Metrics::new()))),
+        ),
+    ]
+}
+
+fn start() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()
+}
+
+// replays `ops` against `store` on a simulated clock, checking the invariants after every request
+fn check_invariants(store: &dyn UsageStore, limit: u64, window_seconds: i64, ops: &[Op]) -> Result<(), TestCaseError> {
+    let rate_limit = RateLimit { limit, duration: Duration::seconds(window_seconds) };
+    let mut now = start();
+    // units allowed per (key, window reset time)
+    let mut allowed: HashMap<(usize, DateTime<Utc>), u64> = HashMap::new();
+    let mut last_reset: HashMap<usize, DateTime<Utc>> = HashMap::new();
+
+    for op in ops {
+        match *op {
+            Op::AdvanceClock { millis } => now += Duration::milliseconds(millis),
+            Op::Request { key, cost } => {
+                let cost = cost.min(limit);
+                let reset = match store.log_usage(KEYS[key], &rate_limit, cost, now).unwrap() {
+                    Ok((remaining, reset)) => {
+                        let used = allowed.entry((key, reset)).or_insert(0);
+                        *used += cost;
+                        prop_assert!(*used <= limit, "allowed {} units in a window limited to {}", used, limit);
+                        prop_assert_eq!(remaining, limit - *used);
+                        reset
+                    }
+                    Err(err) => {
+                        // only limited if this request genuinely wouldn't fit in the current window
+                        let used = allowed.get(&(key, err.time_when_refreshed)).copied().unwrap_or(0);
+                        prop_assert!(used + cost > limit, "limited with {} of {} used", used, limit);
+                        err.time_when_refreshed
+                    }
+                };
+
+                prop_assert!(reset >= now, "window resets in the past");
+                if let Some(previous) = last_reset.insert(key, reset) {
+                    prop_assert!(reset >= previous, "reset time moved backwards from {} to {}", previous, reset);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// requests to a single key for the algorithms, as (ms since the last request, cost)
+fn requests() -> impl Strategy<Value = Vec<(i64, u64)>> {
+    prop::collection::vec((0..3_000i64, 1..=3u64), 1..200)
+}
+
+const START_MS: i64 = 1_704_067_200_000;
+
+// replays `requests` against a SlidingWindow, checking no aligned window lets through more than the limit
+fn check_sliding_window(limit: &Limit, requests: &[(i64, u64)]) -> Result<(), TestCaseError> {
+    let mut window = SlidingWindow::new(limit, START_MS);
+    let mut now_ms = START_MS;
+    // units allowed per window start
+    let mut allowed: HashMap<i64, u64> = HashMap::new();
+
+    for &(advance_ms, cost) in requests {
+        now_ms += advance_ms;
+        // used() rolls the window, so it's read off a copy
+        let mut before = window;
+        let used = before.used(limit, now_ms);
+        match window.try_acquire(limit, cost, now_ms) {
+            Decision::Allowed { remaining, resets_at_ms } => {
+                let total = allowed.entry(window.window_start_ms).or_insert(0);
+                *total += cost;
+                prop_assert!(*total <= limit.limit, "allowed {} units in a window limited to {}", total, limit.limit);
+                prop_assert_eq!(remaining, limit.limit - used - cost);
+                prop_assert!(resets_at_ms > now_ms, "window resets in the past");
+            }
+            Decision::Limited { retry_at_ms } => {
+                prop_assert!(used + cost > limit.limit, "limited with {} of {} used", used, limit.limit);
+                prop_assert!(retry_at_ms > now_ms, "told to retry in the past");
+            }
+        }
+    }
+    Ok(())
+}
+
+// replays `requests` against a TokenBucket, checking it never holds more than the limit and
+// never lets through more than a full bucket plus what refilled in between
+fn check_token_bucket(limit: &Limit, requests: &[(i64, u64)]) -> Result<(), TestCaseError> {
+    let mut bucket = TokenBucket::new(limit, START_MS);
+    let mut now_ms = START_MS;
+    let mut allowed: Vec<(i64, u64)> = Vec::new();
+
+    for &(advance_ms, cost) in requests {
+        now_ms += advance_ms;
+        match bucket.try_acquire(limit, cost, now_ms) {
+            Decision::Allowed { remaining, .. } => {
+                prop_assert!(remaining <= limit.limit, "{} tokens left in a bucket of {}", remaining, limit.limit);
+                allowed.push((now_ms, cost));
+            }
+            Decision::Limited { retry_at_ms } => prop_assert!(retry_at_ms > now_ms, "told to retry in the past"),
+        }
+        prop_assert!(bucket.tokens <= limit.limit, "{} tokens in a bucket of {}", bucket.tokens, limit.limit);
+    }
+
+    for (first, &(from_ms, _)) in allowed.iter().enumerate() {
+        let mut total = 0;
+        for &(at_ms, cost) in &allowed[first..] {
+            total += cost;
+            // a partly refilled token at from_ms can complete in between, hence the one extra
+            let refilled = (at_ms - from_ms) as u64 * limit.limit / limit.window_ms as u64 + 1;
+            prop_assert!(total <= limit.limit + refilled, "allowed {} units in {}ms with a limit of {} per {}ms", total, at_ms - from_ms, limit.limit, limit.window_ms);
+        }
+    }
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn fixed_window_invariants_hold(
+        limit in 1..=10u64,
+        window_seconds in 1..=10i64,
+        ops in prop::collection::vec(op(), 1..200),
+    ) {
+        for (name, store) in stores() {
+            check_invariants(store.as_ref(), limit, window_seconds, &ops)
+                .map_err(|err| TestCaseError::fail(format!("{}: {}", name, err)))?;
+        }
+    }
+
+    #[test]
+    fn sliding_window_invariants_hold(limit in 1..=10u64, window_ms in 1..=10_000i64, requests in requests()) {
+        check_sliding_window(&Limit::new(limit, window_ms), &requests)?;
+    }
+
+    #[test]
+    fn token_bucket_invariants_hold(limit in 1..=10u64, window_ms in 1..=10_000i64, requests in requests()) {
+        check_token_bucket(&Limit::new(limit, window_ms), &requests)?;
+    }
+}
@@ -0,0 +1,131 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rate_limited_service::config::Config;
+use rate_limited_service::listener;
+use tokio::net::{TcpStream, UnixStream};
+use warp::hyper::client::conn;
+use warp::hyper::{Body, Request, StatusCode, Version};
+
+#[tokio::test]
+async fn serves_over_a_unix_socket() {
+    let path = std::env::temp_dir().join(format!("rate-limited-service-{}.sock", std::process::id()));
+    let mut config = Config::default();
+    config.listen.unix_socket = Some(path.to_string_lossy().into_owned());
+    tokio::spawn(listener::serve(Arc::new(config)));
+
+    // wait for the socket to be bound
+    let mut stream = UnixStream::connect(&path).await;
+    for _ in 0..50 {
+        if stream.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        stream = UnixStream::connect(&path).await;
+    }
+    let (mut sender, connection) = conn::handshake(stream.unwrap()).await.unwrap();
+    tokio::spawn(connection);
+
+    let request = Request::post("/vault").header("Authorization", "Bearer unix").body(Body::empty()).unwrap();
+    let response = sender.send_request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().contains_key("X-Ratelimit-Remaining"));
+
+    let _ = std::fs::remove_file(&path);
+}
+
+async fn bind_tcp(config: Config) -> SocketAddr {
+    let mut config = config;
+    config.listen.address = SocketAddr::from(([127, 0, 0, 1], 0));
+    let (addr, server) = listener::bind(Arc::new(config)).await.unwrap();
+    tokio::spawn(server);
+    addr.unwrap()
+}
+
+fn post_vault() -> Request<Body> {
+    Request::post("/vault").header("Authorization", "Bearer tuned").body(Body::empty()).unwrap()
+}
+
+#[tokio::test]
+async fn serves_http2_with_prior_knowledge_unless_disabled() {
+    let addr = bind_tcp(Config::default()).await;
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (mut sender, connection) = conn::Builder::new().http2_only(true).handshake(stream).await.unwrap();
+    tokio::spawn(connection);
+    let response = sender.send_request(post_vault()).await.unwrap();
+    assert_eq!(response.version(), Version::HTTP_2);
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let mut config = Config::default();
+    config.http.http2 = false;
+    let addr = bind_tcp(config).await;
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (mut sender, connection) = conn::Builder::new().http2_only(true).handshake(stream).await.unwrap();
+    tokio::spawn(connection);
+    assert!(sender.send_request(post_vault()).await.is_err());
+}
+
+#[tokio::test]
+async fn holds_connections_beyond_the_limit_until_one_closes() {
+    let mut config = Config::default();
+    config.http.max_connections = Some(1);
+    let addr = bind_tcp(config).await;
+
+    let (mut first, connection) = conn::handshake(TcpStream::connect(addr).await.unwrap()).await.unwrap();
+    let first_connection = tokio::spawn(connection);
+    assert_eq!(first.send_request(post_vault()).await.unwrap().status(), StatusCode::OK);
+
+    let (mut second, connection) = conn::handshake(TcpStream::connect(addr).await.unwrap()).await.unwrap();
+    tokio::spawn(connection);
+    let waiting = tokio::spawn(async move { second.send_request(post_vault()).await });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(!waiting.is_finished());
+
+    drop(first);
+    first_connection.await.unwrap().unwrap();
+    assert_eq!(waiting.await.unwrap().unwrap().status(), StatusCode::OK);
+}
+
+fn post_vault_as(token: usize) -> Request<Body> {
+    Request::post("/vault").header("Authorization", format!("Bearer rotated-{}", token)).body(Body::empty()).unwrap()
+}
+
+#[tokio::test]
+async fn closes_connections_over_their_request_limit_whatever_the_token() {
+    let mut config = Config::default();
+    config.http.max_requests_per_connection = Some(3);
+    let addr = bind_tcp(config).await;
+
+    let (mut sender, connection) = conn::handshake(TcpStream::connect(addr).await.unwrap()).await.unwrap();
+    let connection = tokio::spawn(connection);
+    for token in 0..3 {
+        assert_eq!(sender.send_request(post_vault_as(token)).await.unwrap().status(), StatusCode::OK);
+    }
+    let response = sender.send_request(post_vault_as(3)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()["Connection"], "close");
+    connection.await.unwrap().unwrap();
+
+    // a fresh connection starts over
+    let (mut sender, connection) = conn::handshake(TcpStream::connect(addr).await.unwrap()).await.unwrap();
+    tokio::spawn(connection);
+    assert_eq!(sender.send_request(post_vault_as(4)).await.unwrap().status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn closes_http2_connections_over_their_request_limit() {
+    let mut config = Config::default();
+    config.http.max_requests_per_connection = Some(2);
+    let addr = bind_tcp(config).await;
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (mut sender, connection) = conn::Builder::new().http2_only(true).handshake(stream).await.unwrap();
+    let connection = tokio::spawn(connection);
+    for token in 0..2 {
+        assert_eq!(sender.send_request(post_vault_as(token)).await.unwrap().status(), StatusCode::OK);
+    }
+    assert_eq!(sender.send_request(post_vault_as(2)).await.unwrap().status(), StatusCode::TOO_MANY_REQUESTS);
+    let _ = tokio::time::timeout(Duration::from_secs(5), connection).await.unwrap();
+    assert!(sender.send_request(post_vault_as(3)).await.is_err());
+}
@@ -0,0 +1,107 @@
+use rate_limited_service::config::{Config, RouteConfig};
+use reqwest::StatusCode;
+
+mod common;
+
+use common::spawn;
+
+#[tokio::test]
+async fn links_only_to_pages_that_exist() {
+    let addr = spawn(Config::default());
+    let client = reqwest::Client::new();
+    for id in ["a", "b", "c"] {
+        client.put(format!("http://{}/vault/items/{}", addr, id)).bearer_auth("pager").body("{}").send().await.unwrap();
+    }
+    let list = |query: &str| client.get(format!("http://{}/vault/items?{}", addr, query)).bearer_auth("pager").send();
+
+    let first = list("offset=0&limit=2").await.unwrap();
+    assert_eq!(first.headers()["Link"], "</vault/items?offset=2&limit=2>; rel=\"next\"");
+    let last = list("offset=2&limit=2").await.unwrap();
+    assert_eq!(last.headers()["Link"], "</vault/items?offset=0&limit=2>; rel=\"prev\"");
+
+    // an offset that would overflow past the last page just has nothing after it
+    let beyond = list(&format!("offset={}&limit=2", usize::MAX)).await.unwrap();
+    assert_eq!(beyond.status(), StatusCode::OK);
+    assert!(!beyond.headers()["Link"].to_str().unwrap().contains("next"));
+}
+
+#[tokio::test]
+async fn answers_a_matching_if_none_match_with_not_modified() {
+    let addr = spawn(Config::default());
+    let client = reqwest::Client::new();
+    client.put(format!("http://{}/vault/items/a", addr)).bearer_auth("revalidator").body("{}").send().await.unwrap();
+    let list = |if_none_match: Option<&str>| {
+        let mut request = client.get(format!("http://{}/vault/items", addr)).bearer_auth("revalidator");
+        if let Some(if_none_match) = if_none_match {
+            request = request.header("If-None-Match", if_none_match);
+        }
+        request.send()
+    };
+
+    let response = list(None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let etag = response.headers()["ETag"].to_str().unwrap().to_string();
+
+    // the weak comparison ignores W/, and * matches any listing
+    for if_none_match in [etag.clone(), format!("W/{}", etag), "*".to_string(), format!("\"stale\", {}", etag)] {
+        let response = list(Some(&if_none_match)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED, "{}", if_none_match);
+        assert_eq!(response.headers()["ETag"], etag.as_str());
+        assert!(response.bytes().await.unwrap().is_empty());
+    }
+    assert_eq!(list(Some("\"stale\"")).await.unwrap().status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn keeps_the_etag_while_the_listing_is_unchanged() {
+    let addr = spawn(Config::default());
+    let client = reqwest::Client::new();
+    let put_item = |id: &str| client.put(format!("http://{}/vault/items/{}", addr, id)).bearer_auth("lister").body("{}").send();
+    let etag = || {
+        let request = client.get(format!("http://{}/vault/items", addr)).bearer_auth("lister");
+        async move { request.send().await.unwrap().headers()["ETag"].to_str().unwrap().to_string() }
+    };
+    put_item("a").await.unwrap();
+
+    let first = etag().await;
+    assert_eq!(etag().await, first);
+    put_item("b").await.unwrap();
+    assert_ne!(etag().await, first);
+}
+
+#[tokio::test]
+async fn negotiates_the_encoding_by_q_value() {
+    let mut config = Config::default();
+    config.routes.insert("GET /vault/items".to_string(), RouteConfig { compression: true, ..RouteConfig::default() });
+    let addr = spawn(config);
+    let client = reqwest::Client::new();
+    let data = serde_json::json!({"note": "compressible ".repeat(50)});
+    client.put(format!("http://{}/vault/items/a", addr)).bearer_auth("compressor").json(&data).send().await.unwrap();
+    let list = |accept_encoding: &str| {
+        client.get(format!("http://{}/vault/items", addr)).bearer_auth("compressor").header("Accept-Encoding", accept_encoding).send()
+    };
+
+    let plain = list("identity").await.unwrap();
+    assert!(plain.headers().get("Content-Encoding").is_none());
+    assert_eq!(plain.headers()["Vary"], "Accept-Encoding");
+    let plain = plain.bytes().await.unwrap();
+
+    let cases = [
+        ("gzip;q=0.5, br;q=0.8", Some("br")),
+        ("gzip, br;q=0.5", Some("gzip")),
+        ("br;q=0, gzip", Some("gzip")),
+        ("gzip;q=0", None),
+        ("identity, deflate", None),
+    ];
+    for (accept_encoding, expected) in cases {
+        let response = list(accept_encoding).await.unwrap();
+        let encoding = response.headers().get("Content-Encoding").map(|encoding| encoding.to_str().unwrap().to_string());
+        assert_eq!(encoding.as_deref(), expected, "{}", accept_encoding);
+    }
+
+    // what was compressed is the listing itself
+    let gzipped = list("gzip").await.unwrap().bytes().await.unwrap();
+    let mut decoded = Vec::new();
+    std::io::Read::read_to_end(&mut flate2::read::GzDecoder::new(&gzipped[..]), &mut decoded).unwrap();
+    assert_eq!(decoded, plain);
+}
@@ -0,0 +1,54 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use rate_limited_service::config::Config;
+use rate_limited_service::middleware::RateLimitLayer;
+use rate_limited_service::RateLimiter;
+use reqwest::StatusCode;
+use tower_layer::Layer;
+use warp::hyper::service::make_service_fn;
+use warp::hyper::Server;
+use warp::Filter;
+
+// another service's own warp app, with the rate limiting layered on top
+fn spawn_embedded(limit: u64) -> SocketAddr {
+    let app = warp::path("hello").map(|| "hi").or(warp::path("broken").map(|| warp::reply::with_status("", warp::http::StatusCode::INTERNAL_SERVER_ERROR)));
+    let service = RateLimitLayer::new(RateLimiter::new(), Arc::new(Config::default()))
+        .with_route("GET /*", limit)
+        .layer(warp::service(app));
+
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service_fn(move |_| {
+        let service = service.clone();
+        async move { Ok::<_, Infallible>(service) }
+    }));
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    addr
+}
+
+async fn get(addr: SocketAddr, path: &str) -> reqwest::Response {
+    reqwest::Client::new().get(format!("http://{}{}", addr, path)).header("Authorization", "Bearer embedded").send().await.unwrap()
+}
+
+#[tokio::test]
+async fn limits_requests_to_the_wrapped_service() {
+    let addr = spawn_embedded(1);
+
+    let response = get(addr, "/hello").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["X-Ratelimit-Remaining"], "0");
+    assert_eq!(response.text().await.unwrap(), "hi");
+
+    assert_eq!(get(addr, "/hello").await.status(), StatusCode::TOO_MANY_REQUESTS);
+    let unauthenticated = reqwest::get(format!("http://{}/hello", addr)).await.unwrap();
+    assert_eq!(unauthenticated.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn refunds_server_errors_from_the_wrapped_service() {
+    let addr = spawn_embedded(1);
+
+    assert_eq!(get(addr, "/broken").await.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(get(addr, "/hello").await.status(), StatusCode::OK);
+}
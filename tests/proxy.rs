@@ -0,0 +1,206 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rate_limited_service::config::{Config, RouteConfig};
+use rate_limited_service::proxy::ProxyConfig;
+use reqwest::StatusCode;
+use warp::Filter;
+
+mod common;
+
+// upstream that echoes back what it received
+fn spawn_upstream() -> SocketAddr {
+    let echo = warp::method()
+        .and(warp::path::full())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .and(warp::header::optional::<String>("x-custom"))
+        .and(warp::body::bytes())
+        .map(|method: warp::http::Method, path: warp::path::FullPath, query: String, custom: Option<String>, body: warp::hyper::body::Bytes| {
+            let echo = serde_json::json!({
+                "method": method.as_str(),
+                "path": path.as_str(),
+                "query": query,
+                "custom": custom,
+                "body": String::from_utf8_lossy(&body),
+            });
+            warp::reply::with_header(warp::reply::json(&echo), "X-Upstream", "yes")
+        });
+    let (addr, server) = warp::serve(echo).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    addr
+}
+
+// upstream that answers 503 to the first `failures` requests and 200 after that, counting every request it sees
+fn spawn_flaky_upstream(failures: usize) -> (SocketAddr, Arc<AtomicUsize>) {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let seen = requests.clone();
+    let flaky = warp::any().map(move || {
+        let status = if seen.fetch_add(1, Ordering::SeqCst) < failures { warp::http::StatusCode::SERVICE_UNAVAILABLE } else { warp::http::StatusCode::OK };
+        warp::reply::with_status("", status)
+    });
+    let (addr, server) = warp::serve(flaky).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    (addr, requests)
+}
+
+fn proxy_config(upstream: String) -> ProxyConfig {
+    ProxyConfig { upstream, retry_backoff_ms: 1, ..ProxyConfig::default() }
+}
+
+fn spawn_proxy(proxy: ProxyConfig, routes: Vec<(&str, u64)>) -> SocketAddr {
+    let mut config = Config::default();
+    config.proxy = Some(proxy);
+    for (route, limit) in routes {
+        config.routes.insert(route.to_string(), RouteConfig { limit: Some(limit), ..RouteConfig::default() });
+    }
+    common::spawn(config)
+}
+
+#[tokio::test]
+async fn forwards_allowed_requests_with_headers_and_body() {
+    let upstream = spawn_upstream();
+    let proxy = spawn_proxy(proxy_config(format!("http://{}", upstream)), vec![]);
+
+    let response = reqwest::Client::new()
+        .post(format!("http://{}/orders/42?expand=lines", proxy))
+        .header("Authorization", "Bearer proxied")
+        .header("X-Custom", "kept")
+        .body("order body")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-upstream"], "yes");
+    assert!(response.headers().contains_key("x-ratelimit-remaining"));
+    let echo: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(echo["method"], "POST");
+    assert_eq!(echo["path"], "/orders/42");
+    assert_eq!(echo["query"], "expand=lines");
+    assert_eq!(echo["custom"], "kept");
+    assert_eq!(echo["body"], "order body");
+}
+
+#[tokio::test]
+async fn applies_configured_route_limits_before_forwarding() {
+    let upstream = spawn_upstream();
+    let proxy = spawn_proxy(proxy_config(format!("http://{}", upstream)), vec![("GET /orders/{id}", 1)]);
+    let get = |path: &str| reqwest::Client::new().get(format!("http://{}{}", proxy, path)).header("Authorization", "Bearer limited").send();
+
+    assert_eq!(get("/orders/1").await.unwrap().status(), StatusCode::OK);
+    assert_eq!(get("/orders/1").await.unwrap().status(), StatusCode::TOO_MANY_REQUESTS);
+    // other paths fall back to the catch-all limit
+    assert_eq!(get("/customers").await.unwrap().status(), StatusCode::OK);
+    assert_eq!(reqwest::Client::new().get(format!("http://{}/customers", proxy)).send().await.unwrap().status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn limits_unconfigured_paths_by_method_class() {
+    let upstream = spawn_upstream();
+    let mut config = Config::default();
+    config.proxy = Some(proxy_config(format!("http://{}", upstream)));
+    config.method_defaults.read = Some("3/m".parse().unwrap());
+    config.method_defaults.write = Some("1/m".parse().unwrap());
+    config.routes.insert("POST /orders".to_string(), RouteConfig { limit: Some(5), ..RouteConfig::default() });
+    let proxy = common::spawn(config);
+    let client = reqwest::Client::new();
+    let send = |method: reqwest::Method, path: &str| client.request(method, format!("http://{}{}", proxy, path)).bearer_auth("methods").send();
+
+    // writes to any new endpoint share the write default
+    assert_eq!(send(reqwest::Method::DELETE, "/customers/1").await.unwrap().status(), StatusCode::OK);
+    assert_eq!(send(reqwest::Method::PATCH, "/invoices/2").await.unwrap().status(), StatusCode::TOO_MANY_REQUESTS);
+    // reads are counted apart, and a route with a limit of its own keeps it
+    assert_eq!(header(send(reqwest::Method::GET, "/customers/1").await.unwrap(), "x-ratelimit-remaining"), "2");
+    assert_eq!(header(send(reqwest::Method::POST, "/orders").await.unwrap(), "x-ratelimit-remaining"), "4");
+}
+
+fn header(response: reqwest::Response, name: &str) -> String {
+    response.headers()[name].to_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn answers_bad_gateway_when_the_upstream_is_down() {
+    // nothing listens on the discard port
+    let proxy = spawn_proxy(proxy_config("http://127.0.0.1:9".to_string()), vec![]);
+
+    let response = reqwest::Client::new().get(format!("http://{}/anything", proxy)).header("Authorization", "Bearer down").send().await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    let error: serde_json::Value = response.json().await.unwrap();
+    assert!(error["error"].as_str().unwrap().starts_with("upstream request failed"));
+}
+
+#[tokio::test]
+async fn answers_gateway_timeout_when_the_upstream_is_slow() {
+    let slow = warp::any().then(|| async {
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        "too late"
+    });
+    let (upstream, server) = warp::serve(slow).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    let proxy = spawn_proxy(ProxyConfig { read_timeout_ms: 100, retries: 0, ..proxy_config(format!("http://{}", upstream)) }, vec![]);
+
+    let response = reqwest::Client::new().get(format!("http://{}/slow", proxy)).header("Authorization", "Bearer slow").send().await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    let error: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(error["error"], "upstream timed out");
+}
+
+#[tokio::test]
+async fn retries_idempotent_requests_only() {
+    let (upstream, requests) = spawn_flaky_upstream(2);
+    let proxy = spawn_proxy(proxy_config(format!("http://{}", upstream)), vec![]);
+    let client = reqwest::Client::new();
+
+    let response = client.get(format!("http://{}/flaky", proxy)).header("Authorization", "Bearer retry").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+    let (upstream, requests) = spawn_flaky_upstream(1);
+    let proxy = spawn_proxy(proxy_config(format!("http://{}", upstream)), vec![]);
+    let response = client.post(format!("http://{}/flaky", proxy)).header("Authorization", "Bearer retry").body("x").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn streams_bodies_too_large_to_retry_through_once() {
+    let (upstream, requests) = spawn_flaky_upstream(2);
+    let proxy = spawn_proxy(ProxyConfig { max_retry_body_bytes: 16, ..proxy_config(format!("http://{}", upstream)) }, vec![]);
+    let client = reqwest::Client::new();
+
+    let response = client.put(format!("http://{}/flaky", proxy)).header("Authorization", "Bearer large").body("x".repeat(64)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+    // a body that fits is still retried
+    let response = client.put(format!("http://{}/flaky", proxy)).header("Authorization", "Bearer large").body("small").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn forwards_large_bodies_intact() {
+    let upstream = spawn_upstream();
+    let proxy = spawn_proxy(ProxyConfig { max_retry_body_bytes: 16, ..proxy_config(format!("http://{}", upstream)) }, vec![]);
+
+    let body = "y".repeat(64 * 1024);
+    let response = reqwest::Client::new().put(format!("http://{}/large", proxy)).header("Authorization", "Bearer large").body(body.clone()).send().await.unwrap();
+    let echo: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(echo["body"], body);
+}
+
+#[tokio::test]
+async fn refunds_requests_that_end_in_a_server_error() {
+    let (upstream, _) = spawn_flaky_upstream(1);
+    let proxy = spawn_proxy(ProxyConfig { retries: 0, ..proxy_config(format!("http://{}", upstream)) }, vec![("POST /orders", 1)]);
+    let post = || reqwest::Client::new().post(format!("http://{}/orders", proxy)).header("Authorization", "Bearer refunded").body("").send();
+
+    assert_eq!(post().await.unwrap().status(), StatusCode::SERVICE_UNAVAILABLE);
+    // the failed request's unit was given back, so the one allowed request still fits
+    assert_eq!(post().await.unwrap().status(), StatusCode::OK);
+    assert_eq!(post().await.unwrap().status(), StatusCode::TOO_MANY_REQUESTS);
+}
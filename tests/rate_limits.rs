@@ -0,0 +1,55 @@
+use chrono::Duration;
+use rate_limited_service::config::{Config, GroupConfig, LevelConfig};
+use rate_limited_service::{ParseRateLimitError, RateLimit};
+
+#[test]
+fn parses_limits_with_their_window() {
+    assert_eq!("100/5m".parse(), Ok(RateLimit::new(100).with_window(Duration::minutes(5))));
+    assert_eq!("10/s".parse(), Ok(RateLimit::per_second(10)));
+    assert_eq!(" 5000 / 1h ".parse(), Ok(RateLimit::per_hour(5000)));
+    assert_eq!("1/2d".parse(), Ok(RateLimit::new(1).with_window(Duration::days(2))));
+
+    assert_eq!("100".parse::<RateLimit>(), Err(ParseRateLimitError::Format));
+    assert_eq!("-1/m".parse::<RateLimit>(), Err(ParseRateLimitError::Limit));
+    assert_eq!("100/0s".parse::<RateLimit>(), Err(ParseRateLimitError::Window));
+    assert_eq!("100/-5m".parse::<RateLimit>(), Err(ParseRateLimitError::Window));
+    assert_eq!("100/5w".parse::<RateLimit>(), Err(ParseRateLimitError::Window));
+    assert_eq!("100/".parse::<RateLimit>(), Err(ParseRateLimitError::Window));
+}
+
+#[test]
+fn reads_rates_from_config() {
+    let config = Config::parse(
+        r#"
+        [routes."POST /vault"]
+        rate = "100/5m"
+
+        [limits.token]
+        rate = "10000/h"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.rate_limit("POST /vault", 3), RateLimit::new(100).with_window(Duration::minutes(5)));
+    assert_eq!(config.limits.token.unwrap().rate_limit(), RateLimit::per_hour(10000));
+
+    assert!(Config::parse("[routes.\"POST /vault\"]\nrate = \"100/0m\"").is_err());
+    assert!(Config::parse("[routes.\"POST /vault\"]\nlimit = 5\nwindow_seconds = 0").is_err());
+    assert!(Config::parse("[limits.global]\nlimit = 5\nwindow_seconds = -60").is_err());
+}
+
+#[test]
+fn rejects_windows_too_long_for_a_duration() {
+    let route = Config::parse(&format!("[routes.\"POST /vault\"]\nlimit = 5\nwindow_seconds = {}", i64::MAX)).unwrap_err();
+    assert_eq!(route.to_string(), "window_seconds for POST /vault should be positive and in range");
+    let level = Config::parse(&format!("[limits.token]\nlimit = 5\nwindow_seconds = {}", i64::MAX)).unwrap_err();
+    assert_eq!(level.to_string(), "window_seconds for limits.token should be positive and in range");
+
+    // a level built without going through parse falls back to the default window
+    let level = LevelConfig { limit: 5, window_seconds: Some(i64::MAX), rate: None };
+    assert_eq!(level.rate_limit(), RateLimit::new(5));
+
+    let group = Config::parse(&format!("[groups.writes]\nroutes = [\"POST /vault\"]\nlimit = 5\nwindow_seconds = {}", i64::MAX)).unwrap_err();
+    assert_eq!(group.to_string(), "window_seconds for groups.writes should be positive and in range");
+    let group = GroupConfig { routes: vec!["POST /vault".to_string()], limit: 5, window_seconds: Some(i64::MAX), rate: None };
+    assert_eq!(group.rate_limit(), RateLimit::new(5));
+}
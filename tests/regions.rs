@@ -0,0 +1,49 @@
+use std::sync::Arc;
+
+use rate_limited_service::config::LevelConfig;
+use rate_limited_service::region::{Region, RegionConfig};
+use rate_limited_service::store::{InMemoryStore, UsageStore};
+use rate_limited_service::{LimitLevel, RateLimit, RateLimiter, UsageError};
+
+// an instance in region `name`, every region sharing `store`
+fn in_region(store: &Arc<InMemoryStore>, name: &str, global: Option<u64>) -> RateLimiter {
+    // every ceiling charge is written through, so the regions see each other's usage straight away
+    let config = RegionConfig {
+        name: name.to_string(),
+        global: global.map(|limit| LevelConfig { limit, ..LevelConfig::default() }),
+        max_unsynced_cost: 0,
+        ..RegionConfig::default()
+    };
+    let store: Arc<dyn UsageStore> = store.clone();
+    RateLimiter::with_store(store.clone()).with_region(Region::new(&config, store))
+}
+
+fn post_vault(rate_limiter: &RateLimiter) -> Result<(u64, chrono::DateTime<chrono::Utc>), UsageError> {
+    rate_limiter.clone().log_usage("POST /vault", "Bearer roaming".to_string(), RateLimit::new(2))
+}
+
+#[test]
+fn gives_each_region_its_own_quota() {
+    let store = Arc::new(InMemoryStore::new());
+    let (eu, us) = (in_region(&store, "eu-west-1", None), in_region(&store, "us-east-1", None));
+
+    assert!(post_vault(&eu).is_ok());
+    assert!(post_vault(&eu).is_ok());
+    assert!(post_vault(&eu).is_err());
+    assert_eq!(post_vault(&us).unwrap().0, 1);
+}
+
+#[test]
+fn caps_a_client_across_regions() {
+    let store = Arc::new(InMemoryStore::new());
+    let (eu, us) = (in_region(&store, "eu-west-1", Some(3)), in_region(&store, "us-east-1", Some(3)));
+
+    assert!(post_vault(&eu).is_ok());
+    assert!(post_vault(&eu).is_ok());
+    assert!(post_vault(&us).is_ok());
+    // us-east-1 has a request of its own quota left, but not of the ceiling
+    match post_vault(&us) {
+        Err(UsageError::RateLimited(err)) => assert_eq!(err.level, LimitLevel::Region),
+        other => panic!("expected the ceiling to limit the request, got {:?}", other),
+    }
+}
@@ -0,0 +1,99 @@
+use std::net::SocketAddr;
+
+use chrono::Duration;
+use rate_limited_service::config::{Config, RouteConfig};
+use rate_limited_service::server::POST_VAULT_ROUTE;
+use rate_limited_service::{RateLimit, RateLimiter, ReservationError, UsageError};
+use reqwest::StatusCode;
+use serde_json::json;
+
+mod common;
+
+fn spawn(limit: u64) -> SocketAddr {
+    let mut config = Config::default();
+    config.routes.insert(POST_VAULT_ROUTE.to_string(), RouteConfig { limit: Some(limit), ..RouteConfig::default() });
+    common::spawn(config)
+}
+
+async fn post_vault(addr: SocketAddr, bearer_token: &str) -> StatusCode {
+    reqwest::Client::new().post(format!("http://{}/vault", addr)).header("Authorization", bearer_token).send().await.unwrap().status()
+}
+
+#[tokio::test]
+async fn reserved_quota_is_held_until_committed() {
+    let addr = spawn(3);
+    let client = reqwest::Client::new();
+
+    let reservation: serde_json::Value = client
+        .post(format!("http://{}/vault/reservations", addr))
+        .header("Authorization", "Bearer batch-job")
+        .json(&json!({ "route": POST_VAULT_ROUTE, "amount": 2 }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(reservation["remaining"], 1);
+    let id = reservation["id"].as_str().unwrap();
+
+    assert_eq!(post_vault(addr, "Bearer batch-job").await, StatusCode::OK);
+    assert_eq!(post_vault(addr, "Bearer batch-job").await, StatusCode::TOO_MANY_REQUESTS);
+
+    // only the reserving client can commit
+    let commit = |bearer_token: &'static str| {
+        client
+            .post(format!("http://{}/vault/reservations/{}/commit", addr, id))
+            .header("Authorization", bearer_token)
+            .json(&json!({ "cost": 1 }))
+            .send()
+    };
+    assert_eq!(commit("Bearer someone-else").await.unwrap().status(), StatusCode::NOT_FOUND);
+
+    // the job only used one of the two units it reserved, the other is free again
+    let committed = commit("Bearer batch-job").await.unwrap();
+    assert_eq!(committed.status(), StatusCode::OK);
+    assert_eq!(committed.headers()["X-Ratelimit-Remaining"], "1");
+    assert_eq!(commit("Bearer batch-job").await.unwrap().status(), StatusCode::NOT_FOUND);
+    assert_eq!(post_vault(addr, "Bearer batch-job").await, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn refuses_bodies_over_the_size_limit() {
+    let addr = spawn(3);
+    let client = reqwest::Client::new();
+    let oversized = vec![b' '; 65 * 1024];
+
+    for path in ["/vault/reservations", "/vault/reservations/abc/commit"] {
+        let response = client.post(format!("http://{}{}", addr, path)).header("Authorization", "Bearer batch-job").body(oversized.clone()).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE, "{}", path);
+    }
+}
+
+#[test]
+fn unreleased_reservations_expire() {
+    let rate_limiter = RateLimiter::new();
+    let rate_limit = RateLimit::new(2);
+
+    let reservation = rate_limiter.reserve("jobs", "Bearer job", &rate_limit, 2, Duration::milliseconds(50)).unwrap();
+    assert!(matches!(rate_limiter.reserve("jobs", "Bearer job", &rate_limit, 1, Duration::seconds(60)), Err(UsageError::RateLimited(_))));
+
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    assert!(matches!(rate_limiter.cancel(&reservation.id, "Bearer job", &rate_limit), Err(ReservationError::NotFound)));
+    assert!(rate_limiter.clone().log_weighted_usage("jobs", "Bearer job".to_string(), rate_limit, 2).is_ok());
+}
+
+#[test]
+fn counts_limits_past_i32_without_overflowing() {
+    let rate_limiter = RateLimiter::new();
+    let rate_limit = RateLimit::new(u64::MAX);
+
+    let reservation = rate_limiter.reserve("jobs", "Bearer job", &rate_limit, u64::MAX - 1, Duration::seconds(60)).unwrap();
+    assert_eq!(reservation.remaining, 1);
+    // charging more than was left empties the window instead of wrapping around
+    assert_eq!(rate_limiter.commit(&reservation.id, "Bearer job", &rate_limit, u64::MAX).unwrap().0, 0);
+    assert!(matches!(rate_limiter.clone().log_weighted_usage("jobs", "Bearer job".to_string(), rate_limit, 1), Err(UsageError::RateLimited(_))));
+
+    let (remaining, _) = rate_limiter.clone().log_weighted_usage("batch", "Bearer job".to_string(), RateLimit::new(5_000_000_000), 3_000_000_000).unwrap();
+    assert_eq!(remaining, 2_000_000_000);
+}
@@ -0,0 +1,31 @@
+use rate_limited_service::router::{RoutePattern, Router};
+
+#[test]
+fn matches_parameters_in_either_syntax() {
+    let router = Router::new(["PUT /vault/items/{id}", "DELETE /vault/items/<:id>"]);
+
+    assert_eq!(router.match_route("PUT", "/vault/items/abc"), Some("PUT /vault/items/{id}"));
+    assert_eq!(router.match_route("DELETE", "/vault/items/abc"), Some("DELETE /vault/items/<:id>"));
+    assert_eq!(router.match_route("GET", "/vault/items/abc"), None);
+    assert_eq!(router.match_route("PUT", "/vault/items/abc/extra"), None);
+    assert_eq!(router.match_route("PUT", "/vault/items"), None);
+}
+
+#[test]
+fn prefers_the_most_specific_template() {
+    let router = Router::new(["* /vault/*", "PUT /vault/items/{id}", "PUT /vault/items/pinned", "PUT /vault/{collection}/{id}"]);
+
+    assert_eq!(router.match_route("PUT", "/vault/items/pinned"), Some("PUT /vault/items/pinned"));
+    assert_eq!(router.match_route("PUT", "/vault/items/abc"), Some("PUT /vault/items/{id}"));
+    assert_eq!(router.match_route("PUT", "/vault/archive/abc"), Some("PUT /vault/{collection}/{id}"));
+    assert_eq!(router.match_route("post", "/vault/anything/at/all"), Some("* /vault/*"));
+    assert_eq!(router.match_route("POST", "/other"), None);
+}
+
+#[test]
+fn rejects_malformed_templates() {
+    assert!(RoutePattern::parse("/vault/items").is_none());
+    assert!(RoutePattern::parse("GET vault/items").is_none());
+    assert!(RoutePattern::parse("GET /vault/*/items").is_none());
+    assert!(RoutePattern::parse("POST /vault/items:batch").is_some());
+}
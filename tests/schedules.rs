@@ -0,0 +1,51 @@
+use chrono::{DateTime, Utc};
+use rate_limited_service::config::Config;
+
+fn at(time: &str) -> DateTime<Utc> {
+    time.parse().unwrap()
+}
+
+#[test]
+fn applies_daily_ranges_including_past_midnight() {
+    let config = Config::parse(
+        r#"
+        [[routes."POST /vault".schedules]]
+        limit = 1
+        from = "22:00"
+        until = "06:00"
+        days = ["Fri"]
+
+        [[routes."POST /vault".schedules]]
+        limit = 100
+        from = "09:00:00"
+        until = "17:00:00"
+        "#,
+    )
+    .unwrap();
+    let route = config.route("POST /vault");
+
+    // 2026-10-16 is a Friday
+    assert_eq!(route.scheduled_limit(at("2026-10-16T23:00:00Z")), Some(1));
+    assert_eq!(route.scheduled_limit(at("2026-10-17T05:59:59Z")), Some(1));
+    assert_eq!(route.scheduled_limit(at("2026-10-17T23:00:00Z")), None);
+    assert_eq!(route.scheduled_limit(at("2026-10-16T12:00:00Z")), Some(100));
+    assert_eq!(route.scheduled_limit(at("2026-10-16T17:00:00Z")), None);
+}
+
+#[test]
+fn applies_absolute_ranges() {
+    let config = Config::parse(
+        r#"
+        [[routes."POST /vault".schedules]]
+        limit = 50
+        starts_at = "2026-11-01T09:00:00Z"
+        ends_at = "2026-11-02T09:00:00Z"
+        "#,
+    )
+    .unwrap();
+    let route = config.route("POST /vault");
+
+    assert_eq!(route.scheduled_limit(at("2026-11-01T08:59:59Z")), None);
+    assert_eq!(route.scheduled_limit(at("2026-11-01T12:00:00Z")), Some(50));
+    assert_eq!(route.scheduled_limit(at("2026-11-02T09:00:00Z")), None);
+}
@@ -0,0 +1,767 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use rate_limited_service::config::{Config, DeprecationConfig, GroupConfig, LevelConfig, RouteConfig, ScheduleConfig};
+use rate_limited_service::key_extractor::{KeyExtractorConfig, RequestInfo, Signal};
+use rate_limited_service::response_cache::ResponseCacheConfig;
+use rate_limited_service::scopes::{ApiKeyConfig, AuthError, Authenticator, TokenClaims};
+use rate_limited_service::server::{DELETE_VAULT_ITEM_ROUTE, POST_VAULT_ROUTE};
+use rate_limited_service::RateLimit;
+use reqwest::StatusCode;
+
+mod common;
+
+use common::spawn;
+
+// POST /vault with a small limit and a window short enough to wait out in a test
+fn short_window_config(limit: u64, window_seconds: i64) -> Config {
+    let mut config = Config::default();
+    config.routes.insert(
+        POST_VAULT_ROUTE.to_string(),
+        RouteConfig { limit: Some(limit), window_seconds: Some(window_seconds), ..RouteConfig::default() },
+    );
+    config
+}
+
+async fn post_vault(addr: SocketAddr, bearer_token: Option<&str>) -> reqwest::Response {
+    let mut request = reqwest::Client::new().post(format!("http://{}/vault", addr));
+    if let Some(bearer_token) = bearer_token {
+        request = request.header("Authorization", bearer_token);
+    }
+    request.send().await.unwrap()
+}
+
+fn header(response: &reqwest::Response, name: &str) -> Option<i64> {
+    response.headers().get(name)?.to_str().ok()?.parse().ok()
+}
+
+#[tokio::test]
+async fn rejects_requests_without_a_bearer_token() {
+    let addr = spawn(Config::default());
+
+    let response = post_vault(addr, None).await;
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(response.headers().contains_key("X-Request-Id"));
+    assert_eq!(response.headers()["WWW-Authenticate"], "Bearer realm=\"rate_limited_service\"");
+}
+
+#[tokio::test]
+async fn counts_down_remaining_requests_then_limits() {
+    let addr = spawn(short_window_config(3, 60));
+
+    for expected_remaining in [2, 1, 0] {
+        let response = post_vault(addr, Some("Bearer countdown")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, "X-Ratelimit-Remaining"), Some(expected_remaining));
+    }
+
+    let response = post_vault(addr, Some("Bearer countdown")).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let retry_after = header(&response, "X-Ratelimit-Retry-After").unwrap();
+    assert!((0..=60).contains(&retry_after));
+}
+
+#[tokio::test]
+async fn spreads_retry_after_with_jitter() {
+    let mut config = short_window_config(1, 60);
+    config.retry_after_jitter_seconds = 30;
+    let addr = spawn(config);
+
+    let mut retry_afters = Vec::new();
+    for client in 0..10 {
+        let bearer_token = format!("Bearer jittered-{}", client);
+        post_vault(addr, Some(&bearer_token)).await;
+        let response = post_vault(addr, Some(&bearer_token)).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        retry_afters.push(header(&response, "X-Ratelimit-Retry-After").unwrap());
+    }
+
+    // never earlier than the reset, and not all the same
+    assert!(retry_afters.iter().all(|retry_after| (58..=90).contains(retry_after)));
+    assert!(retry_afters.iter().any(|retry_after| *retry_after != retry_afters[0]));
+}
+
+#[tokio::test]
+async fn caps_retry_after_jitter_at_an_hour() {
+    let mut config = short_window_config(1, 60);
+    config.retry_after_jitter_seconds = u64::MAX;
+    let addr = spawn(config);
+
+    post_vault(addr, Some("Bearer patient")).await;
+    let response = post_vault(addr, Some("Bearer patient")).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!((58..=60 + 60 * 60).contains(&header(&response, "X-Ratelimit-Retry-After").unwrap()));
+}
+
+#[tokio::test]
+async fn renders_the_rate_limited_body() {
+    let addr = spawn(
+        Config::parse(
+            r#"
+            [routes."POST /vault"]
+            limit = 1
+            window_seconds = 60
+            rate_limited_body = '{"error": "limited at the {level} level to {limit} per {window_seconds}s, retry in {retry_after}s"}'
+            rate_limited_content_type = "application/json"
+            "#,
+        )
+        .unwrap(),
+    );
+
+    post_vault(addr, Some("Bearer templated")).await;
+    let response = post_vault(addr, Some("Bearer templated")).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()["Content-Type"], "application/json");
+    let retry_after = header(&response, "X-Ratelimit-Retry-After").unwrap();
+    // the body tells the client the same retry_after as the header
+    let expected = format!(r#"{{"error": "limited at the route level to 1 per 60s, retry in {}s"}}"#, retry_after);
+    assert_eq!(response.text().await.unwrap(), expected);
+}
+
+#[tokio::test]
+async fn limits_each_bearer_token_separately() {
+    let addr = spawn(short_window_config(1, 60));
+
+    assert_eq!(post_vault(addr, Some("Bearer first")).await.status(), StatusCode::OK);
+    assert_eq!(post_vault(addr, Some("Bearer first")).await.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(post_vault(addr, Some("Bearer second")).await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn recovers_once_the_window_has_passed() {
+    let addr = spawn(short_window_config(2, 1));
+
+    assert_eq!(post_vault(addr, Some("Bearer recovery")).await.status(), StatusCode::OK);
+    assert_eq!(post_vault(addr, Some("Bearer recovery")).await.status(), StatusCode::OK);
+    assert_eq!(post_vault(addr, Some("Bearer recovery")).await.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+
+    let response = post_vault(addr, Some("Bearer recovery")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, "X-Ratelimit-Remaining"), Some(1));
+}
+
+#[tokio::test]
+async fn forbids_tokens_missing_a_required_scope_without_charging_them() {
+    let mut config = short_window_config(1, 60);
+    let route = config.routes.get_mut(POST_VAULT_ROUTE).unwrap();
+    route.required_scopes = vec!["vault:write".to_string()];
+    route.scope_limits.insert("vault:bulk".to_string(), 3);
+    config.api_keys = vec![
+        ApiKeyConfig { token_sha256: sha256::digest("reader"), scopes: vec!["vault:read".to_string()], ..ApiKeyConfig::default() },
+        ApiKeyConfig { token_sha256: sha256::digest("bulk-writer"), scopes: vec!["vault:write".to_string(), "vault:bulk".to_string()], ..ApiKeyConfig::default() },
+    ];
+    let addr = spawn(config);
+
+    assert_eq!(post_vault(addr, Some("Bearer reader")).await.status(), StatusCode::FORBIDDEN);
+    // a token that isn't a key at all is unauthenticated rather than forbidden
+    let response = post_vault(addr, Some("Bearer unknown")).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(!response.headers().contains_key("WWW-Authenticate"));
+
+    // the per-scope override lifts the route's limit of 1
+    let response = post_vault(addr, Some("Bearer bulk-writer")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, "X-Ratelimit-Remaining"), Some(2));
+}
+
+#[tokio::test]
+async fn turns_away_clients_that_keep_failing_auth_but_not_heavy_users() {
+    let mut config = short_window_config(10, 60);
+    let route = config.routes.get_mut(POST_VAULT_ROUTE).unwrap();
+    route.key = KeyExtractorConfig::ClientIp { trust_forwarded_for: false };
+    route.required_scopes = vec!["vault:write".to_string()];
+    route.abuse_limit = Some("2/m".parse().unwrap());
+    config.api_keys = vec![ApiKeyConfig { token_sha256: sha256::digest("writer"), scopes: vec!["vault:write".to_string()], ..ApiKeyConfig::default() }];
+    let addr = spawn(config);
+
+    // successful requests don't count against the abuse limit
+    for _ in 0..3 {
+        assert_eq!(post_vault(addr, Some("Bearer writer")).await.status(), StatusCode::OK);
+    }
+    for guess in ["Bearer guess-1", "Bearer guess-2"] {
+        assert_eq!(post_vault(addr, Some(guess)).await.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    // from the same address even the right token is turned away now
+    let response = post_vault(addr, Some("Bearer writer")).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()["X-Ratelimit-Level"], "abuse");
+}
+
+#[tokio::test]
+async fn reports_which_level_of_the_hierarchy_was_exhausted() {
+    let mut config = short_window_config(10, 60);
+    config.limits.tenant = Some(LevelConfig { limit: 2, ..LevelConfig::default() });
+    config.limits.global = Some(LevelConfig { limit: 3, ..LevelConfig::default() });
+    config.api_keys = vec![
+        ApiKeyConfig { token_sha256: sha256::digest("acme-1"), tenant: Some("acme".to_string()), ..ApiKeyConfig::default() },
+        ApiKeyConfig { token_sha256: sha256::digest("acme-2"), tenant: Some("acme".to_string()), ..ApiKeyConfig::default() },
+    ];
+    let addr = spawn(config);
+
+    // the tightest level is reported as remaining
+    assert_eq!(header(&post_vault(addr, Some("Bearer acme-1")).await, "X-Ratelimit-Remaining"), Some(1));
+    post_vault(addr, Some("Bearer acme-2")).await;
+
+    // both of acme's tokens share its tenant limit
+    let response = post_vault(addr, Some("Bearer acme-1")).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()["X-Ratelimit-Level"], "tenant");
+
+    // the rejected request wasn't charged to the global level, so one more fits
+    assert_eq!(post_vault(addr, Some("Bearer other")).await.status(), StatusCode::OK);
+    let response = post_vault(addr, Some("Bearer other")).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()["X-Ratelimit-Level"], "global");
+}
+
+#[tokio::test]
+async fn counts_grouped_routes_against_one_shared_pool() {
+    let mut config = short_window_config(10, 60);
+    let routes = vec![POST_VAULT_ROUTE.to_string(), DELETE_VAULT_ITEM_ROUTE.to_string()];
+    config.groups.insert("writes".to_string(), GroupConfig { routes, rate: Some("3/m".parse().unwrap()), ..GroupConfig::default() });
+    let addr = spawn(config);
+    let client = reqwest::Client::new();
+
+    assert_eq!(header(&post_vault(addr, Some("Bearer writer")).await, "X-Ratelimit-Remaining"), Some(2));
+    let response = client.delete(format!("http://{}/vault/items/a", addr)).bearer_auth("writer").send().await.unwrap();
+    assert_eq!(header(&response, "X-Ratelimit-Remaining"), Some(1));
+    assert_eq!(header(&post_vault(addr, Some("Bearer writer")).await, "X-Ratelimit-Remaining"), Some(0));
+
+    // the group's limit applies in place of the route's own 10
+    let response = post_vault(addr, Some("Bearer writer")).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()["X-Ratelimit-Level"], "group");
+    assert_eq!(response.headers()["X-Ratelimit-Group"], "writes");
+
+    // routes outside the group keep their own limits
+    let response = client.get(format!("http://{}/vault/items", addr)).bearer_auth("writer").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn emits_configured_header_names() {
+    let mut config = short_window_config(1, 60);
+    config.headers.remaining = "X-Rate-Limit-Remaining".to_string();
+    config.headers.retry_after = "X-Rate-Limit-Retry-After".to_string();
+    let addr = spawn(config);
+
+    let response = post_vault(addr, Some("Bearer renamed")).await;
+    assert_eq!(header(&response, "X-Rate-Limit-Remaining"), Some(0));
+    assert!(!response.headers().contains_key("X-Ratelimit-Remaining"));
+
+    let response = post_vault(addr, Some("Bearer renamed")).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(header(&response, "X-Rate-Limit-Retry-After").is_some());
+    assert!(!response.headers().contains_key("X-Ratelimit-Retry-After"));
+}
+
+#[tokio::test]
+async fn warns_past_the_soft_limit_before_rejecting() {
+    let mut config = short_window_config(5, 60);
+    config.routes.get_mut(POST_VAULT_ROUTE).unwrap().soft_limit_percent = Some(60);
+    let addr = spawn(config);
+
+    for _ in 0..2 {
+        let response = post_vault(addr, Some("Bearer soft")).await;
+        assert!(!response.headers().contains_key("X-Ratelimit-Warning"));
+    }
+    let response = post_vault(addr, Some("Bearer soft")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["X-Ratelimit-Warning"], "3 of 5 used, slow down");
+
+    let metrics = reqwest::get(format!("http://{}/metrics", addr)).await.unwrap().text().await.unwrap();
+    assert!(metrics.contains("rate_limiter_soft_limit_warnings_total 1"));
+}
+
+#[tokio::test]
+async fn applies_a_scheduled_limit_while_it_is_in_effect() {
+    let mut config = short_window_config(5, 60);
+    config.routes.get_mut(POST_VAULT_ROUTE).unwrap().schedules = vec![ScheduleConfig {
+        limit: 1,
+        from: None,
+        until: None,
+        days: Vec::new(),
+        starts_at: Some(Utc::now() - chrono::Duration::hours(1)),
+        ends_at: Some(Utc::now() + chrono::Duration::hours(1)),
+    }];
+    let addr = spawn(config);
+
+    assert_eq!(post_vault(addr, Some("Bearer scheduled")).await.status(), StatusCode::OK);
+    assert_eq!(post_vault(addr, Some("Bearer scheduled")).await.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn steers_clients_off_a_deprecated_route() {
+    let mut config = short_window_config(5, 60);
+    config.routes.get_mut(POST_VAULT_ROUTE).unwrap().deprecation = Some(DeprecationConfig {
+        since: "2026-01-01T00:00:00Z".parse().unwrap(),
+        sunset: Some("2027-01-01T00:00:00Z".parse().unwrap()),
+        link: Some("https://example.com/migrating".to_string()),
+        rate: Some(RateLimit::new(1)),
+    });
+    let addr = spawn(config);
+
+    let response = post_vault(addr, Some("Bearer deprecated")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["Deprecation"], "@1767225600");
+    assert_eq!(response.headers()["Sunset"], "Fri, 01 Jan 2027 00:00:00 GMT");
+    assert_eq!(response.headers()["Link"], "<https://example.com/migrating>; rel=\"deprecation\"; type=\"text/html\"");
+
+    // held to the deprecated limit, and still told why
+    let response = post_vault(addr, Some("Bearer deprecated")).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()["Deprecation"], "@1767225600");
+}
+
+#[tokio::test]
+async fn applies_limit_overrides_set_through_the_admin_api() {
+    let mut config = short_window_config(1, 60);
+    config.admin_token = Some("admin".to_string());
+    let addr = spawn(config);
+    let client = reqwest::Client::new();
+    let url = format!("http://{}/admin/limits/Bearer%20partner", addr);
+    let body = serde_json::json!({"limit": 3, "route": POST_VAULT_ROUTE, "ttl_seconds": 3600});
+
+    let response = client.put(&url).json(&body).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = client.put(&url).bearer_auth("admin").json(&body).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    for remaining in (0..3).rev() {
+        let response = post_vault(addr, Some("Bearer partner")).await;
+        assert_eq!(header(&response, "X-Ratelimit-Remaining"), Some(remaining));
+    }
+    assert_eq!(post_vault(addr, Some("Bearer partner")).await.status(), StatusCode::TOO_MANY_REQUESTS);
+    // other clients keep the configured limit
+    assert_eq!(post_vault(addr, Some("Bearer other")).await.status(), StatusCode::OK);
+    assert_eq!(post_vault(addr, Some("Bearer other")).await.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    let response = client.delete(format!("{}?route=POST%20%2Fvault", url)).bearer_auth("admin").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn rejects_limit_overrides_with_windows_or_ttls_out_of_range() {
+    let mut config = Config::default();
+    config.admin_token = Some("admin".to_string());
+    let addr = spawn(config);
+    let client = reqwest::Client::new();
+    let url = format!("http://{}/admin/limits/Bearer%20partner", addr);
+
+    for (window_seconds, ttl_seconds) in [(0, 60), (-60, 60), (i64::MAX, 60), (60, 0), (60, -60), (60, i64::MAX), (60, i64::MAX / 1000)] {
+        let body = serde_json::json!({"limit": 3, "window_seconds": window_seconds, "ttl_seconds": ttl_seconds});
+        let response = client.put(&url).bearer_auth("admin").json(&body).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{} {}", window_seconds, ttl_seconds);
+    }
+    let body = serde_json::json!({"limit": 3, "window_seconds": 60, "ttl_seconds": 60});
+    let response = client.put(&url).bearer_auth("admin").json(&body).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn explains_why_a_malformed_body_was_rejected() {
+    let mut config = Config::default();
+    config.admin_token = Some("admin".to_string());
+    let addr = spawn(config);
+
+    let response = reqwest::Client::new()
+        .put(format!("http://{}/admin/limits/Bearer%20partner", addr))
+        .bearer_auth("admin")
+        .json(&serde_json::json!({"limit": "lots"}))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(response.headers().contains_key("X-Request-Id"));
+    assert!(response.text().await.unwrap().starts_with("invalid request:"));
+}
+
+#[tokio::test]
+async fn authenticates_admin_requests_before_reading_their_bodies() {
+    let config = Config::parse("admin_token = \"admin\"\nbypass_token_secret = \"bypass-secret\"").unwrap();
+    let addr = spawn(config);
+    let client = reqwest::Client::new();
+    let bypass_tokens = format!("http://{}/admin/bypass-tokens", addr);
+    let limits = format!("http://{}/admin/limits/Bearer%20partner", addr);
+
+    // a body that isn't even JSON is never looked at without the admin token
+    let response = client.post(&bypass_tokens).bearer_auth("guess").body("not json").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = client.put(&limits).bearer_auth("guess").body("not json").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let oversized = format!("{{\"subject\": \"{}\", \"ttl_seconds\": 60}}", "a".repeat(32 * 1024));
+    let response = client.post(&bypass_tokens).bearer_auth("admin").body(oversized.clone()).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let response = client.put(&limits).bearer_auth("admin").body(oversized).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let response = client.put(&limits).bearer_auth("admin").json(&serde_json::json!({"limit": 5})).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn answers_other_rejections_the_same_way() {
+    let addr = spawn(Config::default());
+    let client = reqwest::Client::new();
+
+    assert_eq!(client.patch(format!("http://{}/vault", addr)).send().await.unwrap().status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(client.get(format!("http://{}/nowhere", addr)).send().await.unwrap().status(), StatusCode::NOT_FOUND);
+    let response = client.get(format!("http://{}/vault/items?offset=many", addr)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(response.text().await.unwrap().starts_with("invalid request:"));
+}
+
+// an HS256 JWT with the given claims
+fn jwt(secret: &str, claims: serde_json::Value) -> String {
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use hmac::{Hmac, Mac};
+
+    let signed = format!("{}.{}", URL_SAFE_NO_PAD.encode(r#"{"alg":"HS256","typ":"JWT"}"#), URL_SAFE_NO_PAD.encode(claims.to_string()));
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(signed.as_bytes());
+    format!("{}.{}", signed, URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes()))
+}
+
+#[tokio::test]
+async fn accepts_recently_expired_tokens_with_a_warning() {
+    let mut config = short_window_config(10, 60);
+    config.jwt_secret = Some("secret".to_string());
+    config.jwt_expiry_grace_seconds = 300;
+    config.routes.get_mut(POST_VAULT_ROUTE).unwrap().required_scopes = vec!["vault:write".to_string()];
+    let addr = spawn(config);
+    let now = Utc::now().timestamp();
+
+    let fresh = jwt("secret", serde_json::json!({"scope": "vault:write", "exp": now + 60}));
+    let response = post_vault(addr, Some(&format!("Bearer {}", fresh))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!response.headers().contains_key("X-Token-Expiring"));
+
+    let expiring = jwt("secret", serde_json::json!({"scope": "vault:write", "exp": now - 60}));
+    let response = post_vault(addr, Some(&format!("Bearer {}", expiring))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let grace_ends_at: chrono::DateTime<Utc> = response.headers()["X-Token-Expiring"].to_str().unwrap().parse().unwrap();
+    assert_eq!(grace_ends_at.timestamp(), now + 240);
+
+    let expired = jwt("secret", serde_json::json!({"scope": "vault:write", "exp": now - 600}));
+    assert_eq!(post_vault(addr, Some(&format!("Bearer {}", expired))).await.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn rejects_tokens_whose_grace_would_overflow() {
+    assert!(Config::parse(&format!("jwt_expiry_grace_seconds = {}", i64::MAX)).is_err());
+
+    let mut config = short_window_config(10, 60);
+    config.jwt_secret = Some("secret".to_string());
+    config.jwt_expiry_grace_seconds = i64::MAX / 1000;
+    config.routes.get_mut(POST_VAULT_ROUTE).unwrap().required_scopes = vec!["vault:write".to_string()];
+    let addr = spawn(config);
+
+    // the latest expiry there is, any grace at all takes it out of range
+    let exp = chrono::DateTime::<Utc>::MAX_UTC.timestamp();
+    let token = jwt("secret", serde_json::json!({"scope": "vault:write", "exp": exp}));
+    assert_eq!(post_vault(addr, Some(&format!("Bearer {}", token))).await.status(), StatusCode::UNAUTHORIZED);
+}
+
+// trusts a header set by a gateway in front, in place of the configured keys
+#[derive(Debug)]
+struct GatewayAuthenticator;
+
+impl Authenticator for GatewayAuthenticator {
+    fn authenticate(&self, _config: &Config, request: &RequestInfo) -> Result<TokenClaims, AuthError> {
+        match request.header("X-Gateway-Scopes") {
+            Some(scopes) => Ok(TokenClaims { scopes: scopes.split(' ').map(str::to_string).collect(), ..TokenClaims::default() }),
+            None => Err(AuthError::Invalid),
+        }
+    }
+}
+
+#[tokio::test]
+async fn checks_credentials_with_a_configured_authenticator() {
+    let mut config = short_window_config(1, 60);
+    config.routes.get_mut(POST_VAULT_ROUTE).unwrap().required_scopes = vec!["vault:write".to_string()];
+    config.authenticator = Some(Arc::new(GatewayAuthenticator));
+    let addr = spawn(config);
+    let post_vault = |scopes: &'static str| reqwest::Client::new().post(format!("http://{}/vault", addr)).header("Authorization", "Bearer gateway").header("X-Gateway-Scopes", scopes).send();
+
+    assert_eq!(post_vault("vault:read").await.unwrap().status(), StatusCode::FORBIDDEN);
+    assert_eq!(post_vault("vault:read vault:write").await.unwrap().status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn answers_every_auth_failure_alike_under_a_uniform_policy() {
+    let mut config = short_window_config(1, 60);
+    config.routes.get_mut(POST_VAULT_ROUTE).unwrap().required_scopes = vec!["vault:write".to_string()];
+    config.api_keys = vec![ApiKeyConfig { token_sha256: sha256::digest("reader"), scopes: vec!["vault:read".to_string()], ..ApiKeyConfig::default() }];
+    config.auth.uniform = true;
+    let addr = spawn(config);
+
+    for bearer_token in [None, Some("Bearer unknown"), Some("Bearer reader")] {
+        let response = post_vault(addr, bearer_token).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(!response.headers().contains_key("WWW-Authenticate"));
+    }
+}
+
+#[tokio::test]
+async fn reports_usage_statistics_to_admins() {
+    let mut config = short_window_config(2, 60);
+    config.admin_token = Some("admin".to_string());
+    let addr = spawn(config);
+
+    for _ in 0..3 {
+        post_vault(addr, Some("Bearer stats")).await;
+    }
+    post_vault(addr, Some("Bearer other")).await;
+
+    let url = format!("http://{}/admin/stats", addr);
+    let client = reqwest::Client::new();
+    assert_eq!(client.get(&url).send().await.unwrap().status(), StatusCode::UNAUTHORIZED);
+    let stats: serde_json::Value = client.get(&url).bearer_auth("admin").send().await.unwrap().json().await.unwrap();
+    let route = &stats["routes"][POST_VAULT_ROUTE];
+    assert_eq!(route["requests_last_minute"], 4);
+    assert_eq!(route["requests_last_hour"], 4);
+    assert_eq!(route["active_keys"], 2);
+    assert_eq!(route["limited_requests"], 1);
+    assert_eq!(route["p95_remaining"], 1);
+}
+
+#[tokio::test]
+async fn lists_the_clients_with_the_most_rejected_requests() {
+    let mut config = short_window_config(1, 60);
+    config.admin_token = Some("admin".to_string());
+    let addr = spawn(config);
+
+    for _ in 0..4 {
+        post_vault(addr, Some("Bearer noisy")).await;
+    }
+    for _ in 0..2 {
+        post_vault(addr, Some("Bearer quiet")).await;
+    }
+
+    let url = format!("http://{}/admin/top-offenders?limit=1", addr);
+    let report: serde_json::Value = reqwest::Client::new().get(&url).bearer_auth("admin").send().await.unwrap().json().await.unwrap();
+    let noisy = serde_json::json!({"key_sha256": sha256::digest("Bearer noisy"), "tenant": null, "requests": 4, "limited_requests": 3});
+    assert_eq!(report["by_limited_requests"], serde_json::json!([noisy]));
+    assert_eq!(report["by_requests"], serde_json::json!([noisy]));
+}
+
+#[tokio::test]
+async fn clamps_the_top_offenders_window_to_the_kept_hour() {
+    let mut config = short_window_config(1, 60);
+    config.admin_token = Some("admin".to_string());
+    let addr = spawn(config);
+    post_vault(addr, Some("Bearer noisy")).await;
+
+    for window_seconds in [i64::MIN, -60, 0, i64::MAX] {
+        let url = format!("http://{}/admin/top-offenders?window_seconds={}", addr, window_seconds);
+        let response = reqwest::Client::new().get(&url).bearer_auth("admin").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK, "{}", window_seconds);
+    }
+    let url = format!("http://{}/admin/top-offenders?window_seconds={}", addr, i64::MAX);
+    let report: serde_json::Value = reqwest::Client::new().get(&url).bearer_auth("admin").send().await.unwrap().json().await.unwrap();
+    assert_eq!(report["by_requests"][0]["requests"], 1);
+}
+
+#[tokio::test]
+async fn probing_a_route_reports_its_quota_without_charging_it() {
+    let addr = spawn(short_window_config(1, 60));
+    let probe = || {
+        reqwest::Client::new()
+            .head(format!("http://{}/vault/limits/POST%20%2Fvault", addr))
+            .header("Authorization", "Bearer prober")
+            .send()
+    };
+
+    let response = probe().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, "X-Ratelimit-Remaining"), Some(1));
+    assert_eq!(header(&probe().await.unwrap(), "X-Ratelimit-Remaining"), Some(1));
+
+    assert_eq!(post_vault(addr, Some("Bearer prober")).await.status(), StatusCode::OK);
+    let response = probe().await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(header(&response, "X-Ratelimit-Retry-After").is_some());
+
+    let unknown = reqwest::Client::new()
+        .get(format!("http://{}/vault/limits/GET%20%2Fnowhere", addr))
+        .header("Authorization", "Bearer prober")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(unknown.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn reports_what_each_request_cost() {
+    let addr = spawn(Config::default());
+    let client = reqwest::Client::new();
+
+    assert_eq!(header(&post_vault(addr, Some("Bearer batcher")).await, "X-Ratelimit-Cost"), Some(1));
+    let response = client
+        .post(format!("http://{}/vault/items:batch", addr))
+        .header("Authorization", "Bearer batcher")
+        .json(&serde_json::json!({"items": ["a", "b", "c"]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(header(&response, "X-Ratelimit-Cost"), Some(3));
+
+    // a batch of 5 more would still fit, and asking doesn't charge for it
+    let response = client
+        .get(format!("http://{}/vault/limits/POST%20%2Fvault%2Fitems%3Abatch?cost=5", addr))
+        .header("Authorization", "Bearer batcher")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(header(&response, "X-Ratelimit-Remaining"), Some(597));
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["cost"], serde_json::json!({"per_request": 1, "charged_by": "items"}));
+}
+
+#[tokio::test]
+async fn notifies_subscribers_when_quota_runs_low_and_resets() {
+    let addr = spawn(short_window_config(2, 1));
+    let mut events = reqwest::Client::new()
+        .get(format!("http://{}/quota/events?threshold=1", addr))
+        .header("Authorization", "Bearer watched")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(events.status(), StatusCode::OK);
+
+    // another token's usage isn't reported to this subscriber
+    post_vault(addr, Some("Bearer unwatched")).await;
+    post_vault(addr, Some("Bearer unwatched")).await;
+    post_vault(addr, Some("Bearer watched")).await;
+    post_vault(addr, Some("Bearer watched")).await;
+
+    let mut received = String::new();
+    while !received.contains("event:quota_reset") {
+        let chunk = tokio::time::timeout(Duration::from_secs(5), events.chunk()).await.unwrap().unwrap().unwrap();
+        received.push_str(std::str::from_utf8(&chunk).unwrap());
+    }
+    assert_eq!(received.matches("event:quota_low").count(), 1);
+    assert!(received.contains(r#""remaining":0"#));
+    assert!(received.find("event:quota_low") < received.find("event:quota_reset"));
+}
+
+#[tokio::test]
+async fn limits_by_client_ip_without_requiring_a_bearer_token() {
+    let mut config = short_window_config(1, 60);
+    config.routes.get_mut(POST_VAULT_ROUTE).unwrap().key = KeyExtractorConfig::ClientIp { trust_forwarded_for: false };
+    let addr = spawn(config);
+
+    assert_eq!(post_vault(addr, None).await.status(), StatusCode::OK);
+    // a different bearer token from the same address shares the address's quota
+    assert_eq!(post_vault(addr, Some("Bearer other")).await.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn chained_extractors_fall_back_in_order() {
+    let mut config = short_window_config(1, 60);
+    config.routes.get_mut(POST_VAULT_ROUTE).unwrap().key = KeyExtractorConfig::Chain {
+        extractors: vec![KeyExtractorConfig::ApiKeyHeader { header: None }, KeyExtractorConfig::BearerToken],
+    };
+    let addr = spawn(config);
+    let with_api_key = || reqwest::Client::new().post(format!("http://{}/vault", addr)).header("X-Api-Key", "key-1").header("Authorization", "Bearer shared");
+
+    assert_eq!(with_api_key().send().await.unwrap().status(), StatusCode::OK);
+    assert_eq!(with_api_key().send().await.unwrap().status(), StatusCode::TOO_MANY_REQUESTS);
+    // without the api key the bearer token is used, which hasn't been charged yet
+    assert_eq!(post_vault(addr, Some("Bearer shared")).await.status(), StatusCode::OK);
+    assert_eq!(post_vault(addr, None).await.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn fingerprints_count_a_token_per_device() {
+    let mut config = short_window_config(1, 60);
+    config.routes.get_mut(POST_VAULT_ROUTE).unwrap().key = KeyExtractorConfig::Fingerprint {
+        signals: vec![Signal::Token, Signal::UserAgent, Signal::IpSubnet],
+        ipv4_prefix: None,
+        ipv6_prefix: None,
+        trust_forwarded_for: true,
+    };
+    let addr = spawn(config);
+    let from = |token: Option<&str>, user_agent: &str, ip: &str| {
+        let mut request = reqwest::Client::new().post(format!("http://{}/vault", addr)).header("User-Agent", user_agent).header("X-Forwarded-For", ip);
+        if let Some(token) = token {
+            request = request.header("Authorization", token);
+        }
+        request.send()
+    };
+
+    assert_eq!(from(Some("Bearer shared"), "laptop", "203.0.113.5").await.unwrap().status(), StatusCode::OK);
+    // another address in the same /24 is the same device
+    assert_eq!(from(Some("Bearer shared"), "laptop", "203.0.113.77").await.unwrap().status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(from(Some("Bearer shared"), "phone", "203.0.113.5").await.unwrap().status(), StatusCode::OK);
+    assert_eq!(from(Some("Bearer shared"), "laptop", "198.51.100.1").await.unwrap().status(), StatusCode::OK);
+    assert_eq!(from(None, "laptop", "203.0.113.5").await.unwrap().status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn serves_repeated_listings_from_the_cache_at_a_reduced_cost() {
+    let mut config = Config::default();
+    config.response_cache = Some(ResponseCacheConfig { ttl_ms: 60_000, ..ResponseCacheConfig::default() });
+    let addr = spawn(config);
+    let client = reqwest::Client::new();
+    let list = |token: &str| client.get(format!("http://{}/vault/items", addr)).bearer_auth(token).send();
+
+    let first = list("poller").await.unwrap();
+    assert_eq!(first.headers()["X-Cache"], "miss");
+    let second = list("poller").await.unwrap();
+    assert_eq!(second.headers()["X-Cache"], "hit");
+    assert_eq!(second.headers()["X-Ratelimit-Cost"], "0");
+    assert_eq!(header(&second, "X-Ratelimit-Remaining"), header(&first, "X-Ratelimit-Remaining"));
+    // each client has its own cache
+    assert_eq!(list("other").await.unwrap().headers()["X-Cache"], "miss");
+
+    // a write leaves every cached listing stale
+    client.put(format!("http://{}/vault/items/a", addr)).bearer_auth("poller").body("{}").send().await.unwrap();
+    let after_write = list("poller").await.unwrap();
+    assert_eq!(after_write.headers()["X-Cache"], "miss");
+    assert!(after_write.text().await.unwrap().contains(r#""id":"a""#));
+}
+
+#[tokio::test]
+async fn decides_the_limit_before_listing_or_caching_anything() {
+    let mut config = Config::default();
+    config.routes.insert("GET /vault/items".to_string(), RouteConfig { limit: Some(1), window_seconds: Some(1), ..RouteConfig::default() });
+    config.response_cache = Some(ResponseCacheConfig { ttl_ms: 60_000, ..ResponseCacheConfig::default() });
+    let addr = spawn(config);
+    let client = reqwest::Client::new();
+    let list = |offset: usize| client.get(format!("http://{}/vault/items?offset={}", addr, offset)).bearer_auth("poller").send();
+
+    assert_eq!(list(0).await.unwrap().status(), StatusCode::OK);
+    let limited = list(1).await.unwrap();
+    assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(limited.headers().get("X-Cache").is_none());
+
+    // the rejected request left nothing in the cache
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(list(1).await.unwrap().headers()["X-Cache"], "miss");
+}
+
+#[tokio::test]
+async fn applies_limits_configured_for_a_path_template() {
+    let mut config = Config::default();
+    config.routes.insert("PUT /vault/items/{id}".to_string(), RouteConfig { limit: Some(1), ..RouteConfig::default() });
+    let addr = spawn(config);
+    let put_item = |id: &str| reqwest::Client::new().put(format!("http://{}/vault/items/{}", addr, id)).header("Authorization", "Bearer templated").body("{}").send();
+
+    let response = put_item("first").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, "X-Ratelimit-Remaining"), Some(0));
+    assert_eq!(put_item("first").await.unwrap().status(), StatusCode::TOO_MANY_REQUESTS);
+    // items are still counted separately under the template
+    assert_eq!(put_item("second").await.unwrap().status(), StatusCode::OK);
+}
@@ -0,0 +1,173 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use rate_limited_service::sharded::{ShardedStore, ShardingConfig};
+use rate_limited_service::store::{InMemoryStore, LimitOverride, MultiUsageResult, StoreError, UsageCharge, UsageResult, UsageStore};
+use rate_limited_service::RateLimit;
+
+// a shard that can be taken down, like a Redis endpoint going away
+#[derive(Debug, Clone, Default)]
+struct Shard {
+    store: Arc<InMemoryStore>,
+    down: Arc<AtomicBool>,
+    // calls go through but their replies are lost
+    timing_out: Arc<AtomicBool>,
+}
+
+impl Shard {
+    fn call<T>(&self, call: impl FnOnce(&InMemoryStore) -> Result<T, StoreError>) -> Result<T, StoreError> {
+        if self.down.load(Ordering::SeqCst) {
+            return Err(StoreError::Unavailable("connection refused".to_string()));
+        }
+        let result = call(&self.store);
+        match self.timing_out.load(Ordering::SeqCst) {
+            true => Err(StoreError::Timeout),
+            false => result,
+        }
+    }
+}
+
+impl UsageStore for Shard {
+    fn log_usage(&self, key: &str, rate_limit: &RateLimit, cost: u64, now: DateTime<Utc>) -> Result<UsageResult, StoreError> {
+        self.call(|store| store.log_usage(key, rate_limit, cost, now))
+    }
+
+    fn log_usage_many(&self, charges: &[UsageCharge<'_>], cost: u64, now: DateTime<Utc>) -> Result<MultiUsageResult, StoreError> {
+        self.call(|store| store.log_usage_many(charges, cost, now))
+    }
+
+    fn check_usage(&self, key: &str, rate_limit: &RateLimit, cost: u64, now: DateTime<Utc>) -> Result<UsageResult, StoreError> {
+        self.call(|store| store.check_usage(key, rate_limit, cost, now))
+    }
+
+    fn refund(&self, key: &str, rate_limit: &RateLimit, cost: u64, charged_at: DateTime<Utc>) -> Result<(), StoreError> {
+        self.call(|store| store.refund(key, rate_limit, cost, charged_at))
+    }
+
+    fn reserve(&self, key: &str, rate_limit: &RateLimit, id: &str, amount: u64, expires_at: DateTime<Utc>, now: DateTime<Utc>) -> Result<UsageResult, StoreError> {
+        self.call(|store| store.reserve(key, rate_limit, id, amount, expires_at, now))
+    }
+
+    fn release(&self, key: &str, rate_limit: &RateLimit, id: &str, cost: u64, now: DateTime<Utc>) -> Result<Option<UsageResult>, StoreError> {
+        self.call(|store| store.release(key, rate_limit, id, cost, now))
+    }
+
+    fn set_limit_override(&self, key: &str, limit_override: Option<LimitOverride>) -> Result<(), StoreError> {
+        self.call(|store| store.set_limit_override(key, limit_override))
+    }
+
+    fn limit_override(&self, key: &str, now: DateTime<Utc>) -> Result<Option<LimitOverride>, StoreError> {
+        self.call(|store| store.limit_override(key, now))
+    }
+
+    fn migrate_key(&self, from: &str, to: &str) -> Result<(), StoreError> {
+        self.call(|store| store.migrate_key(from, to))
+    }
+
+    fn record_nonce(&self, key: &str, expires_at: DateTime<Utc>, now: DateTime<Utc>) -> Result<bool, StoreError> {
+        self.call(|store| store.record_nonce(key, expires_at, now))
+    }
+
+    fn health_check(&self) -> Result<(), StoreError> {
+        self.call(|_| Ok(()))
+    }
+}
+
+fn sharded(names: &[&str]) -> (ShardedStore<Shard>, Vec<Shard>) {
+    let shards: Vec<Shard> = names.iter().map(|_| Shard::default()).collect();
+    // health is only checked when the tests ask
+    let config = ShardingConfig { health_check_interval_ms: 60 * 60 * 1000, ..ShardingConfig::default() };
+    let store = ShardedStore::new(names.iter().map(|name| name.to_string()).zip(shards.iter().cloned()).collect(), config);
+    (store, shards)
+}
+
+fn keys() -> Vec<String> {
+    (0..1000).map(|key| format!("key-{}", key)).collect()
+}
+
+#[test]
+fn spreads_keys_over_the_shards() {
+    let (store, _) = sharded(&["redis-a:6379", "redis-b:6379", "redis-c:6379"]);
+    let (reordered, _) = sharded(&["redis-c:6379", "redis-a:6379", "redis-b:6379"]);
+
+    for shard in ["redis-a:6379", "redis-b:6379", "redis-c:6379"] {
+        let owned = keys().iter().filter(|key| store.shard_for(key) == Some(shard)).count();
+        assert!((200..=466).contains(&owned), "{} holds {} of 1000 keys", shard, owned);
+    }
+    assert!(keys().iter().all(|key| store.shard_for(key) == reordered.shard_for(key)));
+}
+
+#[test]
+fn adding_a_shard_only_moves_keys_to_it() {
+    let (three, _) = sharded(&["redis-a:6379", "redis-b:6379", "redis-c:6379"]);
+    let (four, _) = sharded(&["redis-a:6379", "redis-b:6379", "redis-c:6379", "redis-d:6379"]);
+
+    let moved: Vec<String> = keys().into_iter().filter(|key| three.shard_for(key) != four.shard_for(key)).collect();
+    assert!(moved.iter().all(|key| four.shard_for(key) == Some("redis-d:6379")));
+    assert!((150..=350).contains(&moved.len()), "{} of 1000 keys moved", moved.len());
+}
+
+#[test]
+fn reroutes_keys_while_their_shard_is_down() {
+    let (store, shards) = sharded(&["redis-a:6379", "redis-b:6379", "redis-c:6379"]);
+    let rate_limit = RateLimit::new(5);
+    let home = store.shard_for("key-1").unwrap().to_string();
+    let home_index = ["redis-a:6379", "redis-b:6379", "redis-c:6379"].iter().position(|name| *name == home).unwrap();
+
+    assert_eq!(store.log_usage("key-1", &rate_limit, 3, Utc::now()).unwrap().unwrap().0, 2);
+
+    // the failed call goes to the next shard, where the key starts afresh
+    shards[home_index].down.store(true, Ordering::SeqCst);
+    assert_eq!(store.log_usage("key-1", &rate_limit, 1, Utc::now()).unwrap().unwrap().0, 4);
+    let fallback = store.shard_for("key-1").unwrap().to_string();
+    assert_ne!(fallback, home);
+    assert_eq!(store.check_health(), vec![home.as_str()]);
+
+    shards[home_index].down.store(false, Ordering::SeqCst);
+    assert!(store.check_health().is_empty());
+    assert_eq!(store.shard_for("key-1"), Some(home.as_str()));
+    assert_eq!(store.log_usage("key-1", &rate_limit, 1, Utc::now()).unwrap().unwrap().0, 1);
+
+    for shard in &shards {
+        shard.down.store(true, Ordering::SeqCst);
+    }
+    assert!(store.log_usage("key-1", &rate_limit, 1, Utc::now()).is_err());
+    assert!(store.health_check().is_err());
+}
+
+#[test]
+fn doesnt_charge_the_next_shard_after_a_timeout() {
+    let names = ["redis-a:6379", "redis-b:6379", "redis-c:6379"];
+    let (store, shards) = sharded(&names);
+    let rate_limit = RateLimit::new(5);
+    let home = names.iter().position(|name| Some(*name) == store.shard_for("key-1")).unwrap();
+
+    shards[home].timing_out.store(true, Ordering::SeqCst);
+    assert!(matches!(store.log_usage("key-1", &rate_limit, 1, Utc::now()), Err(StoreError::Timeout)));
+    // the charge landed on the shard that timed out and on no other
+    for (index, shard) in shards.iter().enumerate() {
+        let remaining = shard.store.check_usage("key-1", &rate_limit, 0, Utc::now()).unwrap().unwrap().0;
+        assert_eq!(remaining, if index == home { 4 } else { 5 }, "{}", names[index]);
+    }
+
+    // reads still move on to the next shard
+    assert_ne!(store.shard_for("key-1"), Some(names[home]));
+    shards[home].timing_out.store(false, Ordering::SeqCst);
+    assert_eq!(store.check_usage("key-1", &rate_limit, 0, Utc::now()).unwrap().unwrap().0, 5);
+}
+
+#[test]
+fn charges_keys_on_different_shards_together() {
+    let (store, _) = sharded(&["redis-a:6379", "redis-b:6379", "redis-c:6379"]);
+    let keys = keys();
+    let other = keys.iter().find(|key| store.shard_for(key) != store.shard_for(&keys[0])).unwrap();
+    let roomy = RateLimit::new(10);
+    let tight = RateLimit::new(1);
+    let charges = [UsageCharge { key: &keys[0], rate_limit: &roomy }, UsageCharge { key: other, rate_limit: &tight }];
+
+    assert!(store.log_usage_many(&charges, 1, Utc::now()).unwrap().is_ok());
+    assert_eq!(store.log_usage_many(&charges, 1, Utc::now()).unwrap().unwrap_err().0, 1);
+    // the rejected call didn't cost the first key anything
+    assert_eq!(store.check_usage(&keys[0], &roomy, 0, Utc::now()).unwrap().unwrap().0, 9);
+}
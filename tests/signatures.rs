@@ -0,0 +1,135 @@
+use std::net::SocketAddr;
+
+use chrono::{Duration, Utc};
+use rate_limited_service::config::Config;
+use rate_limited_service::scopes::ApiKeyConfig;
+use rate_limited_service::signatures::{self, SignatureError, NONCE_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+use rate_limited_service::store::{InMemoryStore, UsageStore};
+use reqwest::StatusCode;
+
+mod common;
+
+const SECRET: &[u8] = b"webhook-secret";
+
+#[test]
+fn verifies_signatures_over_the_timestamp_and_body() {
+    let now = Utc::now();
+    let tolerance = Duration::minutes(5);
+    let timestamp = now.timestamp();
+    let signature = signatures::sign(SECRET, timestamp, None, b"{\"a\":1}");
+    let verify = |timestamp: Option<&str>, signature: Option<&str>, body: &[u8]| signatures::verify(SECRET, timestamp, None, signature, body, now, tolerance);
+
+    assert_eq!(verify(Some(&timestamp.to_string()), Some(&signature), b"{\"a\":1}"), Ok(()));
+    assert_eq!(verify(Some(&timestamp.to_string()), Some(&format!("sha256={}", signature)), b"{\"a\":1}"), Ok(()));
+    assert_eq!(verify(Some(&timestamp.to_string()), Some(&signature), b"{\"a\":2}"), Err(SignatureError::Invalid));
+    assert_eq!(verify(Some(&(timestamp + 1).to_string()), Some(&signature), b"{\"a\":1}"), Err(SignatureError::Invalid));
+    assert_eq!(verify(Some(&timestamp.to_string()), Some("not hex"), b"{\"a\":1}"), Err(SignatureError::Invalid));
+    assert_eq!(verify(None, Some(&signature), b"{\"a\":1}"), Err(SignatureError::Missing));
+    assert_eq!(verify(Some(&timestamp.to_string()), None, b"{\"a\":1}"), Err(SignatureError::Missing));
+
+    let stale = timestamp - 301;
+    let signature = signatures::sign(SECRET, stale, None, b"");
+    assert_eq!(verify(Some(&stale.to_string()), Some(&signature), b""), Err(SignatureError::Stale));
+    let early = timestamp + 301;
+    let signature = signatures::sign(SECRET, early, None, b"");
+    assert_eq!(verify(Some(&early.to_string()), Some(&signature), b""), Err(SignatureError::Stale));
+}
+
+#[test]
+fn signs_the_nonce_with_the_body() {
+    let now = Utc::now();
+    let timestamp = now.timestamp().to_string();
+    let signature = signatures::sign(SECRET, now.timestamp(), Some("n-1"), b"{}");
+    let verify = |nonce: Option<&str>| signatures::verify(SECRET, Some(&timestamp), nonce, Some(&signature), b"{}", now, Duration::minutes(5));
+
+    assert_eq!(verify(Some("n-1")), Ok(()));
+    // swapping the nonce out for a fresh one breaks the signature
+    assert_eq!(verify(Some("n-2")), Err(SignatureError::Invalid));
+    assert_eq!(verify(None), Err(SignatureError::Invalid));
+}
+
+#[test]
+fn remembers_nonces_until_they_expire() {
+    let store = InMemoryStore::new();
+    let now = Utc::now();
+    let expires_at = now + Duration::minutes(10);
+
+    assert_eq!(store.record_nonce("nonce", expires_at, now), Ok(true));
+    assert_eq!(store.record_nonce("nonce", expires_at, now + Duration::minutes(5)), Ok(false));
+    assert_eq!(store.record_nonce("other", expires_at, now), Ok(true));
+    assert_eq!(store.record_nonce("nonce", expires_at + Duration::minutes(10), expires_at + Duration::seconds(1)), Ok(true));
+}
+
+fn spawn() -> SocketAddr {
+    let mut config = Config::default();
+    config.api_keys.push(ApiKeyConfig {
+        token_sha256: sha256::digest("signed-token"),
+        signing_secret: Some(String::from_utf8(SECRET.to_vec()).unwrap()),
+        ..ApiKeyConfig::default()
+    });
+    common::spawn(config)
+}
+
+async fn put_item(addr: SocketAddr, token: &str, body: &'static str, signature: Option<(i64, String)>) -> StatusCode {
+    put_item_with_nonce(addr, token, body, signature, None).await
+}
+
+async fn put_item_with_nonce(addr: SocketAddr, token: &str, body: &'static str, signature: Option<(i64, String)>, nonce: Option<&str>) -> StatusCode {
+    let mut request = reqwest::Client::new()
+        .put(format!("http://{}/vault/items/signed", addr))
+        .header("Authorization", format!("Bearer {}", token))
+        .body(body);
+    if let Some((timestamp, signature)) = signature {
+        request = request.header(TIMESTAMP_HEADER, timestamp).header(SIGNATURE_HEADER, signature);
+    }
+    if let Some(nonce) = nonce {
+        request = request.header(NONCE_HEADER, nonce);
+    }
+    request.send().await.unwrap().status()
+}
+
+#[tokio::test]
+async fn requires_signatures_from_keys_with_a_signing_secret() {
+    let addr = spawn();
+    let now = Utc::now().timestamp();
+    let body = "{\"plan\":\"pro\"}";
+
+    assert_eq!(put_item(addr, "signed-token", body, Some((now, signatures::sign(SECRET, now, None, body.as_bytes())))).await, StatusCode::OK);
+    assert_eq!(put_item(addr, "signed-token", body, None).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(put_item(addr, "signed-token", body, Some((now, signatures::sign(b"wrong", now, None, body.as_bytes())))).await, StatusCode::UNAUTHORIZED);
+    // a captured request replayed later
+    let then = now - 600;
+    assert_eq!(put_item(addr, "signed-token", body, Some((then, signatures::sign(SECRET, then, None, body.as_bytes())))).await, StatusCode::UNAUTHORIZED);
+
+    // tokens without a signing secret aren't asked for one
+    assert_eq!(put_item(addr, "other-token", body, None).await, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn rejects_replayed_nonces_with_a_conflict() {
+    let addr = spawn();
+    let now = Utc::now().timestamp();
+    let body = "{\"plan\":\"pro\"}";
+    let signed = |nonce| Some((now, signatures::sign(SECRET, now, Some(nonce), body.as_bytes())));
+
+    assert_eq!(put_item_with_nonce(addr, "signed-token", body, signed("n-1"), Some("n-1")).await, StatusCode::OK);
+    assert_eq!(put_item_with_nonce(addr, "signed-token", body, signed("n-1"), Some("n-1")).await, StatusCode::CONFLICT);
+    assert_eq!(put_item_with_nonce(addr, "signed-token", body, signed("n-2"), Some("n-2")).await, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn rejected_signatures_cost_nothing() {
+    let addr = spawn();
+    let client = reqwest::Client::new();
+    let post_vault = |signature: Option<String>| {
+        let now = Utc::now().timestamp();
+        let mut request = client.post(format!("http://{}/vault", addr)).header("Authorization", "Bearer signed-token").header(TIMESTAMP_HEADER, now);
+        request = request.header(SIGNATURE_HEADER, signature.unwrap_or_else(|| signatures::sign(SECRET, now, None, b"")));
+        request.send()
+    };
+
+    assert_eq!(post_vault(Some("00".to_string())).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+    let response = post_vault(None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["X-Ratelimit-Remaining"], "2");
+}
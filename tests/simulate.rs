@@ -0,0 +1,53 @@
+use rate_limited_service::config::Config;
+use rate_limited_service::simulate::{self, Algorithm, SimulateError, TraceRecord};
+
+// 2 requests a second on POST /vault, clients bursting across a window boundary
+const TRACE: &str = "timestamp,key,route
+900,Bearer a,POST /vault
+950,Bearer a,POST /vault
+1000,Bearer a,POST /vault
+1050,Bearer a,POST /vault
+1400,Bearer a,POST /vault
+1950,Bearer a,POST /vault
+0,Bearer b,POST /vault
+999,Bearer b,POST /vault
+1001,Bearer b,POST /vault
+1002,Bearer b,POST /vault
+1002,Bearer b,GET /unlimited
+";
+
+#[test]
+fn reports_what_each_algorithm_would_have_limited() {
+    let config = Config::parse("[routes.\"POST /vault\"]\nrate = \"2/1s\"\n").unwrap();
+    let report = simulate::run(&config, &simulate::parse_trace(TRACE).unwrap());
+
+    assert_eq!(report.requests, 11);
+    assert_eq!(report.unlimited, 1);
+    assert_eq!(report.routes["POST /vault"].requests, 10);
+    // b's second window starts at its 1001 request under a fixed window, but the sliding window still counts 999
+    assert_eq!(report.limited(Algorithm::FixedWindow), 3);
+    assert_eq!(report.limited(Algorithm::SlidingWindow), 5);
+    // a has a token back by 1400
+    assert_eq!(report.limited(Algorithm::TokenBucket), 3);
+}
+
+#[test]
+fn counts_grouped_routes_under_their_group() {
+    let config = Config::parse("[groups.writes]\nroutes = [\"POST /vault\", \"DELETE /vault/items/<:id>\"]\nlimit = 1\n").unwrap();
+    let trace = simulate::parse_trace("0,Bearer a,POST /vault\n10,Bearer a,DELETE /vault/items/<:id>\n").unwrap();
+    let report = simulate::run(&config, &trace);
+
+    assert_eq!(report.routes["group writes"].requests, 2);
+    assert_eq!(report.limited(Algorithm::FixedWindow), 1);
+}
+
+#[test]
+fn reads_rfc_3339_timestamps_and_reports_bad_lines() {
+    let trace = simulate::parse_trace("2026-01-01T00:00:00.250Z,Bearer a,POST /vault\n").unwrap();
+    assert_eq!(trace, vec![TraceRecord { timestamp_ms: 1_767_225_600_250, key: "Bearer a".to_string(), route: "POST /vault".to_string() }]);
+
+    match simulate::parse_trace("0,Bearer a,POST /vault\nyesterday,Bearer a,POST /vault\n") {
+        Err(SimulateError::Parse { line, .. }) => assert_eq!(line, 2),
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+}
@@ -0,0 +1,50 @@
+use std::time::Duration;
+
+use rate_limited_service::config::Config;
+use reqwest::StatusCode;
+
+mod common;
+
+#[tokio::test]
+async fn reports_ready_once_warmed_up() {
+    let mut config = Config::default();
+    config.startup.warmup_requests = 5000;
+    let addr = common::spawn(config);
+
+    let mut response = reqwest::get(format!("http://{}/readyz", addr)).await.unwrap();
+    for _ in 0..100 {
+        if response.status() == StatusCode::OK {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        response = reqwest::get(format!("http://{}/readyz", addr)).await.unwrap();
+    }
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.json::<serde_json::Value>().await.unwrap(), serde_json::json!({"phase": "ready"}));
+}
+
+#[cfg(feature = "testing")]
+#[tokio::test]
+async fn waits_for_the_store_before_reporting_ready() {
+    use std::sync::Arc;
+
+    use rate_limited_service::startup::{self, Phase, Readiness, StartupConfig};
+    use rate_limited_service::store::InMemoryStore;
+    use rate_limited_service::testing::{Chaos, ChaosStore};
+    use rate_limited_service::{RateLimit, RateLimiter};
+
+    let chaos = Chaos::new();
+    chaos.set_failing(true);
+    let rate_limiter = RateLimiter::with_store(Arc::new(ChaosStore::new(InMemoryStore::new(), chaos.clone())));
+    let readiness = Readiness::new();
+    let config = StartupConfig { warmup_requests: 10, store_retry_ms: 10, ..StartupConfig::default() };
+    tokio::spawn(startup::run(config, rate_limiter, vec![("POST /vault".to_string(), RateLimit::new(3))], readiness.clone()));
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(readiness.phase(), Phase::CheckingStore);
+
+    chaos.set_failing(false);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(readiness.phase(), Phase::Ready);
+}
@@ -0,0 +1,62 @@
+use std::sync::Arc;
+
+use rate_limited_service::config::{Config, RouteConfig};
+use rate_limited_service::server::{self, GET_VAULT_STREAM_ROUTE};
+use warp::ws::Message;
+
+fn stream_config(messages_per_second: u64) -> Config {
+    let mut config = Config::default();
+    config.routes.insert(
+        GET_VAULT_STREAM_ROUTE.to_string(),
+        RouteConfig { messages_per_second: Some(messages_per_second), ..RouteConfig::default() },
+    );
+    config
+}
+
+#[tokio::test]
+async fn pushes_vault_changes_to_subscribers() {
+    let routes = server::routes(Arc::new(stream_config(10)));
+    let mut client = warp::test::ws()
+        .path("/vault/stream")
+        .header("Authorization", "Bearer subscriber")
+        .handshake(routes.clone())
+        .await
+        .unwrap();
+
+    let response = warp::test::request()
+        .method("PUT")
+        .path("/vault/items/first")
+        .header("Authorization", "Bearer writer")
+        .body(r#"{"colour":"blue"}"#)
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), 200);
+
+    let message = client.recv().await.unwrap();
+    let event: serde_json::Value = serde_json::from_str(message.to_str().unwrap()).unwrap();
+    assert_eq!(event, serde_json::json!({ "type": "put", "item": { "id": "first", "data": { "colour": "blue" } } }));
+}
+
+#[tokio::test]
+async fn disconnects_clients_sending_too_fast() {
+    let routes = server::routes(Arc::new(stream_config(2)));
+    let mut client = warp::test::ws()
+        .path("/vault/stream")
+        .header("Authorization", "Bearer chatty")
+        .handshake(routes)
+        .await
+        .unwrap();
+
+    for _ in 0..3 {
+        client.send(Message::text("hello")).await;
+    }
+
+    client.recv_closed().await.unwrap();
+}
+
+#[tokio::test]
+async fn rejects_stream_requests_without_a_bearer_token() {
+    let routes = server::routes(Arc::new(Config::default()));
+
+    assert!(warp::test::ws().path("/vault/stream").handshake(routes).await.is_err());
+}
@@ -0,0 +1,36 @@
+use rate_limited_service::config::Config;
+use rate_limited_service::telemetry;
+
+#[test]
+fn parses_the_telemetry_table() {
+    let config = Config::parse(
+        r#"
+        [telemetry]
+        otlp_endpoint = "http://tempo:4318/v1/traces"
+        sampling_ratio = 0.1
+        service_name = "vault-rate-limiter"
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(config.telemetry.otlp_endpoint.as_deref(), Some("http://tempo:4318/v1/traces"));
+    assert_eq!(config.telemetry.sampling_ratio, 0.1);
+    assert_eq!(config.telemetry.service_name, "vault-rate-limiter");
+
+    // anything left out keeps its default
+    let config = Config::parse("[telemetry]\nsampling_ratio = 0.5").unwrap();
+    assert_eq!(config.telemetry.otlp_endpoint, None);
+    assert_eq!(config.telemetry.service_name, "rate_limited_service");
+}
+
+#[test]
+fn exports_nothing_by_default() {
+    let config = Config::parse("").unwrap();
+    assert_eq!(config.telemetry.otlp_endpoint, None);
+    assert_eq!(config.telemetry.sampling_ratio, 1.0);
+
+    // without an exporter there's nothing to run on, so no tokio runtime is needed
+    let telemetry = telemetry::init(&config.telemetry).unwrap();
+    tracing::info!("logged to stdout only");
+    drop(telemetry);
+}
@@ -0,0 +1,59 @@
+#![cfg(feature = "testing")]
+
+use std::net::SocketAddr;
+
+use rate_limited_service::config::{Config, RouteConfig};
+use rate_limited_service::server::POST_VAULT_ROUTE;
+use reqwest::StatusCode;
+
+mod common;
+
+fn spawn() -> SocketAddr {
+    let mut config = Config::default();
+    config.admin_token = Some("admin".to_string());
+    config.routes.insert(POST_VAULT_ROUTE.to_string(), RouteConfig { limit: Some(1), window_seconds: Some(60), ..RouteConfig::default() });
+    common::spawn(config)
+}
+
+async fn post_vault(addr: SocketAddr) -> StatusCode {
+    reqwest::Client::new().post(format!("http://{}/vault", addr)).header("Authorization", "Bearer chaos").send().await.unwrap().status()
+}
+
+fn testing(addr: SocketAddr, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+    reqwest::Client::new().request(method, format!("http://{}/testing{}", addr, path)).header("Authorization", "Bearer admin")
+}
+
+#[tokio::test]
+async fn fast_forwards_the_limiter_clock() {
+    let addr = spawn();
+
+    assert_eq!(post_vault(addr).await, StatusCode::OK);
+    assert_eq!(post_vault(addr).await, StatusCode::TOO_MANY_REQUESTS);
+
+    let response = testing(addr, reqwest::Method::POST, "/clock/advance").json(&serde_json::json!({"seconds": 61})).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.json::<serde_json::Value>().await.unwrap()["clock_offset_seconds"], 61);
+    assert_eq!(post_vault(addr).await, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn forces_store_errors_until_cleared() {
+    let addr = spawn();
+
+    let response = testing(addr, reqwest::Method::PUT, "/store").json(&serde_json::json!({"failing": true})).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    // the default failure policy rejects what the store can't decide
+    assert_eq!(post_vault(addr).await, StatusCode::SERVICE_UNAVAILABLE);
+
+    assert_eq!(testing(addr, reqwest::Method::DELETE, "").send().await.unwrap().status(), StatusCode::NO_CONTENT);
+    assert_eq!(post_vault(addr).await, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn only_admins_can_inject_faults() {
+    let addr = spawn();
+
+    let response = reqwest::Client::new().put(format!("http://{}/testing/store", addr)).header("Authorization", "Bearer chaos").json(&serde_json::json!({"failing": true})).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(post_vault(addr).await, StatusCode::OK);
+}
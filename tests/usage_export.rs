@@ -0,0 +1,119 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use rate_limited_service::usage_export::{UsageExportConfig, UsageExporter, UsageLedger};
+use warp::http::StatusCode;
+use warp::hyper::body::Bytes;
+use warp::Filter;
+
+// path, Authorization header and body of each object written
+type Uploads = Arc<Mutex<Vec<(String, String, String)>>>;
+
+// a bucket that keeps what's PUT to it, and answers 503 while `down` is set
+fn spawn_bucket(down: Arc<AtomicBool>) -> (String, Uploads) {
+    let uploads = Uploads::default();
+    let stored = uploads.clone();
+    let bucket = warp::put()
+        .and(warp::path::full())
+        .and(warp::header::<String>("authorization"))
+        .and(warp::body::bytes())
+        .map(move |path: warp::path::FullPath, authorization: String, body: Bytes| {
+            if down.load(Ordering::SeqCst) {
+                return StatusCode::SERVICE_UNAVAILABLE;
+            }
+            stored.lock().unwrap().push((path.as_str().to_string(), authorization, String::from_utf8_lossy(&body).into_owned()));
+            StatusCode::OK
+        });
+    let (addr, server) = warp::serve(bucket).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    (format!("http://{}", addr), uploads)
+}
+
+fn exporter(endpoint: String, ledger: Arc<UsageLedger>) -> UsageExporter {
+    let config = UsageExportConfig {
+        endpoint,
+        bucket: "billing".to_string(),
+        instance: Some("a".to_string()),
+        access_key_id: "AKIDEXAMPLE".to_string(),
+        secret_access_key: "secret".to_string(),
+        ..UsageExportConfig::default()
+    };
+    UsageExporter::new(&config, ledger)
+}
+
+fn at(time: &str) -> DateTime<Utc> {
+    time.parse().unwrap()
+}
+
+#[tokio::test]
+async fn writes_each_finished_hour_as_csv() {
+    let (endpoint, uploads) = spawn_bucket(Arc::default());
+    let ledger = Arc::new(UsageLedger::new());
+    ledger.record(Some("acme"), "POST /vault", false, at("2026-01-02T10:15:00Z"));
+    ledger.record(Some("acme"), "POST /vault", true, at("2026-01-02T10:20:00Z"));
+    ledger.record(None, "GET /vault/items", false, at("2026-01-02T10:40:00Z"));
+    // still going at the time of the export, so left for later
+    ledger.record(Some("acme"), "POST /vault", false, at("2026-01-02T11:05:00Z"));
+    let exporter = exporter(endpoint, ledger);
+
+    assert_eq!(exporter.export(at("2026-01-02T11:30:00Z")).await.unwrap(), 1);
+
+    let uploads = uploads.lock().unwrap().clone();
+    assert_eq!(uploads.len(), 1);
+    let (path, authorization, body) = &uploads[0];
+    assert_eq!(path, "/billing/usage/2026/01/02/10-a.csv");
+    assert!(authorization.starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/"));
+    assert_eq!(body, "hour,tenant,route,requests,limited_requests\n\
+        2026-01-02T10:00:00+00:00,,GET /vault/items,1,0\n\
+        2026-01-02T10:00:00+00:00,acme,POST /vault,2,1\n");
+}
+
+#[tokio::test]
+async fn keeps_hours_that_could_not_be_written_for_the_next_export() {
+    let down = Arc::new(AtomicBool::new(true));
+    let (endpoint, uploads) = spawn_bucket(down.clone());
+    let ledger = Arc::new(UsageLedger::new());
+    ledger.record(Some("acme"), "POST /vault", false, at("2026-01-02T10:15:00Z"));
+    let exporter = exporter(endpoint, ledger.clone());
+
+    assert!(exporter.export(at("2026-01-02T11:00:00Z")).await.is_err());
+    // counted late, e.g. by a request that started just before the hour ended
+    ledger.record(Some("acme"), "POST /vault", false, at("2026-01-02T10:59:59Z"));
+    down.store(false, Ordering::SeqCst);
+    assert_eq!(exporter.export(at("2026-01-02T11:01:00Z")).await.unwrap(), 1);
+
+    let uploads = uploads.lock().unwrap().clone();
+    assert!(uploads[0].2.ends_with("acme,POST /vault,2,0\n"));
+}
+
+#[tokio::test]
+async fn writes_the_running_hour_too_when_stopping() {
+    let (endpoint, uploads) = spawn_bucket(Arc::default());
+    let ledger = Arc::new(UsageLedger::new());
+    ledger.record(Some("acme"), "POST /vault", false, at("2026-01-02T10:15:00Z"));
+    ledger.record(Some("acme"), "POST /vault", true, at("2026-01-02T11:05:00Z"));
+    let exporter = exporter(endpoint, ledger);
+
+    assert_eq!(exporter.export_all(at("2026-01-02T11:30:15Z")).await.unwrap(), 2);
+
+    let uploads = uploads.lock().unwrap().clone();
+    let paths: Vec<&str> = uploads.iter().map(|(path, _, _)| path.as_str()).collect();
+    // named apart from the object an instance restarted as "a" writes once 11:00 is over
+    assert_eq!(paths, vec!["/billing/usage/2026/01/02/10-a.csv", "/billing/usage/2026/01/02/11-a-3015.csv"]);
+    assert!(uploads[1].2.ends_with("acme,POST /vault,1,1\n"));
+}
+
+#[tokio::test]
+async fn flushes_what_was_counted_on_shutdown() {
+    let (endpoint, uploads) = spawn_bucket(Arc::default());
+    let ledger = Arc::new(UsageLedger::new());
+    let handle = exporter(endpoint, ledger.clone()).spawn();
+    ledger.record(Some("acme"), "POST /vault", false, Utc::now());
+
+    handle.shutdown().await;
+
+    let uploads = uploads.lock().unwrap().clone();
+    assert_eq!(uploads.len(), 1);
+    assert!(uploads[0].2.ends_with("acme,POST /vault,1,0\n"));
+}
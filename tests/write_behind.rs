@@ -0,0 +1,161 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use rate_limited_service::store::{InMemoryStore, LimitOverride, MultiUsageResult, StoreError, UsageCharge, UsageResult, UsageStore};
+use rate_limited_service::write_behind::{WriteBehindConfig, WriteBehindStore};
+use rate_limited_service::RateLimit;
+
+// a store shared by several instances, counting the charges that reach it
+#[derive(Debug, Clone, Default)]
+struct SharedStore {
+    store: Arc<InMemoryStore>,
+    charges: Arc<AtomicUsize>,
+}
+
+impl UsageStore for SharedStore {
+    fn log_usage(&self, key: &str, rate_limit: &RateLimit, cost: u64, now: DateTime<Utc>) -> Result<UsageResult, StoreError> {
+        self.charges.fetch_add(1, Ordering::SeqCst);
+        self.store.log_usage(key, rate_limit, cost, now)
+    }
+
+    fn log_usage_many(&self, charges: &[UsageCharge<'_>], cost: u64, now: DateTime<Utc>) -> Result<MultiUsageResult, StoreError> {
+        self.store.log_usage_many(charges, cost, now)
+    }
+
+    fn check_usage(&self, key: &str, rate_limit: &RateLimit, cost: u64, now: DateTime<Utc>) -> Result<UsageResult, StoreError> {
+        self.store.check_usage(key, rate_limit, cost, now)
+    }
+
+    fn refund(&self, key: &str, rate_limit: &RateLimit, cost: u64, charged_at: DateTime<Utc>) -> Result<(), StoreError> {
+        self.store.refund(key, rate_limit, cost, charged_at)
+    }
+
+    fn reserve(&self, key: &str, rate_limit: &RateLimit, id: &str, amount: u64, expires_at: DateTime<Utc>, now: DateTime<Utc>) -> Result<UsageResult, StoreError> {
+        self.store.reserve(key, rate_limit, id, amount, expires_at, now)
+    }
+
+    fn release(&self, key: &str, rate_limit: &RateLimit, id: &str, cost: u64, now: DateTime<Utc>) -> Result<Option<UsageResult>, StoreError> {
+        self.store.release(key, rate_limit, id, cost, now)
+    }
+
+    fn set_limit_override(&self, key: &str, limit_override: Option<LimitOverride>) -> Result<(), StoreError> {
+        self.store.set_limit_override(key, limit_override)
+    }
+
+    fn limit_override(&self, key: &str, now: DateTime<Utc>) -> Result<Option<LimitOverride>, StoreError> {
+        self.store.limit_override(key, now)
+    }
+
+    fn migrate_key(&self, from: &str, to: &str) -> Result<(), StoreError> {
+        self.store.migrate_key(from, to)
+    }
+
+    fn record_nonce(&self, key: &str, expires_at: DateTime<Utc>, now: DateTime<Utc>) -> Result<bool, StoreError> {
+        self.store.record_nonce(key, expires_at, now)
+    }
+}
+
+// flushes are left to the tests
+fn manual(max_pending_cost: u64) -> WriteBehindConfig {
+    WriteBehindConfig { flush_interval_ms: 60 * 60 * 1000, max_pending_cost }
+}
+
+fn remaining(store: &dyn UsageStore, rate_limit: &RateLimit) -> u64 {
+    store.check_usage("key", rate_limit, 0, Utc::now()).unwrap().unwrap().0
+}
+
+#[test]
+fn batches_usage_to_the_backing_store() {
+    let backing = SharedStore::default();
+    let store = WriteBehindStore::new(backing.clone(), manual(100));
+    let rate_limit = RateLimit::new(10);
+
+    for expected in (5..10).rev() {
+        assert_eq!(store.log_usage("key", &rate_limit, 1, Utc::now()).unwrap().unwrap().0, expected);
+    }
+    // only the first request had to ask the store
+    assert_eq!(backing.charges.load(Ordering::SeqCst), 1);
+    assert_eq!(remaining(&backing, &rate_limit), 9);
+
+    store.flush().unwrap();
+    assert_eq!(backing.charges.load(Ordering::SeqCst), 2);
+    assert_eq!(remaining(&backing, &rate_limit), 5);
+}
+
+#[test]
+fn catches_up_with_other_instances_on_flush() {
+    let backing = SharedStore::default();
+    let first = WriteBehindStore::new(backing.clone(), manual(100));
+    let second = WriteBehindStore::new(backing.clone(), manual(100));
+    let rate_limit = RateLimit::new(10);
+
+    for _ in 0..6 {
+        first.log_usage("key", &rate_limit, 1, Utc::now()).unwrap().unwrap();
+    }
+    second.log_usage("key", &rate_limit, 1, Utc::now()).unwrap().unwrap();
+    first.flush().unwrap();
+
+    // the second instance only learns what the first used once it flushes too
+    assert_eq!(second.log_usage("key", &rate_limit, 1, Utc::now()).unwrap().unwrap().0, 7);
+    second.flush().unwrap();
+    assert_eq!(remaining(&second, &rate_limit), 2);
+    assert_eq!(remaining(&backing, &rate_limit), 2);
+}
+
+#[test]
+fn writes_through_once_the_consistency_bound_is_reached() {
+    let backing = SharedStore::default();
+    let store = WriteBehindStore::new(backing.clone(), manual(3));
+    let rate_limit = RateLimit::new(10);
+
+    for _ in 0..4 {
+        store.log_usage("key", &rate_limit, 1, Utc::now()).unwrap().unwrap();
+    }
+    assert_eq!(remaining(&backing, &rate_limit), 9);
+    // a fifth unit would leave four unwritten, so the three pending go first
+    assert_eq!(store.log_usage("key", &rate_limit, 2, Utc::now()).unwrap().unwrap().0, 4);
+    assert_eq!(remaining(&backing, &rate_limit), 4);
+
+    let synchronous = WriteBehindStore::new(SharedStore::default(), manual(0));
+    for _ in 0..3 {
+        synchronous.log_usage("key", &rate_limit, 1, Utc::now()).unwrap().unwrap();
+    }
+    assert_eq!(synchronous.flush(), Ok(()));
+}
+
+#[test]
+fn limits_locally_and_refunds_unwritten_usage() {
+    let backing = SharedStore::default();
+    let store = WriteBehindStore::new(backing.clone(), manual(100));
+    let rate_limit = RateLimit::new(3);
+    let now = Utc::now();
+
+    for _ in 0..3 {
+        store.log_usage("key", &rate_limit, 1, now).unwrap().unwrap();
+    }
+    assert!(store.log_usage("key", &rate_limit, 1, now).unwrap().is_err());
+
+    store.refund("key", &rate_limit, 1, now).unwrap();
+    assert_eq!(store.log_usage("key", &rate_limit, 1, now).unwrap().unwrap().0, 0);
+    store.flush().unwrap();
+    assert_eq!(remaining(&backing, &rate_limit), 0);
+}
+
+#[test]
+fn flushes_in_the_background() {
+    let backing = SharedStore::default();
+    let store = WriteBehindStore::new(backing.clone(), WriteBehindConfig { flush_interval_ms: 10, max_pending_cost: 100 });
+    let rate_limit = RateLimit::new(10);
+
+    for _ in 0..3 {
+        store.log_usage("key", &rate_limit, 1, Utc::now()).unwrap().unwrap();
+    }
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    assert_eq!(remaining(&backing, &rate_limit), 7);
+
+    // and whatever is still pending when the store is dropped
+    store.log_usage("key", &rate_limit, 2, Utc::now()).unwrap().unwrap();
+    drop(store);
+    assert_eq!(remaining(&backing, &rate_limit), 5);
+}